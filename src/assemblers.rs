@@ -0,0 +1,101 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Assembling sources given as the inputs of tapes, for going from the source
+// to the tape in one step.
+
+use std::fs;
+use std::path;
+use std::process;
+
+use files;
+use sound;
+
+
+pub struct Assembler {
+    pub name: &'static str,
+    // With `{source}', `{binary}' and `{listing}' standing for the files:
+    pub args: &'static [&'static str],
+    // Some pick what to write by the extensions of the files:
+    pub binary_extension: &'static str,
+    pub listing_extension: &'static str,
+}
+
+// In the order they're looked for:
+pub const ASSEMBLERS: &[Assembler] = &[
+    Assembler { name: "zmac",      args: &["-o", "{binary}", "-o", "{listing}", "{source}"],                binary_extension: "cim", listing_extension: "lst" },
+    Assembler { name: "pasmo",     args: &["{source}", "{binary}", "{listing}"],                            binary_extension: "bin", listing_extension: "sym" },
+    Assembler { name: "sjasmplus", args: &["--nologo", "--raw={binary}", "--lst={listing}", "{source}"], binary_extension: "bin", listing_extension: "lst" },
+];
+
+// Finds the assembler with the given name, or the first one installed:
+pub fn find_assembler(name: Option<&str>) -> Result<&'static Assembler, String> {
+    let names: Vec<&str> = ASSEMBLERS.iter().map(|assembler| assembler.name).collect();
+    match name {
+        Some(name) => {
+            match ASSEMBLERS.iter().find(|assembler| assembler.name == name.to_lowercase()) {
+                Some(assembler) => { Ok(assembler) },
+                None => { Err(format!("Unknown assembler `{}', expected one of {}", name, names.join(", "))) },
+            }
+        },
+        None => {
+            match ASSEMBLERS.iter().find(|assembler| sound::in_path(assembler.name)) {
+                Some(assembler) => { Ok(assembler) },
+                None => { Err(format!("None of the supported assemblers ({}) is installed", names.join(", "))) },
+            }
+        },
+    }
+}
+
+// Assembles the source in a fresh directory of its own, returning the
+// binary it made.  What the assembler has to say goes on to the user, all
+// of it if it fails:
+pub fn assemble(assembler: &Assembler, source_path: &path::Path, extra_args: &[String]) -> Result<Vec<u8>, String> {
+    let directory = files::make_temporary_directory("trs80m1-mltl")?;
+    let stem = source_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let binary_path = directory.join(format!("{}.{}", stem, assembler.binary_extension));
+    let listing_path = directory.join(format!("{}.{}", stem, assembler.listing_extension));
+
+    let (source, binary, listing) = (source_path.to_string_lossy(), binary_path.to_string_lossy(), listing_path.to_string_lossy());
+    let mut command = process::Command::new(assembler.name);
+    command.args(extra_args);
+    command.args(assembler.args.iter().map(|arg| arg.replace("{source}", &source).replace("{binary}", &binary).replace("{listing}", &listing)));
+    verbose!("Running `{}' on `{}'.", assembler.name, source_path.display());
+    let output = command.output();
+
+    let result = match output {
+        Ok(output) => {
+            let messages = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+            if output.status.success() {
+                for line in messages.lines() {
+                    verbose!("{}: {}", assembler.name, line);
+                }
+                fs::read(&binary_path).map_err(|error| format!("`{}' wrote no binary for `{}': {}", assembler.name, source_path.display(), error))
+            } else {
+                for line in messages.lines() {
+                    eprintln!("{}", line);
+                }
+                Err(format!("`{}' failed to assemble `{}', {}", assembler.name, source_path.display(), output.status))
+            }
+        },
+        Err(error) => { Err(format!("Failed to run `{}': {}", assembler.name, error)) },
+    };
+
+    // The directory is the program's own, along with whatever else the
+    // assembler left in it:
+    let _ = fs::remove_dir_all(&directory);
+    result
+}
@@ -0,0 +1,1212 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use std::f64::consts;
+use std::io;
+
+
+// The Level II ROM records at roughly 500 baud.  Every bit cell starts with
+// a clock pulse, and a 1 bit has an additional pulse in the middle of the
+// cell, a 0 bit doesn't.  A pulse is a short positive swing immediately
+// followed by a negative one.  Bytes are recorded most significant bit first.
+//
+// The Model III and 4 can also record at 1500 baud, by frequency shift
+// keying: every bit is a single full cycle, of 2400 Hz for a 1 bit, and of
+// 1200 Hz for a 0 bit, most significant bit first as well.
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+// Sample rates offered for the recordings, players and emulators expect one
+// of these:
+pub const SAMPLE_RATES: &[u32] = &[22050, 44100, 48000];
+
+// Shapes of the pulses, real cassette inputs often cope better with the
+// rounded ones than with hard square waves:
+#[derive(Clone, Copy, PartialEq)]
+pub enum PulseShape {
+    Square,
+    Sine,
+    // Edges ramping over the rise time:
+    Trapezoid,
+}
+
+pub const PULSE_SHAPES: &[(&str, PulseShape)] = &[
+    ("square",    PulseShape::Square),
+    ("sine",      PulseShape::Sine),
+    ("trapezoid", PulseShape::Trapezoid),
+];
+
+// The ways of recording the bits, the Level II's 500 baud pulses, and the
+// 1500 baud FSK of the Model III and 4's high speed:
+#[derive(Clone, Copy, PartialEq)]
+pub enum Baud {
+    Low,
+    High,
+}
+
+pub const BAUD_RATES: &[(&str, Baud)] = &[
+    ("500",  Baud::Low),
+    ("1500", Baud::High),
+];
+
+// How the recordings get written:
+#[derive(Clone, Copy)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    // 8 or 16:
+    pub bits: u16,
+    // 1 for mono, 2 for stereo, with the signal in both channels:
+    pub channels: u16,
+    // The whole signal the other way up, for decks and adapters which
+    // invert it on the way to the computer:
+    pub invert: bool,
+    // Stereo with the right channel phase-inverted, which some cassette
+    // input adapters need:
+    pub invert_right: bool,
+
+    pub shape: PulseShape,
+    // Of the edges of trapezoid pulses:
+    pub rise_us: u32,
+
+    // Peak level of the pulses, as a fraction of full scale:
+    pub amplitude: f64,
+}
+
+impl AudioFormat {
+    pub fn standard() -> AudioFormat {
+        AudioFormat {
+            sample_rate: SAMPLE_RATE,
+            bits: 16,
+            channels: 1,
+            invert: false,
+            invert_right: false,
+            shape: PulseShape::Square,
+            rise_us: 20,
+            amplitude: DEFAULT_AMPLITUDE,
+        }
+    }
+}
+
+pub const BIT_CELL_US: u32 = 2000;
+const DATA_PULSE_US:  u32 = 1000;
+const HALF_PULSE_US:  u32 = 125;
+
+const FULL_SCALE:     f64 = 32767.0;
+
+// A little below full scale, leaving headroom for the playback chain:
+pub const DEFAULT_AMPLITUDE: f64 = 24000.0 / FULL_SCALE;
+
+// Time it takes to record one byte:
+pub const BYTE_DURATION_US: u32 = 8 * BIT_CELL_US;
+
+
+fn us_to_samples(microseconds: u32, format: &AudioFormat) -> usize {
+    (((microseconds as u64) * (format.sample_rate as u64) + 500_000) / 1_000_000) as usize
+}
+
+// Level of the signal at the given sample of a half of a pulse, from 0 to 1:
+fn pulse_level(index: usize, half_pulse: usize, format: &AudioFormat) -> f64 {
+    match format.shape {
+        PulseShape::Square => { 1.0 },
+        PulseShape::Sine => {
+            (consts::PI * ((index as f64) + 0.5) / (half_pulse as f64)).sin()
+        },
+        PulseShape::Trapezoid => {
+            let rise = us_to_samples(format.rise_us, format).max(1) as f64;
+            let from_edge = (index.min(half_pulse - 1 - index) as f64) + 0.5;
+            (from_edge / rise).min(1.0)
+        },
+    }
+}
+
+fn push_pulse(samples: &mut Vec<i16>, half_pulse_us: u32, format: &AudioFormat) {
+    let half_pulse = us_to_samples(half_pulse_us, format).max(1);
+
+    for polarity in &[1.0, -1.0] {
+        for index in 0..half_pulse {
+            samples.push((FULL_SCALE * format.amplitude * polarity * pulse_level(index, half_pulse, format)).round() as i16);
+        }
+    }
+}
+
+fn push_silence(samples: &mut Vec<i16>, length: usize) {
+    for _counter in 0..length {
+        samples.push(0);
+    }
+}
+
+// Records a bit, with all the timings scaled to bit cells of `cell_us':
+fn push_bit(samples: &mut Vec<i16>, bit: bool, cell_us: u32, format: &AudioFormat) {
+    let half_pulse_us = HALF_PULSE_US * cell_us / BIT_CELL_US;
+    let cell_start = samples.len();
+    let pulse_length = 2 * us_to_samples(half_pulse_us, format).max(1);
+    let data_pulse_at = us_to_samples(DATA_PULSE_US * cell_us / BIT_CELL_US, format);
+    let cell_length = us_to_samples(cell_us, format);
+
+    push_pulse(samples, half_pulse_us, format);
+    if bit {
+        push_silence(samples, data_pulse_at - pulse_length);
+        push_pulse(samples, half_pulse_us, format);
+    }
+    let used = samples.len() - cell_start;
+    push_silence(samples, cell_length - used);
+}
+
+// Recordings are handed on in blocks of this many samples as they're made,
+// rather than held in memory whole, as a side of a long cassette takes
+// hundreds of megabytes at the higher sample rates:
+const STREAM_BLOCK_SAMPLES: usize = 65536;
+
+// Makes a recording a stretch at a time, passing the samples on to the sink
+// a block at a time:
+pub struct Encoder<'a> {
+    format: AudioFormat,
+    samples: Vec<i16>,
+    sink: &'a mut dyn FnMut(&[i16]) -> Result<(), String>,
+    // Samples passed on so far:
+    passed: usize,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(format: &AudioFormat, sink: &'a mut dyn FnMut(&[i16]) -> Result<(), String>) -> Encoder<'a> {
+        Encoder { format: *format, samples: Vec::with_capacity(2 * STREAM_BLOCK_SAMPLES), sink, passed: 0 }
+    }
+
+    // Number of samples recorded so far:
+    pub fn position(&self) -> usize {
+        self.passed + self.samples.len()
+    }
+
+    fn pass_on(&mut self) -> Result<(), String> {
+        if !self.samples.is_empty() {
+            (self.sink)(&self.samples)?;
+            self.passed += self.samples.len();
+            self.samples.clear();
+        }
+        Ok(())
+    }
+
+    fn pass_on_full(&mut self) -> Result<(), String> {
+        if self.samples.len() >= STREAM_BLOCK_SAMPLES {
+            self.pass_on()?;
+        }
+        Ok(())
+    }
+
+    // Records a tape image, reporting the number of bytes done so far
+    // through `progress'.  The `gaps' are stretches of silence to insert,
+    // given as the tape offset they go in front of and their length in
+    // microseconds, sorted by the offset:
+    pub fn tape(&mut self, tape: &[u8], gaps: &[(usize, u32)], progress: &mut dyn FnMut(usize)) -> Result<(), String> {
+        let mut gaps = gaps.iter().peekable();
+
+        for (index, byte) in tape.iter().enumerate() {
+            while let Some(&&(offset, length)) = gaps.peek() {
+                if offset != index {
+                    break;
+                }
+                self.silence(length)?;
+                gaps.next();
+            }
+            for bit_index in (0..8).rev() {
+                push_bit(&mut self.samples, (byte >> bit_index) & 0x01 != 0, BIT_CELL_US, &self.format);
+            }
+            self.pass_on_full()?;
+            if index % 64 == 0 {
+                progress(index);
+            }
+        }
+        progress(tape.len());
+
+        // A bit of silence at the end, so that the last pulse isn't cut off
+        // by players which fade out:
+        let length = us_to_samples(BIT_CELL_US, &self.format) * 8;
+        self.silent_samples(length)
+    }
+
+    // Records bytes with bit cells of `cell_us' rather than the ROM's,
+    // faster or slower, as read by the turbo and the slow loaders:
+    pub fn bytes(&mut self, bytes: &[u8], cell_us: u32) -> Result<(), String> {
+        for byte in bytes {
+            for bit_index in (0..8).rev() {
+                push_bit(&mut self.samples, (byte >> bit_index) & 0x01 != 0, cell_us, &self.format);
+            }
+            self.pass_on_full()?;
+        }
+        Ok(())
+    }
+
+    pub fn silence(&mut self, microseconds: u32) -> Result<(), String> {
+        let length = us_to_samples(microseconds, &self.format);
+        self.silent_samples(length)
+    }
+
+    // Silence may go on for minutes, it's made a block at a time too:
+    fn silent_samples(&mut self, mut length: usize) -> Result<(), String> {
+        while length > 0 {
+            let part = length.min(STREAM_BLOCK_SAMPLES);
+            push_silence(&mut self.samples, part);
+            length -= part;
+            self.pass_on_full()?;
+        }
+        Ok(())
+    }
+
+    // Passes on what's left, for the number of samples recorded in all:
+    pub fn finish(mut self) -> Result<usize, String> {
+        self.pass_on()?;
+        Ok(self.passed)
+    }
+}
+
+// Converts a tape image into audio samples, held in memory whole, as with
+// `Encoder::tape':
+pub fn encode_tape(tape: &[u8], gaps: &[(usize, u32)], format: &AudioFormat, progress: &mut dyn FnMut(usize)) -> Vec<i16> {
+    let mut samples = Vec::with_capacity(tape.len() * 8 * us_to_samples(BIT_CELL_US, format));
+    {
+        let mut sink = |block: &[i16]| -> Result<(), String> {
+            samples.extend_from_slice(block);
+            Ok(())
+        };
+        let mut encoder = Encoder::new(format, &mut sink);
+        // Nothing fails to take the samples:
+        let _ = encoder.tape(tape, gaps, progress);
+        let _ = encoder.finish();
+    }
+    samples
+}
+
+// Where the byte at the given offset of a tape image starts in its
+// recording made by `encode_tape' with the same gaps, in samples:
+pub fn tape_offset_position(offset: usize, gaps: &[(usize, u32)], format: &AudioFormat) -> usize {
+    let gap_samples: usize = gaps.iter().filter(|&&(gap_offset, _length)| gap_offset <= offset)
+                                 .map(|&(_gap_offset, length)| us_to_samples(length, format)).sum();
+    offset * 8 * us_to_samples(BIT_CELL_US, format) + gap_samples
+}
+
+// Length of the pulses the ROM records, in microseconds:
+pub const PULSE_US: u32 = 2 * HALF_PULSE_US;
+
+// Where the pulses of a tape image go, in microseconds from its start, as
+// the ROM records them:
+pub fn tape_pulse_times(tape: &[u8]) -> Vec<f64> {
+    let mut times = Vec::with_capacity(tape.len() * 12);
+    let mut cell_start: u64 = 0;
+
+    for byte in tape {
+        for bit_index in (0..8).rev() {
+            times.push(cell_start as f64);
+            if (byte >> bit_index) & 0x01 != 0 {
+                times.push((cell_start + (DATA_PULSE_US as u64)) as f64);
+            }
+            cell_start += BIT_CELL_US as u64;
+        }
+    }
+
+    times
+}
+
+// Records pulses starting at the given times, each as long as given, both
+// in microseconds, for regenerating a tape exactly from its pulse timings.
+// The times have to be in order:
+pub fn encode_pulses(pulses: &[(f64, f64)], format: &AudioFormat) -> Vec<i16> {
+    let mut samples = Vec::new();
+
+    for &(time, length) in pulses {
+        let start = ((time * (format.sample_rate as f64) / 1_000_000.0).round()) as usize;
+        let end = samples.len();
+        if start > end {
+            push_silence(&mut samples, start - end);
+        }
+        push_pulse(&mut samples, ((length / 2.0).round() as u32).max(1), format);
+    }
+    push_silence(&mut samples, us_to_samples(BIT_CELL_US, format) * 8);
+
+    samples
+}
+
+// The header of WAV data streamed to a player as it's made, with the
+// lengths left at their maximum, as is usual for streams of unknown length:
+pub fn wav_stream_header(format: &AudioFormat) -> Vec<u8> {
+    let mut header = Vec::with_capacity(WAV_HEADER_LENGTH);
+    push_wav_header(&mut header, format, u32::MAX - 36);
+    header
+}
+
+// The samples as the data of such a stream, without the header:
+pub fn wav_stream_data(samples: &[i16], format: &AudioFormat) -> Vec<u8> {
+    let mut data = Vec::with_capacity(samples.len() * ((format.bits / 8) * format.channels) as usize);
+    push_frames(&mut data, samples, format);
+    data
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.push((value & 0x00FF) as u8);
+    buffer.push(((value & 0xFF00) >> 8) as u8);
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    push_u16(buffer, (value & 0x0000FFFF) as u16);
+    push_u16(buffer, ((value & 0xFFFF0000) >> 16) as u16);
+}
+
+const WAV_HEADER_LENGTH: usize = 44;
+
+fn push_wav_header(buffer: &mut Vec<u8>, format: &AudioFormat, data_length: u32) {
+    let frame_size = (format.bits / 8) * format.channels;
+
+    buffer.extend_from_slice(b"RIFF");
+    push_u32(buffer, 36u32.saturating_add(data_length));
+    buffer.extend_from_slice(b"WAVE");
+
+    buffer.extend_from_slice(b"fmt ");
+    push_u32(buffer, 16);                                        // Size of the format chunk.
+    push_u16(buffer, 1);                                         // PCM.
+    push_u16(buffer, format.channels);
+    push_u32(buffer, format.sample_rate);
+    push_u32(buffer, format.sample_rate * (frame_size as u32)); // Byte rate.
+    push_u16(buffer, frame_size);                                // Block align.
+    push_u16(buffer, format.bits);                               // Bits per sample.
+
+    buffer.extend_from_slice(b"data");
+    push_u32(buffer, data_length);
+}
+
+fn push_frames(buffer: &mut Vec<u8>, samples: &[i16], format: &AudioFormat) {
+    for sample in samples {
+        let sample = if format.invert { sample.saturating_neg() } else { *sample };
+        for channel in 0..format.channels {
+            let sample = if channel == 1 && format.invert_right {
+                sample.saturating_neg()
+            } else {
+                sample
+            };
+
+            // 8-bit samples are unsigned:
+            if format.bits == 8 {
+                buffer.push((((sample as i32) >> 8) + 128) as u8);
+            } else {
+                push_u16(buffer, sample as u16);
+            }
+        }
+    }
+}
+
+// Wraps the samples into a PCM RIFF WAVE file of the given format:
+pub fn generate_wav_file(samples: &[i16], format: &AudioFormat) -> Vec<u8> {
+    let frame_size = (format.bits / 8) * format.channels;
+    let data_length = (samples.len() as u32) * (frame_size as u32);
+    let mut buffer = Vec::with_capacity(WAV_HEADER_LENGTH + (data_length as usize));
+
+    push_wav_header(&mut buffer, format, data_length);
+    push_frames(&mut buffer, samples, format);
+
+    buffer
+}
+
+// Writes a WAV file as the samples come, the lengths in the header filled
+// in once they're all there:
+pub struct WavWriter<W: io::Write + io::Seek> {
+    writer: W,
+    format: AudioFormat,
+    data_length: u64,
+    buffer: Vec<u8>,
+}
+
+impl<W: io::Write + io::Seek> WavWriter<W> {
+    pub fn new(mut writer: W, format: &AudioFormat) -> io::Result<WavWriter<W>> {
+        let mut header = Vec::with_capacity(WAV_HEADER_LENGTH);
+        push_wav_header(&mut header, format, 0);
+        writer.write_all(&header)?;
+        Ok(WavWriter { writer, format: *format, data_length: 0, buffer: Vec::new() })
+    }
+
+    pub fn write(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.buffer.clear();
+        push_frames(&mut self.buffer, samples, &self.format);
+        self.data_length += self.buffer.len() as u64;
+        if self.data_length > (u32::MAX - 36) as u64 {
+            return Err(io::Error::other("the recording is too long for a WAV file, which holds up to 4 GB"));
+        }
+        self.writer.write_all(&self.buffer)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let mut header = Vec::with_capacity(WAV_HEADER_LENGTH);
+        push_wav_header(&mut header, &self.format, self.data_length as u32);
+        self.writer.seek(io::SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.seek(io::SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+
+// A recording read back from a WAV file, mixed down to mono:
+pub struct Recording {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+fn read_u16(buffer: &[u8], offset: usize) -> u16 {
+    (buffer[offset] as u16) | ((buffer[offset + 1] as u16) << 8)
+}
+
+fn read_u32(buffer: &[u8], offset: usize) -> u32 {
+    (read_u16(buffer, offset) as u32) | ((read_u16(buffer, offset + 2) as u32) << 16)
+}
+
+// Reads an 8 or 16-bit PCM RIFF WAVE file:
+pub fn parse_wav_file(buffer: &[u8]) -> Result<Recording, String> {
+    if buffer.len() < 12 || &buffer[0..4] != b"RIFF" || &buffer[8..12] != b"WAVE" {
+        return Err("Not a RIFF WAVE file".to_owned());
+    }
+
+    let mut format: Option<(u16, u32, u16)> = None;
+    let mut offset = 12;
+    while offset + 8 <= buffer.len() {
+        let chunk_id = &buffer[offset..(offset + 4)];
+        let chunk_length = read_u32(buffer, offset + 4) as usize;
+        let chunk_start = offset + 8;
+        let chunk_end = chunk_start.saturating_add(chunk_length).min(buffer.len());
+
+        if chunk_id == b"fmt " {
+            if chunk_end - chunk_start < 16 {
+                return Err("The format chunk is too short".to_owned());
+            }
+            if read_u16(buffer, chunk_start) != 1 {
+                return Err("Only uncompressed PCM recordings are supported".to_owned());
+            }
+            format = Some((read_u16(buffer, chunk_start + 2),
+                           read_u32(buffer, chunk_start + 4),
+                           read_u16(buffer, chunk_start + 14)));
+        } else if chunk_id == b"data" {
+            let (channels, sample_rate, bits) = match format {
+                Some(format) => { format },
+                None => { return Err("The data chunk comes before the format chunk".to_owned()); },
+            };
+            if channels == 0 || (bits != 8 && bits != 16) {
+                return Err(format!("Unsupported sample format, {} channels of {} bits", channels, bits));
+            }
+
+            let sample_size = (bits / 8) as usize;
+            let frame_size = sample_size * (channels as usize);
+            let data = &buffer[chunk_start..chunk_end];
+            let frame_count = data.len() / frame_size;
+            let sample_at = |frame: usize, channel: usize| {
+                let offset = frame * frame_size + channel * sample_size;
+                if sample_size == 1 {
+                    ((data[offset] as i32) - 128) << 8
+                } else {
+                    (read_u16(data, offset) as i16) as i32
+                }
+            };
+
+            // Channels recorded phase-inverted against the first one are
+            // flipped back, else they'd cancel it out in the mix:
+            let polarities: Vec<i32> = (0..(channels as usize)).map(|channel| {
+                let correlation: i64 = (0..frame_count).map(|frame| (sample_at(frame, 0) as i64) * (sample_at(frame, channel) as i64)).sum();
+                if correlation < 0 { -1 } else { 1 }
+            }).collect();
+            let samples = (0..frame_count).map(|frame| {
+                let sum: i32 = polarities.iter().enumerate().map(|(channel, polarity)| sample_at(frame, channel) * polarity).sum();
+                (sum / (channels as i32)) as i16
+            }).collect();
+
+            return Ok(Recording { sample_rate, samples });
+        }
+
+        // Chunks are padded to an even length:
+        offset = chunk_start + chunk_length + (chunk_length & 1);
+    }
+
+    Err("The recording contains no data chunk".to_owned())
+}
+
+// Time constants of the pulse detection: the envelope follows the level of
+// the signal over several bit cells, so that it holds between the pulses,
+// and the offset is averaged over longer still, to cancel out DC bias and hum:
+const ENVELOPE_US:    f64 = 10_000.0;
+const OFFSET_US:      f64 = 50_000.0;
+
+// Signals this much below the loudest part of the recording are taken for
+// noise:
+const NOISE_FRACTION: i32 = 16;
+
+// The level of the signal with the offset taken out, and its envelope, are
+// followed through filters which carry over from one sample to the next,
+// which would tie the work down to a sample at a time.  Instead, the samples
+// are taken in blocks of several stretches, run through the filters side by
+// side as if silence came before each of them, and then put together: the
+// offset filter being linear, and the envelope the loudest of the decaying
+// levels, what went before a stretch only adds on, decayed by the length of
+// the stretch taken in so far.  The stretches are long enough for that to be
+// cheap, but short enough for the decay not to vanish:
+const LANES: usize = 8;
+const LANE_SAMPLES: usize = 512;
+
+// How the filters are run, in the stretches side by side, or a sample at a
+// time as they're written down, which `decode --bench' compares against and
+// the results have to match:
+#[derive(Clone, Copy)]
+pub enum Filtering {
+    Lanes,
+    Serial,
+}
+
+// How often the passes over a recording report how far they got, in
+// samples, a whole number of blocks of the stretches side by side:
+const PROGRESS_SAMPLES: usize = 65536;
+
+// Runs the samples through the filters, passing the level and the envelope
+// at each of them to `visit', along with where the sample is, and reporting
+// the number of samples done now and then through `progress':
+fn track_levels<F: FnMut(usize, f64, f64)>(samples: &[i16], sample_rate: u32, filtering: Filtering, progress: &mut dyn FnMut(usize), visit: F) {
+    match filtering {
+        Filtering::Lanes => { track_levels_in_lanes(samples, sample_rate, progress, visit); },
+        Filtering::Serial => { track_levels_serially(samples, sample_rate, progress, visit); },
+    }
+    progress(samples.len());
+}
+
+fn track_levels_serially<F: FnMut(usize, f64, f64)>(samples: &[i16], sample_rate: u32, progress: &mut dyn FnMut(usize), mut visit: F) {
+    let samples_per_us = (sample_rate as f64) / 1_000_000.0;
+    let decay = 0.5f64.powf(1.0 / (ENVELOPE_US * samples_per_us));
+    let offset_rate = 1.0 / (OFFSET_US * samples_per_us);
+
+    let mut offset = 0.0;
+    let mut envelope = 0.0;
+    for (index, sample) in samples.iter().enumerate() {
+        if index.is_multiple_of(PROGRESS_SAMPLES) {
+            progress(index);
+        }
+        offset += ((*sample as f64) - offset) * offset_rate;
+        let level = (*sample as f64) - offset;
+        envelope = (envelope * decay).max(level.abs());
+        visit(index, level, envelope);
+    }
+}
+
+fn track_levels_in_lanes<F: FnMut(usize, f64, f64)>(samples: &[i16], sample_rate: u32, progress: &mut dyn FnMut(usize), mut visit: F) {
+    let samples_per_us = (sample_rate as f64) / 1_000_000.0;
+    let decay = 0.5f64.powf(1.0 / (ENVELOPE_US * samples_per_us));
+    let offset_rate = 1.0 / (OFFSET_US * samples_per_us);
+    let keep = 1.0 - offset_rate;
+
+    // How much of the filters is left a number of samples on:
+    let keep_powers: Vec<f64> = (1..(LANE_SAMPLES as i32 + 1)).map(|count| keep.powi(count)).collect();
+    let decay_powers: Vec<f64> = (1..(LANE_SAMPLES as i32 + 1)).map(|count| decay.powi(count)).collect();
+
+    let mut levels = vec![0.0; LANES * LANE_SAMPLES];
+    let mut envelopes = vec![0.0; LANES * LANE_SAMPLES];
+    let mut offset = 0.0;
+    let mut envelope = 0.0;
+    let mut blocks = samples.chunks_exact(LANES * LANE_SAMPLES);
+    let mut start: usize = 0;
+    for block in &mut blocks {
+        if start.is_multiple_of(PROGRESS_SAMPLES) {
+            progress(start);
+        }
+
+        // The offsets of the stretches side by side, kept in `levels' until
+        // the levels are worked out from them:
+        let mut lane_offsets = [0.0f64; LANES];
+        for index in 0..LANE_SAMPLES {
+            for (lane, lane_offset) in lane_offsets.iter_mut().enumerate() {
+                let position = lane * LANE_SAMPLES + index;
+                *lane_offset += ((block[position] as f64) - *lane_offset) * offset_rate;
+                levels[position] = *lane_offset;
+            }
+        }
+        let mut offset_starts = [0.0f64; LANES];
+        for (offset_start, lane_offset) in offset_starts.iter_mut().zip(&lane_offsets) {
+            *offset_start = offset;
+            offset = lane_offset + keep_powers[LANE_SAMPLES - 1] * offset;
+        }
+        for ((stretch, samples), offset_start) in levels.chunks_mut(LANE_SAMPLES).zip(block.chunks(LANE_SAMPLES)).zip(&offset_starts) {
+            for ((level, sample), power) in stretch.iter_mut().zip(samples).zip(&keep_powers) {
+                *level = (*sample as f64) - (*level + power * offset_start);
+            }
+        }
+
+        let mut lane_envelopes = [0.0f64; LANES];
+        for index in 0..LANE_SAMPLES {
+            for (lane, lane_envelope) in lane_envelopes.iter_mut().enumerate() {
+                let position = lane * LANE_SAMPLES + index;
+                *lane_envelope = (*lane_envelope * decay).max(levels[position].abs());
+                envelopes[position] = *lane_envelope;
+            }
+        }
+        let mut envelope_starts = [0.0f64; LANES];
+        for (envelope_start, lane_envelope) in envelope_starts.iter_mut().zip(&lane_envelopes) {
+            *envelope_start = envelope;
+            envelope = lane_envelope.max(decay_powers[LANE_SAMPLES - 1] * envelope);
+        }
+        for (stretch, envelope_start) in envelopes.chunks_mut(LANE_SAMPLES).zip(&envelope_starts) {
+            for (value, power) in stretch.iter_mut().zip(&decay_powers) {
+                *value = value.max(power * envelope_start);
+            }
+        }
+
+        for (index, (level, envelope)) in levels.iter().zip(&envelopes).enumerate() {
+            visit(start + index, *level, *envelope);
+        }
+        start += block.len();
+    }
+
+    // What's left over is too short to be worth splitting up:
+    for (index, sample) in blocks.remainder().iter().enumerate() {
+        offset += ((*sample as f64) - offset) * offset_rate;
+        let level = (*sample as f64) - offset;
+        envelope = (envelope * decay).max(level.abs());
+        visit(start + index, level, envelope);
+    }
+}
+
+// Finds where the pulses of a recording start, at the samples where the
+// signal first rises above half of its envelope.  The threshold follows the
+// level of the signal, so that weak and fading recordings are read along with
+// loud ones, and the signal has to fall back below half of the threshold
+// before the next pulse is looked for, so that noise on the edges doesn't
+// make pulses of its own:
+pub fn find_pulses(samples: &[i16], sample_rate: u32) -> Vec<usize> {
+    find_pulses_by(samples, sample_rate, Filtering::Lanes, &mut |_done| {})
+}
+
+pub fn find_pulses_by(samples: &[i16], sample_rate: u32, filtering: Filtering, progress: &mut dyn FnMut(usize)) -> Vec<usize> {
+    let peak = samples.iter().map(|sample| (*sample as i32).abs()).max().unwrap_or(0);
+    let floor = (peak / NOISE_FRACTION) as f64;
+    if floor < 1.0 {
+        return Vec::new();
+    }
+
+    let mut pulses = Vec::new();
+    let mut armed = true;
+    track_levels(samples, sample_rate, filtering, progress, |position, level, envelope| {
+        let threshold = (envelope / 2.0).max(floor);
+        if armed && level > threshold {
+            pulses.push(position);
+            armed = false;
+        } else if !armed && level < threshold / 2.0 {
+            armed = true;
+        }
+    });
+    pulses
+}
+
+// Splits a recording at the pauses of at least the given length into the
+// sample ranges of the stretches of signal, such as of the programs recorded
+// one after another on a side of a cassette:
+pub fn split_at_pauses(recording: &Recording, pause_us: u32) -> Vec<(usize, usize)> {
+    let pulses = find_pulses(&recording.samples, recording.sample_rate);
+    let pause = ((pause_us as u64) * (recording.sample_rate as u64) / 1_000_000) as usize;
+
+    let mut stretches = Vec::new();
+    let mut start = 0;
+    for pair in pulses.windows(2) {
+        if pair[1] - pair[0] >= pause {
+            let cut = (pair[0] + pair[1]) / 2;
+            stretches.push((start, cut));
+            start = cut;
+        }
+    }
+    if !pulses.is_empty() {
+        stretches.push((start, recording.samples.len()));
+    }
+
+    stretches
+}
+
+// Measurements of a stretch of a recording, for setting up decks:
+pub struct Measurements {
+    pub peak: i32,
+    pub offset: f64,
+    pub pulse_count: usize,
+    // Median time from one pulse to the next, in microseconds:
+    pub pulse_interval_us: f64,
+}
+
+pub fn measure(samples: &[i16], sample_rate: u32) -> Measurements {
+    let peak = samples.iter().map(|sample| (*sample as i32).abs()).max().unwrap_or(0);
+    let offset = if samples.is_empty() {
+        0.0
+    } else {
+        samples.iter().map(|sample| *sample as f64).sum::<f64>() / (samples.len() as f64)
+    };
+
+    let pulses = find_pulses(samples, sample_rate);
+    let mut intervals: Vec<usize> = pulses.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    intervals.sort_unstable();
+    let pulse_interval_us = match intervals.get(intervals.len() / 2) {
+        Some(interval) => { (*interval as f64) * 1_000_000.0 / (sample_rate as f64) },
+        None => { 0.0 },
+    };
+
+    Measurements {
+        peak,
+        offset,
+        pulse_count: pulses.len(),
+        pulse_interval_us,
+    }
+}
+
+// The outcome of decoding a recording:
+pub struct Decoded {
+    pub bytes: Vec<u8>,
+    // The speed the recording was found to play at, relative to the nominal
+    // baud rate, 1.04 being 4% fast:
+    pub speed: f64,
+
+    // Every bit told apart, in or out of sync, and the sample each of the
+    // bytes starts at:
+    pub bits: Vec<Bit>,
+    pub byte_positions: Vec<usize>,
+    // Pulses which fit neither a clock nor a data pulse, and got skipped:
+    pub noise: Vec<usize>,
+}
+
+pub struct Bit {
+    // The sample the clock pulse of the bit starts at:
+    pub position: usize,
+    pub value: bool,
+    // How clearly the pulses told the bit apart, from 1.0 for pulses right
+    // where they belong down to 0.0 for ones at the edge of being taken for
+    // the other bit:
+    pub margin: f64,
+}
+
+// Below this margin, a bit was only just told apart:
+pub const MARGINAL_BIT: f64 = 0.5;
+
+impl Decoded {
+    // The bits from one sample up to another:
+    pub fn bits_between(&self, start: usize, end: usize) -> &[Bit] {
+        let first = self.bits.partition_point(|bit| bit.position < start);
+        let last = self.bits.partition_point(|bit| bit.position < end);
+        &self.bits[first..last]
+    }
+}
+
+// Gathers the bits told apart into bytes, from the first sync byte on:
+struct Framer {
+    sync_byte: u8,
+    // A byte the sync byte has to follow two of, if any:
+    leader_byte: Option<u8>,
+    bytes: Vec<u8>,
+    bits: Vec<Bit>,
+    byte_positions: Vec<usize>,
+    shift_register: u8,
+    // The two bytes the shift register held before:
+    previous_bytes: u16,
+    bit_count: usize,
+    in_sync: bool,
+}
+
+impl Framer {
+    fn new(sync_byte: u8, leader_byte: Option<u8>) -> Framer {
+        Framer {
+            sync_byte,
+            leader_byte,
+            bytes: Vec::new(),
+            bits: Vec::new(),
+            byte_positions: Vec::new(),
+            shift_register: 0,
+            previous_bytes: 0,
+            bit_count: 0,
+            in_sync: false,
+        }
+    }
+
+    // A pause in the recording loses the sync, unless it came between two
+    // bytes:
+    fn pause(&mut self) {
+        if !(self.in_sync && self.bit_count == 0) {
+            self.in_sync = false;
+            self.shift_register = 0;
+            self.previous_bytes = 0;
+        }
+    }
+
+    // Takes in the next bit, and the pause following it if there is one:
+    fn push(&mut self, bit: Bit, pause: bool) {
+        let value = bit.value;
+        self.bits.push(bit);
+        self.previous_bytes = (self.previous_bytes << 1) | ((self.shift_register >> 7) as u16);
+        self.shift_register = (self.shift_register << 1) | (value as u8);
+        if self.in_sync {
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.bytes.push(self.shift_register);
+                self.byte_positions.push(self.bits[self.bits.len() - 8].position);
+                self.bit_count = 0;
+            }
+        } else if self.shift_register == self.sync_byte && self.bits.len() >= 8
+                  && self.leader_byte.is_none_or(|leader_byte| self.previous_bytes == (leader_byte as u16) * 0x0101 && self.bits.len() >= 24) {
+            self.bytes.push(self.shift_register);
+            self.byte_positions.push(self.bits[self.bits.len() - 8].position);
+            self.in_sync = true;
+            self.bit_count = 0;
+        }
+
+        if pause {
+            self.pause();
+        }
+    }
+
+    fn finish(self, speed: f64, noise: Vec<usize>) -> Decoded {
+        Decoded { bytes: self.bytes, speed, bits: self.bits, byte_positions: self.byte_positions, noise }
+    }
+}
+
+// Decodes a recording back into bytes, starting with the first sync byte.
+// A gap in the pulse train in the middle of a byte restarts the search for
+// a sync byte, as there is one after every leader, gaps between bytes are
+// taken as inter-block pauses.
+//
+// The length of the bit cells is tracked as the pulses come in, like a PLL
+// would, so that tapes recorded or played a bit too fast or too slow, or by
+// a deck whose wow and flutter makes the speed drift, are read as well.  The
+// expected speed is given as a factor, 1.0 for a deck running at the right
+// speed; a slower estimate of the speed carries the timing across pauses.
+// The number of samples gone through is reported through `progress':
+pub fn decode_samples(recording: &Recording, sync_byte: u8, speed: f64, progress: &mut dyn FnMut(usize)) -> Decoded {
+    let pulses = find_pulses_by(&recording.samples, recording.sample_rate, Filtering::Lanes, progress);
+    let nominal_cell = (BIT_CELL_US as f64) * (recording.sample_rate as f64) / 1_000_000.0 / speed;
+    let mut cell = nominal_cell;
+    let mut estimate = nominal_cell;
+    let mut interval_sum = 0.0;
+    let mut interval_count = 0;
+
+    let mut framer = Framer::new(sync_byte, None);
+    let mut noise = Vec::new();
+
+    let mut index = 0;
+    while index < pulses.len() {
+        let position = pulses[index];
+        let gap = if index + 1 < pulses.len() { (pulses[index + 1] - position) as f64 } else { f64::INFINITY };
+
+        // A data pulse makes it a 1 bit, the next clock pulse comes after
+        // it.  A clock pulse followed by nothing is the last 0 bit before a
+        // pause in the recording, which ends the stretch of bits:
+        // The margin is how far the gap is from the thresholds, in
+        // quarters of a cell:
+        let (bit, pause, clock_interval, margin) = if gap > cell / 4.0 && gap < cell * 3.0 / 4.0 {
+            let interval = if index + 2 < pulses.len() { Some((pulses[index + 2] - pulses[index]) as f64) } else { None };
+            index += 2;
+            (true, false, interval, (gap - cell / 4.0).min(cell * 3.0 / 4.0 - gap) / (cell / 4.0))
+        } else if gap >= cell * 3.0 / 4.0 && gap < cell * 3.0 / 2.0 {
+            index += 1;
+            (false, false, Some(gap), ((gap - cell * 3.0 / 4.0).min(cell * 3.0 / 2.0 - gap) / (cell / 4.0)).min(1.0))
+        } else if gap >= cell * 3.0 / 2.0 {
+            index += 1;
+            // The quick timing is lost in a pause, start over from the
+            // longer term estimate:
+            cell = estimate;
+            (false, true, None, 1.0)
+        } else {
+            noise.push(position);
+            index += 1;
+            continue;
+        };
+
+        // Follow the clock pulses slowly, so that a single misplaced one
+        // doesn't throw the timing off, and never too far from the nominal
+        // speed:
+        if let Some(interval) = clock_interval {
+            if interval >= cell * 3.0 / 4.0 && interval < cell * 3.0 / 2.0 {
+                cell += (interval - cell) / 8.0;
+                cell = cell.max(nominal_cell * 2.0 / 3.0).min(nominal_cell * 3.0 / 2.0);
+                estimate += (interval - estimate) / 64.0;
+                estimate = estimate.max(nominal_cell * 2.0 / 3.0).min(nominal_cell * 3.0 / 2.0);
+                interval_sum += interval;
+                interval_count += 1;
+            }
+        }
+
+        framer.push(Bit { position, value: bit, margin }, pause);
+    }
+
+    let speed = if interval_count > 0 {
+        nominal_cell * speed * (interval_count as f64) / interval_sum
+    } else {
+        speed
+    };
+    framer.finish(speed, noise)
+}
+
+// Length of a 1 bit of the high speed FSK, one cycle of 2400 Hz:
+const FSK_ONE_US: f64 = 1_000_000.0 / 2400.0;
+
+// Finds the cycles of a high speed recording, as the samples the signal
+// rises through zero at, along with how long it stays above zero from then
+// on.  The offset is cancelled out as for the pulses, and the signal has to
+// swing past a quarter of its envelope on either side to cross, so that
+// noise around zero doesn't make cycles of its own, the crossing is then
+// taken to be where it last was at zero, as the 2400 Hz cycles get there
+// sooner than the 1200 Hz ones:
+fn find_cycles(samples: &[i16], sample_rate: u32, progress: &mut dyn FnMut(usize)) -> Vec<(usize, usize)> {
+    find_cycles_by(samples, sample_rate, Filtering::Lanes, progress)
+}
+
+fn find_cycles_by(samples: &[i16], sample_rate: u32, filtering: Filtering, progress: &mut dyn FnMut(usize)) -> Vec<(usize, usize)> {
+    let peak = samples.iter().map(|sample| (*sample as i32).abs()).max().unwrap_or(0);
+    let floor = (peak / NOISE_FRACTION) as f64;
+    if floor < 1.0 {
+        return Vec::new();
+    }
+
+    let mut cycles: Vec<(usize, usize)> = Vec::new();
+    let mut positive = false;
+    let mut last_zero = 0;
+    track_levels(samples, sample_rate, filtering, progress, |position, level, envelope| {
+        if (level <= 0.0) != positive {
+            last_zero = position;
+        }
+
+        let threshold = (envelope / 4.0).max(floor);
+        if !positive && level > threshold {
+            cycles.push((last_zero, 0));
+            positive = true;
+        } else if positive && level < -threshold {
+            if let Some(cycle) = cycles.last_mut() {
+                cycle.1 = last_zero - cycle.0;
+            }
+            positive = false;
+        }
+    });
+    cycles
+}
+
+// Decodes a high speed recording, like `decode_samples' does the 500 baud
+// ones.  The length of the 1 bits is tracked the same way, the 0 bits count
+// as two of them, and the last cycle before a pause, which nothing follows
+// to end, is measured by the half of it above zero.  Noise makes up cycles
+// much more easily than pulses, so the sync byte is only taken after a bit
+// of the leader, the longer term estimate of the speed only follows the
+// bits from then on, and cycles which fit no bit lose the sync and the
+// quick timing like pauses do:
+pub fn decode_fsk_samples(recording: &Recording, leader_byte: u8, sync_byte: u8, speed: f64, progress: &mut dyn FnMut(usize)) -> Decoded {
+    let cycles = find_cycles(&recording.samples, recording.sample_rate, progress);
+    let nominal_one = FSK_ONE_US * (recording.sample_rate as f64) / 1_000_000.0 / speed;
+    let mut one = nominal_one;
+    let mut estimate = nominal_one;
+    let mut interval_sum = 0.0;
+    let mut interval_count = 0;
+
+    let mut framer = Framer::new(sync_byte, Some(leader_byte));
+    let mut noise = Vec::new();
+
+    for (index, &(position, half)) in cycles.iter().enumerate() {
+        let following = cycles.get(index + 1).map(|cycle| (cycle.0 - position) as f64);
+        let (length, pause) = match following {
+            Some(length) if length < one * 3.0 => { (length, false) },
+            _ => { ((half * 2) as f64, true) },
+        };
+
+        // The margin is how far the length is from the thresholds, in
+        // halves of a 1 bit:
+        let (bit, interval, margin) = if length >= one / 2.0 && length < one * 3.0 / 2.0 {
+            (true, length, (length - one / 2.0).min(one * 3.0 / 2.0 - length) / (one / 2.0))
+        } else if length >= one * 3.0 / 2.0 && length < one * 3.0 {
+            (false, length / 2.0, ((length - one * 3.0 / 2.0).min(one * 3.0 - length) / (one / 2.0)).min(1.0))
+        } else {
+            noise.push(position);
+            framer.pause();
+            one = estimate;
+            continue;
+        };
+
+        if pause {
+            one = estimate;
+        } else {
+            one += (interval - one) / 8.0;
+            one = one.max(nominal_one * 2.0 / 3.0).min(nominal_one * 3.0 / 2.0);
+            if framer.in_sync {
+                estimate += (interval - estimate) / 64.0;
+                estimate = estimate.max(nominal_one * 2.0 / 3.0).min(nominal_one * 3.0 / 2.0);
+                interval_sum += interval;
+                interval_count += 1;
+            }
+        }
+
+        framer.push(Bit { position, value: bit, margin }, pause);
+    }
+
+    let speed = if interval_count > 0 {
+        nominal_one * speed * (interval_count as f64) / interval_sum
+    } else {
+        speed
+    };
+    framer.finish(speed, noise)
+}
+
+// Tells the two apart by the time from one pulse to the next, which at 500
+// baud is hardly ever shorter than half of a 2 ms bit cell, and at 1500 baud
+// often a 2400 Hz cycle, with room for decks off the right speed:
+pub fn detect_baud(recording: &Recording, progress: &mut dyn FnMut(usize)) -> Baud {
+    let pulses = find_pulses_by(&recording.samples, recording.sample_rate, Filtering::Lanes, progress);
+    let mut intervals: Vec<usize> = pulses.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    intervals.sort_unstable();
+    match intervals.get(intervals.len() / 4) {
+        Some(interval) if (*interval as f64) * 1_000_000.0 / (recording.sample_rate as f64) < 700.0 => { Baud::High },
+        _ => { Baud::Low },
+    }
+}
+
+// The samples the other way up:
+pub fn negate(samples: &[i16]) -> Vec<i16> {
+    samples.iter().map(|sample| sample.saturating_neg()).collect()
+}
+
+// How lopsided the cycles of a high speed recording are, on average, as the
+// difference between the halves above and below zero relative to the length
+// of the cycle:
+fn cycle_asymmetry(samples: &[i16], sample_rate: u32, progress: &mut dyn FnMut(usize)) -> f64 {
+    let cycles = find_cycles(samples, sample_rate, progress);
+    let longest = FSK_ONE_US * 3.0 * (sample_rate as f64) / 1_000_000.0;
+    let asymmetries: Vec<f64> = cycles.windows(2).map(|pair| ((pair[1].0 - pair[0].0) as f64, pair[0].1 as f64))
+                                      .filter(|&(length, _half)| length < longest)
+                                      .map(|(length, half)| (half * 2.0 - length).abs() / length).collect();
+    if asymmetries.is_empty() {
+        return 0.0;
+    }
+    asymmetries.iter().sum::<f64>() / (asymmetries.len() as f64)
+}
+
+// Tells whether a recording is the other way up, as the captures of many
+// sound cards are.  The pulses of 500 baud tapes swing positive first, so the
+// right way up, the pulses of the signal turned over come right behind ones
+// of the signal, rather than before them.  The cycles of high speed tapes
+// start rising through zero, so turned over, ones of 1200 Hz and 2400 Hz get
+// mixed together into lopsided cycles.  Only recordings clearly the other way
+// up are taken to be, so that unclear ones get decoded as they are.  Both
+// ways up get gone through, which `progress' follows as twice the samples:
+pub fn detect_inverted(recording: &Recording, baud: Baud, progress: &mut dyn FnMut(usize)) -> bool {
+    let negated = negate(&recording.samples);
+    let length = recording.samples.len();
+    match baud {
+        Baud::Low => {
+            let pulses = find_pulses_by(&recording.samples, recording.sample_rate, Filtering::Lanes, progress);
+            let turned = find_pulses_by(&negated, recording.sample_rate, Filtering::Lanes, &mut |done| progress(length + done));
+            let (mut behind, mut before) = (0, 0);
+            for position in &pulses {
+                let index = turned.partition_point(|turned_position| turned_position < position);
+                let after = turned.get(index).map(|turned_position| turned_position - position);
+                let ahead = if index > 0 { Some(position - turned[index - 1]) } else { None };
+                match (after, ahead) {
+                    (Some(after), Some(ahead)) if after < ahead => { behind += 1; },
+                    (Some(_after), None) => { behind += 1; },
+                    (_, Some(_ahead)) => { before += 1; },
+                    (None, None) => {},
+                }
+            }
+            before > behind * 2
+        },
+        Baud::High => {
+            let upright = cycle_asymmetry(&recording.samples, recording.sample_rate, progress);
+            cycle_asymmetry(&negated, recording.sample_rate, &mut |done| progress(length + done)) * 2.0 < upright
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_RATE: u32 = 44100;
+
+    // Noise, reproducibly:
+    struct Noise(u32);
+
+    impl Noise {
+        fn next(&mut self, amplitude: f64) -> f64 {
+            self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (((self.0 >> 16) & 0x7FFF) as f64 / 16384.0 - 1.0) * amplitude
+        }
+    }
+
+    // Several blocks of stretches long and then some, fading in and out and
+    // dropping off sharply just before the blocks start, where the envelope
+    // has to be carried across from the block before, with a wandering offset, a pause in the middle and
+    // noise all along, for every part of the filters to have something to
+    // follow.  `wave' gives
+    // the signal at each sample, from its position and a random number:
+    fn synthesize<F: FnMut(usize, f64) -> f64>(mut wave: F) -> Vec<i16> {
+        let length = 11 * LANES * LANE_SAMPLES + 1517;
+        let mut noise = Noise(1);
+        (0..length).map(|index| {
+            let time = (index as f64) / (TEST_RATE as f64);
+            let step = if ((index + 96) / (LANES * LANE_SAMPLES)).is_multiple_of(2) { 1.0 } else { 0.3 };
+            let loudness = step * (0.5 + 0.5 * (time * 2.1).sin().abs());
+            let offset = 2500.0 * (time * 1.3).sin();
+            let signal = if index > length / 2 && index < length / 2 + 3000 { 0.0 } else { wave(index, noise.next(1.0)) };
+            (signal * 12000.0 * loudness + offset + noise.next(400.0)) as i16
+        }).collect()
+    }
+
+    #[test]
+    fn pulses_found_as_a_sample_at_a_time() {
+        // Clock pulses every 2 ms, data pulses between them for the ones:
+        let cell = (TEST_RATE / 500) as usize;
+        let mut one = false;
+        let samples = synthesize(|index, random| {
+            let phase = index % cell;
+            if phase == 0 {
+                one = random > 0.0;
+            }
+            match phase {
+                0..=20 => { (phase as f64 * std::f64::consts::PI / 10.0).sin() },
+                _ if one && phase >= cell / 2 && phase <= cell / 2 + 20 => { ((phase - cell / 2) as f64 * std::f64::consts::PI / 10.0).sin() },
+                _ => { 0.0 },
+            }
+        });
+
+        let pulses = find_pulses_by(&samples, TEST_RATE, Filtering::Lanes, &mut |_done| {});
+        assert!(pulses.len() > samples.len() / cell);
+        assert_eq!(pulses, find_pulses_by(&samples, TEST_RATE, Filtering::Serial, &mut |_done| {}));
+    }
+
+    #[test]
+    fn cycles_found_as_a_sample_at_a_time() {
+        // Cycles of 1200 and 2400 Hz, as on high speed tapes:
+        let mut phase = 0.0;
+        let mut frequency = 1200.0;
+        let samples = synthesize(|_index, random| {
+            let before = phase;
+            phase += frequency / (TEST_RATE as f64);
+            if phase.floor() != before.floor() {
+                frequency = if random > 0.0 { 2400.0 } else { 1200.0 };
+            }
+            (phase * 2.0 * std::f64::consts::PI).sin()
+        });
+
+        let cycles = find_cycles_by(&samples, TEST_RATE, Filtering::Lanes, &mut |_done| {});
+        assert!(cycles.len() > samples.len() / 40);
+        assert_eq!(cycles, find_cycles_by(&samples, TEST_RATE, Filtering::Serial, &mut |_done| {}));
+    }
+
+    #[test]
+    fn short_recordings_found_a_sample_at_a_time() {
+        // Shorter than a block, all of it taken as the remainder:
+        let samples: Vec<i16> = (0..LANES * LANE_SAMPLES - 1).map(|index| if index % 37 < 4 { 8000 } else { -500 }).collect();
+        let pulses = find_pulses_by(&samples, TEST_RATE, Filtering::Lanes, &mut |_done| {});
+        assert!(!pulses.is_empty());
+        assert_eq!(pulses, find_pulses_by(&samples, TEST_RATE, Filtering::Serial, &mut |_done| {}));
+    }
+}
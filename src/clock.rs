@@ -0,0 +1,75 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// The current date and time, in UTC, for naming backups and stamping logs.
+
+use std::time;
+
+
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+// Converts a count of days since 1970-01-01 into a date, see Howard
+// Hinnant's `civil_from_days' algorithm:
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let shifted = days + 719468;
+    let era = shifted.div_euclid(146097);
+    let day_of_era = shifted.rem_euclid(146097) as u32;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = (year_of_era as i64) + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+pub fn now() -> DateTime {
+    let seconds = match time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+        Ok(duration) => { duration.as_secs() as i64 },
+        Err(_error) => { 0 },
+    };
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400) as u32;
+
+    DateTime {
+        year,
+        month,
+        day,
+        hour: second_of_day / 3600,
+        minute: (second_of_day / 60) % 60,
+        second: second_of_day % 60,
+    }
+}
+
+impl DateTime {
+    // Like ISO 8601, but without colons, for use in filenames:
+    pub fn filename_stamp(&self) -> String {
+        format!("{:04}-{:02}-{:02}T{:02}-{:02}-{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+
+    pub fn iso_stamp(&self) -> String {
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
@@ -0,0 +1,59 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Load modules of the disk operating systems, the /CMD files, which the DOS
+// loads and runs by their name.  They're a series of records, each a type
+// byte and a length byte followed by the record's bytes:
+//
+//   05 <length> <name>               the name of the module, skipped
+//   01 <length> <address> <data>     data loading at the address, the length
+//                                    counting the address too, so that 2
+//                                    stands for 256 bytes of data, 1 for 255
+//                                    and 0 for 254
+//   02 02 <address>                  the entry point, ending the module
+//
+use tape;
+
+
+const NAME_RECORD: u8 = 0x05;
+const DATA_RECORD: u8 = 0x01;
+const ENTRY_RECORD: u8 = 0x02;
+
+// Converts an entry of a tape image into a load module, its blocks becoming
+// the data records:
+pub fn generate_cmd_file(entry: &tape::Entry, entry_point: u16) -> Vec<u8> {
+    let mut buffer = Vec::new();
+
+    let name: Vec<u8> = entry.name.iter().cloned().filter(|&byte| byte != b' ').collect();
+    if !name.is_empty() {
+        buffer.push(NAME_RECORD);
+        buffer.push(name.len() as u8);
+        buffer.extend_from_slice(&name);
+    }
+    for block in &entry.blocks {
+        buffer.push(DATA_RECORD);
+        buffer.push((block.data.len() + 2) as u8);
+        buffer.push((block.load_address & 0xFF) as u8);
+        buffer.push((block.load_address >> 8) as u8);
+        buffer.extend_from_slice(&block.data);
+    }
+    buffer.push(ENTRY_RECORD);
+    buffer.push(2);
+    buffer.push((entry_point & 0xFF) as u8);
+    buffer.push((entry_point >> 8) as u8);
+
+    buffer
+}
@@ -0,0 +1,307 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Tape images of the Color Computer, which go by .cas just like the ones of
+// the Model I, so collections mix them up.  Their bytes are framed into
+// blocks of their own, each as:
+//
+//   1 byte       0x55, the end of the leader
+//   1 byte       0x3C, the sync byte
+//   1 byte       block type, 0x00 for the name block, 0x01 for data and
+//                0xFF for the end of the file
+//   1 byte       length of the data, 0 to 255 bytes
+//   n bytes      data
+//   1 byte       sum of the type, the length and the data
+//   1 byte       0x55
+//
+// The name block, after a leader of 0x55 bytes, holds the name padded to 8
+// characters with spaces, the file type (0x00 BASIC, 0x01 data, 0x02
+// machine language), 0xFF for ASCII files, 0xFF for files with gaps between
+// the blocks, and the execution and load addresses, high byte first.  The
+// data blocks follow after another leader, loading one after another from
+// the load address.
+
+use tape;
+
+
+pub const LEADER_BYTE: u8 = 0x55;
+pub const SYNC_BYTE:   u8 = 0x3C;
+pub const NAME_LENGTH: usize = 8;
+
+const NAME_BLOCK: u8 = 0x00;
+const DATA_BLOCK: u8 = 0x01;
+const EOF_BLOCK:  u8 = 0xFF;
+
+const NAME_BLOCK_LENGTH: usize = 15;
+pub const MAX_BLOCK_SIZE: usize = 255;
+
+// What CSAVEM writes, a leader of 128 bytes before the name block and again
+// before the data:
+const LEADER_LENGTH: usize = 128;
+
+pub const BASIC_FILE:   u8 = 0x00;
+pub const DATA_FILE:    u8 = 0x01;
+pub const MACHINE_FILE: u8 = 0x02;
+
+pub struct CocoFile {
+    pub name: Vec<u8>,
+    pub file_type: u8,
+    pub ascii: bool,
+    pub gaps: bool,
+    pub exec_address: u16,
+    pub load_address: u16,
+    pub data: Vec<u8>,
+    // Blocks whose sums didn't match:
+    pub bad_checksums: usize,
+}
+
+impl CocoFile {
+    pub fn name_string(&self) -> String {
+        String::from_utf8_lossy(&self.name).trim_end().to_owned()
+    }
+
+    pub fn type_description(&self) -> &'static str {
+        match self.file_type {
+            BASIC_FILE => { "Color BASIC program" },
+            DATA_FILE => { "data file" },
+            MACHINE_FILE => { "machine language program" },
+            _ => { "file of an unknown type" },
+        }
+    }
+}
+
+// Finds the next block from the position on, returning its type and data,
+// and whether its checksum matched:
+fn read_block(buffer: &[u8], position: &mut usize) -> Result<Option<(u8, Vec<u8>, bool)>, String> {
+    while *position < buffer.len() && buffer[*position] == LEADER_BYTE {
+        *position += 1;
+    }
+    if *position >= buffer.len() || buffer[*position..].iter().all(|&byte| byte == 0x00) {
+        return Ok(None);
+    }
+    let offset = *position;
+    if buffer[offset] != SYNC_BYTE {
+        return Err(format!("Expected the sync byte 0x{:02X} of a Color Computer block at offset {}, found 0x{:02X}", SYNC_BYTE, offset, buffer[offset]));
+    }
+    let (block_type, length) = match (buffer.get(offset + 1), buffer.get(offset + 2)) {
+        (Some(&block_type), Some(&length)) => { (block_type, length as usize) },
+        _ => { return Err(format!("The tape image ends inside the block at offset {}", offset)); },
+    };
+    let data_start = offset + 3;
+    if data_start + length + 1 > buffer.len() {
+        return Err(format!("The tape image ends inside the block at offset {}", offset));
+    }
+    let data = buffer[data_start..data_start + length].to_vec();
+    let sum = data.iter().fold(block_type.wrapping_add(length as u8), |sum, byte| sum.wrapping_add(*byte));
+    let checksum_ok = buffer[data_start + length] == sum;
+    *position = data_start + length + 1;
+    Ok(Some((block_type, data, checksum_ok)))
+}
+
+// Whether the tape image starts the way Color Computer tapes do, with a name
+// block right after a leader of 0x55 bytes:
+pub fn is_coco_tape(buffer: &[u8]) -> bool {
+    let mut position = 0;
+    if buffer.first() != Some(&LEADER_BYTE) {
+        return false;
+    }
+    match read_block(buffer, &mut position) {
+        Ok(Some((block_type, data, checksum_ok))) => {
+            block_type == NAME_BLOCK && data.len() == NAME_BLOCK_LENGTH && checksum_ok
+        },
+        _ => { false },
+    }
+}
+
+// Parses all the files recorded one after another in a Color Computer tape
+// image:
+pub fn parse_coco_tape(buffer: &[u8]) -> Result<Vec<CocoFile>, String> {
+    let mut files = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let offset = position;
+        let (block_type, header, checksum_ok) = match read_block(buffer, &mut position)? {
+            Some(block) => { block },
+            None => { break; },
+        };
+        if block_type != NAME_BLOCK || header.len() != NAME_BLOCK_LENGTH {
+            return Err(format!("Expected the name block of a file at offset {}, found a block of type 0x{:02X}", offset, block_type));
+        }
+        let mut file = CocoFile {
+            name: header[0..NAME_LENGTH].to_vec(),
+            file_type: header[8],
+            ascii: header[9] != 0x00,
+            gaps: header[10] != 0x00,
+            exec_address: ((header[11] as u16) << 8) | (header[12] as u16),
+            load_address: ((header[13] as u16) << 8) | (header[14] as u16),
+            data: Vec::new(),
+            bad_checksums: if checksum_ok { 0 } else { 1 },
+        };
+
+        loop {
+            let offset = position;
+            match read_block(buffer, &mut position)? {
+                Some((DATA_BLOCK, data, checksum_ok)) => {
+                    file.data.extend(data);
+                    if !checksum_ok {
+                        file.bad_checksums += 1;
+                    }
+                },
+                Some((EOF_BLOCK, _data, _checksum_ok)) => { break; },
+                Some((block_type, _data, _checksum_ok)) => {
+                    return Err(format!("Expected a data block of `{}' at offset {}, found a block of type 0x{:02X}", file.name_string(), offset, block_type));
+                },
+                None => {
+                    return Err(format!("The tape image ends before the end of `{}'", file.name_string()));
+                },
+            }
+        }
+        files.push(file);
+    }
+
+    if files.is_empty() {
+        Err("The tape image doesn't contain any files".to_owned())
+    } else {
+        Ok(files)
+    }
+}
+
+fn push_block(block_type: u8, data: &[u8], buffer: &mut Vec<u8>) {
+    buffer.push(LEADER_BYTE);
+    buffer.push(SYNC_BYTE);
+    buffer.push(block_type);
+    buffer.push(data.len() as u8);
+    buffer.extend_from_slice(data);
+    buffer.push(data.iter().fold(block_type.wrapping_add(data.len() as u8), |sum, byte| sum.wrapping_add(*byte)));
+    buffer.push(LEADER_BYTE);
+}
+
+// Appends the file to a Color Computer tape image, the way CSAVEM records
+// it:
+pub fn generate_coco_file(file: &CocoFile, buffer: &mut Vec<u8>) {
+    let mut header = file.name.clone();
+    header.resize(NAME_LENGTH, b' ');
+    header.push(file.file_type);
+    header.push(if file.ascii { 0xFF } else { 0x00 });
+    header.push(if file.gaps { 0xFF } else { 0x00 });
+    header.extend_from_slice(&[(file.exec_address >> 8) as u8, file.exec_address as u8,
+                               (file.load_address >> 8) as u8, file.load_address as u8]);
+
+    buffer.resize(buffer.len() + LEADER_LENGTH, LEADER_BYTE);
+    push_block(NAME_BLOCK, &header, buffer);
+    buffer.resize(buffer.len() + LEADER_LENGTH, LEADER_BYTE);
+    for chunk in file.data.chunks(MAX_BLOCK_SIZE) {
+        push_block(DATA_BLOCK, chunk, buffer);
+    }
+    push_block(EOF_BLOCK, &[], buffer);
+}
+
+// The machine language program of a Model I entry as a Color Computer file,
+// holes between the blocks filled with zeros:
+pub fn from_entry(entry: &tape::Entry) -> Option<CocoFile> {
+    let (start, image, _has_holes) = entry.memory_image()?;
+    let mut name = entry.name.clone();
+    name.resize(NAME_LENGTH, b' ');
+    Some(CocoFile {
+        name,
+        file_type: MACHINE_FILE,
+        ascii: false,
+        gaps: false,
+        exec_address: entry.entry_point.unwrap_or(start),
+        load_address: start,
+        data: image,
+        bad_checksums: 0,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where the first data block of a file starts, past the leaders and the
+    // name block:
+    const FIRST_DATA_BLOCK: usize = LEADER_LENGTH * 2 + NAME_BLOCK_LENGTH + 6;
+
+    fn test_file(name: &[u8], length: usize) -> CocoFile {
+        CocoFile {
+            name: name.to_vec(),
+            file_type: MACHINE_FILE,
+            ascii: false,
+            gaps: false,
+            exec_address: 0x3F10,
+            load_address: 0x3F00,
+            data: (0..length).map(|index| index as u8).collect(),
+            bad_checksums: 0,
+        }
+    }
+
+    #[test]
+    fn files_survive_the_round_trip() {
+        let mut buffer = Vec::new();
+        generate_coco_file(&test_file(b"LONG", 600), &mut buffer);
+        generate_coco_file(&test_file(b"SHORT", 10), &mut buffer);
+        assert!(is_coco_tape(&buffer));
+
+        let files = parse_coco_tape(&buffer).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, b"LONG    ");
+        assert_eq!(files[0].data, test_file(b"LONG", 600).data);
+        assert_eq!(files[1].name_string(), "SHORT");
+        assert_eq!(files[1].data.len(), 10);
+        for file in &files {
+            assert_eq!(file.file_type, MACHINE_FILE);
+            assert!(!file.ascii && !file.gaps);
+            assert_eq!((file.exec_address, file.load_address), (0x3F10, 0x3F00));
+            assert_eq!(file.bad_checksums, 0);
+        }
+    }
+
+    #[test]
+    fn bad_sums_counted() {
+        let mut buffer = Vec::new();
+        generate_coco_file(&test_file(b"DAMAGE", 300), &mut buffer);
+        buffer[FIRST_DATA_BLOCK + 4] ^= 0x01;
+
+        let files = parse_coco_tape(&buffer).unwrap();
+        assert_eq!(files[0].bad_checksums, 1);
+        assert_eq!(files[0].data.len(), 300);
+        assert!(is_coco_tape(&buffer));
+    }
+
+    #[test]
+    fn truncated_images_refused() {
+        let mut buffer = Vec::new();
+        generate_coco_file(&test_file(b"CUT", 300), &mut buffer);
+
+        assert!(parse_coco_tape(&buffer[..FIRST_DATA_BLOCK + 100]).is_err());
+        assert!(parse_coco_tape(&buffer[..FIRST_DATA_BLOCK]).is_err());
+        assert!(parse_coco_tape(&buffer[..LEADER_LENGTH + 10]).is_err());
+        assert!(!is_coco_tape(&buffer[..LEADER_LENGTH + 10]));
+    }
+
+    #[test]
+    fn model_one_tapes_told_apart() {
+        let mut buffer = vec![0x00; 16];
+        buffer.extend_from_slice(&[tape::SYNC_BYTE, tape::SYSTEM_HEADER]);
+        assert!(!is_coco_tape(&buffer));
+
+        let mut buffer = vec![tape::HIGH_SPEED_LEADER; 16];
+        buffer.extend_from_slice(&[tape::HIGH_SPEED_SYNC, tape::SYSTEM_HEADER]);
+        assert!(!is_coco_tape(&buffer));
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use audio;
+use commands;
+use commands::decode;
+use files;
+use tape;
+
+
+// The test recording holds a tone of clock pulses alone, as recorded for 0
+// bits and the leader, one of clock and data pulses, for 1 bits, and then a
+// known pattern of bytes behind a sync byte, each going on for the given time
+// and followed by a second of silence:
+const SECTION_PAUSE_US: u32 = 1_000_000;
+
+const SECTIONS: &[&str] = &["0 bit tone", "1 bit tone", "test pattern"];
+
+// Every byte value, over and over:
+fn pattern_byte(index: usize) -> u8 {
+    index as u8
+}
+
+fn generate(matches: &getopts::Matches, seconds: f64) {
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => {
+            error!("No output file given, use `calibrate -o <file>', or `calibrate --analyze <file>' for a recording of it.");
+            process::exit(1);
+        },
+    };
+    let audio_format = commands::retrieve_audio_format(matches);
+    let section_bytes = ((seconds * 1_000_000.0 / (audio::BYTE_DURATION_US as f64)).ceil() as usize).max(1);
+
+    let mut bytes = vec![0x00; section_bytes];
+    let ones_start = bytes.len();
+    bytes.extend(vec![0xFF; section_bytes]);
+    let pattern_start = bytes.len();
+    bytes.push(tape::SYNC_BYTE);
+    bytes.extend((0..section_bytes).map(pattern_byte));
+
+    let gaps = [(ones_start, SECTION_PAUSE_US), (pattern_start, SECTION_PAUSE_US)];
+    let samples = audio::encode_tape(&bytes, &gaps, &audio_format, &mut |_done| {});
+
+    status!("Recorded {:.0} seconds each of the 0 bit tone ({} Hz), the 1 bit tone ({} Hz) and the test pattern.",
+            seconds, 1_000_000 / audio::BIT_CELL_US, 2 * 1_000_000 / audio::BIT_CELL_US);
+    if !files::write_file(&audio::generate_wav_file(&samples, &audio_format), &out_filepath) {
+        process::exit(1);
+    }
+    status!("Record it onto the tape, play it back into `calibrate --analyze', and adjust the deck until the levels and the speed are right.");
+    process::exit(0);
+}
+
+fn decibels(level: f64) -> f64 {
+    20.0 * (level.max(1.0) / 32767.0).log10()
+}
+
+fn analyze(matches: &getopts::Matches, in_filepath: &path::Path) {
+    let recording = match decode::load_recording(in_filepath, matches.opt_str("converter").as_deref()) {
+        Some(recording) => { recording },
+        None => { process::exit(1); },
+    };
+    let stretches = audio::split_at_pauses(&recording, SECTION_PAUSE_US / 2);
+    println!("{}: {:.1} seconds of audio at {} Hz, {} stretches of signal.", in_filepath.display(),
+             (recording.samples.len() as f64) / (recording.sample_rate as f64), recording.sample_rate, stretches.len());
+
+    let mut good = true;
+    for (index, &(start, end)) in stretches.iter().enumerate() {
+        let measurements = audio::measure(&recording.samples[start..end], recording.sample_rate);
+        if measurements.pulse_count < 2 {
+            continue;
+        }
+
+        // The speed comes from how far apart the pulses are, a bit cell for
+        // the clock pulses alone, and half of one where there are 1 bits:
+        let cell = audio::BIT_CELL_US as f64;
+        let nominal = if measurements.pulse_interval_us > cell * 3.0 / 4.0 { cell } else { cell / 2.0 };
+        let speed = (nominal / measurements.pulse_interval_us - 1.0) * 100.0;
+
+        // Recordings of the whole of the test recording have its sections:
+        let name = if stretches.len() == SECTIONS.len() { SECTIONS[index] } else { "signal" };
+        println!();
+        println!("Stretch {} ({}) at {:.1} seconds, pulses {:.0} us apart:", index + 1, name,
+                 (start as f64) / (recording.sample_rate as f64), measurements.pulse_interval_us);
+        println!("    Peak level:          {:.1} dBFS", decibels(measurements.peak as f64));
+        println!("    DC offset:           {:.1}% of full scale", measurements.offset * 100.0 / 32767.0);
+        println!("    Speed:               {:+.1}%{}", speed, if speed > 0.0 { " (fast)" } else if speed < 0.0 { " (slow)" } else { "" });
+
+        if decibels(measurements.peak as f64) > -1.0 {
+            warning!("The level is close to full scale, the recording is likely clipped, turn the playback volume down.");
+            good = false;
+        } else if decibels(measurements.peak as f64) < -30.0 {
+            warning!("The level is very low, turn the playback volume up.");
+            good = false;
+        }
+        if speed.abs() > 3.0 {
+            warning!("The deck runs {:.1}% {}, which the ROM might not tolerate.", speed.abs(), if speed > 0.0 { "fast" } else { "slow" });
+            good = false;
+        }
+    }
+
+    // Count the bits of the pattern which came back wrong:
+    let decoded = audio::decode_samples(&recording, tape::SYNC_BYTE, 1.0, &mut |_done| {}).bytes;
+    if decoded.len() < 2 {
+        warning!("The test pattern wasn't found, check the levels and the cabling.");
+        process::exit(1);
+    }
+    let pattern = &decoded[1..];
+    let bit_errors: u32 = pattern.iter().enumerate().map(|(index, byte)| (byte ^ pattern_byte(index)).count_ones()).sum();
+    println!();
+    println!("Test pattern:            {} bytes, {} bit errors", pattern.len(), bit_errors);
+
+    println!();
+    if bit_errors == 0 && good {
+        status!("The deck is set up well.  For the azimuth, turn the head screw to where the levels are the highest.");
+        process::exit(0);
+    } else {
+        status!("Adjust the deck and try again.  For the azimuth, turn the head screw to where the levels are the highest.");
+        process::exit(1);
+    }
+}
+
+pub const USAGE: &str = "-o <file> | --analyze <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("o", "output", "Name of the WAV file to write the test recording into.", "FILE");
+    options.optopt("", "seconds", "Length of each of the sections of the test recording, in seconds (10 by default).", "SECONDS");
+    options.optopt("", "analyze", "Measure the levels, the speed and the bit errors of a played-back test recording instead.", "FILE");
+    options.optopt("", "converter", "Program to convert compressed recordings with, ffmpeg or sox (the first one installed by default).", "NAME");
+    commands::add_audio_options(&mut options);
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "calibrate", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    if let Some(argument) = matches.opt_str("analyze") {
+        analyze(&matches, argument.as_ref());
+    }
+
+    let seconds = match matches.opt_str("seconds") {
+        Some(argument) => {
+            match argument.parse::<f64>() {
+                Ok(seconds) if seconds > 0.0 && seconds <= 600.0 => { seconds },
+                _ => {
+                    error!("Invalid section length `{}', expected a positive number of seconds, up to 600.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { 10.0 },
+    };
+    generate(&matches, seconds);
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use coco;
+use commands;
+use files;
+use packing;
+use tape;
+
+
+// What the tape image is converted into:
+#[derive(Clone, Copy, PartialEq)]
+enum Target {
+    Coco,
+    Model1,
+}
+
+const TARGETS: &[(&str, Target)] = &[
+    ("coco",   Target::Coco),
+    ("model1", Target::Model1),
+];
+
+fn to_coco(buffer: &[u8], matches: &getopts::Matches, in_filepath: &path::Path) -> (Vec<u8>, bool) {
+    let entries = match tape::parse_tape(buffer, &commands::retrieve_variant(matches)) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    let mut output = Vec::new();
+    let mut success = true;
+    for (first, count) in tape::group_copies(&entries) {
+        let entry = tape::merge_copies(&entries[first..first + count]);
+        let file = match coco::from_entry(&entry) {
+            Some(file) => { file },
+            None => {
+                error!("Entry `{}' contains no data blocks, leaving it out.", entry.name_string());
+                success = false;
+                continue;
+            },
+        };
+        if entry.bad_checksum_count() > 0 {
+            warning!("Entry `{}' has {} blocks with bad checksums, they're converted as they are.", entry.name_string(), entry.bad_checksum_count());
+        }
+        if entry.memory_image().is_some_and(|(_start, _image, has_holes)| has_holes) {
+            note!("Entry `{}' has holes between its blocks, they're filled with zeros.", entry.name_string());
+        }
+        if entry.entry_point.is_none() {
+            note!("Entry `{}' has no entry point, executing it starts at the load address.", entry.name_string());
+        }
+        status!("`{}': {} bytes at 0x{:04X}, executed at 0x{:04X}.", file.name_string(), file.data.len(), file.load_address, file.exec_address);
+        coco::generate_coco_file(&file, &mut output);
+    }
+    (output, success)
+}
+
+fn to_model1(buffer: &[u8], matches: &getopts::Matches, in_filepath: &path::Path) -> (Vec<u8>, bool) {
+    let files = match coco::parse_coco_tape(buffer) {
+        Ok(files) => { files },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    let mut format = packing::TapeFormat::standard();
+    format.variant = commands::retrieve_variant(matches);
+    let mut output = Vec::new();
+    let mut success = true;
+    for file in &files {
+        // Tokenized Color BASIC and data files mean nothing to the Model I:
+        if file.file_type != coco::MACHINE_FILE {
+            error!("`{}' is a {}, only machine language programs convert, leaving it out.", file.name_string(), file.type_description());
+            success = false;
+            continue;
+        }
+        if file.data.is_empty() || (file.load_address as usize) + file.data.len() > 0x10000 {
+            error!("`{}' holds no data, or doesn't fit into memory from 0x{:04X} on, leaving it out.", file.name_string(), file.load_address);
+            success = false;
+            continue;
+        }
+        if file.bad_checksums > 0 {
+            warning!("`{}' has {} blocks with bad checksums, they're converted as they are.", file.name_string(), file.bad_checksums);
+        }
+        let mut name = file.name.clone();
+        if name[tape::NAME_LENGTH..].iter().any(|&byte| byte != b' ') {
+            warning!("The name `{}' is longer than the {} characters of a Model I entry, it's cut short.", file.name_string(), tape::NAME_LENGTH);
+        }
+        name.truncate(tape::NAME_LENGTH);
+
+        status!("`{}': {} bytes at 0x{:04X}, entry point 0x{:04X}.", String::from_utf8_lossy(&name).trim_end(), file.data.len(),
+                file.load_address, file.exec_address);
+        let entry = packing::PackEntry::linear(in_filepath, file.data.clone(), name, file.load_address, file.exec_address);
+        packing::generate_tape_entry(&entry, &mut output, &format);
+    }
+    (output, success)
+}
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The Model I or Color Computer tape image to convert.", "FILE");
+    options.optopt("o", "output", "Name of the converted tape image to write (input filename with extension changed to .coco.cas or .model1.cas by default).", "FILE");
+    options.optopt("", "to", "What to convert into, `coco' or `model1' (the other one of what the input is by default).", "MACHINE");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "coco", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "coco", &matches);
+
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let is_coco = coco::is_coco_tape(&buffer);
+    let target = match matches.opt_str("to") {
+        Some(argument) => {
+            match TARGETS.iter().find(|&&(name, _target)| name == argument.to_lowercase()) {
+                Some(&(_name, target)) => { target },
+                None => {
+                    let names: Vec<&str> = TARGETS.iter().map(|&(name, _target)| name).collect();
+                    error!("Unknown machine `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None if is_coco => { Target::Model1 },
+        None => { Target::Coco },
+    };
+    match (target, is_coco) {
+        (Target::Model1, true) => { status!("`{}' is a Color Computer tape image, converting it into a Model I one.", in_filepath.display()); },
+        (Target::Coco, false) => { status!("`{}' is a Model I tape image, converting it into a Color Computer one.", in_filepath.display()); },
+        (Target::Model1, false) => {
+            error!("`{}' isn't a Color Computer tape image, it doesn't start with a name block.", in_filepath.display());
+            process::exit(1);
+        },
+        (Target::Coco, true) => {
+            error!("`{}' is a Color Computer tape image already.", in_filepath.display());
+            process::exit(1);
+        },
+    }
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(&in_filepath, if target == Target::Coco { "coco.cas" } else { "model1.cas" }) },
+    };
+    if out_filepath == in_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    let (output, success) = match target {
+        Target::Coco => { to_coco(&buffer, &matches, &in_filepath) },
+        Target::Model1 => { to_model1(&buffer, &matches, &in_filepath) },
+    };
+    if output.is_empty() {
+        error!("Nothing in `{}' could be converted.", in_filepath.display());
+        process::exit(1);
+    }
+    // Only the framing changes, the programs are left as they are:
+    note!("The programs are still code for the CPU they were written for, the Z80 of the Model I or the 6809 of the Color Computer.");
+    if !files::write_file(&output, &out_filepath) || !success {
+        process::exit(1);
+    }
+    process::exit(0);
+}
@@ -0,0 +1,204 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::fs;
+use std::path;
+use std::process;
+
+use commands;
+use files;
+use packing;
+use stubs;
+use tape;
+
+
+// The corpus is a set of tapes which are legal, the ROM loads every one of
+// them, but each goes to an edge of what a tape may be, for testing the
+// cassette support of emulators against.  Their entries check themselves
+// once loaded: the verifier, loading ahead of the data, works out the CRC
+// of the data and returns to the `READY' prompt if it's intact, or puts a
+// `C' into the top right corner of the screen first if it isn't.
+const VERIFIER_ADDRESS: u16 = 0x4A00;
+const DATA_ADDRESS: u16 = 0x5200;
+
+// An entry of a tape of the corpus:
+struct CorpusEntry {
+    name: &'static str,
+    address: u16,
+    length: usize,
+}
+
+struct CorpusTape {
+    file_name: &'static str,
+    description: &'static str,
+    entries: Vec<CorpusEntry>,
+    format: packing::TapeFormat,
+    // The answer to the `MEMORY SIZE?' question, if it can't be left
+    // blank:
+    memory_size: Option<u32>,
+}
+
+fn corpus_tape(file_name: &'static str, description: &'static str, entries: &[(&'static str, u16, usize)]) -> CorpusTape {
+    CorpusTape {
+        file_name,
+        description,
+        entries: entries.iter().map(|&(name, address, length)| CorpusEntry { name, address, length }).collect(),
+        format: packing::TapeFormat::standard(),
+        memory_size: None,
+    }
+}
+
+fn corpus() -> Vec<CorpusTape> {
+    let mut tapes = Vec::new();
+
+    tapes.push(corpus_tape("full-blocks.cas", "Eight data blocks of the full 256 bytes, each with a length byte of 0.",
+                           &[("FULL", DATA_ADDRESS, 8 * tape::MAX_BLOCK_SIZE)]));
+    tapes.push(corpus_tape("short-final-block.cas", "Two full blocks followed by a final block of a single byte.",
+                           &[("SHORT", DATA_ADDRESS, 2 * tape::MAX_BLOCK_SIZE + 1)]));
+
+    let mut tape = corpus_tape("one-byte-blocks.cas", "Blocks of a single byte each, 129 of them with the verifier's.",
+                               &[("TINY", DATA_ADDRESS, 64)]);
+    tape.format.block_size = 1;
+    tapes.push(tape);
+
+    // BASIC keeps its stack at the top of memory unless told otherwise, the
+    // answer puts it below the blocks:
+    let mut tape = corpus_tape("top-of-memory.cas", "Blocks at 0xFE80 and 0xFF80, the last one ending at 0xFFFF, for a 48K machine.",
+                               &[("TOP", 0xFE80, 0x180)]);
+    tape.memory_size = Some(65000);
+    tapes.push(tape);
+
+    let mut tape = corpus_tape("long-leader.cas", "A leader of 4096 bytes, 16 times the usual one.",
+                               &[("LEADER", DATA_ADDRESS, 0x300)]);
+    tape.format.leader_length = 16 * tape::LEADER_LENGTH;
+    tapes.push(tape);
+
+    let mut tape = corpus_tape("block-gaps.cas", "32 zero bytes between the blocks, which the ROM passes over.",
+                               &[("GAPS", DATA_ADDRESS, 0x400)]);
+    tape.format.block_gap = 32;
+    tapes.push(tape);
+
+    let mut tape = corpus_tape("reverse-order.cas", "The data blocks in reverse order, from the highest address down.",
+                               &[("REVERS", DATA_ADDRESS, 0x500)]);
+    tape.format.block_order = packing::BlockOrder::Reverse;
+    tapes.push(tape);
+
+    tapes.push(corpus_tape("duplicate-names.cas", "Two entries of the same name, SYSTEM loads the first one, the second one loading if asked for again.",
+                           &[("DUPE", 0x6000, 0x200), ("DUPE", 0x7000, 0x280)]));
+    tapes.push(corpus_tape("multi-entry.cas", "Three entries, with names of one, two and six characters, SYSTEM passing over the others to get to the one asked for.",
+                           &[("A", 0x6000, 0x100), ("BB", 0x7000, 0x180), ("CCCCCC", 0x8000, 0x200)]));
+
+    tapes
+}
+
+// The data of the entries differ from one another, and have every byte
+// value in them, the block markers included:
+fn entry_data(length: usize, seed: usize) -> Vec<u8> {
+    (0..length).map(|index| ((index + seed * 0x35) % 0x100) as u8).collect()
+}
+
+fn pack_entry(entry: &CorpusEntry, seed: usize) -> packing::PackEntry {
+    let data = entry_data(entry.length, seed);
+    let record_address = VERIFIER_ADDRESS;
+    let verifier_address = record_address + (stubs::VERIFICATION_RECORD_LENGTH as u16);
+    let mut verifier = stubs::verification_record(entry.address, &data);
+    verifier.extend(stubs::verifier(verifier_address, record_address, tape::ROM_BASIC_READY));
+
+    // Loading the verifier and then the data, from the memory in between
+    // only what they take up:
+    let data_offset = (entry.address - VERIFIER_ADDRESS) as usize;
+    let mut image = verifier.clone();
+    image.resize(data_offset, 0x00);
+    image.extend(data);
+
+    let mut name = entry.name.as_bytes().to_vec();
+    name.resize(tape::NAME_LENGTH, b' ');
+    let mut pack_entry = packing::PackEntry::linear(path::Path::new(entry.name), image, name, VERIFIER_ADDRESS, verifier_address);
+    pack_entry.loaded = vec![(0, verifier.len()), (data_offset, entry.length)];
+    pack_entry
+}
+
+// How to load an entry of a tape of the corpus, and what it should load:
+fn describe_entry(entry: &CorpusEntry, pack_entry: &packing::PackEntry, format: &packing::TapeFormat) -> String {
+    let blocks = packing::block_layout(pack_entry, format).len();
+    let verifier_end = VERIFIER_ADDRESS as usize + pack_entry.loaded[0].1 - 1;
+    format!("  Entry `{}': SYSTEM, `{}', `/'.  {} blocks loading into 0x{:04X}-0x{:04X} and 0x{:04X}-0x{:04X}, entry point 0x{:04X}.\n",
+            entry.name, entry.name, blocks, VERIFIER_ADDRESS, verifier_end, entry.address,
+            entry.address as usize + entry.length - 1, pack_entry.entry_point)
+}
+
+pub const USAGE: &str = "-o <directory>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("o", "output", "Directory to write the tapes of the corpus and their index into, created if it doesn't exist.", "DIR");
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "corpus", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    let directory: path::PathBuf = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => {
+            error!("No output directory given, use `{} corpus -o <directory>'.", progname);
+            process::exit(1);
+        },
+    };
+    if let Err(error) = fs::create_dir_all(&directory) {
+        error!("Failed to create the directory `{}': {}.", directory.display(), error);
+        process::exit(1);
+    }
+
+    let mut index = String::from("Tapes of edge cases for testing cassette support against, each of them\n\
+                                  legal, the ROM loads all of them.  The entries check themselves once\n\
+                                  started, returning to the `READY' prompt if their data is intact, with\n\
+                                  a `C' in the top right corner of the screen if it isn't.\n");
+    let mut seed = 0;
+    for tape in corpus() {
+        let out_filepath = directory.join(tape.file_name);
+        index.push_str(&format!("\n{}\n  {}\n", tape.file_name, tape.description));
+        match tape.memory_size {
+            Some(memory_size) => { index.push_str(&format!("  Answer `MEMORY SIZE?' with {}.\n", memory_size)); },
+            None => { index.push_str("  Leave `MEMORY SIZE?' blank.\n"); },
+        }
+
+        let mut buffer = Vec::new();
+        for entry in &tape.entries {
+            let pack_entry = pack_entry(entry, seed);
+            seed += 1;
+            packing::generate_tape_entry(&pack_entry, &mut buffer, &tape.format);
+            index.push_str(&describe_entry(entry, &pack_entry, &tape.format));
+        }
+        if !files::write_file(&buffer, &out_filepath) {
+            process::exit(1);
+        }
+    }
+
+    if !files::write_file(index.as_bytes(), &directory.join("index.txt")) {
+        process::exit(1);
+    }
+    status!("Wrote the corpus and its index into `{}'.", directory.display());
+    process::exit(0);
+}
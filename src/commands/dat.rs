@@ -0,0 +1,260 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::fs;
+use std::path;
+use std::process;
+
+use clock;
+use coco;
+use commands;
+use files;
+use hashes;
+use tape;
+
+
+// DAT files describe the tapes of a collection for ROM managers, which tell
+// from them which of a set are there, and which are damaged or misnamed.
+// Every tape is a game of its own, of the one ROM which is the tape image:
+#[derive(Clone, Copy, PartialEq)]
+enum DatFormat {
+    // The XML of Logiqx, which clrmamepro, RomVault and the like all read:
+    Xml,
+    // The older text format of clrmamepro:
+    Clrmamepro,
+}
+
+const DAT_FORMATS: &[(&str, DatFormat)] = &[
+    ("xml",        DatFormat::Xml),
+    ("clrmamepro", DatFormat::Clrmamepro),
+];
+
+struct Rom {
+    game: String,
+    name: String,
+    size: usize,
+    crc: u32,
+    sha1: [u8; 20],
+}
+
+// Directories are looked through along with the ones in them, collections
+// being sorted into those by publisher or by letter:
+fn collect_tapes(directory: &path::Path, tapes: &mut Vec<path::PathBuf>) {
+    let listing = match fs::read_dir(directory) {
+        Ok(listing) => { listing },
+        Err(error) => {
+            warning!("Failed to list `{}': {}.", directory.display(), error);
+            return;
+        },
+    };
+    let mut paths: Vec<path::PathBuf> = listing.filter_map(|item| item.ok()).map(|item| item.path()).collect();
+    paths.sort();
+    for item_path in paths {
+        if item_path.is_dir() {
+            collect_tapes(&item_path, tapes);
+        } else if let Some(extension) = item_path.extension() {
+            if files::TAPE_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()) {
+                tapes.push(item_path);
+            }
+        }
+    }
+}
+
+fn retrieve_format(matches: &getopts::Matches) -> DatFormat {
+    match matches.opt_str("format") {
+        Some(argument) => {
+            match DAT_FORMATS.iter().find(|&&(name, _format)| name == argument.to_lowercase()) {
+                Some(&(_name, format)) => { format },
+                None => {
+                    let names: Vec<&str> = DAT_FORMATS.iter().map(|&(name, _format)| name).collect();
+                    error!("Unknown DAT format `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { DatFormat::Xml },
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn escape_clrmamepro(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// The header fields, in the order both formats list them:
+fn generate_xml(header: &[(&str, String)], roms: &[Rom]) -> String {
+    let mut dat = String::new();
+    dat.push_str("<?xml version=\"1.0\"?>\n");
+    dat.push_str("<!DOCTYPE datafile PUBLIC \"-//Logiqx//DTD ROM Management Datafile//EN\" \"http://www.logiqx.com/Dats/datafile.dtd\">\n");
+    dat.push_str("<datafile>\n\t<header>\n");
+    for &(field, ref value) in header {
+        dat.push_str(&format!("\t\t<{}>{}</{}>\n", field, escape_xml(value), field));
+    }
+    dat.push_str("\t</header>\n");
+    for rom in roms {
+        dat.push_str(&format!("\t<game name=\"{}\">\n\t\t<description>{}</description>\n", escape_xml(&rom.game), escape_xml(&rom.game)));
+        dat.push_str(&format!("\t\t<rom name=\"{}\" size=\"{}\" crc=\"{:08x}\" sha1=\"{}\"/>\n",
+                              escape_xml(&rom.name), rom.size, rom.crc, hex(&rom.sha1)));
+        dat.push_str("\t</game>\n");
+    }
+    dat.push_str("</datafile>\n");
+    dat
+}
+
+fn generate_clrmamepro(header: &[(&str, String)], roms: &[Rom]) -> String {
+    let mut dat = String::from("clrmamepro (\n");
+    for &(field, ref value) in header {
+        dat.push_str(&format!("\t{} \"{}\"\n", field, escape_clrmamepro(value)));
+    }
+    dat.push_str(")\n");
+    for rom in roms {
+        dat.push_str(&format!("\ngame (\n\tname \"{}\"\n\tdescription \"{}\"\n", escape_clrmamepro(&rom.game), escape_clrmamepro(&rom.game)));
+        dat.push_str(&format!("\trom ( name \"{}\" size {} crc {:08x} sha1 {} )\n)\n",
+                              escape_clrmamepro(&rom.name), rom.size, rom.crc, hex(&rom.sha1)));
+    }
+    dat
+}
+
+pub const USAGE: &str = "<tape or directory>...";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optmulti("i", "input", "A tape image to describe, or a directory whose .cas files are described, along with the ones in the directories in it; may be given several times, or as further arguments.", "FILE");
+    options.optopt("o", "output", "Name of the DAT file to write (the name of the set with the extension .dat by default).", "FILE");
+    options.optopt("", "format", "Format of the DAT file: `xml', the Logiqx XML most ROM managers read (the default), or `clrmamepro', the older text format.", "FORMAT");
+    options.optopt("", "name", "Name of the set (the name of the first input by default).", "NAME");
+    options.optopt("", "description", "Description of the set (its name by default).", "TEXT");
+    options.optopt("", "version", "Version of the set (today's date as YYYYMMDD by default).", "VERSION");
+    options.optopt("", "author", "Author of the DAT file.", "NAME");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "dat", USAGE, true, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    let mut arguments = matches.opt_strs("i");
+    arguments.extend(matches.free.iter().cloned());
+    if arguments.is_empty() {
+        error!("No tapes given to describe, see `{} dat --help'.", progname);
+        process::exit(1);
+    }
+    let format = retrieve_format(&matches);
+    let variant = commands::retrieve_variant(&matches);
+
+    let mut tapes = Vec::new();
+    for argument in &arguments {
+        let argument_path: &path::Path = argument.as_ref();
+        if argument_path.is_dir() {
+            collect_tapes(argument_path, &mut tapes);
+        } else {
+            match files::expand_input_pattern(argument, files::TAPE_EXTENSIONS) {
+                Ok(paths) => { tapes.extend(paths); },
+                Err(error) => {
+                    error!("{}.", error);
+                    process::exit(1);
+                },
+            }
+        }
+    }
+    if tapes.is_empty() {
+        error!("No tape images found in {}.", arguments.join(", "));
+        process::exit(1);
+    }
+
+    // ROM managers expect the names of the games to be unique, and files
+    // which aren't tapes are likely misnamed or damaged, so both are pointed
+    // out, the files are described as they are anyway:
+    let mut roms: Vec<Rom> = Vec::new();
+    for tape_path in &tapes {
+        let buffer = match files::load_file(tape_path) {
+            Some(buffer) => { buffer },
+            None => { process::exit(1); },
+        };
+        if !coco::is_coco_tape(&buffer) {
+            if let Err(error) = tape::parse_tape(&buffer, &variant) {
+                warning!("{}: {}, it's described anyway.", tape_path.display(), error);
+            }
+        }
+        let name = match tape_path.file_name() {
+            Some(name) => { name.to_string_lossy().into_owned() },
+            None => { tape_path.display().to_string() },
+        };
+        let game = match tape_path.file_stem() {
+            Some(stem) => { stem.to_string_lossy().into_owned() },
+            None => { name.clone() },
+        };
+        if let Some(other) = roms.iter().find(|rom| rom.game == game) {
+            warning!("{}: Another tape is named `{}' too, ROM managers tell the games apart by their names.", tape_path.display(), other.name);
+        }
+        verbose!("{}: {} bytes.", tape_path.display(), buffer.len());
+        roms.push(Rom { game, name, size: buffer.len(), crc: hashes::crc32(&buffer), sha1: hashes::sha1(&buffer) });
+    }
+
+    let set_name = match matches.opt_str("name") {
+        Some(name) => { name },
+        None => {
+            let first: &path::Path = arguments[0].as_ref();
+            let first = if first.is_dir() { first.canonicalize().unwrap_or_else(|_error| first.to_owned()) } else { first.to_owned() };
+            match first.file_stem() {
+                Some(stem) => { stem.to_string_lossy().into_owned() },
+                None => { String::from("tapes") },
+            }
+        },
+    };
+    let today = clock::now();
+    let mut header = vec![
+        ("name", set_name.clone()),
+        ("description", matches.opt_str("description").unwrap_or_else(|| set_name.clone())),
+        ("version", matches.opt_str("version").unwrap_or_else(|| format!("{:04}{:02}{:02}", today.year, today.month, today.day))),
+    ];
+    if let Some(author) = matches.opt_str("author") {
+        header.push(("author", author));
+    }
+    let dat = match format {
+        DatFormat::Xml => { generate_xml(&header, &roms) },
+        DatFormat::Clrmamepro => { generate_clrmamepro(&header, &roms) },
+    };
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { path::PathBuf::from(format!("{}.dat", set_name)) },
+    };
+    if tapes.contains(&out_filepath) {
+        error!("The DAT file would overwrite one of the tapes, aborting to prevent data loss.");
+        process::exit(1);
+    }
+    status!("{} tapes, {} bytes in all, described in `{}'.", roms.len(), roms.iter().map(|rom| rom.size).sum::<usize>(), out_filepath.display());
+    if !files::write_file(dat.as_bytes(), &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
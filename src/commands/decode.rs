@@ -0,0 +1,817 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::io;
+use std::io::Write;
+use std::path;
+use std::process;
+
+use audio;
+use commands;
+use commands::info;
+use commands::verify;
+use console;
+use files;
+use filters;
+use plot;
+use progress;
+use sound;
+use tape;
+use template;
+
+
+// The decoder starts with the sync byte, so puts a leader back in front of
+// what it decoded to make it a tape image:
+fn with_leader(decoded: &audio::Decoded, variant: &tape::Variant) -> Vec<u8> {
+    let mut stretch: Vec<u8> = variant.leader.iter().cycle().take(tape::LEADER_LENGTH).cloned().collect();
+    stretch.extend_from_slice(&decoded.bytes);
+    stretch
+}
+
+// The sample the byte at an offset of such a tape image was decoded from
+// starts at, the leader put back counting as the sync byte:
+fn sample_position(decoded: &audio::Decoded, offset: usize) -> usize {
+    let index = offset.max(tape::LEADER_LENGTH) - tape::LEADER_LENGTH;
+    match decoded.byte_positions.get(index) {
+        Some(position) => { *position },
+        None => { decoded.bits.last().map(|bit| bit.position + 1).unwrap_or(0) },
+    }
+}
+
+fn format_time(samples: usize, sample_rate: u32) -> String {
+    let seconds = (samples as f64) / (sample_rate as f64);
+    format!("{}:{:04.1}", (seconds / 60.0) as u64, seconds % 60.0)
+}
+
+// Reports how clearly the bits of each block were told apart, and where in
+// the recording the ones which came out wrong are, so the tape can be
+// cleaned or played again right there.  `start' is where the stretch begins
+// in the whole recording:
+fn report_quality(decoded: &audio::Decoded, entry: &tape::Entry, start: usize, sample_rate: u32) {
+    for (index, block) in entry.blocks.iter().enumerate() {
+        let block_start = sample_position(decoded, block.offset);
+        let block_end = sample_position(decoded, block.offset + 4 + block.data.len() + block.checksum.length());
+        let bits = decoded.bits_between(block_start, block_end);
+        let marginal = bits.iter().filter(|bit| bit.margin < audio::MARGINAL_BIT).count();
+        let noise = decoded.noise.iter().filter(|position| **position >= block_start && **position < block_end).count();
+        let confidence = if bits.is_empty() { 0.0 } else { 100.0 * bits.iter().map(|bit| bit.margin).sum::<f64>() / (bits.len() as f64) };
+        let time = format_time(start + block_start, sample_rate);
+
+        verbose!("Block {} at 0x{:04X}, from {}: {:.0}% confidence, {} marginal bits, {} noise pulses.",
+                 index + 1, block.load_address, time, confidence, marginal, noise);
+        if !block.checksum_ok() {
+            warning!("Block {} at 0x{:04X} of `{}' has a bad checksum, it's at {} into the recording ({} marginal bits, {} noise pulses).",
+                     index + 1, block.load_address, entry.name_string(), time, marginal, noise);
+        } else if marginal > 0 {
+            note!("Block {} at 0x{:04X} of `{}' only just decoded, with {} marginal bits at {} into the recording.",
+                  index + 1, block.load_address, entry.name_string(), marginal, time);
+        }
+    }
+}
+
+// How recordings get decoded, as given with the decoder options:
+#[derive(Clone, Copy)]
+pub struct DecoderSettings {
+    pub speed: f64,
+    // Told from the recording when not given:
+    pub baud: Option<audio::Baud>,
+    pub invert: Option<bool>,
+}
+
+// Decodes a recording at the baud rate given, or at the one it turns out to
+// be recorded at, and the other way up if asked to or if it turns out to be,
+// along with the variant the tape image decoded is framed in.  The progress
+// follows the passes over the recording, one to tell the baud rate, two to
+// tell which way up it is and one to decode it:
+fn decode_recording(recording: &audio::Recording, variant: &tape::Variant, settings: &DecoderSettings) -> (audio::Decoded, tape::Variant) {
+    let length = recording.samples.len();
+    let passes = 1 + if settings.baud.is_none() { 1 } else { 0 } + if settings.invert.is_none() { 2 } else { 0 };
+    let mut progress = progress::Progress::new("Decoding", "samples", passes * length);
+    let mut done = 0;
+
+    let baud = match settings.baud {
+        Some(baud) => { baud },
+        None => {
+            let baud = audio::detect_baud(recording, &mut |samples| progress.update(done + samples));
+            done += length;
+            if baud == audio::Baud::High {
+                status!("The recording is at 1500 baud, decoding it as a high speed Model III tape.");
+            }
+            baud
+        },
+    };
+    let invert = match settings.invert {
+        Some(invert) => { invert },
+        None => {
+            let invert = audio::detect_inverted(recording, baud, &mut |samples| progress.update(done + samples));
+            done += length * 2;
+            if invert {
+                status!("The recording is inverted, decoding it the other way up.");
+            }
+            invert
+        },
+    };
+    let inverted;
+    let recording = if invert {
+        inverted = audio::Recording { sample_rate: recording.sample_rate, samples: audio::negate(&recording.samples) };
+        &inverted
+    } else {
+        recording
+    };
+    let result = match baud {
+        audio::Baud::Low => {
+            (audio::decode_samples(recording, variant.sync, settings.speed, &mut |samples| progress.update(done + samples)), variant.clone())
+        },
+        audio::Baud::High => {
+            let framing = variant.high_speed();
+            (audio::decode_fsk_samples(recording, tape::HIGH_SPEED_LEADER, framing.sync, settings.speed,
+                                       &mut |samples| progress.update(done + samples)), framing)
+        },
+    };
+    progress.finish();
+    result
+}
+
+// Decodes a stretch of signal, and reports on the entries found in it.  The
+// stretch begins `start' samples into the recording:
+pub fn decode_stretch(samples: Vec<i16>, sample_rate: u32, variant: &tape::Variant, settings: &DecoderSettings,
+                      start: usize, first_index: usize) -> Option<(Vec<u8>, Vec<tape::Entry>)> {
+    let recording = audio::Recording { sample_rate, samples };
+    let (decoded, framing) = decode_recording(&recording, variant, settings);
+    if decoded.bytes.is_empty() {
+        warning!("The signal didn't decode into anything, it might be too weak, or not a Level II tape.");
+        return None;
+    }
+
+    // Decks a bit off are common, and worth knowing about when recording
+    // onto one:
+    if (decoded.speed - 1.0).abs() >= 0.005 {
+        status!("The recording plays {:.1}% {}.", (decoded.speed - 1.0).abs() * 100.0, if decoded.speed > 1.0 { "fast" } else { "slow" });
+    }
+
+    let stretch = with_leader(&decoded, &framing);
+    let entries = match tape::parse_tape(&stretch, &framing) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            warning!("The signal decoded into {} bytes which don't make up a tape entry: {}.", decoded.bytes.len(), error);
+            return None;
+        },
+    };
+
+    for (index, entry) in entries.iter().enumerate() {
+        println!();
+        info::print_entry_summary(first_index + index, entry, None);
+        report_quality(&decoded, entry, start, sample_rate);
+    }
+    println!();
+
+    // High speed tapes are kept with the leader and the sync byte of 500
+    // baud ones, so that the rest of the tools read them:
+    if framing.sync != variant.sync {
+        let reframed = tape::reframe(&stretch, &entries, &framing, variant);
+        return match tape::parse_tape(&reframed, variant) {
+            Ok(entries) => { Some((reframed, entries)) },
+            Err(error) => {
+                warning!("The high speed entries don't make up a tape image with the leader and the sync byte given: {}.", error);
+                None
+            },
+        };
+    }
+    Some((stretch, entries))
+}
+
+// Reads a recording, WAV files directly, and anything else through one of
+// the converters:
+pub fn load_recording(in_path: &path::Path, converter: Option<&str>) -> Option<audio::Recording> {
+    let is_wav = match in_path.extension() {
+        Some(extension) => { extension.to_string_lossy().to_lowercase() == "wav" },
+        None => { false },
+    };
+    if is_wav {
+        let buffer = files::load_file(in_path)?;
+        return match audio::parse_wav_file(&buffer) {
+            Ok(recording) => { Some(recording) },
+            Err(error) => {
+                error!("{}: {}.", in_path.display(), error);
+                None
+            },
+        };
+    }
+
+    let converter = match sound::find_program(sound::CONVERTERS, "converter", converter) {
+        Ok(converter) => { converter },
+        Err(error) => {
+            error!("{}, recordings other than WAV files are decoded with one of them.", error);
+            return None;
+        },
+    };
+    status!("Converting `{}' with `{}'.", in_path.display(), converter.name);
+    match sound::convert(converter, in_path, audio::SAMPLE_RATE) {
+        Ok(samples) => { Some(audio::Recording { sample_rate: audio::SAMPLE_RATE, samples }) },
+        Err(error) => {
+            error!("{}: {}.", in_path.display(), error);
+            None
+        },
+    }
+}
+
+// Where the entries and the blocks decoded start in the recording, worth
+// drawing onto its plot:
+fn find_markers(decoded: &audio::Decoded, variant: &tape::Variant) -> Vec<plot::Marker> {
+    let entries = match tape::parse_tape(&with_leader(decoded, variant), variant) {
+        Ok(entries) => { entries },
+        Err(_error) => { return Vec::new(); },
+    };
+    let position_of = |offset: usize| sample_position(decoded, offset);
+
+    let mut markers = Vec::new();
+    for entry in &entries {
+        markers.push(plot::Marker { position: position_of(entry.start_offset), label: format!("entry `{}'", entry.name_string()), bad: false });
+        for (index, block) in entry.blocks.iter().enumerate() {
+            let bad = !block.checksum_ok();
+            let label = format!("block {} at 0x{:04X}{}", index + 1, block.load_address, if bad { ", bad checksum" } else { "" });
+            markers.push(plot::Marker { position: position_of(block.offset), label, bad });
+        }
+        if let Some(entry_point) = entry.entry_point {
+            markers.push(plot::Marker { position: position_of(entry.end_offset - 3), label: format!("end of file, 0x{:04X}", entry_point), bad: false });
+        }
+    }
+    markers
+}
+
+fn retrieve_plot_range(matches: &getopts::Matches, recording: &audio::Recording, decoded: &audio::Decoded) -> (usize, usize) {
+    let rate = recording.sample_rate as f64;
+    let (start, end) = match matches.opt_str("plot-range") {
+        Some(argument) => {
+            let range = argument.split_once('-').and_then(|(start, end)| Some((start.trim().parse::<f64>().ok()?, end.trim().parse::<f64>().ok()?)));
+            match range {
+                Some((start, end)) if start >= 0.0 && start < end => { ((start * rate) as usize, (end * rate) as usize) },
+                _ => {
+                    error!("Invalid plot range `{}', expected the start and the end in seconds, such as `1.5-2'.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => {
+            // The first second around where the decoding starts, or where
+            // the signal does if nothing decoded:
+            let first = match decoded.byte_positions.first() {
+                Some(position) => { *position },
+                None => { decoded.bits.first().map(|bit| bit.position).unwrap_or(0) },
+            };
+            let start = first.saturating_sub((rate / 20.0) as usize);
+            (start, start + (rate as usize))
+        },
+    };
+
+    let end = end.min(recording.samples.len());
+    if start >= end {
+        error!("The plot range starts past the end of the recording, which is {:.1} seconds long.", (recording.samples.len() as f64) / rate);
+        process::exit(1);
+    }
+    (start, end)
+}
+
+// Plots a part of the recording with the pulses, the bits and the blocks
+// found in it:
+fn write_plot(matches: &getopts::Matches, plot_path: &path::Path, recording: &audio::Recording, variant: &tape::Variant,
+              settings: &DecoderSettings) -> bool {
+    let (decoded, framing) = decode_recording(recording, variant, settings);
+    let (start, end) = retrieve_plot_range(matches, recording, &decoded);
+    let svg = plot::plot_recording(recording, start, end, &decoded, &find_markers(&decoded, &framing));
+    status!("Plotting {:.3} to {:.3} seconds of the recording.", (start as f64) / (recording.sample_rate as f64), (end as f64) / (recording.sample_rate as f64));
+    files::write_file(svg.as_bytes(), plot_path)
+}
+
+// Turns a path component made of a tape name into something which works as
+// a filename everywhere:
+fn filename_safe(name: &str) -> String {
+    name.chars().map(|character| if character.is_ascii_alphanumeric() || character == '-' { character } else { '_' }).collect()
+}
+
+// Decodes the stretches of a recording separately, so that a damaged one
+// doesn't throw off the others, and writes each entry into a file of its own:
+fn split_recording(matches: &getopts::Matches, in_filepath: &path::Path, recording: &audio::Recording,
+                   variant: &tape::Variant, settings: &DecoderSettings, pause: u32) -> ! {
+    let stretches = audio::split_at_pauses(recording, pause);
+    status!("Found {} stretches of signal.", stretches.len());
+
+    let mut success = true;
+    let mut index = 0;
+    let mut used_paths: Vec<path::PathBuf> = Vec::new();
+    for (start, end) in stretches {
+        let (stretch, entries) = match decode_stretch(recording.samples[start..end].to_vec(), recording.sample_rate, variant, settings, start, index) {
+            Some(decoded) => { decoded },
+            None => {
+                warning!("Skipping the stretch at {:.1} seconds.", (start as f64) / (recording.sample_rate as f64));
+                continue;
+            },
+        };
+
+        for entry in &entries {
+            let name = entry.name_string().trim_end().to_owned();
+            let out_filepath = match matches.opt_str("o") {
+                Some(argument) => {
+                    let (base_address, end) = entry.address_range().unwrap_or((0, 0));
+                    let mut values = commands::entry_template_values(in_filepath, &entry.name, base_address,
+                                                                     entry.entry_point.unwrap_or(0), (end - (base_address as u32)) as usize);
+                    values.push(("index", template::Value::Number((index + 1) as u32)));
+                    let out_filepath = commands::expand_output_path(&argument, &values);
+                    if used_paths.contains(&out_filepath) {
+                        error!("Several entries would be written into `{}', use a template that tells them apart, such as one with {{index}}.", out_filepath.display());
+                        process::exit(1);
+                    }
+                    out_filepath
+                },
+                None => {
+                    // Named after the entry, unless there's no name to go by
+                    // or several entries share it:
+                    let named = files::default_output_path(path::Path::new(&filename_safe(&name)), "cas");
+                    if name.is_empty() || used_paths.contains(&named) {
+                        files::numbered_output_path(in_filepath, index + 1, "cas")
+                    } else {
+                        named
+                    }
+                },
+            };
+            if out_filepath == in_filepath {
+                error!("The input and output files are the same, aborting to prevent data loss.");
+                process::exit(1);
+            }
+            used_paths.push(out_filepath.clone());
+
+            if !files::write_file(&stretch[entry.start_offset..entry.end_offset], &out_filepath) {
+                success = false;
+            }
+            index += 1;
+        }
+    }
+
+    if index == 0 {
+        error!("No entries found in `{}'.", in_filepath.display());
+        process::exit(1);
+    }
+    status!("Split `{}' into {} entries.", in_filepath.display(), index);
+    process::exit(if success { 0 } else { 1 });
+}
+
+// A line of the summary table of a batch, without the output for
+// recordings which failed to decode:
+struct BatchRow {
+    input: String,
+    output: Option<String>,
+    entries: usize,
+    blocks: usize,
+    bad_blocks: usize,
+}
+
+fn print_batch_summary(rows: &[BatchRow]) {
+    let input_width = rows.iter().map(|row| row.input.len()).max().unwrap_or(0).max(5);
+    let output_width = rows.iter().map(|row| row.output.as_ref().map_or(0, |output| output.len())).max().unwrap_or(0).max(6);
+
+    status!("");
+    status!("{:iw$}  {:ow$}  Entries  Blocks  Bad", "Input", "Output", iw = input_width, ow = output_width);
+    for row in rows {
+        match row.output {
+            Some(ref output) => {
+                status!("{:iw$}  {:ow$}  {:7}  {:6}  {}", row.input, output, row.entries, row.blocks, row.bad_blocks,
+                        iw = input_width, ow = output_width);
+            },
+            None => {
+                status!("{:iw$}  {:ow$}  failed", row.input, "-", iw = input_width, ow = output_width);
+            },
+        }
+    }
+}
+
+// Decodes one recording of a batch, the outputs are claimed in
+// `used_paths', so that no two recordings end up in the same tape image:
+fn batch_decode_one(matches: &getopts::Matches, in_path: &path::Path, variant: &tape::Variant, settings: &DecoderSettings,
+                    used_paths: &mut Vec<path::PathBuf>) -> BatchRow {
+    status!("");
+    status!("Decoding `{}':", in_path.display());
+    let mut row = BatchRow {
+        input: in_path.to_string_lossy().into_owned(),
+        output: None,
+        entries: 0,
+        blocks: 0,
+        bad_blocks: 0,
+    };
+
+    let mut recording = match load_recording(in_path, matches.opt_str("converter").as_deref()) {
+        Some(recording) => { recording },
+        None => { return row; },
+    };
+    filters::apply(&commands::retrieve_filters(matches, recording.sample_rate), &mut recording.samples, recording.sample_rate);
+    let (tape_image, entries) = match decode_stretch(recording.samples, recording.sample_rate, variant, settings, 0, 0) {
+        Some(decoded) => { decoded },
+        None => { return row; },
+    };
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => {
+            let entry = &entries[0];
+            let (base_address, end) = entry.address_range().unwrap_or((0, 0));
+            let values = commands::entry_template_values(in_path, &entry.name, base_address,
+                                                         entry.entry_point.unwrap_or(0), (end - (base_address as u32)) as usize);
+            commands::expand_output_path(&argument, &values)
+        },
+        None => { files::default_output_path(in_path, "cas") },
+    };
+    if out_filepath == in_path || used_paths.contains(&out_filepath) {
+        error!("`{}' would be written over a recording or another tape image of the batch, skipping `{}'.", out_filepath.display(), in_path.display());
+        return row;
+    }
+    used_paths.push(out_filepath.clone());
+
+    if files::write_file(&tape_image, &out_filepath) {
+        row.output = Some(out_filepath.to_string_lossy().into_owned());
+        row.entries = entries.len();
+        row.blocks = entries.iter().map(|entry| entry.blocks.len()).sum();
+        row.bad_blocks = entries.iter().map(|entry| entry.bad_checksum_count()).sum();
+    }
+    row
+}
+
+// Decodes every recording into a tape image of its own, going on with the
+// rest if some of them fail:
+fn batch_decode(progname: &str, matches: &getopts::Matches, variant: &tape::Variant, settings: &DecoderSettings) -> ! {
+    if matches.opt_present("split") || matches.opt_present("plot") || matches.opt_present("retry") {
+        error!("The `--batch' option can't be used together with `--split', `--plot' or `--retry'.");
+        process::exit(1);
+    }
+    if let Some(argument) = matches.opt_str("o") {
+        if !template::is_template(&argument) {
+            error!("The tape images of a batch need names of their own, `--output' can only be a template with `--batch'.");
+            process::exit(1);
+        }
+    }
+
+    let mut arguments: Vec<String> = matches.opt_str("i").into_iter().collect();
+    arguments.extend(matches.free.iter().cloned());
+    if arguments.is_empty() {
+        error!("No recordings given for the batch, see `{} decode --help'.", progname);
+        process::exit(1);
+    }
+    let mut in_paths = Vec::new();
+    for argument in arguments {
+        match files::expand_input_pattern(&argument, files::RECORDING_EXTENSIONS) {
+            Ok(paths) => { in_paths.extend(paths); },
+            Err(error) => {
+                error!("{}.", error);
+                process::exit(1);
+            },
+        }
+    }
+
+    let mut used_paths = Vec::new();
+    let mut progress = progress::Progress::new("Batch", "recordings", in_paths.len());
+    let rows: Vec<BatchRow> = in_paths.iter().enumerate().map(|(index, in_path)| {
+        let row = batch_decode_one(matches, in_path, variant, settings, &mut used_paths);
+        progress.update(index + 1);
+        row
+    }).collect();
+    progress.finish();
+
+    print_batch_summary(&rows);
+    let failures = rows.iter().filter(|row| row.output.is_none()).count();
+    let damaged = rows.iter().filter(|row| row.output.is_some() && row.bad_blocks != 0).count();
+    if failures == 0 && damaged == 0 {
+        status!("Decoded all {} recordings.", rows.len());
+        process::exit(0);
+    }
+    if damaged != 0 {
+        warning!("{} of {} recordings decoded with bad blocks, play them again or clean the tapes.", damaged, rows.len());
+    }
+    if failures != 0 {
+        error!("{} of {} recordings failed to decode.", failures, rows.len());
+    }
+    process::exit(1);
+}
+
+// The other ways of decoding a damaged block again, tried in turn until one
+// of them gets it intact.  The filters only go over the stretch of the
+// recording the block is in, the rest is decoded as it was:
+#[derive(Clone, Copy)]
+enum Retry {
+    Filtered(filters::Filters),
+    // Brought up by the given factor, so that the pulses of a dropout get
+    // above the noise threshold set by the louder rest of the recording:
+    Amplified(f64),
+    Speed(f64),
+    Inverted,
+}
+
+const RETRIES: &[(&str, Retry)] = &[
+    ("with the DC offset removed",    Retry::Filtered(filters::Filters { dc_cutoff: Some(20.0), band_pass: None, click_factor: None, gain: filters::Gain::Fixed(0.0) })),
+    ("band-passed to 200-8000 Hz",    Retry::Filtered(filters::Filters { dc_cutoff: None, band_pass: Some((200.0, 8000.0)), click_factor: None, gain: filters::Gain::Fixed(0.0) })),
+    ("declicked",                     Retry::Filtered(filters::Filters { dc_cutoff: None, band_pass: None, click_factor: Some(2.0), gain: filters::Gain::Fixed(0.0) })),
+    ("declicked more eagerly",        Retry::Filtered(filters::Filters { dc_cutoff: None, band_pass: None, click_factor: Some(1.5), gain: filters::Gain::Fixed(0.0) })),
+    ("amplified by 12 dB",            Retry::Amplified(4.0)),
+    ("as playing 2% slow",            Retry::Speed(0.98)),
+    ("as playing 2% fast",            Retry::Speed(1.02)),
+    ("the other way up",              Retry::Inverted),
+];
+
+// How far on either side of a block the filters start and stop, so that they
+// settle before it:
+const RETRY_MARGIN_US: u64 = 100_000;
+
+// The intact copy of a block among the entries decoded again, in an entry of
+// the same name:
+fn find_intact(entries: &[tape::Entry], entry: &tape::Entry, block: &tape::Block) -> Option<tape::Block> {
+    entries.iter().filter(|other| other.name == entry.name).flat_map(|other| other.blocks.iter())
+           .find(|other| other.checksum_ok() && other.load_address == block.load_address &&
+                         other.data.len() == block.data.len() && other.checksum == block.checksum)
+           .cloned()
+}
+
+// Writes an intact copy of a block over the damaged one in the tape image:
+fn patch_block(tape_image: &mut [u8], block: &tape::Block, intact: &tape::Block) {
+    let data_start = block.offset + 4;
+    let mut checksum = Vec::new();
+    intact.checksum.push(intact.stored_checksum, &mut checksum);
+    tape_image[data_start..data_start + intact.data.len()].copy_from_slice(&intact.data);
+    tape_image[data_start + intact.data.len()..data_start + intact.data.len() + checksum.len()].copy_from_slice(&checksum);
+}
+
+fn decode_entries(recording: &audio::Recording, variant: &tape::Variant, settings: &DecoderSettings) -> Vec<tape::Entry> {
+    let (decoded, framing) = decode_recording(recording, variant, settings);
+    tape::parse_tape(&with_leader(&decoded, &framing), &framing).unwrap_or_default()
+}
+
+// Decodes the recording again each of the other ways, over the stretch from
+// `start' to `end' of it, until the block comes out intact:
+fn retry_block(recording: &audio::Recording, variant: &tape::Variant, settings: &DecoderSettings, start: usize, end: usize,
+               entry: &tape::Entry, block: &tape::Block) -> Option<tape::Block> {
+    let margin = (RETRY_MARGIN_US * (recording.sample_rate as u64) / 1_000_000) as usize;
+    let (from, to) = (start.saturating_sub(margin), (end + margin).min(recording.samples.len()));
+
+    for &(description, retry) in RETRIES {
+        let mut attempt = audio::Recording { sample_rate: recording.sample_rate, samples: recording.samples.clone() };
+        let mut attempt_settings = *settings;
+        match retry {
+            Retry::Filtered(ref retry_filters) => {
+                if retry_filters.band_pass.is_some_and(|(_low, high)| high >= (recording.sample_rate as f64) / 2.0) {
+                    continue;
+                }
+                let mut stretch = attempt.samples[from..to].to_vec();
+                filters::apply(retry_filters, &mut stretch, recording.sample_rate);
+                attempt.samples[from..to].copy_from_slice(&stretch);
+            },
+            Retry::Amplified(factor) => {
+                for sample in &mut attempt.samples[from..to] {
+                    *sample = ((*sample as f64) * factor).round().clamp(-32768.0, 32767.0) as i16;
+                }
+            },
+            Retry::Speed(factor) => { attempt_settings.speed *= factor; },
+            Retry::Inverted => { attempt_settings.invert = attempt_settings.invert.map(|invert| !invert); },
+        }
+
+        verbose!("Decoding it {}.", description);
+        if let Some(intact) = find_intact(&decode_entries(&attempt, variant, &attempt_settings), entry, block) {
+            status!("The block decoded intact {}.", description);
+            return Some(intact);
+        }
+    }
+    None
+}
+
+// Takes the block from another recording of the same tape:
+fn retry_from(matches: &getopts::Matches, in_path: &path::Path, variant: &tape::Variant, settings: &DecoderSettings,
+              entry: &tape::Entry, block: &tape::Block) -> Option<tape::Block> {
+    let mut recording = load_recording(in_path, matches.opt_str("converter").as_deref())?;
+    filters::apply(&commands::retrieve_filters(matches, recording.sample_rate), &mut recording.samples, recording.sample_rate);
+    let intact = find_intact(&decode_entries(&recording, variant, settings), entry, block);
+    match intact {
+        Some(_) => { status!("Took the block from `{}'.", in_path.display()); },
+        None => { warning!("The block isn't intact in `{}' either.", in_path.display()); },
+    }
+    intact
+}
+
+// Goes over the blocks which came out with bad checksums, and offers to
+// decode each again the other ways, or to take it from another recording of
+// the same tape, writing the intact copies found over them in the tape image.
+// The entries are the ones of the tape image, and are decoded again from the
+// recording to tell where their blocks are in it:
+fn retry_damaged(matches: &getopts::Matches, recording: &audio::Recording, variant: &tape::Variant, settings: &DecoderSettings,
+                 tape_image: &mut [u8], entries: &[tape::Entry]) {
+    let damaged = entries.iter().map(|entry| entry.bad_checksum_count()).sum::<usize>();
+    if damaged == 0 {
+        return;
+    }
+
+    // Tried again at the baud rate and the polarity of the first time, so
+    // that they aren't told all over again, other recordings may differ:
+    let baud = settings.baud.unwrap_or_else(|| audio::detect_baud(recording, &mut |_done| {}));
+    let retry_settings = DecoderSettings {
+        speed: settings.speed,
+        baud: Some(baud),
+        invert: Some(settings.invert.unwrap_or_else(|| audio::detect_inverted(recording, baud, &mut |_done| {}))),
+    };
+    let (decoded, framing) = decode_recording(recording, variant, &retry_settings);
+    let decoded_entries = tape::parse_tape(&with_leader(&decoded, &framing), &framing).unwrap_or_default();
+
+    let mut recovered = 0;
+    'entries: for (entry, decoded_entry) in entries.iter().zip(&decoded_entries) {
+        for (index, (block, decoded_block)) in entry.blocks.iter().zip(&decoded_entry.blocks).enumerate() {
+            if block.checksum_ok() {
+                continue;
+            }
+            let start = sample_position(&decoded, decoded_block.offset);
+            let end = sample_position(&decoded, decoded_block.offset + 4 + decoded_block.data.len() + decoded_block.checksum.length());
+
+            println!();
+            println!("Block {} at 0x{:04X} of `{}' has a bad checksum, from {} to {} into the recording.", index + 1, block.load_address,
+                     entry.name_string(), format_time(start, recording.sample_rate), format_time(end, recording.sample_rate));
+            loop {
+                print!("Press Enter to decode it again other ways, give another recording of the tape to take it from, `s' to skip it or `q' to stop: ");
+                let _ = io::stdout().flush();
+                let mut answer = String::new();
+                match io::stdin().read_line(&mut answer) {
+                    Ok(0) | Err(_) => {
+                        println!();
+                        break 'entries;
+                    },
+                    Ok(_length) => { },
+                }
+
+                let intact = match answer.trim() {
+                    "" => { retry_block(recording, variant, &retry_settings, start, end, entry, block) },
+                    "s" => { break; },
+                    "q" => { break 'entries; },
+                    in_path => { retry_from(matches, in_path.as_ref(), variant, settings, entry, block) },
+                };
+                match intact {
+                    Some(intact) => {
+                        patch_block(tape_image, block, &intact);
+                        recovered += 1;
+                        break;
+                    },
+                    None => { println!("The block is still damaged."); },
+                }
+            }
+        }
+    }
+
+    println!();
+    if recovered == damaged {
+        status!("Recovered all {} damaged blocks.", damaged);
+    } else {
+        warning!("Recovered {} of {} damaged blocks.", recovered, damaged);
+    }
+}
+
+pub const USAGE: &str = "-i <file> | --batch <recording>...";
+
+// Times reading, filtering and decoding the recording, for telling how long
+// a batch of real recordings will take, and what the time goes on:
+fn bench(matches: &getopts::Matches, in_filepath: &path::Path, recording: &audio::Recording, variant: &tape::Variant, settings: &DecoderSettings) -> ! {
+    let audio_seconds = (recording.samples.len() as f64) / (recording.sample_rate as f64);
+    println!("Benchmark of `{}', {:.1} seconds of audio at {} Hz:", in_filepath.display(), audio_seconds, recording.sample_rate);
+
+    // Recordings other than WAV files take as long as the converter does:
+    if let Some(buffer) = files::load_file(in_filepath).filter(|buffer| audio::parse_wav_file(buffer).is_ok()) {
+        let seconds = verify::time_stage(&mut || { let _ = audio::parse_wav_file(&buffer); });
+        verify::report_audio_stage("Reading WAV", seconds, audio_seconds);
+    }
+    let filters = commands::retrieve_filters(matches, recording.sample_rate);
+    let seconds = verify::time_stage(&mut || {
+        let mut samples = recording.samples.clone();
+        filters::apply(&filters, &mut samples, recording.sample_rate);
+    });
+    verify::report_audio_stage("Filtering", seconds, audio_seconds);
+
+    let mut filtered = audio::Recording { sample_rate: recording.sample_rate, samples: recording.samples.clone() };
+    filters::apply(&filters, &mut filtered.samples, filtered.sample_rate);
+    let seconds = verify::time_stage(&mut || { audio::find_pulses(&filtered.samples, filtered.sample_rate); });
+    verify::report_audio_stage("Finding pulses", seconds, audio_seconds);
+    // The same, with the filters run a sample at a time, for comparison:
+    let seconds = verify::time_stage(&mut || { audio::find_pulses_by(&filtered.samples, filtered.sample_rate, audio::Filtering::Serial, &mut |_done| {}); });
+    verify::report_audio_stage("Serial pulses", seconds, audio_seconds);
+    // What the baud rate and the polarity were found to be would be told
+    // on every run:
+    let verbosity = console::verbosity();
+    console::set_verbosity(console::QUIET);
+    let seconds = verify::time_stage(&mut || { decode_recording(&filtered, variant, settings); });
+    console::set_verbosity(verbosity);
+    verify::report_audio_stage("Decoding", seconds, audio_seconds);
+    process::exit(0);
+}
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The recording to decode, a WAV file, or an MP3, OGG, FLAC or other compressed recording, converted with ffmpeg or SoX.", "FILE");
+    options.optopt("o", "output", "Name of the tape image to write (input filename with extension changed to .cas by default).", "FILE");
+    options.optflag("", "split", "Split a recording of several programs, such as of a whole side of a cassette, at the pauses between them, and write each entry into a file of its own, named after the entry (or numbered if it has no name), or as given by an `--output' template with {name}, {stem} (of the input filename), {base}, {start}, {end}, {size} and {index} available.");
+    options.optopt("", "split-pause", "Shortest pause to split the recording at with `--split', in seconds (1 by default).", "SECONDS");
+    options.optflag("", "batch", "Decode each recording given, with `--input' or as further arguments, into a tape image of its own named after it, and sum up how they decoded; recordings may also be directories, whose WAV, MP3, OGG, FLAC and other recordings are decoded, or patterns such as `tapes/*.wav'.");
+    options.optflag("", "retry", "Offer to decode the blocks with bad checksums again, through other filters, at other speeds and the other way up, or to take them from another recording of the same tape, patching the intact ones found into the tape image.");
+    options.optopt("", "converter", "Program to convert compressed recordings with, ffmpeg or sox (the first one installed by default).", "NAME");
+    options.optopt("", "plot", "Also write an SVG plot of the waveform with the pulses, the bits and the blocks found drawn over it, for seeing why a recording doesn't decode.", "FILE");
+    options.optopt("", "plot-range", "Part of the recording to plot, from and to the given seconds, such as `1.5-2' (the first second the decoding starts in by default).", "START-END");
+    options.optflag("", "bench", "Time reading, filtering and decoding the recording instead, as how many times faster than real time each runs and how long an hour of it takes, finding the pulses also with the filters run a sample at a time, for comparison.");
+    commands::add_filter_options(&mut options);
+    commands::add_decoder_options(&mut options);
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "decode", USAGE, true, &mut options, args);
+    commands::apply_output_options(&matches);
+    if matches.opt_present("batch") {
+        batch_decode(progname, &matches, &commands::retrieve_variant(&matches), &commands::retrieve_decoder_settings(&matches));
+    }
+    if !matches.free.is_empty() {
+        error!("Unexpected argument `{}', see `{} decode --help'.", matches.free[0], progname);
+        process::exit(1);
+    }
+    let in_filepath = commands::retrieve_input_path(progname, "decode", &matches);
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(&in_filepath, "cas") },
+    };
+    if in_filepath == out_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    let variant = commands::retrieve_variant(&matches);
+    let settings = commands::retrieve_decoder_settings(&matches);
+    let mut recording = match load_recording(&in_filepath, matches.opt_str("converter").as_deref()) {
+        Some(recording) => { recording },
+        None => { process::exit(1); },
+    };
+    if matches.opt_present("bench") {
+        bench(&matches, &in_filepath, &recording, &variant, &settings);
+    }
+    status!("{}: {:.1} seconds of audio at {} Hz.", in_filepath.display(),
+            (recording.samples.len() as f64) / (recording.sample_rate as f64), recording.sample_rate);
+    filters::apply(&commands::retrieve_filters(&matches, recording.sample_rate), &mut recording.samples, recording.sample_rate);
+    if let Some(argument) = matches.opt_str("plot") {
+        let plot_path = commands::expand_output_path(&argument, &[]);
+        if plot_path == in_filepath {
+            error!("The input and plot files are the same, aborting to prevent data loss.");
+            process::exit(1);
+        }
+        if !write_plot(&matches, &plot_path, &recording, &variant, &settings) {
+            process::exit(1);
+        }
+    }
+
+    if matches.opt_present("split") {
+        if matches.opt_present("retry") {
+            error!("The `--retry' option can't be used together with `--split'.");
+            process::exit(1);
+        }
+        let pause = match matches.opt_str("split-pause") {
+            Some(argument) => {
+                match argument.parse::<f64>() {
+                    Ok(seconds) if seconds > 0.0 && seconds <= 600.0 => { (seconds * 1_000_000.0) as u32 },
+                    _ => {
+                        error!("Invalid `--split-pause' length `{}', expected a positive number of seconds, up to 600.", argument);
+                        process::exit(1);
+                    },
+                }
+            },
+            None => { 1_000_000 },
+        };
+        split_recording(&matches, &in_filepath, &recording, &variant, &settings, pause);
+    }
+
+    let (mut tape_image, entries) = match decode_stretch(recording.samples.clone(), recording.sample_rate, &variant, &settings, 0, 0) {
+        Some(decoded) => { decoded },
+        None => { process::exit(1); },
+    };
+    if matches.opt_present("retry") {
+        retry_damaged(&matches, &recording, &variant, &settings, &mut tape_image, &entries);
+    }
+    if !files::write_file(&tape_image, &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
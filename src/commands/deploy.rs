@@ -0,0 +1,228 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use cmdfile;
+use commands;
+use files;
+use tape;
+
+
+// The SD card interfaces of the Model I take programs from the card's FAT
+// file system, listing its directory themselves, so there's no catalog to
+// keep up to date.  The names have to be ones the DOS takes, though:
+#[derive(Clone, Copy, PartialEq)]
+enum Firmware {
+    // FreHD only emulates hard drives, programs get onto them with IMPORT2
+    // from the root of the card:
+    FreHd,
+    // TRS-IO loads programs from the root of the card with its menu:
+    TrsIo,
+}
+
+const FIRMWARES: &[(&str, Firmware)] = &[
+    ("frehd",  Firmware::FreHd),
+    ("trs-io", Firmware::TrsIo),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Cmd,
+    Cas,
+}
+
+const FORMATS: &[(&str, Format)] = &[
+    ("cmd", Format::Cmd),
+    ("cas", Format::Cas),
+];
+
+fn retrieve_firmware(matches: &getopts::Matches) -> Firmware {
+    match matches.opt_str("firmware") {
+        Some(argument) => {
+            match FIRMWARES.iter().find(|&&(name, _firmware)| name == argument.to_lowercase()) {
+                Some(&(_name, firmware)) => { firmware },
+                None => {
+                    let names: Vec<&str> = FIRMWARES.iter().map(|&(name, _firmware)| name).collect();
+                    error!("Unknown firmware `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { Firmware::TrsIo },
+    }
+}
+
+fn retrieve_format(matches: &getopts::Matches, firmware: Firmware) -> Format {
+    let format = match matches.opt_str("format") {
+        Some(argument) => {
+            match FORMATS.iter().find(|&&(name, _format)| name == argument.to_lowercase()) {
+                Some(&(_name, format)) => { format },
+                None => {
+                    let names: Vec<&str> = FORMATS.iter().map(|&(name, _format)| name).collect();
+                    error!("Unknown format `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { Format::Cmd },
+    };
+    if format == Format::Cas && firmware == Firmware::FreHd {
+        error!("FreHD doesn't load tape images, only /CMD files imported onto its hard drives.");
+        process::exit(1);
+    }
+    format
+}
+
+// A name the DOS takes, up to 8 letters and digits starting with a letter:
+fn dos_name(name: &str) -> Option<String> {
+    let name: String = name.chars().filter(|character| character.is_ascii_alphanumeric()).take(8).collect::<String>().to_uppercase();
+    match name.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() => { Some(name) },
+        _ => { None },
+    }
+}
+
+pub const USAGE: &str = "-i <file> --card <directory>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to put onto the card, every entry becoming a program of its own.", "FILE");
+    options.optopt("", "card", "Where the SD card is mounted.", "DIRECTORY");
+    options.optopt("", "firmware", "The interface the card goes into, `trs-io' (the default) or `frehd'.", "NAME");
+    options.optopt("", "format", "Put the programs onto the card as `cmd' files (the default), or as `cas' tape images for TRS-IO.", "FORMAT");
+    options.optopt("", "name", "Name of the program on the card, up to 8 letters and digits (the name of the entry by default, or of the input file if the entry has none).", "NAME");
+    options.optflag("", "ignore-checksums", "Put entries onto the card even if some of their blocks have bad checksums.");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "deploy", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "deploy", &matches);
+
+    let card: path::PathBuf = match matches.opt_str("card") {
+        Some(argument) => { argument.into() },
+        None => {
+            error!("No card given, use `--card' with the directory the SD card is mounted at.");
+            process::exit(1);
+        },
+    };
+    if !card.is_dir() {
+        error!("`{}' is not a directory, is the card mounted?", card.display());
+        process::exit(1);
+    }
+    let firmware = retrieve_firmware(&matches);
+    let format = retrieve_format(&matches, firmware);
+
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &commands::retrieve_variant(&matches)) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+    if matches.opt_present("name") && entries.len() > 1 {
+        error!("`{}' has {} entries, `--name' only names one.", in_filepath.display(), entries.len());
+        process::exit(1);
+    }
+
+    let stem = in_filepath.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut used_names: Vec<String> = Vec::new();
+    let mut success = true;
+    for (index, entry) in entries.iter().enumerate() {
+        let name = match matches.opt_str("name") {
+            Some(argument) => {
+                match dos_name(&argument) {
+                    Some(name) => { name },
+                    None => {
+                        error!("Invalid name `{}', expected up to 8 letters and digits, starting with a letter.", argument);
+                        process::exit(1);
+                    },
+                }
+            },
+            None => {
+                match dos_name(&entry.name_string()).or_else(|| dos_name(&stem)) {
+                    Some(name) => { name },
+                    None => {
+                        error!("Entry {} has no name the DOS takes, give one with `--name'.", index + 1);
+                        process::exit(1);
+                    },
+                }
+            },
+        };
+        if used_names.contains(&name) {
+            error!("Several entries would be named `{}' on the card, split the tape and name them with `--name'.", name);
+            process::exit(1);
+        }
+        used_names.push(name.clone());
+
+        if entry.bad_checksum_count() != 0 && !matches.opt_present("ignore-checksums") {
+            error!("Entry `{}' has {} blocks with bad checksums, use `--ignore-checksums' to put it onto the card anyway.",
+                   entry.name_string(), entry.bad_checksum_count());
+            success = false;
+            continue;
+        }
+        let (program, extension) = match format {
+            Format::Cmd => {
+                if entry.blocks.is_empty() {
+                    error!("Entry `{}' contains no data blocks.", entry.name_string());
+                    success = false;
+                    continue;
+                }
+                let entry_point = match entry.entry_point {
+                    Some(entry_point) => { entry_point },
+                    None => {
+                        warning!("Entry `{}' has no entry point, the program returns to BASIC once loaded.", entry.name_string());
+                        tape::ROM_BASIC_READY
+                    },
+                };
+                (cmdfile::generate_cmd_file(entry, entry_point), "CMD")
+            },
+            Format::Cas => { (buffer[entry.start_offset..entry.end_offset].to_vec(), "CAS") },
+        };
+
+        let out_filepath = card.join(format!("{}.{}", name, extension));
+        status!("Entry {}: `{}' as `{}'", index + 1, entry.name_string(), out_filepath.display());
+        if !files::write_file(&program, &out_filepath) {
+            success = false;
+            continue;
+        }
+        if firmware == Firmware::FreHd {
+            status!("Import it onto the hard drive with `IMPORT2 {}.{} {}/{}'.", name, extension, name, extension);
+        }
+    }
+
+    if success {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
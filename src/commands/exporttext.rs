@@ -0,0 +1,85 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::process;
+
+use commands;
+use files;
+use tape;
+use tapetext;
+
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to describe as text.", "FILE");
+    options.optopt("o", "output", "Name of the text file to write (input filename with extension changed to .txt by default).", "FILE");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "export-text", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "export-text", &matches);
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(&in_filepath, "txt") },
+    };
+    if out_filepath == in_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    let variant = commands::retrieve_variant(&matches);
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    let title = format!("Tape image `{}', made into one again with `{} import-text'.", in_filepath.display(), progname);
+    let text = tapetext::generate_text(&buffer, &entries, &variant, &title);
+
+    // The text is only any good if it makes the same tape again:
+    match tapetext::parse_text(&text) {
+        Ok((ref rebuilt, ref _variant)) if *rebuilt == buffer => { },
+        _ => {
+            error!("The text of `{}' doesn't make the same tape image again, please report this as a bug.", in_filepath.display());
+            process::exit(1);
+        },
+    }
+
+    status!("{}: {} entries, {} bytes, described in {} lines.", in_filepath.display(), entries.len(), buffer.len(), text.lines().count());
+    if !files::write_file(text.as_bytes(), &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
@@ -0,0 +1,75 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::process;
+
+use commands;
+use files;
+use tape;
+use tapetext;
+
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The text describing the tape image, as written by `export-text'.", "FILE");
+    options.optopt("o", "output", "Name of the tape image to write (input filename with extension changed to .cas by default).", "FILE");
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "import-text", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "import-text", &matches);
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(&in_filepath, "cas") },
+    };
+    if out_filepath == in_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let (tape_image, variant) = match tapetext::parse_text(&String::from_utf8_lossy(&buffer)) {
+        Ok(parsed) => { parsed },
+        Err(error) => {
+            error!("{}, {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    // Hand edits may well have broken the structure, which is worth
+    // knowing about, but it's what was asked for:
+    if let Err(error) = tape::parse_tape(&tape_image, &variant) {
+        warning!("The tape image made of `{}' doesn't read back: {}, it's written anyway.", in_filepath.display(), error);
+    }
+    if !files::write_file(&tape_image, &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
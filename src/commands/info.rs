@@ -0,0 +1,118 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::process;
+
+use commands;
+use files;
+use tape;
+
+
+// Prints the details of an entry, the copy is its number and the number of
+// copies for entries recorded several times back to back:
+pub fn print_entry_summary(index: usize, entry: &tape::Entry, copy: Option<(usize, usize)>) {
+    let full_blocks = entry.blocks.iter().filter(|block| block.data.len() == tape::MAX_BLOCK_SIZE).count();
+    let short_blocks = entry.blocks.len() - full_blocks;
+
+    println!("Entry {}: `{}'", index + 1, entry.name_string());
+    println!("    Tape offsets:        {}-{}", entry.start_offset, entry.end_offset);
+    if let Some((number, count)) = copy {
+        println!("    Copy:                {} of {}", number, count);
+    }
+    println!("    Blocks:              {} ({} of 256 bytes, {} shorter)", entry.blocks.len(), full_blocks, short_blocks);
+    match entry.address_range() {
+        Some((start, end)) => {
+            println!("    Load range:          0x{:04X}-0x{:04X} ({} bytes)", start, end - 1, entry.data_length());
+        },
+        None => {
+            println!("    Load range:          (no data)");
+        },
+    }
+    match entry.entry_point {
+        Some(entry_point) => { println!("    Entry point address: 0x{:04X}", entry_point); },
+        None => { println!("    Entry point address: (none, no end of file record)"); },
+    }
+    for comment in &entry.comments {
+        println!("    Comment:             {}", comment);
+    }
+    match entry.bad_checksum_count() {
+        0 => { println!("    Checksums:           all OK"); },
+        bad => { println!("    Checksums:           {} bad", bad); },
+    }
+    if entry.skipped_bytes != 0 {
+        println!("    Skipped bytes:       {}", entry.skipped_bytes);
+    }
+}
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to inspect.", "FILE");
+    options.optflag("l", "blocks", "List the individual data blocks as well.");
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "info", USAGE, false, &mut options, args);
+    let in_filepath = commands::retrieve_input_path(progname, "info", &matches);
+
+    let variant = commands::retrieve_variant(&matches);
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    // Mark the entries which are copies of one another:
+    let mut copies: Vec<Option<(usize, usize)>> = vec![None; entries.len()];
+    for (first, count) in tape::group_copies(&entries) {
+        if count > 1 {
+            for number in 0..count {
+                copies[first + number] = Some((number + 1, count));
+            }
+        }
+    }
+
+    println!("{}: {} bytes, {} entries.", in_filepath.display(), buffer.len(), entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        println!();
+        print_entry_summary(index, entry, copies[index]);
+
+        if matches.opt_present("l") {
+            println!();
+            println!("    Block  Offset  Address  Length  Checksum");
+            for (block_index, block) in entry.blocks.iter().enumerate() {
+                println!("    {:5}  {:6}  0x{:04X}   {:6}  {} {}", block_index + 1, block.offset,
+                         block.load_address, block.data.len(), block.checksum.format(block.stored_checksum),
+                         if block.checksum_ok() { "OK" } else { "BAD" });
+            }
+        }
+    }
+}
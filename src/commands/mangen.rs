@@ -0,0 +1,226 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::process;
+
+use commands;
+use files;
+
+
+const PROGRAM: &str = "trs80m1-mltl";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const USAGE: &str = "";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+// An option as listed in a manual page:
+struct ManOption {
+    short_name: Option<String>,
+    long_name: String,
+    hint: Option<String>,
+    description: String,
+}
+
+// Splits off the first word of the text:
+fn next_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(' ') {
+        Some(end) => { (&text[..end], &text[end..]) },
+        None => { (text, "") },
+    }
+}
+
+// Getopts doesn't tell what options it knows about, but its usage messages
+// list them.  The short one tells which options take an argument, which is
+// needed to tell where the option ends and the description starts in the
+// long one:
+fn option_list(options: &getopts::Options) -> Vec<ManOption> {
+    let short_usage = options.short_usage("");
+    let mut takes_argument: Vec<bool> = Vec::new();
+    for word in short_usage.split_whitespace().skip(1) {
+        if word.starts_with("[-") || word.starts_with('-') {
+            takes_argument.push(false);
+        } else if let Some(last) = takes_argument.last_mut() {
+            *last = true;
+        }
+    }
+
+    let mut list: Vec<ManOption> = Vec::new();
+    for line in options.usage("").lines() {
+        let is_option = line.starts_with("    -") || line.starts_with("        --");
+        if !is_option {
+            // Continued descriptions are indented:
+            if line.starts_with("    ") {
+                if let Some(last) = list.last_mut() {
+                    if !last.description.is_empty() {
+                        last.description.push(' ');
+                    }
+                    last.description.push_str(line.trim());
+                }
+            }
+            continue;
+        }
+
+        let (mut word, mut rest) = next_word(line);
+        let short_name = if word.len() == 3 && word.ends_with(',') {
+            let short_name = word[..2].to_owned();
+            let next = next_word(rest);
+            word = next.0;
+            rest = next.1;
+            Some(short_name)
+        } else {
+            None
+        };
+        let long_name = word.to_owned();
+
+        let hint = if takes_argument.get(list.len()).cloned().unwrap_or(false) {
+            let next = next_word(rest);
+            rest = next.1;
+            Some(next.0.to_owned())
+        } else {
+            None
+        };
+
+        list.push(ManOption {
+            short_name,
+            long_name,
+            hint,
+            description: rest.trim().to_owned(),
+        });
+    }
+    list
+}
+
+fn escape(text: &str) -> String {
+    let escaped = text.replace('\\', "\\e").replace('-', "\\-");
+    if escaped.starts_with('.') || escaped.starts_with('\'') {
+        format!("\\&{}", escaped)
+    } else {
+        escaped
+    }
+}
+
+fn page_header(page_name: &str, summary: &str) -> String {
+    let mut page = String::new();
+    page.push_str(&format!(".TH {} 1 \"\" \"{} {}\" \"User Commands\"\n", page_name.to_uppercase(), PROGRAM, VERSION));
+    page.push_str(".SH NAME\n");
+    page.push_str(&format!("{} \\- {}\n", escape(page_name), escape(summary.trim_end_matches('.'))));
+    page
+}
+
+fn command_page(command: &commands::Command) -> String {
+    let mut options = (command.options)();
+    commands::add_common_options(&mut options);
+
+    let page_name = format!("{}-{}", PROGRAM, command.name);
+    let mut page = page_header(&page_name, command.summary);
+
+    // The first line of the usage is the synopsis, the rest explains it:
+    let mut usage_lines = command.usage.lines();
+    page.push_str(".SH SYNOPSIS\n");
+    page.push_str(&format!(".B {} {}\n", escape(PROGRAM), escape(command.name)));
+    page.push_str(&format!("[options] {}\n", escape(usage_lines.next().unwrap_or(""))));
+
+    page.push_str(".SH DESCRIPTION\n");
+    page.push_str(&format!("{}\n", escape(command.summary)));
+    for line in usage_lines {
+        if line.is_empty() {
+            page.push_str(".PP\n");
+        } else {
+            page.push_str(&format!("{}\n", escape(line)));
+        }
+    }
+
+    page.push_str(".SH OPTIONS\n");
+    for option in option_list(&options) {
+        page.push_str(".TP\n");
+        let mut names = String::new();
+        if let Some(ref short_name) = option.short_name {
+            names.push_str(&format!("\\fB{}\\fR, ", escape(short_name)));
+        }
+        names.push_str(&format!("\\fB{}\\fR", escape(&option.long_name)));
+        if let Some(ref hint) = option.hint {
+            names.push_str(&format!(" \\fI{}\\fR", escape(hint)));
+        }
+        page.push_str(&format!("{}\n{}\n", names, escape(&option.description)));
+    }
+
+    page.push_str(".SH SEE ALSO\n");
+    page.push_str(&format!(".BR {} (1)\n", escape(PROGRAM)));
+    page
+}
+
+fn main_page() -> String {
+    let mut page = page_header(PROGRAM, "TRS-80 Model I machine language tape linker");
+
+    page.push_str(".SH SYNOPSIS\n");
+    page.push_str(&format!(".B {}\n\\fIcommand\\fR [options]\n", escape(PROGRAM)));
+    page.push_str(".SH DESCRIPTION\n");
+    page.push_str("Packs raw binaries into CAS tape images which Level II machines can load with the \\fBSYSTEM\\fR command, and converts, inspects and takes apart such images.\n");
+    page.push_str(".SH COMMANDS\n");
+    for command in commands::COMMANDS {
+        page.push_str(&format!(".TP\n.B {}\n{}\n", escape(command.name), escape(command.summary)));
+    }
+    page.push_str(".SH FILES\n");
+    page.push_str(".TP\n.I ~/.config/trs80m1\\-mltl/config.toml\nUser-level option defaults.\n");
+    page.push_str(".TP\n.I .trs80m1\\-mltl.toml\nPer-project option defaults, looked up in the current directory and the ones above it.\n");
+    page.push_str(".SH ENVIRONMENT\n");
+    page.push_str(".TP\n.B TRS80_MLTL_*\nOption defaults, such as \\fBTRS80_MLTL_OUTPUT_DIR\\fR for \\fB\\-\\-output\\-dir\\fR.\n");
+    page.push_str(".TP\n.B NO_COLOR\nDisables colored diagnostics.\n");
+    page.push_str(".SH SEE ALSO\n");
+    let see_also: Vec<String> = commands::COMMANDS.iter().map(|command| {
+        format!(".BR {}\\-{} (1)", escape(PROGRAM), escape(command.name))
+    }).collect();
+    page.push_str(&see_also.join(" ,\n"));
+    page.push('\n');
+    page
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "mangen", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    let mut pages = vec![(PROGRAM.to_owned(), main_page())];
+    for command in commands::COMMANDS {
+        pages.push((format!("{}-{}", PROGRAM, command.name), command_page(command)));
+    }
+
+    let mut success = true;
+    for (page_name, page) in pages {
+        let out_filepath = files::default_output_path(page_name.as_ref(), "1");
+        if !files::write_file(page.as_bytes(), &out_filepath) {
+            success = false;
+        }
+    }
+
+    if success {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,968 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use audio;
+use config;
+use console;
+use files;
+use filters;
+use numbers;
+use sound;
+use tape;
+use template;
+
+pub mod pack;
+pub mod unpack;
+pub mod info;
+pub mod verify;
+pub mod simulate;
+pub mod wav;
+pub mod play;
+pub mod record;
+pub mod decode;
+pub mod calibrate;
+pub mod pulses;
+pub mod testtape;
+pub mod corpus;
+pub mod serve;
+pub mod deploy;
+pub mod serial;
+pub mod split;
+pub mod coco;
+pub mod dat;
+pub mod exporttext;
+pub mod importtext;
+pub mod mangen;
+pub mod selftest;
+
+
+pub struct Command {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub run: fn(progname: &str, args: &[String]),
+    pub options: fn() -> getopts::Options,
+    pub usage: &'static str,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command { name: "pack",   summary: "Pack a raw binary into a machine language tape image.",
+              run: pack::run, options: pack::options, usage: pack::USAGE },
+    Command { name: "unpack", summary: "Extract the memory images of the entries of a tape image.",
+              run: unpack::run, options: unpack::options, usage: unpack::USAGE },
+    Command { name: "info",   summary: "List the entries and blocks of a tape image.",
+              run: info::run, options: info::options, usage: info::USAGE },
+    Command { name: "verify", summary: "Check the structure and the checksums of a tape image.",
+              run: verify::run, options: verify::options, usage: verify::USAGE },
+    Command { name: "simulate", summary: "Load a tape image in a simulated Model I, and run the program a little.",
+              run: simulate::run, options: simulate::options, usage: simulate::USAGE },
+    Command { name: "wav",    summary: "Convert a tape image into a WAV recording.",
+              run: wav::run, options: wav::options, usage: wav::USAGE },
+    Command { name: "play",   summary: "Play a tape image through the sound card.",
+              run: play::run, options: play::options, usage: play::USAGE },
+    Command { name: "record", summary: "Capture a tape image from the sound card.",
+              run: record::run, options: record::options, usage: record::USAGE },
+    Command { name: "decode", summary: "Decode a recording of a tape into a tape image.",
+              run: decode::run, options: decode::options, usage: decode::USAGE },
+    Command { name: "calibrate", summary: "Make a test recording for setting up a deck, and analyze it.",
+              run: calibrate::run, options: calibrate::options, usage: calibrate::USAGE },
+    Command { name: "pulses", summary: "List the pulse timings of a tape, or record a list of them.",
+              run: pulses::run, options: pulses::options, usage: pulses::USAGE },
+    Command { name: "testtape", summary: "Make a self-checking tape of test patterns, or check one read back.",
+              run: testtape::run, options: testtape::options, usage: testtape::USAGE },
+    Command { name: "corpus", summary: "Make a set of tapes of edge cases, for testing the cassette support of emulators.",
+              run: corpus::run, options: corpus::options, usage: corpus::USAGE },
+    Command { name: "deploy", summary: "Put a tape image onto the SD card of a TRS-IO or a FreHD.",
+              run: deploy::run, options: deploy::options, usage: deploy::USAGE },
+    Command { name: "serial", summary: "Send a program to a machine with an RS-232-C interface, or make its receiver.",
+              run: serial::run, options: serial::options, usage: serial::USAGE },
+    Command { name: "serve",  summary: "Serve tape images over the network to emulators and hardware bridges.",
+              run: serve::run, options: serve::options, usage: serve::USAGE },
+    Command { name: "split",  summary: "Split a multi-entry tape image into one file per entry.",
+              run: split::run, options: split::options, usage: split::USAGE },
+    Command { name: "coco",   summary: "Convert between Model I and Color Computer tape images, which share the .cas extension.",
+              run: coco::run, options: coco::options, usage: coco::USAGE },
+    Command { name: "dat",    summary: "Describe a collection of tape images in a DAT file, for ROM managers to check sets with.",
+              run: dat::run, options: dat::options, usage: dat::USAGE },
+    Command { name: "export-text", summary: "Describe a tape image as text, for keeping it in version control.",
+              run: exporttext::run, options: exporttext::options, usage: exporttext::USAGE },
+    Command { name: "import-text", summary: "Make a tape image of its description as text.",
+              run: importtext::run, options: importtext::options, usage: importtext::USAGE },
+    Command { name: "mangen", summary: "Generate the manual pages of the program and its commands.",
+              run: mangen::run, options: mangen::options, usage: mangen::USAGE },
+    Command { name: "selftest", summary: "Check that this build packs and reads back tapes right, for packagers and new platforms.",
+              run: selftest::run, options: selftest::options, usage: selftest::USAGE },
+];
+
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+
+// Turns a configuration setting into the equivalent command-line arguments.
+// The error is None if the command has no such option at all:
+fn setting_arguments(options: &getopts::Options, setting: &config::Setting) -> Result<Vec<String>, Option<String>> {
+    let option = format!("--{}", setting.key);
+
+    let arguments = match setting.value {
+        config::Value::Str(ref string) => {
+            // Strings given to flags, as from environment variables, are
+            // read as booleans:
+            match options.parse(&[option.clone(), string.clone()]) {
+                Ok(ref matches) if !matches.free.is_empty() => {
+                    match string.to_lowercase().as_str() {
+                        "1" | "true" | "yes" | "on" => { vec![option] },
+                        "0" | "false" | "no" | "off" | "" => { vec![] },
+                        _ => {
+                            return Err(Some(format!("`{}' is a flag, its value should be true or false", setting.key)));
+                        },
+                    }
+                },
+                _ => { vec![option, string.clone()] },
+            }
+        },
+        config::Value::Boolean(true) => { vec![option] },
+        config::Value::Boolean(false) => { vec![] },
+        config::Value::Integer(number) => {
+            // Either the value of an option, or a count for a flag:
+            match options.parse(&[option.clone(), number.to_string()]) {
+                Ok(ref matches) if !matches.free.is_empty() => {
+                    vec![option; number.max(0) as usize]
+                },
+                _ => { vec![option, number.to_string()] },
+            }
+        },
+    };
+
+    // Check that the option exists and takes what it was given:
+    let check_arguments = if arguments.is_empty() { vec![format!("--{}", setting.key)] } else { arguments.clone() };
+    match options.parse(&check_arguments) {
+        Ok(ref matches) if !matches.free.is_empty() => {
+            Err(Some(format!("`{}' is a flag, its value should be true or false", setting.key)))
+        },
+        Ok(_matches) => { Ok(arguments) },
+        Err(getopts::Fail::ArgumentMissing(_name)) if arguments.is_empty() => { Ok(arguments) },
+        Err(getopts::Fail::UnrecognizedOption(_name)) => { Err(None) },
+        Err(error) => { Err(Some(error.to_string().trim_end_matches('.').to_owned())) },
+    }
+}
+
+// Options which can't be given together, as each of them sets the same
+// thing as the others of its group another way.  A default for one of them
+// is left out when another one of the group is given on the command line:
+const CONFLICTING_OPTIONS: &[&[&str]] = &[
+    &["quiet", "verbose"],
+    &["amplitude", "gain"],
+    &["input-gain", "normalize"],
+    &["page-aligned", "align-blocks"],
+    &["turbo", "slow"],
+    &["http", "raw"],
+];
+
+// The option, along with the ones it can't be given together with:
+fn option_group(key: &str) -> Vec<String> {
+    let mut keys = vec![key.to_owned()];
+    for group in CONFLICTING_OPTIONS.iter().filter(|group| group.contains(&key)) {
+        keys.extend(group.iter().filter(|other| **other != key).map(|other| other.to_string()));
+    }
+    keys
+}
+
+// Whether any command has the option, which tells settings meant for other
+// commands from ones which are misspelt or meant for no option at all:
+fn known_option(key: &str) -> bool {
+    COMMANDS.iter().any(|command| {
+        let mut options = (command.options)();
+        add_common_options(&mut options);
+        !matches!(options.parse(&[format!("--{}", key)]), Err(getopts::Fail::UnrecognizedOption(_name)))
+    })
+}
+
+// The arguments the settings add in front of the command line, for the
+// options it doesn't already give.  A setting is also left out when one
+// of an option it can't be used together with overrides it, as from an
+// environment variable over a configuration file:
+fn default_arguments(command: &str, options: &getopts::Options, settings: &[config::Setting],
+                     matches: &getopts::Matches) -> Vec<String> {
+    // Later settings override earlier ones, so go through them backwards:
+    let mut seen_keys: Vec<&str> = Vec::new();
+    let mut defaults: Vec<String> = Vec::new();
+    for setting in settings.iter().rev() {
+        let group = option_group(&setting.key);
+        if group.iter().any(|key| seen_keys.contains(&key.as_str())) {
+            continue;
+        }
+        seen_keys.push(&setting.key);
+
+        match setting_arguments(options, setting) {
+            Ok(arguments) => {
+                if !matches.opts_present(&group) {
+                    defaults.extend(arguments);
+                }
+            },
+
+            // Settings meant for all commands only apply to those which
+            // have the option:
+            Err(None) if !setting.command_specific => {
+                if !known_option(&setting.key) {
+                    warning!("{}: No command has a `--{}' option, the setting is ignored.", setting.origin, setting.key);
+                }
+            },
+            Err(None) => {
+                error!("{}: The `{}' command has no `--{}' option.", setting.origin, command, setting.key);
+                process::exit(1);
+            },
+            Err(Some(error)) => {
+                error!("{}: {}.", setting.origin, error);
+                process::exit(1);
+            },
+        }
+    }
+
+    defaults
+}
+
+// Fills in the options not given on the command line from the configuration
+// files:
+fn apply_config_defaults(command: &str, options: &getopts::Options,
+                         args: &[String], matches: getopts::Matches) -> getopts::Matches {
+    let settings = match config::load_settings(command, !matches.opt_present("no-config")) {
+        Ok(settings) => { settings },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+
+    let mut defaults = default_arguments(command, options, &settings, &matches);
+    if defaults.is_empty() {
+        return matches;
+    }
+
+    defaults.extend_from_slice(args);
+    match options.parse(&defaults) {
+        Ok(matches) => { matches },
+        Err(error) => {
+            error!("Error in the configuration file defaults: {}", error);
+            process::exit(1);
+        },
+    }
+}
+
+// Options shared by all commands:
+pub fn add_common_options(options: &mut getopts::Options) {
+    options.optflag("q", "quiet", "Only print errors and warnings.");
+    options.optflagmulti("v", "verbose", "Print per-block detail, repeat for even more.");
+    options.optopt("", "log-file", "Also record all messages, with timestamps and whatever the verbosity, at the end of the given file.", "FILE");
+    options.optflag("", "no-config", "Ignore the configuration files (environment variables still apply).");
+    options.optflag("h", "help", "Show this help listing.");
+}
+
+// Parses the options of a command, handling the help option and parsing
+// errors, which terminate the program.  Arguments other than options are
+// only accepted if `free_arguments' is set:
+pub fn parse_options(progname: &str, command: &str, usage_tail: &str, free_arguments: bool,
+                     options: &mut getopts::Options, args: &[String]) -> getopts::Matches {
+
+    add_common_options(options);
+
+    let matches = match options.parse(args) {
+        Ok(matches) => { matches },
+        Err(error) => {
+            error!("Argument parsing error: {}", error);
+            process::exit(1);
+        },
+    };
+
+    // Help should always be handled first:
+    if matches.opt_present("h") {
+        let brief = format!("Usage: {} {} [options] {}", progname, command, usage_tail);
+        println!("{}", options.usage(&brief));
+        process::exit(0);
+    }
+    let matches = apply_config_defaults(command, options, args, matches);
+
+    if let Some(name) = matches.opt_str("log-file") {
+        if let Err(error) = console::open_log_file(name.as_ref()) {
+            error!("{}.", error);
+            process::exit(1);
+        }
+    }
+
+    if matches.opt_present("q") && matches.opt_present("v") {
+        error!("The `--quiet' and `--verbose' options can't be used together.");
+        process::exit(1);
+    }
+    if matches.opt_present("q") {
+        console::set_verbosity(console::QUIET);
+    } else {
+        console::set_verbosity(console::NORMAL + matches.opt_count("v"));
+    }
+
+    if !free_arguments && !matches.free.is_empty() {
+        error!("Unexpected argument `{}', see `{} {} --help'.", matches.free[0], progname, command);
+        process::exit(1);
+    }
+
+    matches
+}
+
+// Options describing the audio produced, shared by the commands which make
+// recordings:
+pub fn add_audio_options(options: &mut getopts::Options) {
+    options.optopt("", "sample-rate", "Sample rate of the recording, 22050, 44100 (the default) or 48000 Hz.", "HZ");
+    options.optopt("", "bits", "Bits per sample, 8 or 16 (the default).", "BITS");
+    options.optopt("", "channels", "Number of channels, 1 for mono (the default) or 2 for stereo, with the signal in both.", "COUNT");
+    options.optflag("", "invert", "Record the signal the other way up, for decks and cassette interfaces which invert it.");
+    options.optflag("", "invert-right", "Record in stereo with the right channel phase-inverted, as some cassette input adapters and decks need.");
+    options.optopt("", "waveform", "Shape of the pulses: `square' (the default), `sine', or `trapezoid' with edges ramping over the rise time. Cassette inputs often load more reliably from the rounded ones.", "SHAPE");
+    options.optopt("", "rise-time", "Rise time of the edges of trapezoid pulses in microseconds (20 by default).", "US");
+    options.optopt("", "amplitude", "Peak level of the recording as a fraction of full scale, above 0 and up to 1 (0.73 by default).", "LEVEL");
+    options.optopt("", "gain", "Peak level of the recording in decibels relative to full scale, 0 or below (about -2.7 by default). The Model I cassette input is picky about levels, try lowering this if the loads fail.", "DB");
+}
+
+pub fn retrieve_sample_rate(matches: &getopts::Matches) -> u32 {
+    match matches.opt_str("sample-rate") {
+        Some(argument) => {
+            match argument.parse::<u32>() {
+                Ok(rate) if audio::SAMPLE_RATES.contains(&rate) => { rate },
+                _ => {
+                    let rates: Vec<String> = audio::SAMPLE_RATES.iter().map(|rate| rate.to_string()).collect();
+                    error!("Unsupported sample rate `{}', expected one of {}.", argument, rates.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { audio::SAMPLE_RATE },
+    }
+}
+
+// Prints the devices the player or the recorder can be told to use with
+// `--device', for `--list-audio-devices':
+pub fn list_audio_devices(program: &sound::Program, input: bool) -> ! {
+    let devices = match sound::list_devices(program, input) {
+        Ok(devices) => { devices },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+    if devices.is_empty() {
+        error!("No audio {} devices found for `{}'.", if input { "input" } else { "output" }, program.name);
+        process::exit(1);
+    }
+    status!("Audio {} devices of `{}', to be given with `--device':", if input { "input" } else { "output" }, program.name);
+    for device in &devices {
+        if device.description.is_empty() {
+            println!("{}", device.name);
+        } else {
+            println!("{}\n    {}", device.name, device.description);
+        }
+    }
+    process::exit(0);
+}
+
+pub fn retrieve_audio_format(matches: &getopts::Matches) -> audio::AudioFormat {
+    let mut format = audio::AudioFormat::standard();
+
+    format.sample_rate = retrieve_sample_rate(matches);
+    if let Some(argument) = matches.opt_str("bits") {
+        format.bits = match argument.as_str() {
+            "8" => { 8 },
+            "16" => { 16 },
+            _ => {
+                error!("Unsupported number of bits per sample `{}', expected 8 or 16.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    if let Some(argument) = matches.opt_str("channels") {
+        format.channels = match argument.as_str() {
+            "1" => { 1 },
+            "2" => { 2 },
+            _ => {
+                error!("Unsupported number of channels `{}', expected 1 or 2.", argument);
+                process::exit(1);
+            },
+        };
+    }
+
+    format.invert = matches.opt_present("invert");
+    if matches.opt_present("invert-right") {
+        if matches.opt_present("channels") && format.channels != 2 {
+            error!("The `--invert-right' option needs a stereo recording, conflicting with `--channels {}'.", format.channels);
+            process::exit(1);
+        }
+        format.channels = 2;
+        format.invert_right = true;
+    }
+
+    if let Some(argument) = matches.opt_str("waveform") {
+        format.shape = match audio::PULSE_SHAPES.iter().find(|&&(name, _shape)| name == argument.to_lowercase()) {
+            Some(&(_name, shape)) => { shape },
+            None => {
+                let names: Vec<&str> = audio::PULSE_SHAPES.iter().map(|&(name, _shape)| name).collect();
+                error!("Unknown waveform `{}', expected one of {}.", argument, names.join(", "));
+                process::exit(1);
+            },
+        };
+    }
+    if let Some(argument) = matches.opt_str("rise-time") {
+        if format.shape != audio::PulseShape::Trapezoid {
+            warning!("The `--rise-time' option only applies to `--waveform trapezoid'.");
+        }
+        format.rise_us = match argument.parse::<u32>() {
+            Ok(rise) if rise > 0 => { rise },
+            _ => {
+                error!("Invalid rise time `{}', expected a positive number of microseconds.", argument);
+                process::exit(1);
+            },
+        };
+    }
+
+    if matches.opt_present("amplitude") && matches.opt_present("gain") {
+        error!("The `--amplitude' and `--gain' options both set the level of the recording, use only one of them.");
+        process::exit(1);
+    }
+    if let Some(argument) = matches.opt_str("amplitude") {
+        format.amplitude = match argument.parse::<f64>() {
+            Ok(amplitude) if amplitude > 0.0 && amplitude <= 1.0 => { amplitude },
+            _ => {
+                error!("Invalid amplitude `{}', expected a number above 0 and up to 1.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    if let Some(argument) = matches.opt_str("gain") {
+        format.amplitude = match argument.parse::<f64>() {
+            Ok(gain) if gain <= 0.0 && gain.is_finite() => { 10.0f64.powf(gain / 20.0) },
+            _ => {
+                error!("Invalid gain `{}', expected a number of decibels of 0 or below, as the recording can't exceed full scale.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    // Quieter than this, the pulses drown in the quantization of 8-bit
+    // samples and in the noise of the playback chain:
+    if format.amplitude < 0.01 {
+        warning!("The recording is very quiet, at {:.1} dB below full scale, it may not load.", -20.0 * format.amplitude.log10());
+    }
+
+    format
+}
+
+// Options for cleaning up recordings before they're decoded:
+pub fn add_filter_options(options: &mut getopts::Options) {
+    options.optflag("", "remove-dc", "Remove the DC offset of the recording with a high-pass filter.");
+    options.optopt("", "dc-cutoff", "Cutoff frequency of the filter removing the DC offset in Hz (20 by default), implies `--remove-dc'.", "HZ");
+    options.optopt("", "band-pass", "Only let through the frequencies between the two given in Hz, such as `200-8000', cutting out hum and hiss.", "LOW-HIGH");
+    options.optflag("", "declick", "Remove clicks and pops, short bursts much louder than the signal around them.");
+    options.optopt("", "click-threshold", "How many times louder than the signal around a burst has to be to be taken for a click (2 by default), implies `--declick'.", "FACTOR");
+    options.optflag("", "normalize", "Bring the level of every recording to 3 dB below full scale, rather than only the ones more than 20 dB below it, as by default.");
+    options.optopt("", "input-gain", "Amplify the recording by the given number of dB instead, or attenuate it by a negative one, 0 leaves quiet recordings as they are.", "DB");
+}
+
+fn parse_positive(option: &str, argument: &str, unit: &str) -> f64 {
+    match argument.trim().parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => { number },
+        _ => {
+            error!("Invalid `--{}' value `{}', expected a positive number{}.", option, argument, unit);
+            process::exit(1);
+        },
+    }
+}
+
+pub fn retrieve_filters(matches: &getopts::Matches, sample_rate: u32) -> filters::Filters {
+    let mut filters = filters::Filters::none();
+
+    if let Some(argument) = matches.opt_str("dc-cutoff") {
+        filters.dc_cutoff = Some(parse_positive("dc-cutoff", &argument, " of Hz"));
+    } else if matches.opt_present("remove-dc") {
+        filters.dc_cutoff = Some(20.0);
+    }
+
+    if let Some(argument) = matches.opt_str("band-pass") {
+        let (low, high) = match argument.split_once('-') {
+            Some((low, high)) => { (parse_positive("band-pass", low, " of Hz"), parse_positive("band-pass", high, " of Hz")) },
+            None => {
+                error!("Invalid pass band `{}', expected the lower and the upper frequency in Hz, such as `200-8000'.", argument);
+                process::exit(1);
+            },
+        };
+        if low >= high || high >= (sample_rate as f64) / 2.0 {
+            error!("Invalid pass band `{}', the lower frequency has to be below the upper one, and that below {} Hz, half of the sample rate.", argument, sample_rate / 2);
+            process::exit(1);
+        }
+        filters.band_pass = Some((low, high));
+    }
+
+    if let Some(argument) = matches.opt_str("click-threshold") {
+        let factor = parse_positive("click-threshold", &argument, "");
+        if factor <= 1.0 {
+            error!("Invalid click threshold `{}', clicks have to be louder than the signal around them, expected a number above 1.", argument);
+            process::exit(1);
+        }
+        filters.click_factor = Some(factor);
+    } else if matches.opt_present("declick") {
+        filters.click_factor = Some(2.0);
+    }
+
+    filters.gain = match matches.opt_str("input-gain") {
+        Some(argument) => {
+            if matches.opt_present("normalize") {
+                error!("The `--input-gain' and `--normalize' options can't be used together.");
+                process::exit(1);
+            }
+            match argument.trim().trim_start_matches('+').parse::<f64>() {
+                Ok(decibels) if decibels.abs() <= 60.0 => { filters::Gain::Fixed(decibels) },
+                _ => {
+                    error!("Invalid `--input-gain' value `{}', expected a number of dB between -60 and 60.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None if matches.opt_present("normalize") => { filters::Gain::Normalize },
+        None => { filters::Gain::Auto },
+    };
+
+    filters
+}
+
+// Options of the decoder, for tapes played by decks too far off the right
+// speed for it to follow, and for telling it the baud rate and the polarity:
+pub fn add_decoder_options(options: &mut getopts::Options) {
+    options.optopt("", "speed", "Speed the deck plays the tape at, as a factor such as 1.04 or as a percentage such as `+4%' for one running 4% fast, for when it's too far off for the decoder to follow on its own.", "FACTOR");
+    options.optopt("", "baud", "Baud rate the tape is recorded at, 500 for Level II tapes, or 1500 for the high speed FSK tapes of the Model III and 4 (told apart from the recording by default).", "RATE");
+    options.optflag("", "invert", "Decode the recording the other way up, as sound cards and decks which invert the signal capture it (told from the recording by default).");
+}
+
+// How to decode the recordings, as given with the options above:
+pub fn retrieve_decoder_settings(matches: &getopts::Matches) -> decode::DecoderSettings {
+    decode::DecoderSettings {
+        speed: retrieve_speed(matches),
+        baud: retrieve_baud(matches),
+        invert: retrieve_invert(matches),
+    }
+}
+
+// Whether `--invert' is given, none if the polarity is to be detected:
+fn retrieve_invert(matches: &getopts::Matches) -> Option<bool> {
+    if matches.opt_present("invert") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+// The baud rate given with `--baud', none if it's to be detected:
+fn retrieve_baud(matches: &getopts::Matches) -> Option<audio::Baud> {
+    let argument = matches.opt_str("baud")?;
+    match audio::BAUD_RATES.iter().find(|&&(name, _baud)| name == argument.trim()) {
+        Some(&(_name, baud)) => { Some(baud) },
+        None => {
+            let names: Vec<&str> = audio::BAUD_RATES.iter().map(|&(name, _baud)| name).collect();
+            error!("Unknown baud rate `{}', expected one of {}.", argument, names.join(", "));
+            process::exit(1);
+        },
+    }
+}
+
+// The speed given with `--speed', as a factor, 1.0 if none is:
+fn retrieve_speed(matches: &getopts::Matches) -> f64 {
+    let argument = match matches.opt_str("speed") {
+        Some(argument) => { argument },
+        None => { return 1.0; },
+    };
+    let trimmed = argument.trim();
+    let speed = match trimmed.strip_suffix('%') {
+        Some(percentage) => { percentage.trim_start_matches('+').parse::<f64>().ok().map(|percentage| 1.0 + percentage / 100.0) },
+        None => { trimmed.parse::<f64>().ok() },
+    };
+    match speed {
+        Some(speed) if (0.75..=1.25).contains(&speed) => { speed },
+        _ => {
+            error!("Invalid speed `{}', expected a factor between 0.75 and 1.25, or a percentage between -25% and +25%.", argument);
+            process::exit(1);
+        },
+    }
+}
+
+// Option for checking that a tape fits onto a cassette, shared by the
+// commands which produce tapes:
+pub fn add_duration_options(options: &mut getopts::Options) {
+    options.optopt("", "max-duration", "Warn if the tape plays for longer than TIME, in seconds or as MINUTES:SECONDS (such as 5:00 for one side of a C-10 cassette).", "TIME");
+}
+
+// Cassettes tapes get mastered onto, with the playing time of a side in
+// seconds:
+const CASSETTES: &[(&str, u64)] = &[
+    ("C-10", 5 * 60),
+    ("C-20", 10 * 60),
+    ("C-30", 15 * 60),
+    ("C-60", 30 * 60),
+];
+
+fn parse_duration(argument: &str) -> Option<u64> {
+    let mut fields = argument.splitn(2, ':');
+    let first = fields.next()?.trim().parse::<u64>().ok()?;
+    match fields.next() {
+        Some(seconds) => {
+            let seconds = seconds.trim().parse::<u64>().ok()?;
+            if seconds < 60 { Some(first * 60 + seconds) } else { None }
+        },
+        None => { Some(first) },
+    }
+}
+
+// Reports the playing time of a tape, and warns if it's over the one given
+// with `--max-duration':
+pub fn report_tape_duration(matches: &getopts::Matches, microseconds: u64) {
+    let seconds = microseconds.div_ceil(1_000_000);
+    let fits = CASSETTES.iter().find(|&&(_name, side)| seconds <= side);
+    match fits {
+        Some(&(name, _side)) => {
+            status!("Tape duration: {}:{:02}, fits on one side of a {} cassette.", seconds / 60, seconds % 60, name);
+        },
+        None => {
+            status!("Tape duration: {}:{:02}.", seconds / 60, seconds % 60);
+        },
+    }
+
+    if let Some(argument) = matches.opt_str("max-duration") {
+        match parse_duration(&argument) {
+            Some(limit) => {
+                if seconds > limit {
+                    warning!("The tape plays for {}:{:02}, over the maximum of {}:{:02}.", seconds / 60, seconds % 60, limit / 60, limit % 60);
+                }
+            },
+            None => {
+                error!("Invalid duration `{}', expected seconds or MINUTES:SECONDS.", argument);
+                process::exit(1);
+            },
+        }
+    }
+}
+
+// Options shared by the commands which produce output files:
+pub fn add_output_options(options: &mut getopts::Options) {
+    options.optflag("f", "force", "Overwrite existing output files without asking.");
+    options.optflag("", "backup", "Keep replaced output files as backups, named after them with a timestamp (in UTC) and a .bak extension added.");
+    options.optopt("", "backup-keep", "Number of backups of each output file to keep with `--backup', the oldest ones are removed (all of them are kept by default).", "COUNT");
+    options.optopt("", "output-dir", "Directory to place output files with default names into (the current directory by default).", "DIR");
+}
+
+pub fn apply_output_options(matches: &getopts::Matches) {
+    files::set_allow_overwrite(matches.opt_present("f"));
+
+    let backups_kept = match matches.opt_str("backup-keep") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(count) if count > 0 => { count },
+                _ => {
+                    error!("Invalid backup count `{}', expected a positive number.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { 0 },
+    };
+    files::set_backups(matches.opt_present("backup"), backups_kept);
+
+    if let Some(name) = matches.opt_str("output-dir") {
+        let directory = (name.as_ref() as &path::Path).to_owned();
+        if !directory.is_dir() {
+            error!("The specified output directory `{}' is not a directory.", directory.display());
+            process::exit(1);
+        }
+        files::set_output_directory(directory);
+    }
+}
+
+// Options overriding the marker bytes and the checksums of the tape format,
+// for tapes meant for non-standard or copy-protected loaders, shared by the
+// commands which read or write tape images:
+pub fn add_variant_options(options: &mut getopts::Options) {
+    options.optopt("", "leader-pattern", "Bytes the leader is made of (in hex, comma separated, repeated for the length of the leader), 00 by default.", "BYTES");
+    options.optopt("", "sync-byte", "Value of the sync byte following the leader (0xA5 by default).", "BYTE");
+    options.optopt("", "header-byte", "Value of the SYSTEM format header byte following the sync byte (0x55 by default).", "BYTE");
+    options.optopt("", "data-marker", "Value of the marker in front of each data block (0x3C by default).", "BYTE");
+    options.optopt("", "eof-marker", "Value of the end of file marker in front of the entry point (0x78 by default).", "BYTE");
+    options.optopt("", "checksum", "How the blocks are checksummed: `sum' of the load address and the data (the default, as the ROM does), its two's complement `negated-sum', `xor', `crc8', or a two byte `crc16'.", "SCHEME");
+}
+
+// Retrieves an option holding a byte value, in the notations of addresses:
+pub fn retrieve_byte_option(matches: &getopts::Matches, option: &str, default: u8) -> u8 {
+    match matches.opt_str(option) {
+        Some(argument) => {
+            match numbers::parse_number(&argument, 16) {
+                Ok(value) if value <= 0xFF => { value as u8 },
+                Ok(_value) => {
+                    error!("Invalid `--{}' value `{}', it has to fit into a byte.", option, argument);
+                    process::exit(1);
+                },
+                Err(error) => {
+                    error!("Invalid `--{}' value `{}': {}.", option, argument, error);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { default },
+    }
+}
+
+// Whether the bits of the sync byte show up anywhere in the repeated leader
+// pattern, off the byte boundaries included:
+fn leader_hides_sync(leader: &[u8], sync: u8) -> bool {
+    let mut shift_register: u8 = 0;
+    for byte in leader.iter().cycle().take(leader.len() * 2 + 1) {
+        for bit in (0..8).rev() {
+            shift_register = (shift_register << 1) | ((byte >> bit) & 1);
+            if shift_register == sync {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn retrieve_variant(matches: &getopts::Matches) -> tape::Variant {
+    let standard = tape::Variant::standard();
+    let leader = match matches.opt_str("leader-pattern") {
+        Some(argument) => {
+            let mut pattern = Vec::new();
+            for value in argument.split(',') {
+                match numbers::parse_number(value.trim(), 16) {
+                    Ok(byte) if byte <= 0xFF => { pattern.push(byte as u8); },
+                    _ => {
+                        error!("Invalid leader pattern `{}', expected bytes in hex separated by commas.", argument);
+                        process::exit(1);
+                    },
+                }
+            }
+            pattern
+        },
+        None => { standard.leader.clone() },
+    };
+    let variant = tape::Variant {
+        leader,
+        sync:   retrieve_byte_option(matches, "sync-byte", standard.sync),
+        header: retrieve_byte_option(matches, "header-byte", standard.header),
+        data:   retrieve_byte_option(matches, "data-marker", standard.data),
+        eof:    retrieve_byte_option(matches, "eof-marker", standard.eof),
+        checksum: match matches.opt_str("checksum") {
+            Some(argument) => {
+                match tape::CHECKSUMS.iter().find(|&&(name, _checksum)| name == argument.to_lowercase()) {
+                    Some(&(_name, checksum)) => { checksum },
+                    None => {
+                        let names: Vec<&str> = tape::CHECKSUMS.iter().map(|&(name, _checksum)| name).collect();
+                        error!("Unknown checksum scheme `{}', expected one of {}.", argument, names.join(", "));
+                        process::exit(1);
+                    },
+                }
+            },
+            None => { standard.checksum },
+        },
+    };
+
+    // The leader has to be told apart from what follows it, also by the
+    // bit by bit search for the sync byte in recordings, and the blocks
+    // from the end of the entry:
+    if variant.is_leader_byte(variant.sync) {
+        error!("The sync byte 0x{:02X} can't be a part of the leader, it couldn't be told apart from it.", variant.sync);
+        process::exit(1);
+    }
+    if variant.leader.iter().any(|byte| *byte == variant.header || *byte == variant.data || *byte == variant.eof) {
+        error!("The leader pattern can't contain the header byte or the block markers.");
+        process::exit(1);
+    }
+    if leader_hides_sync(&variant.leader, variant.sync) {
+        error!("The leader pattern holds the bits of the sync byte 0x{:02X}, recordings would lose the bit sync in it.", variant.sync);
+        process::exit(1);
+    }
+    if variant.data == variant.eof {
+        error!("The data block marker and the end of file marker have to differ.");
+        process::exit(1);
+    }
+
+    variant
+}
+
+// Length of a pause, as given on the command line:
+pub enum GapLength {
+    Bytes(usize),
+    Milliseconds(u32),
+}
+
+// Retrieves a pause length option, a number of bytes, or of milliseconds
+// if followed by `ms':
+pub fn retrieve_gap_length(matches: &getopts::Matches, option: &str) -> Option<GapLength> {
+    let argument = matches.opt_str(option)?;
+
+    let gap = match argument.strip_suffix("ms") {
+        Some(digits) => { digits.trim().parse::<u32>().ok().map(GapLength::Milliseconds) },
+        None => { argument.trim().parse::<usize>().ok().map(GapLength::Bytes) },
+    };
+    match gap {
+        Some(gap) => { Some(gap) },
+        None => {
+            error!("Invalid `--{}' length `{}', expected a number of bytes, or of milliseconds followed by `ms'.", option, argument);
+            process::exit(1);
+        },
+    }
+}
+
+// Works out the path given with `--output', which may be a template:
+pub fn expand_output_path(argument: &str, values: &[(&str, template::Value)]) -> path::PathBuf {
+    if !template::is_template(argument) {
+        return (argument.as_ref() as &path::Path).to_owned();
+    }
+    match template::expand(argument, values) {
+        Ok(expanded) => { (expanded.as_ref() as &path::Path).to_owned() },
+        Err(error) => {
+            error!("Invalid output filename template `{}': {}.", argument, error);
+            process::exit(1);
+        },
+    }
+}
+
+// Template values describing a tape entry and the file it comes from:
+pub fn entry_template_values(in_path: &path::Path, name: &[u8], base_address: u16,
+                             entry_point: u16, size: usize) -> Vec<(&'static str, template::Value)> {
+    let stem = match in_path.file_stem() {
+        Some(stem) => { stem.to_string_lossy().into_owned() },
+        None => { String::new() },
+    };
+
+    vec![("name",  template::Value::Str(String::from_utf8_lossy(name).trim_end().to_owned())),
+         ("stem",  template::Value::Str(stem)),
+         ("base",  template::Value::Number(base_address as u32)),
+         ("start", template::Value::Number(entry_point as u32)),
+         ("end",   template::Value::Number((base_address as u32) + (size as u32))),
+         ("size",  template::Value::Number(size as u32))]
+}
+
+// Retrieves the mandatory input file option shared by most commands:
+pub fn retrieve_input_path(progname: &str, command: &str, matches: &getopts::Matches) -> path::PathBuf {
+    match matches.opt_str("i") {
+        Some(name) => {
+            let new_path = (name.as_ref() as &path::Path).to_owned();
+            if !new_path.is_file() {
+                error!("The specified input file `{}' is not a file.", new_path.display());
+                process::exit(1);
+            }
+            new_path
+        },
+        None => {
+            error!("Input file not specified, please provide it with the `--input' command-line option.");
+            eprintln!();
+            eprintln!("Some mandatory command-line options are missing, see `{} {} --help'.", progname, command);
+            process::exit(1);
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_options(command: &str) -> getopts::Options {
+        let mut options = (find_command(command).unwrap().options)();
+        add_common_options(&mut options);
+        options
+    }
+
+    fn setting(key: &str, value: config::Value) -> config::Setting {
+        config::Setting { key: key.to_owned(), value, origin: "test.toml".to_owned(), command_specific: false }
+    }
+
+    // The defaults the setting adds to the command line given:
+    fn defaults_with(command: &str, setting: config::Setting, args: &[&str]) -> Vec<String> {
+        let options = command_options(command);
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let matches = options.parse(&args).unwrap();
+        default_arguments(command, &options, &[setting], &matches)
+    }
+
+    #[test]
+    fn defaults_fill_in_missing_options() {
+        assert_eq!(defaults_with("pack", setting("quiet", config::Value::Boolean(true)), &[]), vec!["--quiet"]);
+        assert_eq!(defaults_with("pack", setting("leader-length", config::Value::Integer(512)), &["-i", "game.bin"]),
+                   vec!["--leader-length", "512"]);
+        assert!(defaults_with("pack", setting("leader-length", config::Value::Integer(512)), &["--leader-length", "64"]).is_empty());
+    }
+
+    #[test]
+    fn verbose_overrides_default_quiet() {
+        assert!(defaults_with("pack", setting("quiet", config::Value::Boolean(true)), &["-v"]).is_empty());
+        assert!(defaults_with("pack", setting("verbose", config::Value::Integer(2)), &["--quiet"]).is_empty());
+    }
+
+    #[test]
+    fn gain_overrides_default_amplitude() {
+        assert!(defaults_with("wav", setting("amplitude", config::Value::Str("0.5".to_owned())), &["--gain", "-6"]).is_empty());
+        assert!(defaults_with("wav", setting("gain", config::Value::Str("-6".to_owned())), &["--amplitude", "0.5"]).is_empty());
+    }
+
+    #[test]
+    fn align_blocks_overrides_default_page_aligned() {
+        assert!(defaults_with("pack", setting("page-aligned", config::Value::Boolean(true)), &["--align-blocks", "80"]).is_empty());
+        assert!(defaults_with("pack", setting("align-blocks", config::Value::Str("80".to_owned())), &["--page-aligned"]).is_empty());
+    }
+
+    #[test]
+    fn normalize_overrides_default_input_gain() {
+        assert!(defaults_with("decode", setting("input-gain", config::Value::Str("6".to_owned())), &["--normalize"]).is_empty());
+        assert!(defaults_with("decode", setting("normalize", config::Value::Boolean(true)), &["--input-gain", "6"]).is_empty());
+    }
+
+    #[test]
+    fn slow_overrides_default_turbo() {
+        assert!(defaults_with("wav", setting("turbo", config::Value::Boolean(true)), &["--slow"]).is_empty());
+        assert!(defaults_with("wav", setting("slow", config::Value::Boolean(true)), &["--turbo"]).is_empty());
+    }
+
+    #[test]
+    fn later_settings_override_conflicting_ones() {
+        let options = command_options("pack");
+        let matches = options.parse(&[] as &[String]).unwrap();
+        let settings = vec![setting("quiet", config::Value::Boolean(true)), setting("verbose", config::Value::Str("1".to_owned()))];
+        assert_eq!(default_arguments("pack", &options, &settings, &matches), vec!["--verbose"]);
+        let settings = vec![setting("page-aligned", config::Value::Boolean(true)), setting("align-blocks", config::Value::Str("80".to_owned())),
+                            setting("leader-length", config::Value::Integer(64))];
+        assert_eq!(default_arguments("pack", &options, &settings, &matches), vec!["--leader-length", "64", "--align-blocks", "80"]);
+    }
+
+    #[test]
+    fn known_options() {
+        assert!(known_option("output-dir"));
+        assert!(known_option("quiet"));
+        assert!(known_option("amplitude"));
+        assert!(!known_option("no-such-option"));
+        assert_eq!(config::environment_key("OUTPUT_DIR"), "output-dir");
+        assert!(known_option(&config::environment_key("MACHINE")));
+    }
+
+    #[test]
+    fn raw_overrides_default_http() {
+        assert!(defaults_with("serve", setting("http", config::Value::Boolean(true)), &["--raw"]).is_empty());
+        assert!(defaults_with("serve", setting("raw", config::Value::Boolean(true)), &["--http"]).is_empty());
+    }
+}
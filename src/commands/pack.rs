@@ -0,0 +1,1875 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::env;
+use std::path;
+use std::process;
+use std::slice;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use assemblers;
+use audio;
+use commands;
+use commands::simulate;
+use config;
+use console;
+use emulators;
+use files;
+use json;
+use memory;
+use numbers;
+use packing;
+use progress;
+use simulator;
+use stubs;
+use symbols;
+use tape;
+use template;
+use watch;
+
+
+// An entry to be recorded onto the tape, as given on the command line,
+// either through `--input' and friends, or as a `FILE:NAME:BASE:START'
+// argument:
+struct EntrySpec {
+    in_path: path::PathBuf,
+    name: Option<String>,
+    base: Option<String>,
+    start: Option<String>,
+
+    // Further files loaded as a part of the entry, with the expressions
+    // giving their addresses:
+    segments: Vec<(path::PathBuf, String)>,
+
+    // `ADDR=VALUE[,VALUE...]' patches of the loaded data:
+    pokes: Vec<String>,
+
+    // `BLOCK=ADDR' overrides of where blocks load into:
+    block_addresses: Vec<String>,
+}
+
+// What the gaps between the segments of an entry are filled with, instead
+// of being left out of the tape.  Only gaps of up to `max_length' bytes get
+// filled, if it's given:
+struct GapFill {
+    pattern: Vec<u8>,
+    max_length: Option<usize>,
+}
+
+// Settings shared by all the entries:
+struct PackSettings {
+    bare_radix: u32,
+    no_autostart: bool,
+    end_record: bool,
+    allow_external_entry: bool,
+    allow_wrap: bool,
+    name_charset: NameCharset,
+    keep_case: bool,
+    name_check: NameCheck,
+    ram_end: Option<u32>,
+    overlap_levels: Vec<OverlapLevel>,
+    relocate_via: Option<String>,
+    gap_fill: Option<GapFill>,
+    comments: Vec<String>,
+    verify_block: bool,
+    verifier: bool,
+    assembler: Option<String>,
+    assembler_args: Vec<String>,
+}
+
+
+// Evaluates an address argument, `what' describes it in error messages:
+fn retrieve_address(argument: &str, what: &str, bare_radix: u32,
+                    variables: &[(&str, u32)]) -> Option<u16> {
+    match numbers::evaluate_expression(argument, bare_radix, variables) {
+        Ok(address) => {
+            if address > 0xFFFF {
+                error!("The specified {} 0x{:04X} doesn't fit into the Z80's address space.", what, address);
+                None
+            } else {
+                Some(address as u16)
+            }
+        }
+        Err(error) => {
+            error!("Failed to parse the {} argument `{}': {}.", what, argument, error);
+            None
+        },
+    }
+}
+
+// Characters allowed in the tape entry name, in addition to the space:
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum NameCharset {
+    Letters,
+    Alphanumeric,
+    Extended,
+    // Any printable ASCII, for emulators and modified ROMs:
+    Printable,
+}
+
+// Punctuation allowed with `--name-charset extended'.  The slash is left out
+// since the SYSTEM prompt takes it as the command to start execution:
+const EXTENDED_NAME_PUNCTUATION: &str = "!#$%&+-.?@";
+
+fn retrieve_name_charset(matches: &getopts::Matches) -> Option<NameCharset> {
+    match matches.opt_str("name-charset") {
+        Some(argument) => {
+            match argument.to_lowercase().as_str() {
+                "letters"  => { Some(NameCharset::Letters) },
+                "alnum"    => { Some(NameCharset::Alphanumeric) },
+                "extended" => { Some(NameCharset::Extended) },
+                "printable" => { Some(NameCharset::Printable) },
+                _ => {
+                    error!("Unknown name character set `{}', expected `letters', `alnum', `extended' or `printable'.", argument);
+                    None
+                },
+            }
+        },
+        None => { Some(NameCharset::Letters) },
+    }
+}
+
+// How to treat characters in `--name' which can't be used in the name:
+#[derive(Clone, Copy, PartialEq)]
+enum NameCheck {
+    Filter,
+    Warn,
+    Strict,
+}
+
+fn retrieve_name_check(matches: &getopts::Matches) -> Option<NameCheck> {
+    match matches.opt_str("name-check") {
+        Some(argument) => {
+            match argument.to_lowercase().as_str() {
+                "filter" => { Some(NameCheck::Filter) },
+                "warn"   => { Some(NameCheck::Warn) },
+                "strict" => { Some(NameCheck::Strict) },
+                _ => {
+                    error!("Unknown name check mode `{}', expected `filter', `warn' or `strict'.", argument);
+                    None
+                },
+            }
+        },
+        None => { Some(NameCheck::Filter) },
+    }
+}
+
+// What to say about entries loading into the areas in `memory::REGIONS',
+// kept in the same order:
+#[derive(Clone, Copy, PartialEq)]
+enum OverlapLevel {
+    Ignore,
+    Note,
+    Warn,
+}
+
+fn retrieve_overlap_levels(matches: &getopts::Matches) -> Option<Vec<OverlapLevel>> {
+    let mut levels: Vec<OverlapLevel> = memory::REGIONS.iter().map(|region| {
+        if region.warn { OverlapLevel::Warn } else { OverlapLevel::Note }
+    }).collect();
+
+    for &(option, level) in &[("note-overlap", OverlapLevel::Note), ("allow-overlap", OverlapLevel::Ignore)] {
+        for argument in matches.opt_strs(option) {
+            for name in argument.split(',').map(|name| name.trim().to_lowercase()) {
+                if name == "all" {
+                    levels.iter_mut().for_each(|current| *current = level);
+                    continue;
+                }
+                match memory::REGIONS.iter().position(|region| region.name == name) {
+                    Some(index) => { levels[index] = level; },
+                    None => {
+                        let names: Vec<&str> = memory::REGIONS.iter().map(|region| region.name).collect();
+                        error!("Unknown memory area `{}' given to `--{}', expected one of {} or `all'.", name, option, names.join(", "));
+                        return None;
+                    },
+                }
+            }
+        }
+    }
+    Some(levels)
+}
+
+fn retrieve_sparse_fill(matches: &getopts::Matches) -> packing::SparseFill {
+    let byte = commands::retrieve_byte_option(matches, "sparse-fill", 0x00);
+    let min_run = match matches.opt_str("sparse-min-run") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(length) if length > 0 => { length },
+                _ => {
+                    error!("Invalid run length `{}', expected a positive number of bytes.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { 64 },
+    };
+
+    // Nothing clears the skipped memory when the tape is loaded, not even
+    // to zeros, whatever was in it before stays there:
+    warning!("The memory left out of the tape has to hold 0x{:02X} already when the tape is loaded.", byte);
+    packing::SparseFill { byte, min_run }
+}
+
+fn retrieve_gap_fill(matches: &getopts::Matches) -> Option<GapFill> {
+    if !matches.opt_present("gap-fill") && !matches.opt_present("gap-fill-max") {
+        return None;
+    }
+    if !matches.opt_present("segment") {
+        error!("The `--gap-fill' and `--gap-fill-max' options only apply to entries with `--segment'.");
+        process::exit(1);
+    }
+
+    let argument = matches.opt_str("gap-fill").unwrap_or_else(|| "00".to_owned());
+    let mut pattern = Vec::new();
+    for value in argument.split(',') {
+        match numbers::parse_number(value.trim(), 16) {
+            Ok(byte) if byte <= 0xFF => { pattern.push(byte as u8); },
+            _ => {
+                error!("Invalid gap fill pattern `{}', expected bytes in hex separated by commas.", argument);
+                process::exit(1);
+            },
+        }
+    }
+    let max_length = match matches.opt_str("gap-fill-max") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(length) => { Some(length) },
+                Err(_error) => {
+                    error!("Invalid gap length `{}', expected a number of bytes.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { None },
+    };
+
+    Some(GapFill { pattern, max_length })
+}
+
+struct EntryName {
+    name: Vec<u8>,
+    has_letters: bool,
+
+    // Characters which were stripped, and whether the name was cut short:
+    dropped: String,
+    truncated: bool,
+}
+
+// The names are uppercase only, as the ROM would have them, unless asked to
+// keep the case:
+fn retrieve_tape_entry_name(template: &str, charset: NameCharset, keep_case: bool) -> EntryName {
+
+    let mut entry_name = vec![0x20; 6];
+    let mut name_iter: usize = 0;
+    let mut has_first_char = false;
+    let mut dropped = String::new();
+    let mut truncated = false;
+
+    for character in template.chars() {
+        // Rust strings are Unicode, but here, we need ASCII, and only
+        // letters and the space, plus digits and some punctuation if the
+        // character set allows them.
+        //
+        // Thankfully, ASCII is a subset of Unicode, and we can simply ignore
+        // anything which doesn't fit our criteria.
+        //
+        let char_val = character as u32;
+
+        let (new_byte, add_char) = if (char_val == 0x20) && has_first_char {
+            (0x20, true)
+        } else if (0x41..=0x5A).contains(&char_val) {
+            has_first_char = true;
+            (char_val as u8, true)
+        } else if (0x61..=0x7A).contains(&char_val) {
+            has_first_char = true;
+            if keep_case {
+                (char_val as u8, true)
+            } else {
+                ((char_val - 0x20) as u8, true)
+            }
+        } else if ((0x30..=0x39).contains(&char_val) && charset >= NameCharset::Alphanumeric) ||
+                  (EXTENDED_NAME_PUNCTUATION.contains(character) && charset >= NameCharset::Extended) ||
+                  ((0x21..=0x7E).contains(&char_val) && charset >= NameCharset::Printable) {
+            has_first_char = true;
+            (char_val as u8, true)
+        } else {
+            (0, false)
+        };
+
+        if add_char {
+            if name_iter == 6 {
+                if new_byte != 0x20 {
+                    truncated = true;
+                }
+            } else {
+                entry_name[name_iter] = new_byte;
+                name_iter += 1;
+            }
+        } else if char_val != 0x20 {
+            dropped.push(character);
+        }
+    }
+
+    assert!(entry_name.len() == 6);
+    EntryName {
+        name: entry_name,
+        has_letters: has_first_char,
+        dropped,
+        truncated,
+    }
+}
+
+// Reports on the changes made to a name given with `--name', the return
+// value tells whether packing may go on:
+fn check_tape_entry_name(requested: &str, entry_name: &EntryName, check: NameCheck) -> bool {
+    if check == NameCheck::Filter || (entry_name.dropped.is_empty() && !entry_name.truncated) {
+        return true;
+    }
+    let severity = if check == NameCheck::Strict { console::Severity::Error } else { console::Severity::Warning };
+
+    if !entry_name.dropped.is_empty() {
+        console::report(severity, format_args!("The name `{}' contains characters which can't be used on the tape: `{}'.", requested, entry_name.dropped));
+    }
+    if entry_name.truncated {
+        console::report(severity, format_args!("The name `{}' is longer than 6 characters, and would be cut short.", requested));
+    }
+    check != NameCheck::Strict
+}
+
+// Refuses files which are most likely not what the user wanted to pack:
+fn check_input_file(new_path: &path::Path) -> bool {
+    if !new_path.is_file() {
+        error!("The specified input file `{}' is not a file.", new_path.display());
+        return false;
+    }
+    if let Some(extension_os) = new_path.extension() {
+        let extension = extension_os.to_string_lossy().into_owned();
+        let cmp_ext = extension.to_uppercase();
+
+        // Assembly sources are assembled, but there's no compiling
+        // anything else:
+        if cmp_ext == "C"   ||
+           cmp_ext == "CXX" || cmp_ext == "CPP" ||
+           cmp_ext == "C++" || cmp_ext == "F"   ||
+           cmp_ext == "F66" || cmp_ext == "F77" ||
+           cmp_ext == "F90" || cmp_ext == "F95" {
+
+            error!("The specified input file `{}' has a `{}' extension, and is most likely a source code file.  Rename it if you _really_ want to pack it into a machine language cassette image.", new_path.display(), extension);
+            return false;
+        }
+        if cmp_ext == "CAS" || cmp_ext == "CPT" {
+            error!("The specified input file `{}' has a `{}' extension, and is most likely already a cassette image.  Rename it if you _really_ want to pack it into a machine language cassette image.", new_path.display(), extension);
+            return false;
+        }
+        if cmp_ext == "LST" || cmp_ext == "TXT" ||
+           cmp_ext == "INI" || cmp_ext == "CONF" {
+            error!("The specified input file `{}' has a `{}' extension, and is most likely a plain text file.  Rename it if you _really_ want to pack it into a machine language cassette image.", new_path.display(), extension);
+            return false;
+        }
+        if !cmp_ext.is_empty() && cmp_ext != "BIN" && cmp_ext != "ROM" &&
+           !files::SOURCE_EXTENSIONS.contains(&extension.to_lowercase().as_str()) {
+            warning!("The specified input file `{}' has a `{}' extension (`rom' or `bin' expected).", new_path.display(), extension);
+        }
+    }
+
+    true
+}
+
+// Splits up a `FILE[:NAME[:BASE[:START]]]' argument, empty fields are the
+// same as left out ones:
+fn parse_entry_spec(argument: &str) -> EntrySpec {
+    let mut fields = argument.splitn(4, ':');
+    let mut next_field = || {
+        match fields.next() {
+            Some(field) if !field.is_empty() => { Some(field.to_owned()) },
+            _ => { None },
+        }
+    };
+
+    let in_path = (next_field().unwrap_or_default().as_ref() as &path::Path).to_owned();
+    let name  = next_field();
+    let base  = next_field();
+    let start = next_field();
+
+    EntrySpec {
+        in_path,
+        name,
+        base,
+        start,
+        segments: Vec::new(),
+        pokes: Vec::new(),
+        block_addresses: Vec::new(),
+    }
+}
+
+// Splits up a `FILE@ADDR' segment argument:
+fn parse_segment_spec(argument: &str) -> Option<(path::PathBuf, String)> {
+    match argument.rfind('@') {
+        Some(position) if position > 0 && position + 1 < argument.len() => {
+            Some(((argument[..position].as_ref() as &path::Path).to_owned(), argument[position + 1..].to_owned()))
+        },
+        _ => {
+            error!("Invalid segment `{}', expected `FILE@ADDR'.", argument);
+            None
+        },
+    }
+}
+
+// Checks where the data of an entry would load into, the end is exclusive:
+fn check_load_range(in_path: &path::Path, start: u32, end: u32, settings: &PackSettings) -> bool {
+    // The ROM only checks that the data fits into the address space:
+    if let Some(ram_end) = settings.ram_end {
+        if end > ram_end {
+            error!("`{}' would load up to 0x{:04X}, past the end of RAM at 0x{:04X} of a {}K machine.",
+                   in_path.display(), end - 1, ram_end - 1, (ram_end - memory::RAM_START) / 1024);
+            return false;
+        }
+    }
+
+    // Loading over the ROM, the devices or the system's variables is the
+    // usual cause of tapes which mysteriously fail to load:
+    for (region, level) in memory::REGIONS.iter().zip(&settings.overlap_levels) {
+        if !region.overlaps(start, end) {
+            continue;
+        }
+        let overlap_start = region.start.max(start);
+        let overlap_end = region.end.min(end) - 1;
+
+        match *level {
+            OverlapLevel::Warn => {
+                warning!("`{}' loads into {} at 0x{:04X}-0x{:04X}, use `--allow-overlap {}' if that's intended.",
+                         in_path.display(), region.description, overlap_start, overlap_end, region.name);
+            },
+            OverlapLevel::Note => {
+                note!("`{}' loads into {} at 0x{:04X}-0x{:04X}.", in_path.display(), region.description, overlap_start, overlap_end);
+            },
+            OverlapLevel::Ignore => { },
+        }
+    }
+    true
+}
+
+// Warns about data loading over what the ROM's loader uses while reading the
+// tape, the classic cause of loads hanging at the last block.  The end is
+// exclusive:
+fn check_loader_hazards(in_path: &path::Path, start: u32, end: u32, settings: &PackSettings) {
+    let mut hazards = Vec::new();
+    for &(area_start, area_end, description) in memory::LOADER_AREAS {
+        if start < area_end && area_start < end {
+            hazards.push((area_start, area_end, description.to_owned()));
+        }
+    }
+    if let Some(ram_end) = settings.ram_end {
+        let stack_start = ram_end - memory::LOADER_STACK_SPAN;
+        if end > stack_start && start < ram_end {
+            hazards.push((stack_start, ram_end, "the stack the ROM uses while loading".to_owned()));
+        }
+    }
+    if hazards.is_empty() {
+        return;
+    }
+
+    for (area_start, area_end, description) in hazards {
+        warning!("`{}' loads over {} at 0x{:04X}-0x{:04X}, the tape will likely fail to load.",
+                 in_path.display(), description, area_start, area_end - 1);
+    }
+    eprintln!();
+    eprintln!("Loading the data elsewhere with `--relocate-via ADDR' has it moved into place once the tape is read.");
+}
+
+// Appends the verification record, and the verifier if asked for, to the
+// data of an entry.  Returns the new data, entry point and the offsets of
+// the blocks they start:
+fn append_verification(spec: &EntrySpec, data: Vec<u8>, base_address: u16, entry_point: u16,
+                       settings: &PackSettings) -> Option<(Vec<u8>, u16, Vec<usize>)> {
+    let record_address = (base_address as u32) + (data.len() as u32);
+    let verifier_address = record_address + (stubs::VERIFICATION_RECORD_LENGTH as u32);
+
+    let mut extra = stubs::verification_record(base_address, &data);
+    let mut block_breaks = vec![data.len()];
+    let mut new_entry_point = entry_point;
+    if settings.verifier {
+        extra.extend(stubs::verifier(verifier_address as u16, record_address as u16, entry_point));
+        new_entry_point = verifier_address as u16;
+    }
+
+    let extra_end = record_address + (extra.len() as u32);
+    if extra_end > 0x10000 {
+        error!("The verification block of `{}' doesn't fit into the address space after the data.", spec.in_path.display());
+        return None;
+    }
+    if !check_load_range(&spec.in_path, record_address, extra_end, settings) {
+        return None;
+    }
+    if settings.relocate_via.is_none() {
+        check_loader_hazards(&spec.in_path, record_address, extra_end, settings);
+    }
+    if settings.verifier {
+        note!("The verifier at 0x{:04X} checks `{}' and then jumps to 0x{:04X}.", verifier_address, spec.in_path.display(), entry_point);
+        block_breaks.push(data.len() + stubs::VERIFICATION_RECORD_LENGTH);
+    }
+
+    let mut data = data;
+    data.extend(extra);
+    Some((data, new_entry_point, block_breaks))
+}
+
+// Moves the data of an entry to the staging address, and appends the stub
+// which copies it to the base address once loaded.  Returns the new data,
+// base address and entry point:
+fn relocate_entry(spec: &EntrySpec, data: Vec<u8>, base_address: u16, entry_point: u16,
+                  staging_address: u16, settings: &PackSettings) -> Option<(Vec<u8>, u16, u16)> {
+    let length = data.len();
+    let stub_address = (staging_address as usize) + length;
+    let staged_end = stub_address + stubs::RELOCATION_STUB_LENGTH;
+    if staged_end > 0x10000 {
+        error!("`{}' and the relocation stub don't fit into the address space at the staging address 0x{:04X}.", spec.in_path.display(), staging_address);
+        return None;
+    }
+    if stub_address < (base_address as usize) + length && (base_address as usize) < staged_end {
+        error!("The relocation stub of `{}' at 0x{:04X} would be overwritten by the data it moves to 0x{:04X}-0x{:04X}, choose another staging address.",
+               spec.in_path.display(), stub_address, base_address, (base_address as usize) + length - 1);
+        return None;
+    }
+    if !check_load_range(&spec.in_path, staging_address as u32, staged_end as u32, settings) {
+        return None;
+    }
+    check_loader_hazards(&spec.in_path, staging_address as u32, staged_end as u32, settings);
+
+    let mut staged = data;
+    staged.extend(stubs::relocation_stub(staging_address, base_address, length as u16, entry_point));
+    note!("`{}' is loaded at 0x{:04X}, the stub at 0x{:04X} moves it to 0x{:04X} and jumps to 0x{:04X}.",
+          spec.in_path.display(), staging_address, stub_address, base_address, entry_point);
+
+    Some((staged, staging_address, stub_address as u16))
+}
+
+// Checks that the entry point leads into the loaded data, or to one of the
+// usual places in the ROM.  Transposed base and entry point addresses make
+// for tapes which load fine and crash right away:
+fn check_entry_point(in_path: &path::Path, entry_point: u16, base_address: u16, loaded: &[packing::Stretch],
+                     settings: &PackSettings) -> bool {
+    let offset = entry_point.wrapping_sub(base_address) as usize;
+    let inside = loaded.iter().any(|&(start, length)| offset >= start && offset < start + length);
+    if inside || settings.allow_external_entry {
+        return true;
+    }
+    if let Some(&(_address, description)) = memory::KNOWN_ENTRY_POINTS.iter().find(|&&(known, _)| known == entry_point) {
+        note!("The entry point of `{}' leads to {} at 0x{:04X}.", in_path.display(), description, entry_point);
+        return true;
+    }
+
+    error!("The entry point 0x{:04X} of `{}' lies outside of the loaded data, use `--allow-external-entry' if that's intended.",
+           entry_point, in_path.display());
+    false
+}
+
+// Patches the data of an entry as asked for by its `--poke' options, the
+// addresses have to fall into the loaded data:
+fn apply_pokes(spec: &EntrySpec, data: &mut [u8], base_address: u16, loaded: &[packing::Stretch],
+               settings: &PackSettings) -> bool {
+    let variables = [("base", base_address as u32),
+                     ("end", (base_address as u32) + (data.len() as u32))];
+    for argument in &spec.pokes {
+        let (address, values) = match argument.find('=') {
+            Some(position) => { (&argument[..position], &argument[position + 1..]) },
+            None => {
+                error!("Invalid patch `{}', expected `ADDR=VALUE[,VALUE...]'.", argument);
+                return false;
+            },
+        };
+        let address = match retrieve_address(address, "patch address", settings.bare_radix, &variables) {
+            Some(address) => { address as usize },
+            None => { return false; },
+        };
+        let mut bytes = Vec::new();
+        for value in values.split(',') {
+            match numbers::parse_number(value.trim(), settings.bare_radix) {
+                Ok(byte) if byte <= 0xFF => { bytes.push(byte as u8); },
+                _ => {
+                    error!("Invalid byte value `{}' in the patch `{}'.", value, argument);
+                    return false;
+                },
+            }
+        }
+
+        let offset = (address as u16).wrapping_sub(base_address) as usize;
+        let inside = loaded.iter().any(|&(start, length)| offset >= start && offset + bytes.len() <= start + length);
+        if !inside {
+            error!("The patch `{}' doesn't fall into the data loaded from `{}'.", argument, spec.in_path.display());
+            return false;
+        }
+        let original: Vec<String> = data[offset..offset + bytes.len()].iter().map(|byte| format!("{:02X}", byte)).collect();
+        let patched: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        status!("Patching 0x{:04X}: {} -> {}", address, original.join(" "), patched.join(" "));
+        data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+    true
+}
+
+// Collects the `BLOCK=ADDR' overrides given on the command line, and in the
+// table file, which has one `BLOCK ADDR' or `BLOCK=ADDR' pair per line, with
+// `#' starting comments:
+fn retrieve_block_addresses(matches: &getopts::Matches) -> Vec<String> {
+    let mut overrides = matches.opt_strs("block-address");
+
+    if let Some(name) = matches.opt_str("block-address-file") {
+        let buffer = match files::load_file(name.as_ref()) {
+            Some(buffer) => { buffer },
+            None => { process::exit(1); },
+        };
+        for line in String::from_utf8_lossy(&buffer).lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(|character: char| character == '=' || character.is_whitespace())
+                                        .filter(|field| !field.is_empty()).collect();
+            if fields.len() != 2 {
+                error!("{}: Invalid line `{}', expected `BLOCK ADDR'.", name, line);
+                process::exit(1);
+            }
+            overrides.push(format!("{}={}", fields[0], fields[1]));
+        }
+    }
+    overrides
+}
+
+// Works out the block load address overrides of an entry, the blocks are
+// counted from 1 on the command line:
+fn resolve_block_addresses(spec: &EntrySpec, base_address: u16, size: u32,
+                           settings: &PackSettings) -> Option<Vec<(usize, u16)>> {
+    let variables = [("base", base_address as u32),
+                     ("size", size),
+                     ("end", (base_address as u32) + size)];
+    let mut overrides: Vec<(usize, u16)> = Vec::new();
+
+    for argument in &spec.block_addresses {
+        let (block, address) = match argument.find('=') {
+            Some(position) => { (&argument[..position], &argument[position + 1..]) },
+            None => {
+                error!("Invalid block address override `{}', expected `BLOCK=ADDR'.", argument);
+                return None;
+            },
+        };
+        let block = match block.trim().parse::<usize>() {
+            Ok(block) if block >= 1 => { block - 1 },
+            _ => {
+                error!("Invalid block number `{}', expected a number counting from 1.", block);
+                return None;
+            },
+        };
+        let address = retrieve_address(address.trim(), "block load address", settings.bare_radix, &variables)?;
+        if overrides.iter().any(|&(other, _address)| other == block) {
+            error!("The load address of block {} is overridden more than once.", block + 1);
+            return None;
+        }
+        overrides.push((block, address));
+    }
+    Some(overrides)
+}
+
+// Puts the data of an entry together with its further segments, which load
+// into addresses of their own.  Returns the combined image, its base address
+// and the stretches of it which get loaded:
+fn add_segments(spec: &EntrySpec, data: Vec<u8>, base_address: u16,
+                settings: &PackSettings) -> Option<(Vec<u8>, u16, Vec<packing::Stretch>)> {
+    let mut pieces = vec![(spec.in_path.clone(), base_address as u32, data)];
+    for (in_path, argument) in &spec.segments {
+        if !check_input_file(in_path) {
+            return None;
+        }
+        let segment = load_input(in_path, settings)?;
+        let size = segment.len() as u32;
+        let address = retrieve_address(argument, "segment address", settings.bare_radix,
+                                       &[("base", base_address as u32), ("size", size)])? as u32;
+        if address + size > 0x10000 {
+            error!("The segment `{}' at 0x{:04X} doesn't fit into the Z80's address space.", in_path.display(), address);
+            return None;
+        }
+        if !check_load_range(in_path, address, address + size, settings) {
+            return None;
+        }
+        check_loader_hazards(in_path, address, address + size, settings);
+        pieces.push((in_path.clone(), address, segment));
+    }
+    pieces.sort_by_key(|piece| piece.1);
+
+    // What lies between the segments is left out of the tape:
+    let start = pieces[0].1;
+    let mut image = Vec::new();
+    let mut loaded = Vec::new();
+    for (in_path, address, segment) in pieces {
+        let offset = (address - start) as usize;
+        if offset < image.len() {
+            error!("The segment `{}' at 0x{:04X} overlaps the one before it.", in_path.display(), address);
+            return None;
+        }
+        verbose!("Segment `{}' loads at 0x{:04X}-0x{:04X}.", in_path.display(), address,
+                 address + (segment.len() as u32) - 1);
+
+        // Filled gaps load along with the segments around them:
+        let gap_start = image.len();
+        let gap_length = offset - gap_start;
+        let filled = match settings.gap_fill {
+            Some(ref fill) if gap_length > 0 && fill.max_length.map(|max| gap_length <= max).unwrap_or(true) => {
+                verbose!("Filling the gap of {} bytes at 0x{:04X}.", gap_length, start + (gap_start as u32));
+                image.extend(fill.pattern.iter().cycle().take(gap_length));
+                true
+            },
+            _ => {
+                image.resize(offset, 0x00);
+                false
+            },
+        };
+        match loaded.last_mut() {
+            Some(&mut (_, ref mut length)) if filled => { *length += gap_length + segment.len(); },
+            _ => { loaded.push((offset, segment.len())); },
+        }
+        image.extend(segment);
+    }
+
+    Some((image, start as u16, loaded))
+}
+
+// Loads the input of an entry, assembling it first if it's a source:
+fn load_input(in_path: &path::Path, settings: &PackSettings) -> Option<Vec<u8>> {
+    let is_source = in_path.extension().is_some_and(|extension| files::SOURCE_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()));
+    if !is_source {
+        return files::load_file(in_path);
+    }
+
+    // Read for the dependency listing, it's what the tape is made from:
+    files::load_file(in_path)?;
+    let assembler = match assemblers::find_assembler(settings.assembler.as_deref()) {
+        Ok(assembler) => { assembler },
+        Err(error) if settings.assembler.is_none() => {
+            error!("{}, assemble `{}' into a binary first.", error, in_path.display());
+            return None;
+        },
+        Err(error) => {
+            error!("{}.", error);
+            return None;
+        },
+    };
+    match assemblers::assemble(assembler, in_path, &settings.assembler_args) {
+        Ok(data) => {
+            status!("{}: assembled with `{}' into {} bytes.", in_path.display(), assembler.name, data.len());
+            Some(data)
+        },
+        Err(error) => {
+            error!("{}.", error);
+            None
+        },
+    }
+}
+
+// Loads the input of an entry and works out its name and addresses:
+fn resolve_entry(progname: &str, spec: &EntrySpec, settings: &PackSettings) -> Option<packing::PackEntry> {
+    if !check_input_file(&spec.in_path) {
+        return None;
+    }
+
+    // Address expressions may refer to the size of the input, so it needs
+    // to be loaded first:
+    let data = load_input(&spec.in_path, settings)?;
+    let input_size = data.len() as u32;
+
+    let base_address = match spec.base {
+        Some(ref argument) => {
+            retrieve_address(argument, "base address", settings.bare_radix, &[("size", input_size)])?
+        },
+        None => {
+            error!("Base address of `{}' not specified, please provide it with the `--base' command-line option, or in the `FILE:NAME:BASE:START' argument.", spec.in_path.display());
+            return None;
+        },
+    };
+
+    let code_address = base_address;
+    let end = (base_address as u32) + input_size;
+
+    // Data wrapping around past 0xFFFF goes on at 0x0000:
+    let load_ranges = if end > 0x10000 {
+        if !settings.allow_wrap {
+            error!("`{}' would load past the end of the Z80's address space, use `--allow-wrap' if it's meant to wrap around to 0x0000.", spec.in_path.display());
+            return None;
+        }
+        note!("`{}' wraps around past 0xFFFF, loading on at 0x0000.", spec.in_path.display());
+        vec![(base_address as u32, 0x10000), (0, end - 0x10000)]
+    } else {
+        vec![(base_address as u32, end)]
+    };
+    for &(range_start, range_end) in &load_ranges {
+        if !check_load_range(&spec.in_path, range_start, range_end, settings) {
+            return None;
+        }
+
+        // Relocated data gets moved into place only once the tape is read:
+        if settings.relocate_via.is_none() {
+            check_loader_hazards(&spec.in_path, range_start, range_end, settings);
+        }
+    }
+
+    let entry_variables = [("base", base_address as u32),
+                           ("size", input_size),
+                           ("end",  end)];
+    let entry_point = match spec.start {
+        Some(ref argument) => {
+            if settings.no_autostart {
+                error!("An entry point can't be used together with `--no-autostart' or `--data-convention'.");
+                return None;
+            }
+            retrieve_address(argument, "entry point address", settings.bare_radix, &entry_variables)?
+        },
+        None => {
+            if !settings.end_record {
+                note!("Creating a data-only entry without the end of file record.");
+                tape::ROM_BASIC_READY
+            } else if settings.no_autostart {
+                note!("Creating a data-only entry, the entry point returns to the BASIC `READY' prompt.");
+                tape::ROM_BASIC_READY
+            } else {
+                note!("Entry point not specified, defaulting to the base address 0x{:04X}.", base_address);
+                base_address
+            }
+        },
+    };
+
+    // Entries made of several segments leave out what lies between them:
+    let (data, base_address, segments_loaded) = if spec.segments.is_empty() {
+        (data, base_address, None)
+    } else {
+        if settings.verify_block || settings.relocate_via.is_some() {
+            error!("Entries made of several segments can't be used together with `--verify-block', `--verifier' or `--relocate-via'.");
+            return None;
+        }
+        let (data, base_address, loaded) = add_segments(spec, data, base_address, settings)?;
+        (data, base_address, Some(loaded))
+    };
+    let mut data = data;
+    let stretches = segments_loaded.clone().unwrap_or_else(|| vec![(0, data.len())]);
+    if spec.start.is_some() && !check_entry_point(&spec.in_path, entry_point, base_address, &stretches, settings) {
+        return None;
+    }
+    if !apply_pokes(spec, &mut data, base_address, &stretches, settings) {
+        return None;
+    }
+
+    // The verification record and the verifier go after the data, in
+    // blocks of their own, and get relocated along with it:
+    let (data, entry_point, block_breaks) = if settings.verify_block {
+        append_verification(spec, data, base_address, entry_point, settings)?
+    } else {
+        (data, entry_point, Vec::new())
+    };
+
+    // The data goes through the staging area if it would load over memory
+    // in use while the tape is being read:
+    let (data, base_address, entry_point) = match settings.relocate_via {
+        Some(ref argument) => {
+            let staging_address = retrieve_address(argument, "staging address", settings.bare_radix,
+                                                   &[("base", base_address as u32), ("size", input_size)])?;
+            relocate_entry(spec, data, base_address, entry_point, staging_address, settings)?
+        },
+        None => { (data, base_address, entry_point) },
+    };
+
+    // The filename of the input filepath is used for the default name.
+    //
+    // I feel that unwrap is reasonable here because we've already checked
+    // that this is indeed a file.
+    //
+    let mut default_entry_name = (spec.in_path.file_name().unwrap().as_ref() as &path::Path).to_owned();
+    default_entry_name.set_extension("");
+
+    let entry_name = match spec.name {
+        Some(ref requested) => {
+            let entry_name = retrieve_tape_entry_name(requested, settings.name_charset, settings.keep_case);
+            if !check_tape_entry_name(requested, &entry_name, settings.name_check) {
+                return None;
+            }
+            entry_name
+        },
+        None => {
+            retrieve_tape_entry_name(&default_entry_name.to_string_lossy(), settings.name_charset, settings.keep_case)
+        },
+    };
+    if !entry_name.has_letters {
+        error!("The name of the data entry to be \"recorded onto the tape\" from `{}' is empty, this could be because there either are no plain ASCII letters (or other characters allowed by `--name-charset') in your input filename, or in the name you provided.", spec.in_path.display());
+        eprintln!();
+        eprintln!("Please provide a valid name for the data entry, see `{} pack --help'.", progname);
+
+        return None;
+    }
+
+    let loaded = segments_loaded.unwrap_or_else(|| vec![(0, data.len())]);
+    let block_addresses = resolve_block_addresses(spec, base_address, data.len() as u32, settings)?;
+    Some(packing::PackEntry {
+        in_path: spec.in_path.clone(),
+        data,
+        name: entry_name.name,
+        base_address,
+        entry_point,
+        code_address,
+        loaded,
+        block_breaks,
+        block_addresses,
+        comments: settings.comments.clone(),
+        end_record: settings.end_record,
+    })
+}
+
+// Loads and validates the tape image to be appended to:
+fn load_existing_tape(out_path: &path::Path, variant: &tape::Variant) -> Option<Vec<u8>> {
+    if !out_path.is_file() {
+        error!("The tape image `{}' to append to doesn't exist.", out_path.display());
+        return None;
+    }
+    let buffer = files::load_file(out_path)?;
+
+    match tape::parse_tape(&buffer, variant) {
+        Ok(entries) => {
+            let bad_blocks: usize = entries.iter().map(|entry| entry.bad_checksum_count()).sum();
+            if bad_blocks != 0 {
+                error!("The tape image `{}' has {} blocks with bad checksums, refusing to append to it.", out_path.display(), bad_blocks);
+                return None;
+            }
+            status!("Appending to `{}', which holds {} entries.", out_path.display(), entries.len());
+            status!("");
+            Some(buffer)
+        },
+        Err(error) => {
+            error!("{}: {}, refusing to append to it.", out_path.display(), error);
+            None
+        },
+    }
+}
+
+
+// Packs the inputs again in a child process every time they change, with the
+// same options, so that a failed run doesn't end the watch:
+fn watch_inputs(progname: &str, args: &[String], matches: &getopts::Matches, in_paths: &[path::PathBuf]) -> ! {
+    let executable = match env::current_exe() {
+        Ok(executable) => { executable },
+        Err(error) => {
+            error!("Failed to find the program's executable: {}.", error);
+            process::exit(1);
+        },
+    };
+
+    let mut child_args: Vec<String> = vec!["pack".to_owned()];
+    child_args.extend(args.iter().filter(|argument| *argument != "--watch").cloned());
+    if !matches.opt_present("f") {
+        child_args.push("--force".to_owned());
+    }
+    if !matches.opt_present("q") && !matches.opt_present("v") {
+        child_args.push("--quiet".to_owned());
+    }
+
+    status!("Watching {} input files for changes, press Ctrl+C to stop.", in_paths.len());
+    let mut run_count = 0;
+    watch::watch(in_paths, &mut || {
+        run_count += 1;
+
+        // Keep a `watch' setting in the environment or the configuration
+        // files from applying to the child:
+        let status = process::Command::new(&executable).args(&child_args)
+                                      .env(format!("{}WATCH", config::ENVIRONMENT_PREFIX), "0").status();
+        match status {
+            Ok(ref status) if status.success() => {
+                status!("[{}] Packed {} input files.", run_count, in_paths.len());
+            },
+            Ok(_status) => {
+                status!("[{}] Packing failed, waiting for the next change.", run_count);
+            },
+            Err(error) => {
+                error!("Failed to run `{}': {}.", progname, error);
+                process::exit(1);
+            },
+        }
+    });
+}
+
+// Writes the symbols of the first entry next to the tape, for the debugger
+// of trs80gp, moved to where its code runs if it was assembled elsewhere.
+// Returns where they went:
+fn write_symbols(matches: &getopts::Matches, out_path: &path::Path, first: &packing::PackEntry) -> Option<path::PathBuf> {
+    let in_path: path::PathBuf = matches.opt_str("symbols")?.into();
+    let buffer = match files::load_file(&in_path) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let text = String::from_utf8_lossy(&buffer);
+    let is_listing = in_path.extension().is_some_and(|extension| files::LISTING_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()));
+    let mut symbols = if is_listing {
+        symbols::parse_listing(&text)
+    } else {
+        match symbols::parse_symbol_file(&text) {
+            Ok(symbols) => { symbols },
+            Err(error) => {
+                error!("{}, {}.", in_path.display(), error);
+                process::exit(1);
+            },
+        }
+    };
+    if symbols.symbols.is_empty() && symbols.lines.is_empty() {
+        error!("No symbols found in `{}'.", in_path.display());
+        process::exit(1);
+    }
+
+    // Listings tell where they were assembled to, symbol files have to be
+    // told:
+    let origin = match matches.opt_str("symbols-origin") {
+        Some(argument) => {
+            match retrieve_address(&argument, "symbols origin", 16, &[]) {
+                Some(origin) => { origin },
+                None => { process::exit(1); },
+            }
+        },
+        None => { symbols.origin().unwrap_or(first.code_address) },
+    };
+    let offset = first.code_address.wrapping_sub(origin);
+    if offset != 0 {
+        note!("`{}' was assembled at 0x{:04X}, moving its symbols to 0x{:04X}.", in_path.display(), origin, first.code_address);
+    }
+    symbols.shift(offset);
+
+    // Bytes of the listing which differ from the input point at a listing
+    // of another build, unless it was assembled elsewhere, with addresses of
+    // its own in the code:
+    let input = if offset == 0 { files::load_file(&first.in_path) } else { None };
+    if let Some(input) = input {
+        let stale = symbols.lines.iter().find(|line| {
+            let start = line.address.wrapping_sub(first.code_address) as usize;
+            !line.bytes.is_empty() && input.get(start..start + line.bytes.len()).is_some_and(|bytes| bytes != line.bytes.as_slice())
+        });
+        if let Some(line) = stale {
+            warning!("`{}' doesn't match `{}' at 0x{:04X}, is it the listing of another build?", in_path.display(), first.in_path.display(), line.address);
+        }
+    }
+
+    let bds_path = match matches.opt_str("symbols-output") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(out_path, "bds") },
+    };
+    let source_name = in_path.with_extension("asm");
+    let source_name = source_name.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    status!("Symbols: {} from `{}', {} listing lines.", symbols.symbols.len(), in_path.display(), symbols.lines.len());
+    if !files::write_file(symbols::generate_bds(&symbols, &source_name).as_bytes(), &bds_path) {
+        process::exit(1);
+    }
+    Some(bds_path)
+}
+
+// The keys loading the first entry with SYSTEM and running it:
+fn first_entry_keys(entries: &[packing::PackEntry]) -> String {
+    let first = &entries[0];
+    if !first.end_record {
+        warning!("The entry `{}' has no end of file record for SYSTEM to finish loading it with, it may not run.",
+                 String::from_utf8_lossy(&first.name));
+    }
+    emulators::system_keys(&String::from_utf8_lossy(&first.name))
+}
+
+// Writes the keys loading the first entry next to the tape, for emulators
+// started by test scripts:
+fn write_keys_file(matches: &getopts::Matches, out_path: &path::Path, entries: &[packing::PackEntry]) {
+    let format = match matches.opt_str("keys-format") {
+        Some(argument) => {
+            match emulators::KEYS_FORMATS.iter().find(|&&(name, _format, _extension)| name == argument.to_lowercase()) {
+                Some(&(_name, format, _extension)) => { Some(format) },
+                None => {
+                    let names: Vec<&str> = emulators::KEYS_FORMATS.iter().map(|&(name, _format, _extension)| name).collect();
+                    error!("Unknown keys file format `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { None },
+    };
+    let keys_path = match matches.opt_str("keys-output") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => {
+            let format = format.unwrap_or(emulators::KeysFormat::Text);
+            let extension = emulators::KEYS_FORMATS.iter().find(|&&(_name, other, _extension)| other == format).map_or("keys", |&(_name, _format, extension)| extension);
+            files::default_output_path(out_path, extension)
+        },
+    };
+    // Told by the extension if not given:
+    let format = format.unwrap_or_else(|| {
+        let extension = keys_path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        emulators::KEYS_FORMATS.iter().find(|&&(_name, _format, other)| other == extension).map_or(emulators::KeysFormat::Text, |&(_name, format, _extension)| format)
+    });
+
+    let file_name = |path: &path::Path| path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let keys = emulators::generate_keys_file(&first_entry_keys(entries), format, &file_name(out_path), &file_name(&keys_path));
+    if !files::write_file(keys.as_bytes(), &keys_path) {
+        process::exit(1);
+    }
+}
+
+// Starts an emulator with the tape just made, for trying it out, with the
+// commands loading the first entry typed in if asked to:
+fn run_emulator(matches: &getopts::Matches, out_path: &path::Path, entries: &[packing::PackEntry],
+                symbols: Option<&path::Path>) {
+    let emulator = match emulators::find_emulator(matches.opt_str("run").as_deref()) {
+        Ok(emulator) => { emulator },
+        Err(error) => {
+            error!("{}, or leave out `--run'.", error);
+            process::exit(1);
+        },
+    };
+    let keys = if matches.opt_present("run-keys") {
+        Some(first_entry_keys(entries))
+    } else {
+        None
+    };
+
+    status!("Starting `{}' with `{}'.", emulator.name, out_path.display());
+    if let Err(error) = emulators::run(emulator, out_path, keys.as_deref(), symbols) {
+        error!("{}.", error);
+        process::exit(1);
+    }
+}
+
+// Reads a freshly written tape back, and checks that its last entries hold
+// exactly what was packed:
+fn verify_written_tape(out_path: &path::Path, entries: &[packing::PackEntry], format: &packing::TapeFormat) -> bool {
+    let buffer = match files::load_file(out_path) {
+        Some(buffer) => { buffer },
+        None => { return false; },
+    };
+    let tape_entries = match tape::parse_tape(&buffer, &format.variant) {
+        Ok(tape_entries) => { tape_entries },
+        Err(error) => {
+            error!("Verification of `{}' failed: {}.", out_path.display(), error);
+            return false;
+        },
+    };
+    let written_entries = entries.len() * format.copies;
+    if tape_entries.len() < written_entries {
+        error!("Verification of `{}' failed, only {} of {} entries were read back.", out_path.display(), tape_entries.len(), written_entries);
+        return false;
+    }
+
+    // Every copy is checked on its own:
+    let first_new = tape_entries.len() - written_entries;
+    for (index, tape_entry) in tape_entries[first_new..].iter().enumerate() {
+        let entry = &entries[index / format.copies];
+
+        // The blocks have to be the ones the data was split into, padding
+        // may carry the last one past the data:
+        let layout = packing::block_layout(entry, format);
+        let sequence = packing::block_sequence(layout.len(), format.block_order);
+        let image_intact = tape_entry.blocks.len() == layout.len() &&
+                           tape_entry.blocks.iter().zip(&sequence).all(|(block, &block_index)| {
+            let (offset, length) = layout[block_index];
+            let data_end = entry.data.len().min(offset + length);
+            block.load_address == entry.block_address(block_index, offset) && block.data.len() == length &&
+            block.data[..data_end - offset] == entry.data[offset..data_end]
+        });
+        let expected_entry_point = if entry.end_record { Some(entry.entry_point) } else { None };
+        if !image_intact || tape_entry.name != entry.name || tape_entry.entry_point != expected_entry_point ||
+           tape_entry.comments != entry.comments ||
+           tape_entry.bad_checksum_count() != 0 {
+            error!("Verification of `{}' failed, entry `{}' doesn't read back as it was packed.", out_path.display(), tape_entry.name_string());
+            return false;
+        }
+    }
+
+    status!("Verified `{}', {} entries read back intact.", out_path.display(), written_entries);
+    true
+}
+
+// Loads the entries of a freshly written tape in a simulated machine, and
+// checks that the ROM puts their blocks where they were meant to go, and
+// would start them at their entry points:
+fn simulate_written_tape(buffer: &[u8], entries: &[packing::PackEntry], format: &packing::TapeFormat, ram_end: Option<u32>) -> bool {
+    let ram_end = ram_end.unwrap_or_else(simulator::default_ram_end);
+    for entry in entries {
+        if !entry.end_record {
+            note!("Entry `{}' has no end of file record, SYSTEM can't load it, so it isn't simulated.", String::from_utf8_lossy(&entry.name));
+            continue;
+        }
+        let mut machine = simulator::Machine::new(buffer, ram_end);
+        let load = match machine.system_load(Some(&entry.name)) {
+            Ok(load) => { load },
+            Err(error) => {
+                error!("Simulation of `{}' failed: {}.", String::from_utf8_lossy(&entry.name), error);
+                return false;
+            },
+        };
+        if !simulate::report_load(&load) {
+            return false;
+        }
+
+        // What memory should hold, the blocks loading in the order of the
+        // tape, later ones over earlier ones:
+        let mut expected: Vec<Option<u8>> = vec![None; 0x10000];
+        let layout = packing::block_layout(entry, format);
+        for block_index in packing::block_sequence(layout.len(), format.block_order) {
+            let (offset, length) = layout[block_index];
+            let address = entry.block_address(block_index, offset);
+            for index in 0..length.min(entry.data.len().saturating_sub(offset)) {
+                expected[address.wrapping_add(index as u16) as usize] = Some(entry.data[offset + index]);
+            }
+        }
+        // Leaving out the screen, which the ROM draws on while loading:
+        let misplaced = expected.iter().enumerate().filter(|&(address, _byte)| !(0x3C00..0x4000).contains(&address))
+                                .find(|&(address, byte)| byte.is_some_and(|byte| machine.memory[address] != byte));
+        if let Some((address, byte)) = misplaced {
+            error!("Simulation of `{}' failed, 0x{:04X} holds 0x{:02X} instead of 0x{:02X} once loaded.",
+                   String::from_utf8_lossy(&entry.name), address, machine.memory[address], byte.unwrap_or(0));
+            return false;
+        }
+        if load.entry_point != entry.entry_point {
+            error!("Simulation of `{}' failed, the ROM would start it at 0x{:04X} instead of 0x{:04X}.",
+                   String::from_utf8_lossy(&entry.name), load.entry_point, entry.entry_point);
+            return false;
+        }
+        if !simulate::report_run(&mut machine, load.entry_point, simulate::DEFAULT_INSTRUCTIONS) {
+            return false;
+        }
+    }
+    true
+}
+
+// A line of the summary table of a batch, without the output for inputs
+// which failed to pack:
+struct BatchRow {
+    input: String,
+    output: Option<String>,
+    name: String,
+    base_address: u16,
+    entry_point: u16,
+    size: usize,
+}
+
+fn print_batch_summary(rows: &[BatchRow]) {
+    let input_width = rows.iter().map(|row| row.input.len()).max().unwrap_or(0).max(5);
+    let output_width = rows.iter().map(|row| row.output.as_ref().map_or(0, |output| output.len())).max().unwrap_or(0).max(6);
+
+    status!("");
+    status!("{:iw$}  {:ow$}  Name    Base    Entry   Size", "Input", "Output", iw = input_width, ow = output_width);
+    for row in rows {
+        match row.output {
+            Some(ref output) => {
+                status!("{:iw$}  {:ow$}  {:6}  0x{:04X}  0x{:04X}  {}", row.input, output, row.name,
+                        row.base_address, row.entry_point, row.size, iw = input_width, ow = output_width);
+            },
+            None => {
+                status!("{:iw$}  {:ow$}  failed", row.input, "-", iw = input_width, ow = output_width);
+            },
+        }
+    }
+}
+
+// Runs `work' on each of the items, on up to `jobs' threads at once, and
+// returns the results in the order of the items:
+fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, work: &(dyn Fn(&T) -> R + Sync)) -> Vec<R> {
+    let next_item = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_item| None).collect());
+
+    thread::scope(|scope| {
+        for _worker in 0..jobs.max(1) {
+            scope.spawn(|| {
+                loop {
+                    let index = next_item.fetch_add(1, Ordering::Relaxed);
+                    if index >= items.len() {
+                        break;
+                    }
+                    let result = work(&items[index]);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().map(|result| result.expect("an item was left out")).collect()
+}
+
+// Packs one file of a batch, the outputs are claimed in `used_paths', so
+// that no two files end up on the same tape:
+fn batch_pack_one(progname: &str, spec: &EntrySpec, matches: &getopts::Matches, settings: &PackSettings,
+                  format: &packing::TapeFormat, used_paths: &Mutex<Vec<path::PathBuf>>) -> BatchRow {
+    status!("");
+    status!("Packing `{}':", spec.in_path.display());
+    let mut row = BatchRow {
+        input: spec.in_path.to_string_lossy().into_owned(),
+        output: None,
+        name: String::new(),
+        base_address: 0,
+        entry_point: 0,
+        size: 0,
+    };
+
+    let entry = match resolve_entry(progname, spec, settings) {
+        Some(entry) => { entry },
+        None => { return row; },
+    };
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => {
+            let values = commands::entry_template_values(&entry.in_path, &entry.name, entry.base_address,
+                                                         entry.entry_point, entry.data.len());
+            commands::expand_output_path(&argument, &values)
+        },
+        None => { files::default_output_path(&entry.in_path, "cas") },
+    };
+
+    {
+        let mut used_paths = used_paths.lock().unwrap();
+        if out_filepath == entry.in_path || used_paths.contains(&out_filepath) {
+            error!("`{}' would be written over an input or another tape of the batch, skipping `{}'.", out_filepath.display(), entry.in_path.display());
+            return row;
+        }
+        used_paths.push(out_filepath.clone());
+    }
+
+    let tape = packing::pack(slice::from_ref(&entry), format, &out_filepath, None);
+    if tape.is_some() &&
+       (!matches.opt_present("verify-after-write") || verify_written_tape(&out_filepath, slice::from_ref(&entry), format)) &&
+       (!matches.opt_present("simulate") || simulate_written_tape(tape.as_deref().unwrap_or_default(), slice::from_ref(&entry), format, settings.ram_end)) {
+        row.output = Some(out_filepath.to_string_lossy().into_owned());
+        row.name = String::from_utf8_lossy(&entry.name).into_owned();
+        row.base_address = entry.base_address;
+        row.entry_point = entry.entry_point;
+        row.size = entry.data.len();
+    }
+    row
+}
+
+// Packs every input onto a tape of its own, going on with the rest if some
+// of them fail:
+fn batch_pack(progname: &str, matches: &getopts::Matches, settings: &PackSettings, format: &packing::TapeFormat) -> ! {
+    if matches.opt_present("a") || matches.opt_present("watch") || matches.opt_present("json") || matches.opt_present("depfile") || matches.opt_present("run") || matches.opt_present("symbols") ||
+       matches.opt_present("keys-output") || matches.opt_present("keys-format") {
+        error!("The `--batch' option can't be used together with `--append', `--watch', `--json', `--depfile', `--run', `--symbols' or `--keys-output'.");
+        process::exit(1);
+    }
+    if matches.opt_present("n") {
+        error!("Each file of a batch is named after itself, `--name' can't be used with `--batch'.");
+        process::exit(1);
+    }
+    if matches.opt_present("segment") || matches.opt_present("poke") ||
+       matches.opt_present("block-address") || matches.opt_present("block-address-file") {
+        error!("The `--segment', `--poke' and `--block-address' options can't be used together with `--batch'.");
+        process::exit(1);
+    }
+    if let Some(argument) = matches.opt_str("o") {
+        if !template::is_template(&argument) {
+            error!("The tapes of a batch need names of their own, `--output' can only be a template with `--batch'.");
+            process::exit(1);
+        }
+    }
+
+    // The addresses given through the options apply to every file, unless
+    // a `FILE:NAME:BASE:START' argument has its own:
+    let mut arguments: Vec<EntrySpec> = Vec::new();
+    if let Some(name) = matches.opt_str("i") {
+        arguments.push(EntrySpec { in_path: (name.as_ref() as &path::Path).to_owned(), name: None, base: None, start: None, segments: Vec::new(), pokes: Vec::new(), block_addresses: Vec::new() });
+    }
+    arguments.extend(matches.free.iter().map(|argument| parse_entry_spec(argument)));
+    if arguments.is_empty() {
+        error!("No input files given for the batch, see `{} pack --help'.", progname);
+        process::exit(1);
+    }
+
+    let mut specs = Vec::new();
+    for argument in arguments {
+        let in_paths = match files::expand_input_pattern(&argument.in_path.to_string_lossy(), files::BINARY_EXTENSIONS) {
+            Ok(in_paths) => { in_paths },
+            Err(error) => {
+                error!("{}.", error);
+                process::exit(1);
+            },
+        };
+        for in_path in in_paths {
+            specs.push(EntrySpec {
+                in_path,
+                name: argument.name.clone(),
+                base: argument.base.clone().or_else(|| matches.opt_str("b")),
+                start: argument.start.clone().or_else(|| matches.opt_str("s")),
+                segments: Vec::new(),
+                pokes: Vec::new(),
+                block_addresses: Vec::new(),
+            });
+        }
+    }
+
+    let jobs = match matches.opt_str("jobs") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(jobs) if jobs > 0 => { jobs },
+                _ => {
+                    error!("Invalid number of jobs `{}', expected a positive number.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { thread::available_parallelism().map(|jobs| jobs.get()).unwrap_or(1) },
+    };
+    let jobs = jobs.min(specs.len());
+
+    // The messages of files packed at the same time would be mixed up, so
+    // only the errors, the warnings, the progress and the summary are shown
+    // then:
+    let progress = Mutex::new(progress::Progress::new("Packing", "files", specs.len()));
+    let packed = AtomicUsize::new(0);
+    let verbosity = console::verbosity();
+    if jobs > 1 {
+        console::set_verbosity(console::QUIET);
+    }
+    let used_paths = Mutex::new(Vec::new());
+    let rows = parallel_map(&specs, jobs, &|spec| {
+        let row = batch_pack_one(progname, spec, matches, settings, format, &used_paths);
+        progress.lock().unwrap().update(packed.fetch_add(1, Ordering::Relaxed) + 1);
+        row
+    });
+    progress.lock().unwrap().finish();
+    console::set_verbosity(verbosity);
+
+    print_batch_summary(&rows);
+    let failures = rows.iter().filter(|row| row.output.is_none()).count();
+    if failures == 0 {
+        status!("Packed all {} files.", rows.len());
+        process::exit(0);
+    } else {
+        error!("{} of {} files failed to pack.", failures, rows.len());
+        process::exit(1);
+    }
+}
+
+// The summary printed with `--json', the tape is the written one, if any:
+fn json_summary(entries: &[packing::PackEntry], out_path: &path::Path, tape: Option<&[u8]>,
+                format: &packing::TapeFormat) -> json::Json {
+    // The new entries are the last ones on the tape, there may be others
+    // in front of them when appending:
+    let tape_entries = match tape {
+        Some(tape) => { tape::parse_tape(tape, &format.variant).unwrap_or_default() },
+        None => { Vec::new() },
+    };
+    let first_new = tape_entries.len().saturating_sub(entries.len() * format.copies);
+
+    let mut entry_objects = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        let blocks = match tape_entries.get(first_new + index * format.copies) {
+            Some(tape_entry) => {
+                tape_entry.blocks.iter().map(|block| {
+                    json::Json::Object(vec![("address",  json::Json::Number(block.load_address as i64)),
+                                            ("length",   json::Json::Number(block.data.len() as i64)),
+                                            ("checksum", json::Json::Number(block.stored_checksum as i64))])
+                }).collect()
+            },
+            None => { Vec::new() },
+        };
+
+        entry_objects.push(json::Json::Object(vec![
+            ("input",       json::Json::Str(entry.in_path.to_string_lossy().into_owned())),
+            ("size",        json::Json::Number(entry.data.len() as i64)),
+            ("name",        json::Json::Str(String::from_utf8_lossy(&entry.name).trim_end().to_owned())),
+            ("base",        json::Json::Number(entry.base_address as i64)),
+            ("end",         json::Json::Number((entry.base_address as i64) + (entry.data.len() as i64))),
+            ("entry_point", json::Json::Number(entry.entry_point as i64)),
+            ("end_record",  json::Json::Bool(entry.end_record)),
+            ("comments",    json::Json::Array(entry.comments.iter().map(|comment| json::Json::Str(comment.clone())).collect())),
+            ("copies",      json::Json::Number(format.copies as i64)),
+            ("blocks",      json::Json::Array(blocks)),
+        ]));
+    }
+
+    json::Json::Object(vec![
+        ("success",     json::Json::Bool(tape.is_some())),
+        ("output",      json::Json::Str(out_path.to_string_lossy().into_owned())),
+        ("output_size", json::Json::Number(tape.map(|tape| tape.len() as i64).unwrap_or(0))),
+        ("entries",     json::Json::Array(entry_objects)),
+        ("warnings",    json::strings(&console::warnings())),
+        ("notes",       json::strings(&console::notes())),
+    ])
+}
+
+pub const USAGE: &str = "-i <file> -b <base_addr> [-s <entry_point>] [FILE:NAME:BASE:START...]\n\nFurther entries may be recorded onto the same tape by listing them as\n`FILE:NAME:BASE:START' arguments, each with its own name and addresses.\nThe NAME and START fields may be left empty or out.";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The file to pack into a machine language tape file.", "FILE");
+    options.optopt("o", "output", "Name of the destination file (input filename with extension changed to .cas by default). May be a template such as `{name}_{base:04X}.cas', with {name}, {stem} (of the input filename), {base}, {start}, {end} and {size} of the first entry available.", "FILE");
+    options.optopt("b", "base", "Starting address of where the data will reside after being loaded (in hex, 0x8000, $8000, &H8000 and 8000h are accepted as well, and decimal as #32768). May be an expression using +, -, *, / and parentheses, with `size' standing for the length of the input file, e.g. `0x8000-size'.", "ADDR");
+    options.optopt("s", "start", "Address of the execution entry point (in hex, same notations as for --base). Defaults to the base address. May be an expression, with `base', `size' and `end' (one past the last loaded byte) available, e.g. `base+0x20'.", "ADDR");
+    options.optmulti("", "poke", "Patch the `--input' entry before packing it, storing the bytes VALUE (in hex, comma separated) from the Z80 address ADDR on. May be repeated.", "ADDR=VALUE[,VALUE...]");
+    options.optopt("", "gap-fill", "Fill the gaps between the segments of the entry with the given pattern of bytes (in hex, comma separated, 00 by default) and record them along, instead of leaving them out.", "BYTES");
+    options.optopt("", "gap-fill-max", "Only fill gaps of up to the given number of bytes, the longer ones are still left out.", "BYTES");
+    options.optopt("", "block-order", "Order to record the blocks in: `sequential' (the default), `reverse', or `interleave' (every other block, then the ones in between), as some protection schemes did. The ROM loader takes the blocks in any order.", "ORDER");
+    options.optmulti("", "block-address", "Have block number BLOCK (counting from 1 in the order of the data) of the `--input' entry load at ADDR instead of the address of its data, to reproduce original tapes with blocks loading out of order. May be repeated.", "BLOCK=ADDR");
+    options.optopt("", "block-address-file", "Read `--block-address' overrides from FILE, one `BLOCK ADDR' pair per line.", "FILE");
+    options.optmulti("", "segment", "Also load FILE at ADDR as a part of the `--input' entry, for code, tables and vectors spread over memory. May be repeated, the address may be an expression with `base' (of the input) and `size' (of FILE) available.", "FILE@ADDR");
+    options.optmulti("", "comment", "Record a comment, such as the author, version, date or build, in front of the blocks of each entry, where the ROM loader skips it. May be repeated, `info' shows the comments.", "TEXT");
+    options.optflag("", "allow-wrap", "Let the data wrap around past 0xFFFF to 0x0000, as some diagnostic and protection tapes do on purpose, instead of refusing it.");
+    options.optflag("", "allow-external-entry", "Accept entry points outside of the loaded data, which usually means the base and entry point addresses were mixed up.");
+    options.optflag("", "no-autostart", "Create data-only entries, whose entry point returns to BASIC instead of running the loaded data.");
+    options.optopt("", "data-convention", "Create data-only entries following the given convention, `basic' (the entry point returns to BASIC, same as `--no-autostart') or `omit' (no end of file record, for data read by a program's own loader).", "CONVENTION");
+    options.optflag("", "decimal", "Treat addresses without a hexadecimal prefix or suffix as decimal numbers, rather than marking each one decimal as in #32768.");
+    options.optopt("n", "name", "Name of the data entry on the tape (input filename without extension by default). Names are up to 6 characters, the ASCII letters turned upper-case unless `--keep-case' is given and the spaces after the first character, plus the digits and punctuation kept by `--name-charset'; anything else is stripped, and anything past the sixth character cut off.", "NAME");
+    options.optopt("", "name-check", "What to do with characters in --name which don't fit the character set, or with names over 6 characters: `filter' them silently (the default), `warn' about it, or be `strict' and refuse to continue.", "MODE");
+    options.optopt("", "name-charset", "Characters kept in the tape entry name: `letters' (the default), `alnum' to keep digits as well, or `extended' to also keep the punctuation !#$%&+-.?@, or `printable' for any printable ASCII, which only emulators and modified ROMs may support.", "SET");
+    options.optflag("", "keep-case", "Keep lowercase letters in the tape entry name instead of turning them uppercase, for emulators and modified ROMs which support them.");
+    options.optopt("", "ram", "RAM size of the machine the tape is meant for, `4k', `16k', `32k' or `48k', data which would load past the end of its RAM is refused.", "SIZE");
+    options.optmulti("", "allow-overlap", "Don't warn about data loading into the given memory areas: rom, io, keyboard, video, system, dos, or all. May be repeated, or given a comma separated list.", "AREAS");
+    options.optmulti("", "note-overlap", "Only mention data loading into the given memory areas, instead of warning about it.", "AREAS");
+    options.optflag("", "verify-block", "Add a block after the data holding its address range and CRC-16, for the program to check itself with once loaded.");
+    options.optflag("", "verifier", "Add a routine after the verification block which checks the loaded data and only then runs it, implies `--verify-block'.");
+    options.optopt("", "relocate-via", "Load the data at the given staging address instead (same notations as for --start, with `base' and `size' available), followed by a short routine which copies it to the base address and jumps to the entry point. Meant for programs which would load over memory in use while the tape is being read.", "ADDR");
+    options.optopt("", "leader-length", "Number of bytes in the leader in front of each entry (256 by default, as written by the ROM).", "BYTES");
+    options.optopt("", "block-size", "Number of data bytes in each block, from 1 to 256 (256 by default, as written by the ROM). Shorter blocks are easier on some custom loaders and marginal cassette decks.", "BYTES");
+    options.optopt("", "block-gap", "Length of the run of zero bytes inserted between data blocks, for hardware interfaces and slow loaders which need the time to keep up, in bytes, or in milliseconds if followed by `ms' (none by default).", "LENGTH");
+    options.optflag("", "sparse", "Leave runs of the fill byte out of the tape, which shortens tapes of padded images, but the memory they would load into has to hold the fill byte already.");
+    options.optopt("", "sparse-fill", "The fill byte left out with `--sparse' (0x00 by default), implies `--sparse'.", "BYTE");
+    options.optopt("", "sparse-min-run", "Shortest run of the fill byte left out with `--sparse' (64 bytes by default), implies `--sparse'.", "BYTES");
+    options.optopt("", "pad-last-block", "Fill the last block of each entry up to the full block size with the given byte (in hex), for loaders which misbehave on short blocks.", "BYTE");
+    options.optflag("", "page-aligned", "Start a new block at every 256 byte page, so that the blocks after the first load at addresses ending with 00 (same as `--align-blocks 100').");
+    options.optopt("", "align-blocks", "Keep the blocks from crossing multiples of the given number of bytes (in hex, such as 100 for pages), so that they load at aligned addresses.", "BYTES");
+    options.optopt("", "copies", "Record each entry the given number of times back to back, as publishers did, so that a dropout in one copy doesn't ruin the tape (once by default).", "COUNT");
+    commands::add_variant_options(&mut options);
+    commands::add_duration_options(&mut options);
+    options.optflag("a", "append", "Add the entries to the end of the existing output tape image, instead of creating a new one.");
+    options.optflag("", "batch", "Pack each input file onto a tape of its own, with default names; inputs may also be directories, whose .bin and .rom files are packed, or patterns such as `dumps/*.bin'.");
+    options.optopt("j", "jobs", "Number of files of a `--batch' to pack at the same time (the number of processors by default).", "COUNT");
+    options.optflag("", "watch", "Keep running, and pack the input files again whenever they change.");
+    options.optopt("", "assembler", "Assembler to assemble inputs ending in .asm or .z80 with, zmac, pasmo or sjasmplus (the first one installed by default).", "NAME");
+    options.optopt("", "assembler-args", "Further arguments for the assembler, such as include directories, separated by spaces.", "ARGS");
+    options.optopt("", "depfile", "Write a Make-style dependency file listing every file read into FILE.", "FILE");
+    options.optflag("", "verify-after-write", "Read the written tape image back, and check that it holds the packed data.");
+    options.optflag("", "simulate", "Load the written tape in a simulated Model I, checking that the ROM puts every block where it belongs and would start the program at its entry point, then run the program a little.");
+    options.optflagopt("", "run", "Start an emulator with the tape once it's made, given as `--run=trs80gp', `--run=sdltrs' or `--run=xtrs' (the first one installed by default).", "EMULATOR");
+    options.optopt("", "symbols", "Symbol file or listing (.lst) of the assembler for the first input, to write the symbols of for the debugger of trs80gp, which `--run=trs80gp' loads them into.", "FILE");
+    options.optopt("", "symbols-origin", "Address the symbols were assembled at, if not where the first input runs (listings tell it themselves), for moving them along with the code.", "ADDR");
+    options.optopt("", "symbols-output", "Name of the symbols for the debugger to write (output filename with extension changed to .bds by default).", "FILE");
+    options.optflag("", "run-keys", "Also have the emulator type the commands loading the first entry with SYSTEM and running it, as far as it can be given keys to type (trs80gp).");
+    options.optopt("", "keys-output", "Write the keys loading the first entry with SYSTEM and running it into FILE, for emulators started by test scripts, of the `--keys-format' given, or told by the extension (.lua or .ini for MAME, plain text otherwise).", "FILE");
+    options.optopt("", "keys-format", "Format of the keys file: `text' as typed, `mame' as a Lua script for MAME's `-autoboot_script', or `mame-ini' setting its `autoboot_command' (output filename with extension changed to .keys, .lua or .ini if not given a `--keys-output').", "FORMAT");
+    options.optflag("", "json", "Print a summary of the run as a JSON object instead of the usual messages.");
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "pack", USAGE, true, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    // Nothing but the summary should end up on the standard output:
+    let json_output = matches.opt_present("json");
+    if json_output {
+        console::set_verbosity(console::QUIET);
+    }
+
+    let name_charset = match retrieve_name_charset(&matches) {
+        Some(charset) => { charset },
+        None => { process::exit(1); },
+    };
+    let name_check = match retrieve_name_check(&matches) {
+        Some(check) => { check },
+        None => { process::exit(1); },
+    };
+    let ram_end = match matches.opt_str("ram") {
+        Some(argument) => {
+            match memory::ram_end(&argument) {
+                Some(end) => { Some(end) },
+                None => {
+                    error!("Unknown RAM size `{}', expected `4k', `16k', `32k' or `48k'.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { None },
+    };
+    let overlap_levels = match retrieve_overlap_levels(&matches) {
+        Some(levels) => { levels },
+        None => { process::exit(1); },
+    };
+    let end_record = match matches.opt_str("data-convention") {
+        Some(argument) => {
+            match argument.as_str() {
+                "basic" => { true },
+                "omit" => { false },
+                _ => {
+                    error!("Unknown data entry convention `{}', expected `basic' or `omit'.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { true },
+    };
+    if !end_record && (matches.opt_present("relocate-via") || matches.opt_present("verifier")) {
+        error!("Entries without the end of file record have no entry point, they can't be used together with `--relocate-via' or `--verifier'.");
+        process::exit(1);
+    }
+    let settings = PackSettings {
+        bare_radix: if matches.opt_present("decimal") { 10 } else { 16 },
+        no_autostart: matches.opt_present("no-autostart") || matches.opt_present("data-convention"),
+        end_record,
+        allow_external_entry: matches.opt_present("allow-external-entry"),
+        allow_wrap: matches.opt_present("allow-wrap"),
+        name_charset,
+        keep_case: matches.opt_present("keep-case"),
+        name_check,
+        ram_end,
+        overlap_levels,
+        relocate_via: matches.opt_str("relocate-via"),
+        gap_fill: retrieve_gap_fill(&matches),
+        comments: matches.opt_strs("comment"),
+        verify_block: matches.opt_present("verify-block") || matches.opt_present("verifier"),
+        verifier: matches.opt_present("verifier"),
+        assembler: matches.opt_str("assembler"),
+        assembler_args: matches.opt_str("assembler-args").map(|args| args.split_whitespace().map(|arg| arg.to_owned()).collect()).unwrap_or_default(),
+    };
+
+    let mut format = packing::TapeFormat::standard();
+    if let Some(argument) = matches.opt_str("leader-length") {
+        format.leader_length = match argument.parse::<usize>() {
+            Ok(length) if length > 0 => { length },
+            _ => {
+                error!("Invalid leader length `{}', expected a positive number of bytes.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    format.variant = commands::retrieve_variant(&matches);
+    if matches.opt_present("simulate") && !simulator::is_rom_format(&format.variant) {
+        error!("The tape can't be simulated with `--simulate', the ROM only reads tapes with the standard markers and checksum.");
+        process::exit(1);
+    }
+    format.allow_wrap = settings.allow_wrap;
+    if let Some(argument) = matches.opt_str("block-order") {
+        format.block_order = match packing::BLOCK_ORDERS.iter().find(|&&(name, _order)| name == argument.to_lowercase()) {
+            Some(&(_name, order)) => { order },
+            None => {
+                let names: Vec<&str> = packing::BLOCK_ORDERS.iter().map(|&(name, _order)| name).collect();
+                error!("Unknown block order `{}', expected one of {}.", argument, names.join(", "));
+                process::exit(1);
+            },
+        };
+    }
+    for comment in &settings.comments {
+        if !tape::comment_valid(comment) {
+            error!("The comment `{}' may only contain printable ASCII characters.", comment);
+            process::exit(1);
+        }
+        let mut record = Vec::new();
+        tape::push_comment(comment, &mut record);
+        if record.contains(&format.variant.data) || record.contains(&format.variant.eof) {
+            error!("The comment `{}' would be taken for a block marker, leave it out or use other markers.", comment);
+            process::exit(1);
+        }
+    }
+    format.block_gap = match commands::retrieve_gap_length(&matches, "block-gap") {
+        Some(commands::GapLength::Bytes(length)) => { length },
+        Some(commands::GapLength::Milliseconds(length)) => {
+            ((length as u64) * 1000).div_ceil(audio::BYTE_DURATION_US as u64) as usize
+        },
+        None => { 0 },
+    };
+    if let Some(argument) = matches.opt_str("copies") {
+        format.copies = match argument.parse::<usize>() {
+            Ok(copies) if copies > 0 => { copies },
+            _ => {
+                error!("Invalid number of copies `{}', expected a positive number.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    if matches.opt_present("pad-last-block") {
+        format.pad_last_block = Some(commands::retrieve_byte_option(&matches, "pad-last-block", 0x00));
+    }
+    if matches.opt_present("page-aligned") {
+        if matches.opt_present("align-blocks") {
+            error!("The `--page-aligned' and `--align-blocks' options can't be used together.");
+            process::exit(1);
+        }
+        format.block_alignment = Some(0x100);
+    }
+    if let Some(argument) = matches.opt_str("align-blocks") {
+        format.block_alignment = match numbers::parse_number(&argument, 16) {
+            Ok(alignment) if (1..=0x10000).contains(&alignment) => { Some(alignment as usize) },
+            _ => {
+                error!("Invalid block alignment `{}', expected a number of bytes from 1 to 0x10000.", argument);
+                process::exit(1);
+            },
+        };
+    }
+    if matches.opt_present("sparse") || matches.opt_present("sparse-fill") || matches.opt_present("sparse-min-run") {
+        format.sparse = Some(retrieve_sparse_fill(&matches));
+    }
+    if let Some(argument) = matches.opt_str("block-size") {
+        format.block_size = match argument.parse::<usize>() {
+            Ok(size) if (1..=tape::MAX_BLOCK_SIZE).contains(&size) => { size },
+            _ => {
+                error!("Invalid block size `{}', expected a number of bytes from 1 to {}.", argument, tape::MAX_BLOCK_SIZE);
+                process::exit(1);
+            },
+        };
+    }
+
+    if matches.opt_present("batch") {
+        batch_pack(progname, &matches, &settings, &format);
+    }
+
+    // Collect the entries, the one given through the options comes first:
+    let mut specs = Vec::new();
+    if let Some(name) = matches.opt_str("i") {
+        let mut segments = Vec::new();
+        for argument in matches.opt_strs("segment") {
+            match parse_segment_spec(&argument) {
+                Some(segment) => { segments.push(segment); },
+                None => { process::exit(1); },
+            }
+        }
+        specs.push(EntrySpec {
+            in_path: (name.as_ref() as &path::Path).to_owned(),
+            name: matches.opt_str("n"),
+            base: matches.opt_str("b"),
+            start: matches.opt_str("s"),
+            segments,
+            pokes: matches.opt_strs("poke"),
+            block_addresses: retrieve_block_addresses(&matches),
+        });
+    } else if matches.opt_present("n") || matches.opt_present("b") || matches.opt_present("s") ||
+              matches.opt_present("segment") || matches.opt_present("poke") ||
+              matches.opt_present("block-address") || matches.opt_present("block-address-file") {
+        error!("The `--name', `--base', `--start', `--segment', `--poke' and `--block-address' options apply to the `--input' file, which is not specified.");
+        process::exit(1);
+    }
+    for argument in &matches.free {
+        specs.push(parse_entry_spec(argument));
+    }
+
+    if specs.is_empty() {
+        error!("Input file not specified, please provide it with the `--input' command-line option.");
+        eprintln!();
+        eprintln!("Some mandatory command-line options are missing, see `{} pack --help'.", progname);
+
+        process::exit(1);
+    }
+
+    if matches.opt_present("watch") {
+        if matches.opt_present("a") || json_output || matches.opt_present("run") {
+            error!("The `--watch' option can't be used together with `--append', `--json' or `--run'.");
+            process::exit(1);
+        }
+        let in_paths: Vec<path::PathBuf> = specs.iter().flat_map(|spec| {
+            Some(spec.in_path.clone()).into_iter().chain(spec.segments.iter().map(|segment| segment.0.clone()))
+        }).chain(matches.opt_str("symbols").map(path::PathBuf::from)).collect();
+        watch_inputs(progname, args, &matches, &in_paths);
+    }
+
+    let mut entries = Vec::with_capacity(specs.len());
+    for spec in &specs {
+        match resolve_entry(progname, spec, &settings) {
+            Some(entry) => { entries.push(entry); },
+            None => { process::exit(1); },
+        }
+    }
+    for entry in &entries {
+        let block_count = packing::block_layout(entry, &format).len();
+        if let Some(&(block, _address)) = entry.block_addresses.iter().find(|&&(block, _address)| block >= block_count) {
+            error!("Block {} of `{}' has its load address overridden, but the entry only has {} blocks.",
+                   block + 1, entry.in_path.display(), block_count);
+            process::exit(1);
+        }
+    }
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => {
+            let first = &entries[0];
+            let values = commands::entry_template_values(&first.in_path, &first.name, first.base_address,
+                                                         first.entry_point, first.data.len());
+            commands::expand_output_path(&argument, &values)
+        },
+        None => { files::default_output_path(&specs[0].in_path, "cas") },
+    };
+
+    // Only append to tapes which are in a good shape:
+    let existing_tape = if matches.opt_present("a") {
+        match load_existing_tape(&out_filepath, &format.variant) {
+            Some(buffer) => { Some(buffer) },
+            None => { process::exit(1); },
+        }
+    } else {
+        None
+    };
+
+    for (index, entry) in entries.iter().enumerate() {
+        status!("Input filename:       `{}'", entry.in_path.display());
+        if index == 0 {
+            status!("Output filename:      `{}'", out_filepath.display());
+        }
+        status!("Tape data entry name: `{}'", String::from_utf8(entry.name.clone()).expect("invalid characters in the tape data entry name, these should've been filtered out"));
+        status!("Base address:          0x{:04X}", entry.base_address);
+        if entry.end_record {
+            status!("Entry point address:   0x{:04X}", entry.entry_point);
+        } else {
+            status!("Entry point address:   (none, no end of file record)");
+        }
+        status!("");
+
+        if entry.in_path == out_filepath {
+            error!("The input and output files are the same, aborting to prevent data loss.");
+            process::exit(1);
+        }
+    }
+
+    // Perform the packing:
+    let mut tape = packing::pack(&entries, &format, &out_filepath, existing_tape);
+    if let Some(ref buffer) = tape {
+        commands::report_tape_duration(&matches, (buffer.len() as u64) * (audio::BYTE_DURATION_US as u64));
+    }
+    if tape.is_some() && matches.opt_present("verify-after-write") && !verify_written_tape(&out_filepath, &entries, &format) {
+        tape = None;
+    }
+    if matches.opt_present("simulate") && tape.as_ref().is_some_and(|buffer| !simulate_written_tape(buffer, &entries, &format, settings.ram_end)) {
+        tape = None;
+    }
+    if json_output {
+        println!("{}", json_summary(&entries, &out_filepath, tape.as_deref(), &format));
+    }
+    if tape.is_none() {
+        process::exit(1);
+    }
+
+    let symbols_filepath = write_symbols(&matches, &out_filepath, &entries[0]);
+    if matches.opt_present("keys-output") || matches.opt_present("keys-format") {
+        write_keys_file(&matches, &out_filepath, &entries);
+    }
+
+    // The tape being appended to was read too, but it's the target:
+    if let Some(name) = matches.opt_str("depfile") {
+        let dep_filepath = (name.as_ref() as &path::Path).to_owned();
+        let inputs: Vec<path::PathBuf> = files::loaded_files().into_iter().filter(|path| *path != out_filepath).collect();
+        if !files::write_dependency_file(&dep_filepath, &out_filepath, &inputs) {
+            process::exit(1);
+        }
+    }
+    if matches.opt_present("run") {
+        run_emulator(&matches, &out_filepath, &entries, symbols_filepath.as_deref());
+    }
+    process::exit(0);
+}
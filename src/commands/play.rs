@@ -0,0 +1,321 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::path;
+use std::process;
+use std::thread;
+use std::time;
+
+use audio;
+use commands;
+use commands::wav;
+use console;
+use gpio;
+use sound;
+
+
+// The recording goes to the player in pieces of this many seconds, kept just
+// a little ahead of what's playing, so that pausing takes effect at once:
+const CHUNK_SECONDS: f64 = 0.05;
+const LEAD_SECONDS: f64 = 0.25;
+
+// Plays the recording with the keyboard in control, as getting the timing of
+// the load right on a real machine often takes a couple of tries.  Space
+// pauses and resumes, `r' goes back to the start of the entry playing, and
+// `q' stops:
+fn play_with_keys(player: &sound::Program, device: Option<&str>, keys: &console::KeyReader,
+                  samples: &[i16], starts: &[usize], format: &audio::AudioFormat) -> Result<(), String> {
+    let mut child = sound::start_player(player, device)?;
+    let mut stdin = match child.stdin.take() {
+        Some(stdin) => { stdin },
+        None => { return Err(format!("Failed to pass the recording to `{}'", player.name)); },
+    };
+
+    let rate = format.sample_rate as f64;
+    let chunk_length = ((rate * CHUNK_SECONDS) as usize).max(1);
+    let started = time::Instant::now();
+    let mut written: usize = 0;
+    let mut position: usize = 0;
+    let mut paused = false;
+
+    // The player quitting early shows in its exit status:
+    let mut result = stdin.write_all(&audio::wav_stream_header(format));
+    while result.is_ok() && position < samples.len() {
+        while let Some(key) = keys.key() {
+            match key.to_ascii_lowercase() {
+                b' ' => {
+                    paused = !paused;
+                    status!("{}", if paused { "Paused, press space to go on." } else { "Playing." });
+                },
+                b'r' => {
+                    let entry = starts.iter().rposition(|start| *start <= position).unwrap_or(0);
+                    position = starts.get(entry).cloned().unwrap_or(0);
+                    status!("Back to the start of entry {}{}.", entry + 1, if paused { ", press space to play it" } else { "" });
+                },
+                b'q' => {
+                    status!("Stopped.");
+                    position = samples.len();
+                },
+                _ => { },
+            }
+        }
+        if position >= samples.len() {
+            break;
+        }
+
+        // Keep the player fed with silence while paused:
+        let ahead = (written as f64) / rate - started.elapsed().as_secs_f64();
+        if ahead > LEAD_SECONDS {
+            thread::sleep(time::Duration::from_millis(10));
+            continue;
+        }
+        let chunk = if paused {
+            vec![0; chunk_length]
+        } else {
+            let end = (position + chunk_length).min(samples.len());
+            let chunk = samples[position..end].to_vec();
+            position = end;
+            chunk
+        };
+        written += chunk.len();
+        result = stdin.write_all(&audio::wav_stream_data(&chunk, format));
+    }
+    drop(stdin);
+
+    sound::wait_for_player(player, child)
+}
+
+// Edges later than this are counted and reported, the ROM's loader takes
+// some more, but it points at other programs getting in the way:
+const GPIO_LATE_US: u64 = 50;
+// Keys are only looked at with at least this long to go to the next edge:
+const GPIO_SLACK_US: u64 = 2000;
+
+// Plays the recording on a GPIO pin, waiting out the time to every edge of
+// the signal, the last bit of it spinning rather than sleeping, for the
+// timing to stay exact.  Space pauses and resumes, `q' stops:
+fn play_on_pin(pin: &mut gpio::Pin, keys: Option<&console::KeyReader>, edges: &[(u64, bool)]) -> Result<(), String> {
+    let started = time::Instant::now();
+    let elapsed_us = || started.elapsed().as_micros() as u64;
+    // Time spent paused, which the edges are put off by:
+    let mut paused_us = 0;
+    let mut late = 0;
+    let mut latest = 0;
+
+    for &(time, active) in edges {
+        let deadline = time + paused_us;
+        while elapsed_us() + GPIO_SLACK_US < deadline {
+            match keys.and_then(|keys| keys.key()).map(|key| key.to_ascii_lowercase()) {
+                Some(b' ') => {
+                    status!("Paused, press space to go on.");
+                    let paused_at = elapsed_us();
+                    loop {
+                        match keys.and_then(|keys| keys.key()).map(|key| key.to_ascii_lowercase()) {
+                            Some(b' ') => { break; },
+                            Some(b'q') => {
+                                status!("Stopped.");
+                                return Ok(());
+                            },
+                            _ => { thread::sleep(time::Duration::from_millis(10)); },
+                        }
+                    }
+                    paused_us += elapsed_us() - paused_at;
+                    status!("Playing.");
+                },
+                Some(b'q') => {
+                    status!("Stopped.");
+                    return Ok(());
+                },
+                _ => {
+                    thread::sleep(time::Duration::from_millis(1));
+                },
+            }
+        }
+        let deadline = time + paused_us;
+        while elapsed_us() < deadline {
+            std::hint::spin_loop();
+        }
+        pin.set(active)?;
+
+        let lateness = elapsed_us() - deadline;
+        if lateness > GPIO_LATE_US {
+            late += 1;
+            latest = latest.max(lateness);
+        }
+    }
+
+    if late != 0 {
+        warning!("{} edges of the signal came late, by up to {} us, try running with a real time priority, such as with `chrt -f 50', on an otherwise idle Pi.", late, latest);
+    }
+    Ok(())
+}
+
+fn play_gpio(matches: &getopts::Matches, argument: &str, in_filepath: &path::Path) -> ! {
+    let number = match argument.parse::<u32>() {
+        Ok(number) if number <= 53 => { number },
+        _ => {
+            error!("Invalid GPIO pin `{}', expected the BCM number of a pin, such as 18.", argument);
+            process::exit(1);
+        },
+    };
+
+    // The pin follows the positive halves of the pulses of a recording made
+    // at its own rate:
+    let variant = commands::retrieve_variant(matches);
+    let mut audio_format = commands::retrieve_audio_format(matches);
+    let active_high = !audio_format.invert;
+    audio_format.sample_rate = gpio::SAMPLE_RATE;
+    audio_format.channels = 1;
+    audio_format.invert = false;
+    audio_format.invert_right = false;
+    audio_format.shape = audio::PulseShape::Square;
+    // Set the pin up first, so that a missing one doesn't waste the time
+    // taken by the encoding:
+    let mut pin = match gpio::Pin::open(number, active_high) {
+        Ok(pin) => { pin },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+    let (buffer, entries) = wav::load_tape(matches, in_filepath, &variant);
+    let (samples, _starts) = wav::encode_recording(matches, &buffer, &entries, &audio_format);
+    let edges = gpio::edges(&samples);
+    drop(samples);
+
+    status!("Playing on GPIO {}, start the load on the machine now, such as with SYSTEM.", number);
+    let keys = console::KeyReader::start();
+    if keys.is_some() {
+        status!("Press space to pause and go on, `q' to stop.");
+    }
+    let result = play_on_pin(&mut pin, keys.as_ref(), &edges);
+    drop(pin);
+    // Exiting doesn't run the destructors, the terminal is put back first:
+    drop(keys);
+    match result {
+        Ok(()) => {
+            status!("Finished playing `{}'.", in_filepath.display());
+            process::exit(0);
+        },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    }
+}
+
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to play.", "FILE");
+    options.optopt("", "player", "Program to play the recording with, one of aplay, paplay, ffplay or play (the first one installed by default).", "NAME");
+    options.optopt("", "gpio", "Play on the GPIO pin with the given BCM number of a Raspberry Pi instead of the sound card, driving the cassette input of the machine through a coupling capacitor and a divider. `--invert' makes the pin go low for the pulses.", "PIN");
+    options.optopt("", "device", "Audio output device to play the recording on, as listed by `--list-audio-devices', such as `hw:1,0' for aplay, a sink name for paplay, or the name of a CoreAudio or WASAPI device for play (the default device by default).", "DEVICE");
+    options.optflag("", "list-audio-devices", "List the output devices the player can be told to use with `--device', and stop.");
+    wav::add_recording_options(&mut options);
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "play", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    if matches.opt_present("list-audio-devices") {
+        match sound::find_program(sound::PLAYERS, "player", matches.opt_str("player").as_deref()) {
+            Ok(player) => { commands::list_audio_devices(player, false); },
+            Err(error) => {
+                error!("{}.", error);
+                process::exit(1);
+            },
+        }
+    }
+    let in_filepath = commands::retrieve_input_path(progname, "play", &matches);
+
+    if let Some(argument) = matches.opt_str("gpio") {
+        if matches.opt_present("player") || matches.opt_present("device") {
+            error!("The `--gpio' option can't be used together with `--player' or `--device'.");
+            process::exit(1);
+        }
+        play_gpio(&matches, &argument, &in_filepath);
+    }
+
+    // Find the player first, so that a missing one doesn't waste the time
+    // taken by the encoding:
+    let player = match sound::find_program(sound::PLAYERS, "player", matches.opt_str("player").as_deref()) {
+        Ok(player) => { player },
+        Err(error) => {
+            error!("{}, write a WAV file with the `wav' command instead.", error);
+            process::exit(1);
+        },
+    };
+
+    let variant = commands::retrieve_variant(&matches);
+    let audio_format = commands::retrieve_audio_format(&matches);
+    let (buffer, entries) = wav::load_tape(&matches, &in_filepath, &variant);
+
+    let device = matches.opt_str("device");
+    // The encoding may stop the program on an error, so it's all done before
+    // the terminal is switched over to reading keys:
+    let plan = wav::plan_recording(&matches, &entries);
+    let recording = if io::stdin().is_terminal() {
+        Some(wav::encode_planned(&matches, &plan, &buffer, &entries, &audio_format))
+    } else {
+        None
+    };
+    let keys = match recording {
+        Some(_) => { console::KeyReader::start() },
+        None => { None },
+    };
+    status!("Playing through `{}', start the load on the machine now, such as with SYSTEM.", player.name);
+    let result = match (recording, keys) {
+        (Some((samples, starts)), Some(keys)) => {
+            status!("Press space to pause and go on, `r' to go back to the start of the entry, `q' to stop.");
+            play_with_keys(player, device.as_deref(), &keys, &samples, &starts, &audio_format)
+        },
+        // Without the keys to go back with, the recording goes to the player
+        // as it's made, rather than being held in memory whole:
+        _ => {
+            sound::play(player, device.as_deref(), &mut |stdin| {
+                stdin.write_all(&audio::wav_stream_header(&audio_format)).map_err(|error| error.to_string())?;
+                wav::stream_recording(&matches, &plan, &buffer, &entries, &audio_format,
+                                      &mut |samples| stdin.write_all(&audio::wav_stream_data(samples, &audio_format)).map_err(|error| error.to_string()))?;
+                Ok(())
+            })
+        },
+    };
+    match result {
+        Ok(()) => {
+            status!("Finished playing `{}'.", in_filepath.display());
+            process::exit(0);
+        },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    }
+}
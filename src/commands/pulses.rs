@@ -0,0 +1,185 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use audio;
+use commands;
+use commands::decode;
+use files;
+use filters;
+use tape;
+
+
+// Pulse lists are text, one pulse per line, giving the time it starts at in
+// microseconds from the start of the tape, optionally followed by its length
+// in microseconds, the ROM's by default.  Lines starting with `#' are
+// comments:
+//
+//   # Pulses of `game.wav', in microseconds:
+//   1234.5
+//   2230.1 250
+//
+fn is_recording(in_path: &path::Path) -> bool {
+    match in_path.extension() {
+        Some(extension) => { files::RECORDING_EXTENSIONS.contains(&extension.to_string_lossy().to_lowercase().as_str()) },
+        None => { false },
+    }
+}
+
+fn export(matches: &getopts::Matches, in_filepath: &path::Path) -> ! {
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(in_filepath, "pulses") },
+    };
+    if out_filepath == in_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    // Recordings give the pulses as they came off the tape, tape images the
+    // pulses the ROM would record for them:
+    let times = if is_recording(in_filepath) {
+        let mut recording = match decode::load_recording(in_filepath, matches.opt_str("converter").as_deref()) {
+            Some(recording) => { recording },
+            None => { process::exit(1); },
+        };
+        filters::apply(&commands::retrieve_filters(matches, recording.sample_rate), &mut recording.samples, recording.sample_rate);
+        let rate = recording.sample_rate as f64;
+        audio::find_pulses(&recording.samples, recording.sample_rate).into_iter()
+                          .map(|position| (position as f64) * 1_000_000.0 / rate).collect::<Vec<f64>>()
+    } else {
+        let buffer = match files::load_file(in_filepath) {
+            Some(buffer) => { buffer },
+            None => { process::exit(1); },
+        };
+        if let Err(error) = tape::parse_tape(&buffer, &commands::retrieve_variant(matches)) {
+            warning!("{}: {}, the pulses are listed anyway.", in_filepath.display(), error);
+        }
+        audio::tape_pulse_times(&buffer)
+    };
+    if times.is_empty() {
+        error!("No pulses found in `{}'.", in_filepath.display());
+        process::exit(1);
+    }
+
+    let mut list = format!("# Pulses of `{}', in microseconds from the start.\n", in_filepath.display());
+    for time in &times {
+        list.push_str(&format!("{:.1}\n", time));
+    }
+    status!("{}: {} pulses over {:.1} seconds.", in_filepath.display(), times.len(), times[times.len() - 1] / 1_000_000.0);
+    if !files::write_file(list.as_bytes(), &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+fn parse_pulse_list(text: &str, in_filepath: &path::Path) -> Vec<(f64, f64)> {
+    let mut pulses: Vec<(f64, f64)> = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let time = fields.next().and_then(|field| field.parse::<f64>().ok());
+        let length = match fields.next() {
+            Some(field) => { field.parse::<f64>().ok() },
+            None => { Some(audio::PULSE_US as f64) },
+        };
+        let (time, length) = match (time, length, fields.next()) {
+            (Some(time), Some(length), None) if time >= 0.0 && time.is_finite() && length > 0.0 && length < 100_000.0 => { (time, length) },
+            _ => {
+                error!("{}, line {}: Expected the time of a pulse in microseconds, optionally followed by its length, found `{}'.",
+                       in_filepath.display(), index + 1, line);
+                process::exit(1);
+            },
+        };
+        if let Some(&(previous, _length)) = pulses.last() {
+            if time <= previous {
+                error!("{}, line {}: The pulse at {} us comes before the one on the line above, the pulses have to be in order.",
+                       in_filepath.display(), index + 1, time);
+                process::exit(1);
+            }
+        }
+        pulses.push((time, length));
+    }
+
+    pulses
+}
+
+fn import(matches: &getopts::Matches, in_filepath: &path::Path) -> ! {
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => { files::default_output_path(in_filepath, "wav") },
+    };
+    if out_filepath == in_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+
+    let buffer = match files::load_file(in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let pulses = parse_pulse_list(&String::from_utf8_lossy(&buffer), in_filepath);
+    if pulses.is_empty() {
+        error!("No pulses listed in `{}'.", in_filepath.display());
+        process::exit(1);
+    }
+
+    let audio_format = commands::retrieve_audio_format(matches);
+    let samples = audio::encode_pulses(&pulses, &audio_format);
+    status!("Recorded {} pulses, {:.1} seconds of audio.", pulses.len(), (samples.len() as f64) / (audio_format.sample_rate as f64));
+    if !files::write_file(&audio::generate_wav_file(&samples, &audio_format), &out_filepath) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
+
+pub const USAGE: &str = "-i <file> | --import <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The recording or the tape image to list the pulses of, recordings as read from the tape, tape images as the ROM would record them.", "FILE");
+    options.optopt("o", "output", "Name of the pulse list to write, or of the WAV file with `--import' (input filename with extension changed to .pulses or .wav by default).", "FILE");
+    options.optopt("", "import", "Record the pulses of a pulse list into a WAV file instead, exactly at their times.", "FILE");
+    options.optopt("", "converter", "Program to convert compressed recordings with, ffmpeg or sox (the first one installed by default).", "NAME");
+    commands::add_filter_options(&mut options);
+    commands::add_audio_options(&mut options);
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "pulses", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    if let Some(argument) = matches.opt_str("import") {
+        import(&matches, argument.as_ref());
+    }
+    let in_filepath = commands::retrieve_input_path(progname, "pulses", &matches);
+    export(&matches, &in_filepath);
+}
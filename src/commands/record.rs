@@ -0,0 +1,221 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::io::Read;
+use std::process;
+
+use commands;
+use commands::decode;
+use files;
+use filters;
+use sound;
+use tape;
+
+
+// Peak level the signal has to reach to be taken for a tape playing rather
+// than for the noise of the input, about 30 dB below full scale:
+const SIGNAL_LEVEL: i32 = 1024;
+
+// The input is looked at in chunks of a tenth of a second:
+const CHUNKS_PER_SECOND: u32 = 10;
+
+fn retrieve_seconds(matches: &getopts::Matches, option: &str, default: Option<f64>) -> Option<f64> {
+    let argument = match matches.opt_str(option) {
+        Some(argument) => { argument },
+        None => { return default; },
+    };
+    match argument.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => { Some(seconds) },
+        _ => {
+            error!("Invalid `--{}' length `{}', expected a positive number of seconds.", option, argument);
+            process::exit(1);
+        },
+    }
+}
+
+pub const USAGE: &str = "-o <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("o", "output", "Name of the tape image to write, updated after every stretch of signal decoded.", "FILE");
+    options.optopt("", "recorder", "Program to capture the audio with, one of arecord, parec or rec (the first one installed by default).", "NAME");
+    options.optopt("", "device", "Audio input device to capture from, as listed by `--list-audio-devices', such as `hw:1,0' for arecord, a source name for parec, or the name of a CoreAudio or WASAPI device for rec (the default device by default).", "DEVICE");
+    options.optflag("", "list-audio-devices", "List the input devices the recorder can be told to use with `--device', and stop.");
+    options.optopt("", "sample-rate", "Sample rate to capture at, 22050, 44100 (the default) or 48000 Hz.", "HZ");
+    options.optopt("", "entries", "Number of entries to capture before stopping (1 by default).", "COUNT");
+    options.optopt("", "end-silence", "Length of the silence which ends a stretch of signal, after which it's decoded, in seconds (2 by default).", "SECONDS");
+    options.optopt("", "timeout", "Give up if no signal comes within this many seconds (waits for good by default).", "SECONDS");
+    commands::add_filter_options(&mut options);
+    commands::add_decoder_options(&mut options);
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "record", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    if matches.opt_present("list-audio-devices") {
+        match sound::find_program(sound::RECORDERS, "recorder", matches.opt_str("recorder").as_deref()) {
+            Ok(recorder) => { commands::list_audio_devices(recorder, true); },
+            Err(error) => {
+                error!("{}.", error);
+                process::exit(1);
+            },
+        }
+    }
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => {
+            error!("No output file given, use `{} record -o <file>'.", progname);
+            process::exit(1);
+        },
+    };
+    let variant = commands::retrieve_variant(&matches);
+    let sample_rate = commands::retrieve_sample_rate(&matches);
+    let filters = commands::retrieve_filters(&matches, sample_rate);
+    // Signals meant to be amplified are taken for one at their level:
+    let signal_level = match filters.gain {
+        filters::Gain::Fixed(decibels) => { ((SIGNAL_LEVEL as f64) / 10f64.powf(decibels / 20.0)).clamp(16.0, 16384.0) as i32 },
+        _ => { SIGNAL_LEVEL },
+    };
+    let settings = commands::retrieve_decoder_settings(&matches);
+    let wanted_entries = match matches.opt_str("entries") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(count) if count > 0 => { count },
+                _ => {
+                    error!("Invalid number of entries `{}', expected a positive number.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { 1 },
+    };
+    let end_silence = retrieve_seconds(&matches, "end-silence", Some(2.0)).unwrap_or(2.0);
+    let timeout = retrieve_seconds(&matches, "timeout", None);
+
+    let recorder = match sound::find_program(sound::RECORDERS, "recorder", matches.opt_str("recorder").as_deref()) {
+        Ok(recorder) => { recorder },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+    let mut child = match sound::capture(recorder, matches.opt_str("device").as_deref(), sample_rate) {
+        Ok(child) => { child },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+    let mut input = match child.stdout.take() {
+        Some(input) => { input },
+        None => { process::exit(1); },
+    };
+    status!("Listening through `{}' at {} Hz, start playing the tape now.", recorder.name, sample_rate);
+
+    let chunk_length = (sample_rate / CHUNKS_PER_SECOND) as usize;
+    let end_chunks = ((end_silence * (CHUNKS_PER_SECOND as f64)).ceil() as usize).max(1);
+    let timeout_chunks = timeout.map(|timeout| (timeout * (CHUNKS_PER_SECOND as f64)).ceil() as usize);
+
+    let mut chunk = vec![0u8; chunk_length * 2];
+    let mut stretch: Vec<i16> = Vec::new();
+    let mut quiet_chunks = 0;
+    let mut waited_chunks = 0;
+    let mut captured = 0;
+    let mut tape_image: Vec<u8> = Vec::new();
+    let mut entry_count = 0;
+    let mut written = false;
+
+    loop {
+        let finished_input = input.read_exact(&mut chunk).is_err();
+        let samples: Vec<i16> = chunk.chunks(2).map(|sample| ((sample[0] as u16) | ((sample[1] as u16) << 8)) as i16).collect();
+        let peak = samples.iter().map(|sample| (*sample as i32).abs()).max().unwrap_or(0);
+
+        if !finished_input {
+            captured += samples.len();
+            if peak >= signal_level {
+                if stretch.is_empty() {
+                    status!("Receiving...");
+                }
+                stretch.extend_from_slice(&samples);
+                quiet_chunks = 0;
+            } else if !stretch.is_empty() {
+                stretch.extend_from_slice(&samples);
+                quiet_chunks += 1;
+            } else {
+                waited_chunks += 1;
+                if timeout_chunks.is_some_and(|timeout_chunks| waited_chunks >= timeout_chunks) {
+                    error!("No signal came before the `--timeout' ran out, check the cabling and the volume of the player.");
+                    let _ = child.kill();
+                    process::exit(1);
+                }
+            }
+        }
+        if stretch.is_empty() || (quiet_chunks < end_chunks && !finished_input) {
+            if finished_input {
+                break;
+            }
+            continue;
+        }
+
+        // The signal has ended, see what it held:
+        status!("Decoding {:.1} seconds of signal.", (stretch.len() as f64) / (sample_rate as f64));
+        let start = captured - stretch.len();
+        let mut samples = stretch.split_off(0);
+        filters::apply(&filters, &mut samples, sample_rate);
+        if let Some((decoded, _entries)) = decode::decode_stretch(samples, sample_rate, &variant, &settings, start, entry_count) {
+            tape_image.extend_from_slice(&decoded);
+            entry_count = match tape::parse_tape(&tape_image, &variant) {
+                Ok(entries) => { entries.len() },
+                Err(_error) => { entry_count },
+            };
+
+            // Keep what's been captured so far, in case the rest fails:
+            let stored = if written { files::store_file(&tape_image, &out_filepath) } else { files::write_file(&tape_image, &out_filepath) };
+            if !stored {
+                let _ = child.kill();
+                process::exit(1);
+            }
+            written = true;
+        }
+        quiet_chunks = 0;
+        waited_chunks = 0;
+
+        if entry_count >= wanted_entries || finished_input {
+            break;
+        }
+        status!("Captured {} of {} entries, start playing the next one.", entry_count, wanted_entries);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+
+    if entry_count < wanted_entries {
+        error!("The input ended after {} of {} entries.", entry_count, wanted_entries);
+        process::exit(1);
+    }
+    status!("Captured {} entries into `{}'.", entry_count, out_filepath.display());
+    process::exit(0);
+}
@@ -0,0 +1,322 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use audio;
+use coco;
+use commands;
+use console;
+use hashes;
+use packing;
+use simulator;
+use tape;
+use tapetext;
+
+
+// The self-test packs a few tapes of made up data in memory, the way the
+// `pack' command does, and checks that everything else reads them back as
+// they were: the parser, the checksums, the text form, the recordings, the
+// simulated ROM and the Color Computer conversion.  Nothing is written, it's
+// a quick check of a new build, such as on a platform of its own.
+struct TestTape {
+    description: String,
+    // Names, load addresses and lengths of the entries:
+    entries: Vec<(&'static [u8], u16, usize)>,
+    format: packing::TapeFormat,
+}
+
+fn test_tape(description: &str, entries: &[(&'static [u8], u16, usize)]) -> TestTape {
+    TestTape { description: description.to_owned(), entries: entries.to_vec(), format: packing::TapeFormat::standard() }
+}
+
+fn test_tapes() -> Vec<TestTape> {
+    let mut tapes = Vec::new();
+
+    tapes.push(test_tape("a standard tape", &[(b"GAME  ", 0x5200, 3 * tape::MAX_BLOCK_SIZE + 17)]));
+    tapes.push(test_tape("a tape of several entries", &[(b"FIRST ", 0x5200, 300), (b"SECOND", 0x7000, 40), (b"THIRD ", 0x4A00, 1)]));
+    tapes.push(test_tape("a tape of blocks off page boundaries", &[(b"ODD   ", 0x6F81, 2 * tape::MAX_BLOCK_SIZE)]));
+
+    let mut tape = test_tape("a tape of one byte blocks", &[(b"TINY  ", 0x5200, 20)]);
+    tape.format.block_size = 1;
+    tapes.push(tape);
+
+    let mut tape = test_tape("a high speed tape", &[(b"FAST  ", 0x5200, 600)]);
+    tape.format.variant = tape.format.variant.high_speed();
+    tapes.push(tape);
+
+    for &(name, checksum) in tape::CHECKSUMS.iter().filter(|&&(_name, checksum)| checksum != tape::Checksum::Sum) {
+        let mut tape = test_tape(&format!("a tape of {} checksums", name), &[(b"CHECK ", 0x5200, 2 * tape::MAX_BLOCK_SIZE + 5)]);
+        tape.format.variant.checksum = checksum;
+        tapes.push(tape);
+    }
+
+    tapes
+}
+
+// Data which looks like a program as far as the tape is concerned, every
+// byte value turning up, the same every run:
+fn test_data(length: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2_654_435_761) | 1;
+    (0..length).map(|_index| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        (state >> 24) as u8
+    }).collect()
+}
+
+// The messages of the packer would get in between the results, so only its
+// errors and warnings are shown:
+fn pack_entries(entries: &[packing::PackEntry], format: &packing::TapeFormat) -> Vec<u8> {
+    let verbosity = console::verbosity();
+    console::set_verbosity(console::QUIET);
+    let mut buffer = Vec::new();
+    for entry in entries {
+        packing::generate_tape_entry(entry, &mut buffer, format);
+    }
+    console::set_verbosity(verbosity);
+    buffer
+}
+
+fn pack_test_tape(test: &TestTape) -> (Vec<packing::PackEntry>, Vec<u8>) {
+    let entries: Vec<packing::PackEntry> = test.entries.iter().enumerate().map(|(index, &(name, address, length))| {
+        packing::PackEntry::linear(path::Path::new(""), test_data(length, index as u32 + 1), name.to_vec(), address, address)
+    }).collect();
+    let buffer = pack_entries(&entries, &test.format);
+    (entries, buffer)
+}
+
+// The entries read back, each as it was packed:
+fn check_parse(entries: &[packing::PackEntry], buffer: &[u8], format: &packing::TapeFormat) -> Result<Vec<tape::Entry>, String> {
+    let parsed = tape::parse_tape(buffer, &format.variant)?;
+    if parsed.len() != entries.len() {
+        return Err(format!("{} entries read back of the {} packed", parsed.len(), entries.len()));
+    }
+    for (entry, read) in entries.iter().zip(&parsed) {
+        let name = read.name_string();
+        if read.name != entry.name {
+            return Err(format!("The entry `{}' reads back as `{}'", String::from_utf8_lossy(&entry.name), name));
+        }
+        let blocks = entry.data.len().div_ceil(format.block_size);
+        if read.blocks.len() != blocks {
+            return Err(format!("The entry `{}' reads back in {} blocks rather than {}", name, read.blocks.len(), blocks));
+        }
+        if read.bad_checksum_count() != 0 {
+            return Err(format!("{} blocks of the entry `{}' read back with bad checksums", read.bad_checksum_count(), name));
+        }
+        match read.memory_image() {
+            Some((start, ref image, false)) if start == entry.base_address && *image == entry.data => {},
+            _ => { return Err(format!("The data of the entry `{}' reads back differently", name)); },
+        }
+        if read.entry_point != Some(entry.entry_point) {
+            return Err(format!("The entry point of the entry `{}' reads back differently", name));
+        }
+    }
+    Ok(parsed)
+}
+
+// Packing what the parser read gives the very same tape image:
+fn check_repack(parsed: &[tape::Entry], buffer: &[u8], format: &packing::TapeFormat) -> Result<(), String> {
+    let mut entries = Vec::new();
+    for read in parsed {
+        let (start, image, _has_holes) = match read.memory_image() {
+            Some(image) => { image },
+            None => { return Err(format!("The entry `{}' has no data", read.name_string())); },
+        };
+        entries.push(packing::PackEntry::linear(path::Path::new(""), image, read.name.clone(), start, read.entry_point.unwrap_or(start)));
+    }
+    compare_bytes(&pack_entries(&entries, format), buffer, "packed again")
+}
+
+fn compare_bytes(result: &[u8], expected: &[u8], what: &str) -> Result<(), String> {
+    if result == expected {
+        return Ok(());
+    }
+    let difference = result.iter().zip(expected).position(|(result, expected)| result != expected)
+                           .unwrap_or(result.len().min(expected.len()));
+    Err(format!("The tape image {} differs from byte {} on", what, difference))
+}
+
+// Damaging a block, in its data or in its checksum, shows up as a bad
+// checksum of that block and no other:
+fn check_checksums(parsed: &[tape::Entry], buffer: &[u8], format: &packing::TapeFormat) -> Result<(), String> {
+    for (entry_index, entry) in parsed.iter().enumerate() {
+        for (block_index, block) in entry.blocks.iter().enumerate() {
+            // The data follows the marker, the length and the address:
+            let data_offset = block.offset + 4;
+            for &offset in &[data_offset, data_offset + block.data.len()] {
+                let mut damaged = buffer.to_vec();
+                damaged[offset] ^= 0x01;
+                let reread = tape::parse_tape(&damaged, &format.variant)?;
+                let bad: Vec<(usize, usize)> = reread.iter().enumerate().flat_map(|(entry_index, entry)| {
+                    entry.blocks.iter().enumerate().filter(|&(_block_index, block)| !block.checksum_ok())
+                                .map(move |(block_index, _block)| (entry_index, block_index))
+                }).collect();
+                if bad != [(entry_index, block_index)] {
+                    return Err(format!("Damaging the byte at offset {}, in block {} of the entry `{}', isn't caught by its checksum alone",
+                                       offset, block_index + 1, entry.name_string()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_text(parsed: &[tape::Entry], buffer: &[u8], format: &packing::TapeFormat) -> Result<(), String> {
+    let text = tapetext::generate_text(buffer, parsed, &format.variant, "Self-test tape.");
+    let (tape_image, _variant) = tapetext::parse_text(&text)?;
+    compare_bytes(&tape_image, buffer, "made of its text form")
+}
+
+// Recorded at every sample rate, and decoded again from the WAV file, the
+// recording gives back the tape from the first sync byte on:
+fn check_recording(buffer: &[u8], format: &packing::TapeFormat) -> Result<(), String> {
+    let expected = match buffer.iter().position(|byte| *byte == format.variant.sync) {
+        Some(sync_offset) => { &buffer[sync_offset..] },
+        None => { return Err("The tape image has no sync byte".to_owned()); },
+    };
+    for &sample_rate in audio::SAMPLE_RATES {
+        let mut audio_format = audio::AudioFormat::standard();
+        audio_format.sample_rate = sample_rate;
+        let samples = audio::encode_tape(buffer, &[], &audio_format, &mut |_done| {});
+        let recording = audio::parse_wav_file(&audio::generate_wav_file(&samples, &audio_format))?;
+        if recording.samples != samples {
+            return Err(format!("The samples of the WAV file at {} Hz read back differently", sample_rate));
+        }
+        let decoded = audio::decode_samples(&recording, format.variant.sync, 1.0, &mut |_done| {}).bytes;
+        compare_bytes(&decoded, expected, &format!("recorded at {} Hz and decoded", sample_rate))?;
+    }
+    Ok(())
+}
+
+// SYSTEM in the simulated machine loads every entry where it belongs:
+fn check_simulator(entries: &[packing::PackEntry], buffer: &[u8]) -> Result<(), String> {
+    let mut machine = simulator::Machine::new(buffer, simulator::default_ram_end());
+    for entry in entries {
+        let load = machine.system_load(Some(&entry.name))?;
+        let name = String::from_utf8_lossy(&entry.name).trim_end().to_owned();
+        if load.blocks.iter().any(|block| !block.checksum_ok) {
+            return Err(format!("The ROM reads the entry `{}' with bad checksums", name));
+        }
+        let start = entry.base_address as usize;
+        if machine.memory[start..start + entry.data.len()] != entry.data[..] {
+            return Err(format!("The ROM loads the entry `{}' differently", name));
+        }
+        if load.entry_point != entry.entry_point {
+            return Err(format!("The ROM takes the entry point of the entry `{}' differently", name));
+        }
+    }
+    Ok(())
+}
+
+// Made into a Color Computer file and back, the entries keep their data and
+// their addresses:
+fn check_coco(parsed: &[tape::Entry]) -> Result<(), String> {
+    for entry in parsed {
+        let file = match coco::from_entry(entry) {
+            Some(file) => { file },
+            None => { return Err(format!("The entry `{}' can't be made into a Color Computer file", entry.name_string())); },
+        };
+        let mut buffer = Vec::new();
+        coco::generate_coco_file(&file, &mut buffer);
+        if !coco::is_coco_tape(&buffer) {
+            return Err(format!("The Color Computer file of the entry `{}' isn't recognized as one", entry.name_string()));
+        }
+        let read = coco::parse_coco_tape(&buffer)?;
+        match read.first() {
+            Some(read) if read.data == file.data && read.load_address == file.load_address && read.exec_address == file.exec_address
+                          && read.bad_checksums == 0 => {},
+            _ => { return Err(format!("The Color Computer file of the entry `{}' reads back differently", entry.name_string())); },
+        }
+    }
+    Ok(())
+}
+
+// The hashes against the answers their specifications give:
+fn check_hashes() -> Result<(), String> {
+    if hashes::crc32(b"123456789") != 0xCBF4_3926 {
+        return Err("CRC-32 gives the wrong result".to_owned());
+    }
+    if hashes::sha1(b"abc")[..] != [0xA9, 0x99, 0x3E, 0x36, 0x47, 0x06, 0x81, 0x6A, 0xBA, 0x3E,
+                                      0x25, 0x71, 0x78, 0x50, 0xC2, 0x6C, 0x9C, 0xD0, 0xD8, 0x9D] {
+        return Err("SHA-1 gives the wrong result".to_owned());
+    }
+    if tape::crc16(b"123456789".iter()) != 0x29B1 {
+        return Err("CRC-16 gives the wrong result".to_owned());
+    }
+    // A block of 0xFF and 0x02 at 0x5200, as the ROM sums it up:
+    if tape::block_checksum(0x5200, &[0xFF, 0x02]) != 0x53 {
+        return Err("The block checksum gives the wrong result".to_owned());
+    }
+    Ok(())
+}
+
+// Reports the result of a check, counting the failed ones:
+fn report(what: &str, result: Result<(), String>, checks: &mut usize, failures: &mut usize) {
+    *checks += 1;
+    match result {
+        Ok(()) => { status!("{}: passed.", what); },
+        Err(error) => {
+            error!("{}: {}.", what, error);
+            *failures += 1;
+        },
+    }
+}
+
+pub const USAGE: &str = "";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    getopts::Options::new()
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let _matches = commands::parse_options(progname, "selftest", USAGE, false, &mut options, args);
+
+    let (mut checks, mut failures) = (0, 0);
+    report("Hashes and checksums", check_hashes(), &mut checks, &mut failures);
+    for test in test_tapes() {
+        let (entries, buffer) = pack_test_tape(&test);
+        let parsed = match check_parse(&entries, &buffer, &test.format) {
+            Ok(parsed) => { parsed },
+            Err(error) => {
+                report(&format!("Reading back {}", test.description), Err(error), &mut checks, &mut failures);
+                continue;
+            },
+        };
+        report(&format!("Reading back {}", test.description), Ok(()), &mut checks, &mut failures);
+        report(&format!("Packing {} again", test.description), check_repack(&parsed, &buffer, &test.format), &mut checks, &mut failures);
+        report(&format!("Damaging {}", test.description), check_checksums(&parsed, &buffer, &test.format), &mut checks, &mut failures);
+        report(&format!("The text form of {}", test.description), check_text(&parsed, &buffer, &test.format), &mut checks, &mut failures);
+        report(&format!("Recording {}", test.description), check_recording(&buffer, &test.format), &mut checks, &mut failures);
+        if simulator::is_rom_format(&test.format.variant) {
+            report(&format!("Loading {} in the simulator", test.description), check_simulator(&entries, &buffer), &mut checks, &mut failures);
+        }
+        report(&format!("Converting {} for the Color Computer", test.description), check_coco(&parsed), &mut checks, &mut failures);
+    }
+
+    if failures != 0 {
+        error!("{} of {} checks failed, this build of {} doesn't work right.", failures, checks, progname);
+        process::exit(1);
+    }
+    status!("All {} checks passed.", checks);
+    process::exit(0);
+}
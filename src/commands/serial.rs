@@ -0,0 +1,239 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::fs;
+use std::io::Write;
+use std::path;
+use std::process;
+
+use commands;
+use files;
+use numbers;
+use packing;
+use progress;
+use stubs;
+use tape;
+
+
+// Machines fitted with the RS-232-C interface load programs over it much
+// faster than from tape: the receiver is loaded from tape once, and then
+// takes program after program sent from the serial port of the computer.
+const DEFAULT_RECEIVER_ADDRESS: u16 = 0x7F00;
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+fn retrieve_baud_rate(matches: &getopts::Matches) -> (u32, u8) {
+    let rate = match matches.opt_str("baud") {
+        // Anything not a number isn't any of the rates either:
+        Some(argument) => { argument.trim().parse::<u32>().unwrap_or(0) },
+        None => { DEFAULT_BAUD_RATE },
+    };
+    match stubs::SERIAL_BAUD_RATES.iter().find(|&&(offered, _switches)| offered == rate) {
+        Some(&(rate, switches)) => { (rate, switches) },
+        None => {
+            let rates: Vec<String> = stubs::SERIAL_BAUD_RATES.iter().map(|&(rate, _switches)| rate.to_string()).collect();
+            error!("Invalid baud rate `{}', expected one of {}.", matches.opt_str("baud").unwrap_or_default(), rates.join(", "));
+            process::exit(1);
+        },
+    }
+}
+
+fn retrieve_receiver_address(matches: &getopts::Matches) -> u16 {
+    match matches.opt_str("address") {
+        Some(argument) => {
+            match numbers::parse_number(&argument, 16) {
+                Ok(address) if address <= 0xFFFF => { address as u16 },
+                Ok(_address) => {
+                    error!("Invalid `--address' value `{}', addresses go up to 0xFFFF.", argument);
+                    process::exit(1);
+                },
+                Err(error) => {
+                    error!("Invalid `--address' value `{}': {}.", argument, error);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { DEFAULT_RECEIVER_ADDRESS },
+    }
+}
+
+fn receiver_length() -> usize {
+    stubs::serial_receiver(0, 0).len()
+}
+
+// Makes the tape of the receiver, for loading with SYSTEM:
+fn make_receiver(matches: &getopts::Matches, out_filepath: &path::Path) -> ! {
+    let (rate, switches) = retrieve_baud_rate(matches);
+    let address = retrieve_receiver_address(matches);
+    if (address as usize) + receiver_length() > 0x10000 {
+        error!("The receiver doesn't fit into memory from 0x{:04X} on, it's {} bytes long.", address, receiver_length());
+        process::exit(1);
+    }
+
+    let receiver = stubs::serial_receiver(address, switches);
+    let mut format = packing::TapeFormat::standard();
+    format.variant = commands::retrieve_variant(matches);
+    let entry = packing::PackEntry::linear(out_filepath, receiver, b"SERIAL".to_vec(), address, address);
+    let mut buffer = Vec::new();
+    packing::generate_tape_entry(&entry, &mut buffer, &format);
+
+    status!("Serial receiver of {} bytes at 0x{:04X}, reading at {} baud, 8 data bits, no parity and 1 stop bit.",
+            entry.data.len(), address, rate);
+    if !files::write_file(&buffer, out_filepath) {
+        process::exit(1);
+    }
+    status!("Load it with SYSTEM and start it with `/', then send the programs with `serial -i <tape> --port <device>'.");
+    process::exit(0);
+}
+
+// Sets the serial port up with stty, which reads the settings of the
+// terminal on its standard input:
+fn configure_port(port: &path::Path, rate: u32) -> Result<(), String> {
+    let device = match fs::File::open(port) {
+        Ok(device) => { device },
+        Err(error) => { return Err(format!("Failed to open `{}': {}", port.display(), error)); },
+    };
+    let rate = rate.to_string();
+    let status = process::Command::new("stty").args([rate.as_str(), "raw", "cs8", "-cstopb", "-parenb", "-crtscts", "-echo"])
+                                              .stdin(device).status();
+    match status {
+        Ok(status) if status.success() => { Ok(()) },
+        Ok(status) => { Err(format!("`stty' failed to set up `{}', {}", port.display(), status)) },
+        Err(error) => { Err(format!("Failed to run `stty': {}", error)) },
+    }
+}
+
+// Sends an entry of a tape image to the receiver:
+fn send(matches: &getopts::Matches, in_filepath: &path::Path) -> ! {
+    let port: path::PathBuf = match matches.opt_str("port") {
+        Some(argument) => { argument.into() },
+        None => {
+            error!("No serial port given to send `{}' through, use `--port' with a device such as /dev/ttyUSB0.", in_filepath.display());
+            process::exit(1);
+        },
+    };
+    let (rate, _switches) = retrieve_baud_rate(matches);
+    let buffer = match files::load_file(in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &commands::retrieve_variant(matches)) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+    let index = match matches.opt_str("entry") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(index) if index >= 1 && index <= entries.len() => { index - 1 },
+                _ => {
+                    error!("Invalid entry `{}', `{}' has {} entries.", argument, in_filepath.display(), entries.len());
+                    process::exit(1);
+                },
+            }
+        },
+        None => { 0 },
+    };
+    let entry = &entries[index];
+    if entry.blocks.is_empty() {
+        error!("Entry `{}' contains no data blocks.", entry.name_string());
+        process::exit(1);
+    }
+    if entry.bad_checksum_count() != 0 {
+        warning!("Entry `{}' has {} blocks with bad checksums, the receiver gets them with good ones.", entry.name_string(), entry.bad_checksum_count());
+    }
+
+    // The receiver has to stay in place while the data comes in:
+    let receiver_start = retrieve_receiver_address(matches) as u32;
+    let receiver_end = receiver_start + (receiver_length() as u32);
+    if let Some(block) = entry.blocks.iter().find(|block| (block.load_address as u32) < receiver_end && receiver_start < block.end_address()) {
+        warning!("The block at 0x{:04X}-0x{:04X} loads over the receiver at 0x{:04X}-0x{:04X}, it won't get much further.",
+                 block.load_address, block.end_address() - 1, receiver_start, receiver_end - 1);
+    }
+
+    let blocks: Vec<(u16, &[u8])> = entry.blocks.iter().map(|block| (block.load_address, block.data.as_slice())).collect();
+    let payload = stubs::serial_payload(&blocks, entry.entry_point.unwrap_or(tape::ROM_BASIC_READY));
+
+    if let Err(error) = configure_port(&port, rate) {
+        error!("{}.", error);
+        process::exit(1);
+    }
+    let mut device = match fs::OpenOptions::new().write(true).open(&port) {
+        Ok(device) => { device },
+        Err(error) => {
+            error!("Failed to open `{}': {}.", port.display(), error);
+            process::exit(1);
+        },
+    };
+    status!("Sending `{}', {} bytes, through `{}' at {} baud, about {:.1} seconds.", entry.name_string(), payload.len(), port.display(), rate,
+            (payload.len() as f64) * 10.0 / (rate as f64));
+
+    // In small pieces, for the progress to follow:
+    let mut progress = progress::Progress::new("Sending", "bytes", payload.len());
+    for (index, piece) in payload.chunks(64).enumerate() {
+        if let Err(error) = device.write_all(piece).and_then(|_| device.flush()) {
+            progress.finish();
+            error!("Failed to send to `{}': {}.", port.display(), error);
+            process::exit(1);
+        }
+        progress.update((index * 64 + piece.len()).min(payload.len()));
+    }
+    progress.finish();
+    status!("Sent `{}'.", entry.name_string());
+    process::exit(0);
+}
+
+pub const USAGE: &str = "-i <file> --port <device> | --receiver -o <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to send an entry of.", "FILE");
+    options.optopt("", "port", "Serial port to send through, such as /dev/ttyUSB0.", "DEVICE");
+    options.optopt("", "entry", "Entry of the tape image to send (numbered from 1, the first one by default).", "NUMBER");
+    options.optflag("", "receiver", "Make the tape of the receiver instead, which the machine loads with SYSTEM and runs to take the programs sent.");
+    options.optopt("o", "output", "Name of the receiver's tape image to write with `--receiver'.", "FILE");
+    options.optopt("", "address", "Address the receiver loads at (in hex, 0x7F00 by default), give the same one when sending so that programs loading over it are caught.", "ADDR");
+    options.optopt("", "baud", "Baud rate of the serial line, 300, 600, 1200, 2400, 4800, 9600 (the default) or 19200, the same when making the receiver and when sending.", "RATE");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "serial", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    if matches.opt_present("receiver") {
+        let out_filepath = match matches.opt_str("o") {
+            Some(argument) => { commands::expand_output_path(&argument, &[]) },
+            None => {
+                error!("No output file given for the receiver, use `{} serial --receiver -o <file>'.", progname);
+                process::exit(1);
+            },
+        };
+        make_receiver(&matches, &out_filepath);
+    }
+    let in_filepath = commands::retrieve_input_path(progname, "serial", &matches);
+    send(&matches, &in_filepath);
+}
@@ -0,0 +1,682 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+use getopts;
+
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::Read;
+use std::io::Write;
+use std::net;
+use std::path;
+use std::process;
+use std::thread;
+use std::time;
+
+use audio;
+use commands;
+use files;
+use json;
+use tape;
+use websocket;
+
+
+// Tapes are served over plain TCP, to emulators and hardware bridges with
+// network cassette support.  Clients send commands as lines of text, and get
+// back a line starting with `OK' or `ERR', the tapes and the entries are
+// numbered from 1 on:
+//
+//   LIST                  OK <count>, then a line `<tape> <bytes> <filename>'
+//                         for each tape
+//   ENTRIES <tape>        OK <count>, then a line `<entry> <start> <end>
+//                         <entry point> <offset> <name>' for each entry, the
+//                         addresses in hex, `-' for no entry point
+//   GET <tape> [<entry>]  OK <bytes>, then the bytes of the tape image, from
+//                         the start of the entry on if one is given
+//   QUIT                  OK, and the connection is closed
+//
+// With `--raw', the tape image is instead sent to every client right away,
+// and the connection closed, for the ones which just read a stream.
+const DEFAULT_LISTEN: &str = "127.0.0.1:8580";
+
+// The tapes to serve, looked for again for every request, so that the ones
+// added to the directories served since show up:
+fn list_tapes(arguments: &[String]) -> Vec<path::PathBuf> {
+    let mut tapes = Vec::new();
+    for argument in arguments {
+        match files::expand_input_pattern(argument, files::TAPE_EXTENSIONS) {
+            Ok(paths) => { tapes.extend(paths.into_iter().filter(|path| path.is_file())); },
+            Err(error) => { warning!("{}.", error); },
+        }
+    }
+    tapes
+}
+
+// Clients are only told the names of the tapes, not where on the host they
+// are:
+fn file_name(tape_path: &path::Path) -> String {
+    tape_path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+// Tapes are read again for every request too, so that the ones rebuilt
+// meanwhile are served as they are now:
+fn load_tape(tape_path: &path::Path, variant: &tape::Variant) -> Result<(Vec<u8>, Vec<tape::Entry>), String> {
+    let buffer = match fs::read(tape_path) {
+        Ok(buffer) => { buffer },
+        Err(error) => { return Err(format!("Failed to read `{}': {}", file_name(tape_path), error)); },
+    };
+    match tape::parse_tape(&buffer, variant) {
+        Ok(entries) => { Ok((buffer, entries)) },
+        Err(error) => { Err(format!("{}: {}", file_name(tape_path), error)) },
+    }
+}
+
+fn parse_index(argument: Option<&str>, count: usize, what: &str) -> Result<usize, String> {
+    match argument.map(|argument| argument.parse::<usize>()) {
+        Some(Ok(index)) if index >= 1 && index <= count => { Ok(index - 1) },
+        Some(_) => { Err(format!("No {} `{}', there are {}", what, argument.unwrap_or(""), count)) },
+        None => { Err(format!("No {} given", what)) },
+    }
+}
+
+// The part of the tape image sent for the entry asked for, all of it if none
+// is:
+fn tape_from_entry(tape_path: &path::Path, entry: Option<&str>, variant: &tape::Variant) -> Result<Vec<u8>, String> {
+    let (buffer, entries) = load_tape(tape_path, variant)?;
+    match entry {
+        Some(_) => {
+            let index = parse_index(entry, entries.len(), "entry")?;
+            Ok(buffer[entries[index].start_offset..].to_vec())
+        },
+        None => { Ok(buffer) },
+    }
+}
+
+// Answers one command, returns false for ones ending the connection:
+fn answer(line: &str, writer: &mut dyn Write, arguments: &[String], variant: &tape::Variant) -> io::Result<bool> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let command = words.first().map(|word| word.to_uppercase()).unwrap_or_default();
+    let tapes = list_tapes(arguments);
+
+    let result = match command.as_str() {
+        "LIST" => {
+            let mut lines = format!("OK {}\n", tapes.len());
+            for (index, tape_path) in tapes.iter().enumerate() {
+                let length = fs::metadata(tape_path).map(|metadata| metadata.len()).unwrap_or(0);
+                lines.push_str(&format!("{} {} {}\n", index + 1, length, file_name(tape_path)));
+            }
+            Ok(lines.into_bytes())
+        },
+        "ENTRIES" => {
+            parse_index(words.get(1).cloned(), tapes.len(), "tape").and_then(|index| load_tape(&tapes[index], variant)).map(|(_buffer, entries)| {
+                let mut lines = format!("OK {}\n", entries.len());
+                for (index, entry) in entries.iter().enumerate() {
+                    let (start, end) = entry.address_range().unwrap_or((0, 0));
+                    let entry_point = entry.entry_point.map_or("-".to_owned(), |entry_point| format!("{:04X}", entry_point));
+                    lines.push_str(&format!("{} {:04X} {:04X} {} {} {}\n", index + 1, start, end.max(1) - 1, entry_point,
+                                            entry.start_offset, entry.name_string().trim_end()));
+                }
+                lines.into_bytes()
+            })
+        },
+        "GET" => {
+            parse_index(words.get(1).cloned(), tapes.len(), "tape").and_then(|index| tape_from_entry(&tapes[index], words.get(2).cloned(), variant)).map(|data| {
+                let mut response = format!("OK {}\n", data.len()).into_bytes();
+                response.extend(data);
+                response
+            })
+        },
+        "QUIT" => {
+            writer.write_all(b"OK\n")?;
+            return Ok(false);
+        },
+        "" => { return Ok(true); },
+        _ => { Err(format!("Unknown command `{}', expected LIST, ENTRIES, GET or QUIT", words[0])) },
+    };
+
+    match result {
+        Ok(response) => { writer.write_all(&response)?; },
+        Err(error) => { writer.write_all(format!("ERR {}\n", error).as_bytes())?; },
+    }
+    Ok(true)
+}
+
+fn serve_client(stream: net::TcpStream, arguments: &[String], variant: &tape::Variant) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    writer.write_all(b"OK trs80m1-mltl tape server\n")?;
+    for line in io::BufReader::new(stream).lines() {
+        if !answer(&line?, &mut writer, arguments, variant)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Sends the tape to the client and hangs up:
+fn serve_raw(mut stream: net::TcpStream, arguments: &[String], entry: Option<&str>, variant: &tape::Variant) -> io::Result<()> {
+    let tapes = list_tapes(arguments);
+    let result = match tapes.first() {
+        Some(tape_path) => { tape_from_entry(tape_path, entry, variant) },
+        None => { Err("No tapes to serve".to_owned()) },
+    };
+    match result {
+        Ok(data) => { stream.write_all(&data) },
+        Err(error) => {
+            // There's no way to tell the client, which only reads:
+            warning!("{}.", error);
+            Ok(())
+        },
+    }
+}
+
+// With `--http', the tapes are served to browsers instead, such as to web
+// based emulators, with CORS headers letting pages from anywhere read them:
+//
+//   GET /           the tapes as a JSON array, with their entries
+//   GET /tapes/<n>  the tape image, or from the entry on, as with
+//                   `/tapes/1?entry=2'
+//   GET /stream/<n> a WebSocket streaming the tape in real time, also from
+//                   an entry on, as the bytes of the image or with
+//                   `?format=audio' as the samples of its recording
+fn tape_json(index: usize, tape_path: &path::Path, variant: &tape::Variant) -> json::Json {
+    let mut members = vec![
+        ("index", json::Json::Number((index + 1) as i64)),
+        ("file",  json::Json::Str(file_name(tape_path))),
+        ("url",   json::Json::Str(format!("/tapes/{}", index + 1))),
+    ];
+    match load_tape(tape_path, variant) {
+        Ok((buffer, entries)) => {
+            members.push(("size", json::Json::Number(buffer.len() as i64)));
+            members.push(("entries", json::Json::Array(entries.iter().enumerate().map(|(entry_index, entry)| {
+                let (start, end) = entry.address_range().unwrap_or((0, 0));
+                json::Json::Object(vec![
+                    ("name",        json::Json::Str(entry.name_string().trim_end().to_owned())),
+                    ("start",       json::Json::Number(start as i64)),
+                    ("end",         json::Json::Number(end as i64)),
+                    ("entry_point", entry.entry_point.map_or(json::Json::Null, |entry_point| json::Json::Number(entry_point as i64))),
+                    ("blocks",      json::Json::Number(entry.blocks.len() as i64)),
+                    ("bad_blocks",  json::Json::Number(entry.bad_checksum_count() as i64)),
+                    ("url",         json::Json::Str(format!("/tapes/{}?entry={}", index + 1, entry_index + 1))),
+                ])
+            }).collect())));
+        },
+        Err(error) => { members.push(("error", json::Json::Str(error))); },
+    }
+    json::Json::Object(members)
+}
+
+// Tapes streamed over WebSockets go out as they'd come off the tape, in
+// binary messages a few times a second.  The first message describes the
+// stream, as a JSON object with its `format' (`cas' or `audio'), `size' in
+// bytes, `duration' in seconds, and for audio the `sample_rate', `bits' and
+// `channels' of the samples, little endian.  The page controls the stream
+// with the text messages `play', `pause' and `seek <seconds>', each answered
+// with a JSON object giving the `state' (`playing', `paused' or `ended')
+// and the `position' in seconds; it starts out playing.
+const STREAM_TICK_MS: u64 = 50;
+
+struct Stream {
+    data: Vec<u8>,
+    bytes_per_second: f64,
+    // Bytes of a sample frame, which the stream isn't cut in the middle of:
+    frame: usize,
+    // What a blank stretch of tape is made of, silence or leader bytes:
+    blank: u8,
+    description: json::Json,
+}
+
+fn make_stream(buffer: Vec<u8>, audio_stream: bool, audio_format: &audio::AudioFormat) -> Stream {
+    if audio_stream {
+        let samples = audio::encode_tape(&buffer, &[], audio_format, &mut |_done| {});
+        let data = audio::wav_stream_data(&samples, audio_format);
+        let frame = ((audio_format.bits / 8) * audio_format.channels) as usize;
+        let description = json::Json::Object(vec![
+            ("format",      json::Json::Str("audio".to_owned())),
+            ("size",        json::Json::Number(data.len() as i64)),
+            ("duration",    json::Json::Float((samples.len() as f64) / (audio_format.sample_rate as f64))),
+            ("sample_rate", json::Json::Number(audio_format.sample_rate as i64)),
+            ("bits",        json::Json::Number(audio_format.bits as i64)),
+            ("channels",    json::Json::Number(audio_format.channels as i64)),
+        ]);
+        let blank = if audio_format.bits == 8 { 0x80 } else { 0x00 };
+        Stream { data, bytes_per_second: (audio_format.sample_rate as f64) * (frame as f64), frame, blank, description }
+    } else {
+        let bytes_per_second = 1_000_000.0 / (audio::BYTE_DURATION_US as f64);
+        let description = json::Json::Object(vec![
+            ("format",   json::Json::Str("cas".to_owned())),
+            ("size",     json::Json::Number(buffer.len() as i64)),
+            ("duration", json::Json::Float((buffer.len() as f64) / bytes_per_second)),
+        ]);
+        Stream { data: buffer, bytes_per_second, frame: 1, blank: 0x00, description }
+    }
+}
+
+fn stream_state(state: &str, position: usize, stream: &Stream) -> Vec<u8> {
+    let seconds = ((position as f64) / stream.bytes_per_second * 1000.0).round() / 1000.0;
+    json::Json::Object(vec![
+        ("state",    json::Json::Str(state.to_owned())),
+        ("position", json::Json::Float(seconds)),
+    ]).to_string().into_bytes()
+}
+
+// Streams the tape, taking the control messages between the messages sent:
+fn serve_stream(socket: net::TcpStream, mut received: Vec<u8>, stream: &Stream) -> io::Result<()> {
+    let mut writer = socket.try_clone()?;
+    let mut reader = socket;
+    reader.set_read_timeout(Some(time::Duration::from_millis(STREAM_TICK_MS / 5)))?;
+    websocket::write_frame(&mut writer, websocket::TEXT, stream.description.to_string().as_bytes())?;
+
+    let mut position = 0;
+    // Where and when it last started playing, or None while paused:
+    let mut playing: Option<(usize, time::Instant)> = Some((0, time::Instant::now()));
+    let mut last_sent = time::Instant::now();
+    loop {
+        let mut buffer = [0u8; 1024];
+        match reader.read(&mut buffer) {
+            Ok(0) => { return Ok(()); },
+            Ok(length) => { received.extend_from_slice(&buffer[..length]); },
+            Err(ref error) if error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut => {},
+            Err(error) => { return Err(error); },
+        }
+
+        loop {
+            let (opcode, payload) = match websocket::take_frame(&mut received) {
+                Ok(Some(frame)) => { frame },
+                Ok(None) => { break; },
+                Err(error) => {
+                    verbose!("{}, closing the connection.", error);
+                    websocket::write_frame(&mut writer, websocket::CLOSE, &websocket::PROTOCOL_ERROR.to_be_bytes())?;
+                    return Ok(());
+                },
+            };
+            match opcode {
+                websocket::CLOSE => {
+                    websocket::write_frame(&mut writer, websocket::CLOSE, &payload[..payload.len().min(2)])?;
+                    return Ok(());
+                },
+                websocket::PING => { websocket::write_frame(&mut writer, websocket::PONG, &payload)?; },
+                websocket::TEXT => {
+                    let message = String::from_utf8_lossy(&payload).trim().to_lowercase();
+                    let words: Vec<&str> = message.split_whitespace().collect();
+                    let seek = match words.as_slice() {
+                        ["seek", seconds] => { seconds.parse::<f64>().ok().filter(|seconds| *seconds >= 0.0) },
+                        _ => { None },
+                    };
+                    match (words.as_slice(), seek) {
+                        (["play"], _) => {
+                            if playing.is_none() {
+                                playing = Some((position, time::Instant::now()));
+                            }
+                        },
+                        (["pause"], _) => { playing = None; },
+                        (["seek", _], Some(seconds)) => {
+                            let target = ((seconds * stream.bytes_per_second) as usize / stream.frame) * stream.frame;
+                            position = target.min(stream.data.len());
+                            if playing.is_some() {
+                                playing = Some((position, time::Instant::now()));
+                            }
+                        },
+                        _ => {
+                            let error = json::Json::Object(vec![("error", json::Json::Str(format!("Unknown message `{}', expected play, pause or seek <seconds>", message)))]);
+                            websocket::write_frame(&mut writer, websocket::TEXT, error.to_string().as_bytes())?;
+                            continue;
+                        },
+                    }
+                    let state = if playing.is_some() { "playing" } else { "paused" };
+                    websocket::write_frame(&mut writer, websocket::TEXT, &stream_state(state, position, stream))?;
+                },
+                _ => {},
+            }
+        }
+
+        // Whatever the tape got to since, a few times a second:
+        if let Some((start, since)) = playing {
+            if last_sent.elapsed() < time::Duration::from_millis(STREAM_TICK_MS) {
+                continue;
+            }
+            let due = start + ((since.elapsed().as_secs_f64() * stream.bytes_per_second) as usize / stream.frame) * stream.frame;
+            let due = due.min(stream.data.len());
+            if due > position {
+                websocket::write_frame(&mut writer, websocket::BINARY, &stream.data[position..due])?;
+                position = due;
+            }
+            last_sent = time::Instant::now();
+            if position == stream.data.len() {
+                playing = None;
+                websocket::write_frame(&mut writer, websocket::TEXT, &stream_state("ended", position, stream))?;
+            }
+        }
+    }
+}
+
+fn write_response(writer: &mut dyn Write, status: &str, content_type: &str, body: &[u8], extra_headers: &str) -> io::Result<()> {
+    write!(writer, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nConnection: close\r\n{}\r\n",
+           status, content_type, body.len(), extra_headers)?;
+    writer.write_all(body)
+}
+
+// The longest lines of requests read, and the most headers, so that no
+// client can have the server hold on to more than a little of what it sends:
+const MAX_REQUEST_LINE: u64 = 8192;
+const MAX_HEADERS: usize = 100;
+
+// Reads a line of the request, None if it goes on for longer than allowed:
+fn read_request_line(reader: &mut io::BufReader<net::TcpStream>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    let length = reader.by_ref().take(MAX_REQUEST_LINE).read_line(&mut line)?;
+    if length as u64 == MAX_REQUEST_LINE && !line.ends_with('\n') {
+        Ok(None)
+    } else {
+        Ok(Some(line))
+    }
+}
+
+// Answers a single request, and hangs up:
+fn serve_http(stream: net::TcpStream, arguments: &[String], variant: &tape::Variant, audio_format: &audio::AudioFormat) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = io::BufReader::new(stream);
+    let request = match read_request_line(&mut reader)? {
+        Some(request) => { request },
+        None => { return write_response(&mut writer, "400 Bad Request", "text/plain", b"Bad request, the request line is too long.\n", ""); },
+    };
+    // Only the key of WebSocket handshakes matters, but all the headers
+    // have to be read before answering:
+    let mut websocket_key = None;
+    let mut header_count = 0;
+    loop {
+        let header = match read_request_line(&mut reader)? {
+            Some(header) if header_count < MAX_HEADERS => { header },
+            _ => { return write_response(&mut writer, "431 Request Header Fields Too Large", "text/plain", b"The request headers are too large.\n", ""); },
+        };
+        if header.trim().is_empty() {
+            break;
+        }
+        header_count += 1;
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("sec-websocket-key") {
+                websocket_key = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    let words: Vec<&str> = request.split_whitespace().collect();
+    let (method, target) = match (words.first(), words.get(1)) {
+        (Some(method), Some(target)) => { (*method, *target) },
+        _ => { return write_response(&mut writer, "400 Bad Request", "text/plain", b"Bad request.\n", ""); },
+    };
+    verbose!("{} {}", method, target);
+    if method == "OPTIONS" {
+        return write_response(&mut writer, "204 No Content", "text/plain", b"",
+                              "Access-Control-Allow-Methods: GET, OPTIONS\r\nAccess-Control-Allow-Headers: *\r\n");
+    }
+    if method != "GET" {
+        return write_response(&mut writer, "405 Method Not Allowed", "text/plain", b"Only GET requests are served.\n", "Allow: GET, OPTIONS\r\n");
+    }
+
+    let (target_path, query) = target.split_once('?').unwrap_or((target, ""));
+    let entry = query.split('&').find_map(|parameter| parameter.strip_prefix("entry="));
+    let tapes = list_tapes(arguments);
+    let result = if target_path == "/" || target_path == "/tapes" {
+        let listing = json::Json::Array(tapes.iter().enumerate().map(|(index, tape_path)| tape_json(index, tape_path, variant)).collect());
+        Ok(("application/json", format!("{}\n", listing).into_bytes()))
+    } else if let Some(index) = target_path.strip_prefix("/stream/") {
+        let key = match websocket_key {
+            Some(key) => { key },
+            None => { return write_response(&mut writer, "426 Upgrade Required", "text/plain", b"Streams are served over WebSockets.\n", "Upgrade: websocket\r\n"); },
+        };
+        let audio_stream = match query.split('&').find_map(|parameter| parameter.strip_prefix("format=")) {
+            Some("audio") => { true },
+            Some("cas") | None => { false },
+            Some(format) => { return write_response(&mut writer, "400 Bad Request", "text/plain", format!("Unknown format `{}', expected cas or audio.\n", format).as_bytes(), ""); },
+        };
+        match parse_index(Some(index), tapes.len(), "tape").and_then(|index| tape_from_entry(&tapes[index], entry, variant)) {
+            Ok(data) => {
+                writer.write_all(websocket::handshake_response(&key).as_bytes())?;
+                let received = reader.buffer().to_vec();
+                return serve_stream(reader.into_inner(), received, &make_stream(data, audio_stream, audio_format));
+            },
+            Err(error) => { Err(error) },
+        }
+    } else if let Some(index) = target_path.strip_prefix("/tapes/") {
+        parse_index(Some(index), tapes.len(), "tape").and_then(|index| tape_from_entry(&tapes[index], entry, variant))
+                                                     .map(|data| ("application/octet-stream", data))
+    } else {
+        Err(format!("Nothing at `{}', the tapes are listed at /", target_path))
+    };
+
+    match result {
+        Ok((content_type, body)) => { write_response(&mut writer, "200 OK", content_type, &body, "") },
+        Err(error) => { write_response(&mut writer, "404 Not Found", "text/plain", format!("{}.\n", error).as_bytes(), "") },
+    }
+}
+
+// With `--fifo', the first tape is written into a named pipe instead, which
+// an emulator or a hardware bridge reads as its cassette, or into a PTY or
+// a serial port already there.  The writes block until the reader takes the
+// bytes, so it reads at its own pace, unless `--pace' holds them back to the
+// speed of a real tape.  Once the tape is over, blank tape follows for as
+// long as the reader keeps the pipe open, and the next reader to open it
+// gets the tape from the start again.  With `--once', or into a terminal,
+// the tape is written just once, and the end of it is the end of the file.
+const FIFO_CHUNK: usize = 256;
+
+// Makes the named pipe unless the path is one already, or a terminal.
+// Returns whether it's a named pipe:
+#[cfg(unix)]
+fn prepare_fifo(fifo_path: &path::Path) -> Result<bool, String> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::metadata(fifo_path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => { Ok(true) },
+        Ok(metadata) if metadata.file_type().is_char_device() => { Ok(false) },
+        Ok(_metadata) => { Err(format!("`{}' is neither a named pipe nor a terminal", fifo_path.display())) },
+        Err(_error) => {
+            match process::Command::new("mkfifo").arg(fifo_path).status() {
+                Ok(status) if status.success() => { Ok(true) },
+                Ok(status) => { Err(format!("`mkfifo' failed to make `{}', {}", fifo_path.display(), status)) },
+                Err(error) => { Err(format!("Failed to run `mkfifo': {}", error)) },
+            }
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn prepare_fifo(_fifo_path: &path::Path) -> Result<bool, String> {
+    Err("Named pipes and PTYs are only supported on Unix-like systems, serve the tapes over TCP or HTTP instead".to_owned())
+}
+
+// Plays the tape into the reader, followed by blank tape until the reader
+// goes away if asked to.  Returns whether the reader took all of the tape:
+fn play_into(writer: &mut dyn Write, stream: &Stream, pace: bool, blank_tape: bool) -> io::Result<bool> {
+    let blank = vec![stream.blank; FIFO_CHUNK * stream.frame];
+    let start = time::Instant::now();
+    let mut position = 0;
+    loop {
+        let end = if pace {
+            let due = ((start.elapsed().as_secs_f64() * stream.bytes_per_second) as usize / stream.frame) * stream.frame;
+            if due <= position {
+                thread::sleep(time::Duration::from_millis(STREAM_TICK_MS));
+                continue;
+            }
+            due
+        } else {
+            position + blank.len()
+        };
+        // What of the tape is due, or the blank tape after it:
+        let chunk = if position < stream.data.len() {
+            &stream.data[position..end.min(stream.data.len())]
+        } else if blank_tape {
+            &blank[..(end - position).min(blank.len())]
+        } else {
+            writer.flush()?;
+            return Ok(true);
+        };
+        match writer.write_all(chunk) {
+            Ok(()) => { position += chunk.len(); },
+            Err(ref error) if error.kind() == io::ErrorKind::BrokenPipe => { return Ok(position >= stream.data.len()); },
+            Err(error) => { return Err(error); },
+        }
+    }
+}
+
+fn serve_fifo(fifo_path: &path::Path, stream: &Stream, pace: bool, once: bool) -> ! {
+    let is_fifo = match prepare_fifo(fifo_path) {
+        Ok(is_fifo) => { is_fifo },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+    let duration = (stream.data.len() as f64) / stream.bytes_per_second;
+    status!("Playing the tape into `{}', {:.1} seconds of it{}, press Ctrl+C to stop.", fifo_path.display(), duration,
+            if pace { " in real time" } else { " at the pace of the reader" });
+
+    loop {
+        // Opening a named pipe waits for the reader:
+        let mut file = match fs::OpenOptions::new().write(true).open(fifo_path) {
+            Ok(file) => { file },
+            Err(error) => {
+                error!("Failed to open `{}': {}.", fifo_path.display(), error);
+                process::exit(1);
+            },
+        };
+        verbose!("A reader opened `{}', starting the tape.", fifo_path.display());
+        let once = once || !is_fifo;
+        match play_into(&mut file, stream, pace, !once) {
+            Ok(true) => { status!("The tape played to the end."); },
+            Ok(false) => { status!("The reader closed `{}' before the end of the tape.", fifo_path.display()); },
+            Err(error) => {
+                error!("Failed to write into `{}': {}.", fifo_path.display(), error);
+                process::exit(1);
+            },
+        }
+        if once {
+            process::exit(0);
+        }
+    }
+}
+
+pub const USAGE: &str = "<tape or directory>...";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optmulti("i", "input", "A tape image to serve, or a directory whose .cas files are served, or a pattern such as `build/*.cas'; may be given several times, or as further arguments.", "FILE");
+    options.optopt("", "listen", "Address and port to listen at (127.0.0.1:8580 by default, use 0.0.0.0:8580 to serve the whole network).", "ADDR:PORT");
+    options.optflag("", "http", "Serve the tapes over HTTP instead, listed as JSON at / and each at /tapes/<number>, for web based emulators, or streamed in real time over a WebSocket at /stream/<number>.");
+    options.optflag("", "raw", "Send the first tape to every client as soon as it connects and hang up, for clients which just read a stream of bytes.");
+    options.optopt("", "entry", "Send the tape from the given entry on with `--raw' or `--fifo' (numbered from 1).", "NUMBER");
+    options.optopt("", "fifo", "Write the first tape into a named pipe instead, made if it isn't there, or into a PTY or a serial port, for an emulator or a hardware bridge reading it as its cassette.  Blank tape follows the tape until the reader closes the pipe, the next one to open it gets the tape from the start again.", "PATH");
+    options.optflag("", "pace", "Write into the `--fifo' only as fast as a real tape plays, rather than as fast as the reader takes it.");
+    options.optflag("", "once", "Write the tape into the `--fifo' just once, ending the file at the end of it, and stop.");
+    options.optflag("", "audio", "Write the samples of the tape's recording into the `--fifo', as raw PCM of the `--sample-rate', `--bits' and `--channels' given, rather than the bytes of the tape image.");
+    commands::add_audio_options(&mut options);
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "serve", USAGE, true, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    let mut arguments = matches.opt_strs("i");
+    arguments.extend(matches.free.iter().cloned());
+    if arguments.is_empty() {
+        error!("No tapes given to serve, see `{} serve --help'.", progname);
+        process::exit(1);
+    }
+    if matches.opt_present("http") && matches.opt_present("raw") {
+        error!("The `--http' and `--raw' options can't be used together.");
+        process::exit(1);
+    }
+    if matches.opt_present("fifo") && (matches.opt_present("http") || matches.opt_present("raw") || matches.opt_present("listen")) {
+        error!("The `--fifo' option can't be used together with `--http', `--raw' or `--listen'.");
+        process::exit(1);
+    }
+    if (matches.opt_present("pace") || matches.opt_present("once") || matches.opt_present("audio")) && !matches.opt_present("fifo") {
+        error!("The `--pace', `--once' and `--audio' options only apply to `--fifo'.");
+        process::exit(1);
+    }
+    if matches.opt_present("entry") && !matches.opt_present("raw") && !matches.opt_present("fifo") {
+        error!("The `--entry' option only applies to `--raw' and `--fifo', other clients ask for the entries themselves.");
+        process::exit(1);
+    }
+    let variant = commands::retrieve_variant(&matches);
+    let raw = matches.opt_present("raw");
+    let http = matches.opt_present("http");
+    let entry = matches.opt_str("entry");
+    let audio_format = commands::retrieve_audio_format(&matches);
+
+    if let Some(argument) = matches.opt_str("fifo") {
+        let tapes = list_tapes(&arguments);
+        let buffer = match tapes.first() {
+            Some(tape_path) => { tape_from_entry(tape_path, entry.as_deref(), &variant) },
+            None => { Err("No tapes to play".to_owned()) },
+        };
+        let buffer = match buffer {
+            Ok(buffer) => { buffer },
+            Err(error) => {
+                error!("{}.", error);
+                process::exit(1);
+            },
+        };
+        let stream = make_stream(buffer, matches.opt_present("audio"), &audio_format);
+        serve_fifo(argument.as_ref(), &stream, matches.opt_present("pace"), matches.opt_present("once"));
+    }
+
+    let address = matches.opt_str("listen").unwrap_or_else(|| DEFAULT_LISTEN.to_owned());
+    let listener = match net::TcpListener::bind(&address) {
+        Ok(listener) => { listener },
+        Err(error) => {
+            error!("Failed to listen at `{}': {}.", address, error);
+            process::exit(1);
+        },
+    };
+    let protocol = if http { " over HTTP" } else if raw { " as a raw stream" } else { "" };
+    status!("Serving {} tapes at {}{}, press Ctrl+C to stop.", list_tapes(&arguments).len(), address, protocol);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => { stream },
+            Err(error) => {
+                warning!("Failed to accept a connection: {}.", error);
+                continue;
+            },
+        };
+        let peer = stream.peer_addr().map(|peer| peer.to_string()).unwrap_or_else(|_error| "an unknown address".to_owned());
+        verbose!("Connection from {}.", peer);
+
+        let (arguments, variant, entry) = (arguments.clone(), variant.clone(), entry.clone());
+        thread::spawn(move || {
+            let result = if http {
+                serve_http(stream, &arguments, &variant, &audio_format)
+            } else if raw {
+                serve_raw(stream, &arguments, entry.as_deref(), &variant)
+            } else {
+                serve_client(stream, &arguments, &variant)
+            };
+            if let Err(error) = result {
+                verbose!("Connection from {} lost: {}.", peer, error);
+            }
+        });
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::process;
+
+use commands;
+use files;
+use memory;
+use simulator;
+use tape;
+
+
+// Instructions a program gets to run once loaded, a fraction of a second of
+// the real machine's time:
+pub const DEFAULT_INSTRUCTIONS: usize = 1_000_000;
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to load in simulation.", "FILE");
+    options.optopt("", "entry", "Number of the entry to load, counting from 1, as if its name was typed at SYSTEM's `*?' prompt (the first one by default).", "NUMBER");
+    options.optopt("", "ram", "RAM size of the simulated machine, `4k', `16k', `32k' or `48k' (the default).", "SIZE");
+    options.optopt("", "instructions", "Number of instructions the program gets to run once loaded (1000000 by default), 0 to only load it.", "COUNT");
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+// Looks up the end of RAM for the `--ram' option:
+pub fn retrieve_ram_end(matches: &getopts::Matches) -> u32 {
+    match matches.opt_str("ram") {
+        Some(argument) => {
+            match memory::ram_end(&argument) {
+                Some(end) => { end },
+                None => {
+                    error!("Unknown RAM size `{}', expected `4k', `16k', `32k' or `48k'.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { simulator::default_ram_end() },
+    }
+}
+
+// Tells what got loaded over while the tape was still being read, returns
+// whether nothing did:
+fn report_clobbers(clobbers: &[simulator::Clobber]) -> bool {
+    for clobber in clobbers {
+        let range = if clobber.start == clobber.end {
+            format!("0x{:04X}", clobber.start)
+        } else {
+            format!("0x{:04X}-0x{:04X}", clobber.start, clobber.end)
+        };
+        error!("{} loads over {} at {} before the load is done, the tape will likely fail to load.", clobber.by, clobber.what, range);
+    }
+    clobbers.is_empty()
+}
+
+// Tells what the ROM made of the entry, and whether it loaded properly:
+pub fn report_load(load: &simulator::Load) -> bool {
+    for name in &load.passed_entries {
+        note!("SYSTEM passes over the entry `{}' looking for `{}'.", String::from_utf8_lossy(name), String::from_utf8_lossy(&load.name));
+    }
+    let range = match (load.blocks.iter().map(|block| block.address as u32).min(),
+                       load.blocks.iter().map(|block| (block.address as u32) + (block.length as u32)).max()) {
+        (Some(start), Some(end)) => { format!(" into 0x{:04X}-0x{:04X}", start, end - 1) },
+        _ => { String::new() },
+    };
+    status!("SYSTEM loads `{}', {} blocks{}, entry point 0x{:04X}.", String::from_utf8_lossy(&load.name), load.blocks.len(), range, load.entry_point);
+    if load.skipped_bytes > 0 {
+        note!("The ROM passes over {} bytes between the blocks.", load.skipped_bytes);
+    }
+
+    let mut success = true;
+    for block in &load.blocks {
+        if !block.checksum_ok {
+            error!("The block at 0x{:04X} has a bad checksum, the ROM shows a `C'.", block.address);
+            success = false;
+        }
+        if block.lost_bytes > 0 {
+            error!("{} bytes of the block at 0x{:04X} go into the ROM, the keyboard or past the end of RAM, and are lost.", block.lost_bytes, block.address);
+            success = false;
+        }
+    }
+    report_clobbers(&load.clobbers) && success
+}
+
+// Runs the loaded program, telling what it did, and whether it loaded the
+// rest of the tape, if it reads any, without loading over what it needs:
+pub fn report_run(machine: &mut simulator::Machine, entry_point: u16, instructions: usize) -> bool {
+    if instructions == 0 {
+        return true;
+    }
+    let (stop, count) = machine.run(entry_point, instructions);
+    status!("Run from 0x{:04X} for {} instructions, the program {}.", entry_point, count, stop.describe());
+
+    let lines = machine.screen_lines();
+    if !lines.is_empty() {
+        status!("The screen shows:");
+        for line in lines {
+            status!("  |{}", line);
+        }
+    }
+    report_clobbers(&machine.clobbers)
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "simulate", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "simulate", &matches);
+    let ram_end = retrieve_ram_end(&matches);
+    let instructions = match matches.opt_str("instructions") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(count) => { count },
+                Err(_error) => {
+                    error!("Invalid number of instructions `{}', expected a number.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { DEFAULT_INSTRUCTIONS },
+    };
+
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+
+    // The entry is picked by its name, as read by the tape parser, which
+    // may well pick up a different one than the ROM if the tape is off:
+    let wanted_name = match matches.opt_str("entry") {
+        Some(argument) => {
+            let entries = match tape::parse_tape(&buffer, &tape::Variant::standard()) {
+                Ok(entries) => { entries },
+                Err(error) => {
+                    error!("{}: {}, so there's no telling the entries apart.", in_filepath.display(), error);
+                    process::exit(1);
+                },
+            };
+            match argument.parse::<usize>() {
+                Ok(number) if number >= 1 && number <= entries.len() => { Some(entries[number - 1].name.clone()) },
+                _ => {
+                    error!("Invalid entry number `{}', the tape has entries 1 to {}.", argument, entries.len());
+                    process::exit(1);
+                },
+            }
+        },
+        None => { None },
+    };
+
+    let mut machine = simulator::Machine::new(&buffer, ram_end);
+    let load = match machine.system_load(wanted_name.as_deref()) {
+        Ok(load) => { load },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+    let success = report_load(&load);
+    let success = report_run(&mut machine, load.entry_point, instructions) && success;
+    process::exit(if success { 0 } else { 1 });
+}
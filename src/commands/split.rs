@@ -0,0 +1,96 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use commands;
+use files;
+use tape;
+use template;
+
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to split.", "FILE");
+    options.optopt("o", "output", "Template for the names of the pieces (the input filename numbered and with extension changed to .cas by default), such as `{index}_{name}.cas', with {name}, {stem} (of the input filename), {base}, {start}, {end}, {size} and {index} available.", "TEMPLATE");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "split", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "split", &matches);
+
+    let variant = commands::retrieve_variant(&matches);
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    // Each piece is a verbatim copy of the entry's bytes, leader included:
+    let mut success = true;
+    let mut used_paths: Vec<path::PathBuf> = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let out_filepath = match matches.opt_str("o") {
+            Some(argument) => {
+                let (base_address, end) = entry.address_range().unwrap_or((0, 0));
+                let mut values = commands::entry_template_values(&in_filepath, &entry.name, base_address,
+                                                                 entry.entry_point.unwrap_or(0), (end - (base_address as u32)) as usize);
+                values.push(("index", template::Value::Number((index + 1) as u32)));
+                commands::expand_output_path(&argument, &values)
+            },
+            None => { files::numbered_output_path(&in_filepath, index + 1, "cas") },
+        };
+        if out_filepath == in_filepath {
+            error!("The input and output files are the same, aborting to prevent data loss.");
+            process::exit(1);
+        }
+        if used_paths.contains(&out_filepath) {
+            error!("Several entries would be written into `{}', use a template that tells them apart, such as one with {{index}}.", out_filepath.display());
+            process::exit(1);
+        }
+        used_paths.push(out_filepath.clone());
+
+        status!("Entry {}: `{}'", index + 1, entry.name_string());
+        if !files::write_file(&buffer[entry.start_offset..entry.end_offset], &out_filepath) {
+            success = false;
+        }
+    }
+
+    if success {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
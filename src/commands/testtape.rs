@@ -0,0 +1,241 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use commands;
+use files;
+use numbers;
+use packing;
+use stubs;
+use stubs::PatternStep;
+use tape;
+
+
+// Test tapes are SYSTEM tapes of blocks of known patterns, followed by a
+// routine which checks them once loaded, run as the entry point.  The
+// patterns repeat for as many blocks as asked for:
+const PATTERNS: &[(&str, &[(u8, PatternStep)])] = &[
+    // Every byte value in turn:
+    ("incrementing", &[(0x00, PatternStep::Increment)]),
+    // A 1 walking through 0s, and a 0 walking through 1s:
+    ("walking",      &[(0x01, PatternStep::Rotate), (0xFE, PatternStep::Rotate)]),
+    // Blocks of all 0 bits, all 1 bits, and the two alternating ones:
+    ("alternating",  &[(0x00, PatternStep::Hold), (0xFF, PatternStep::Hold), (0x55, PatternStep::Hold), (0xAA, PatternStep::Hold)]),
+    ("all",          &[(0x00, PatternStep::Increment), (0x01, PatternStep::Rotate), (0xFE, PatternStep::Rotate),
+                       (0x00, PatternStep::Hold), (0xFF, PatternStep::Hold), (0x55, PatternStep::Hold), (0xAA, PatternStep::Hold)]),
+];
+
+const DEFAULT_ADDRESS: u16 = 0x5200;
+const DEFAULT_BLOCKS: usize = 8;
+
+// The blocks of the test tape, as given on the command line:
+fn retrieve_blocks(matches: &getopts::Matches) -> Vec<(u8, PatternStep)> {
+    let pattern = match matches.opt_str("pattern") {
+        Some(argument) => {
+            match PATTERNS.iter().find(|&&(name, _blocks)| name == argument.to_lowercase()) {
+                Some(&(_name, blocks)) => { blocks },
+                None => {
+                    let names: Vec<&str> = PATTERNS.iter().map(|&(name, _blocks)| name).collect();
+                    error!("Unknown test pattern `{}', expected one of {}.", argument, names.join(", "));
+                    process::exit(1);
+                },
+            }
+        },
+        None => { PATTERNS[PATTERNS.len() - 1].1 },
+    };
+    let count = match matches.opt_str("blocks") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(count) if (1..=128).contains(&count) => { count },
+                _ => {
+                    error!("Invalid number of blocks `{}', expected a number from 1 to 128.", argument);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { DEFAULT_BLOCKS },
+    };
+    pattern.iter().cycle().take(count).cloned().collect()
+}
+
+fn retrieve_address(matches: &getopts::Matches, length: usize) -> u16 {
+    let address = match matches.opt_str("address") {
+        Some(argument) => {
+            match numbers::parse_number(&argument, 16) {
+                Ok(address) if address <= 0xFFFF => { address as u16 },
+                Ok(_address) => {
+                    error!("Invalid `--address' value `{}', addresses go up to 0xFFFF.", argument);
+                    process::exit(1);
+                },
+                Err(error) => {
+                    error!("Invalid `--address' value `{}': {}.", argument, error);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { DEFAULT_ADDRESS },
+    };
+    if (address as usize) + length > 0x10000 {
+        error!("The test tape doesn't fit into memory from 0x{:04X} on, it's {} bytes long.", address, length);
+        process::exit(1);
+    }
+    address
+}
+
+fn pattern_block(first: u8, step: PatternStep) -> Vec<u8> {
+    let mut block = Vec::with_capacity(stubs::PATTERN_BLOCK_LENGTH);
+    let mut value = first;
+    for _count in 0..stubs::PATTERN_BLOCK_LENGTH {
+        block.push(value);
+        value = step.next(value);
+    }
+    block
+}
+
+fn generate(matches: &getopts::Matches, out_filepath: &path::Path) -> ! {
+    let blocks = retrieve_blocks(matches);
+    let data: Vec<u8> = blocks.iter().flat_map(|&(first, step)| pattern_block(first, step)).collect();
+
+    // The checker goes right behind the patterns, it's the same length
+    // wherever it is:
+    let checker_length = stubs::pattern_checker(0, 0, &blocks).len();
+    let address = retrieve_address(matches, data.len() + checker_length);
+    let checker_address = address.wrapping_add(data.len() as u16);
+    let mut image = data;
+    image.extend(stubs::pattern_checker(checker_address, address, &blocks));
+
+    let mut format = packing::TapeFormat::standard();
+    format.variant = commands::retrieve_variant(matches);
+    let entry = packing::PackEntry::linear(out_filepath, image, b"TEST  ".to_vec(), address, checker_address);
+    let mut buffer = Vec::new();
+    packing::generate_tape_entry(&entry, &mut buffer, &format);
+
+    status!("Test tape of {} pattern blocks at 0x{:04X}-0x{:04X}, checked by the routine at 0x{:04X}.",
+            blocks.len(), address, checker_address - 1, checker_address);
+    if !files::write_file(&buffer, out_filepath) {
+        process::exit(1);
+    }
+    status!("Load it with SYSTEM and start it with `/', it displays a `.' for every intact block, an `X' for every damaged one.");
+    process::exit(0);
+}
+
+// Compares the patterns of a test tape read back, such as one saved by an
+// emulator or decoded from a recording, with what they should be:
+fn check(matches: &getopts::Matches, in_filepath: &path::Path) -> ! {
+    let blocks = retrieve_blocks(matches);
+    let address = retrieve_address(matches, blocks.len() * stubs::PATTERN_BLOCK_LENGTH);
+    let variant = commands::retrieve_variant(matches);
+    let buffer = match files::load_file(in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+    let entry = tape::merge_copies(&entries[0..tape::group_copies(&entries)[0].1]);
+    let (start, image, _has_holes) = match entry.memory_image() {
+        Some(image) => { image },
+        None => {
+            error!("Entry `{}' contains no data blocks.", entry.name_string());
+            process::exit(1);
+        },
+    };
+
+    // Bits which keep coming out wrong point at a line of the interface:
+    let mut bit_errors = [0usize; 8];
+    let mut bad_blocks = 0;
+    for (index, &(first, step)) in blocks.iter().enumerate() {
+        let block_address = (address as usize) + index * stubs::PATTERN_BLOCK_LENGTH;
+        let expected = pattern_block(first, step);
+        let loaded = if block_address >= (start as usize) && block_address + expected.len() <= (start as usize) + image.len() {
+            &image[block_address - (start as usize)..block_address - (start as usize) + expected.len()]
+        } else {
+            error!("Block {} at 0x{:04X} is missing from the tape.", index + 1, block_address);
+            bad_blocks += 1;
+            continue;
+        };
+
+        let wrong: Vec<usize> = (0..expected.len()).filter(|offset| loaded[*offset] != expected[*offset]).collect();
+        for offset in &wrong {
+            let difference = loaded[*offset] ^ expected[*offset];
+            for (bit, count) in bit_errors.iter_mut().enumerate() {
+                *count += ((difference >> bit) & 1) as usize;
+            }
+        }
+        match wrong.first() {
+            Some(offset) => {
+                error!("Block {} at 0x{:04X}: {} bytes wrong, the first at 0x{:04X}, 0x{:02X} instead of 0x{:02X}.", index + 1, block_address,
+                       wrong.len(), block_address + offset, loaded[*offset], expected[*offset]);
+                bad_blocks += 1;
+            },
+            None => {
+                verbose!("Block {} at 0x{:04X}: intact.", index + 1, block_address);
+            },
+        }
+    }
+
+    if bad_blocks == 0 {
+        status!("{}: OK, all {} pattern blocks intact.", in_filepath.display(), blocks.len());
+        process::exit(0);
+    }
+    let counts: Vec<String> = (0..8).rev().map(|bit| format!("{}: {}", bit, bit_errors[bit])).collect();
+    status!("Wrong bits by position: {}.", counts.join(", "));
+    error!("{}: {} of {} pattern blocks damaged.", in_filepath.display(), bad_blocks, blocks.len());
+    process::exit(1);
+}
+
+pub const USAGE: &str = "-o <file> | --check <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("o", "output", "Name of the test tape image to write.", "FILE");
+    options.optopt("", "pattern", "Patterns of the blocks: `incrementing' bytes, `walking' ones and zeros, `alternating' blocks of 0x00, 0xFF, 0x55 and 0xAA, or `all' of them (the default).", "PATTERN");
+    options.optopt("", "blocks", "Number of 256 byte blocks on the tape, the pattern repeating over them (8 by default).", "COUNT");
+    options.optopt("", "address", "Address the blocks load at (in hex, 0x5200 by default), the routine checking them goes right behind them.", "ADDR");
+    options.optopt("", "check", "Check the pattern blocks of a test tape image read back instead, such as one saved by an emulator or decoded from a recording, made with the same `--pattern', `--blocks' and `--address'.", "FILE");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "testtape", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+
+    if let Some(argument) = matches.opt_str("check") {
+        check(&matches, argument.as_ref());
+    }
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => { commands::expand_output_path(&argument, &[]) },
+        None => {
+            error!("No output file given, use `{} testtape -o <file>', or `--check <file>' for a test tape read back.", progname);
+            process::exit(1);
+        },
+    };
+    generate(&matches, &out_filepath);
+}
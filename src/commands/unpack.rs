@@ -0,0 +1,173 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+
+use commands;
+use files;
+use tape;
+use template;
+
+
+fn retrieve_entry_number(matches: &getopts::Matches, entry_count: usize) -> Option<usize> {
+    match matches.opt_str("e") {
+        Some(argument) => {
+            match argument.parse::<usize>() {
+                Ok(number) if number >= 1 && number <= entry_count => { Some(number - 1) },
+                _ => {
+                    error!("Invalid entry number `{}', the tape contains {} entries.", argument, entry_count);
+                    process::exit(1);
+                },
+            }
+        },
+        None => { None },
+    }
+}
+
+fn unpack_entry(entry: &tape::Entry, out_path: &path::Path, ignore_checksums: bool) -> bool {
+    let bad_checksums = entry.bad_checksum_count();
+    if bad_checksums != 0 {
+        if ignore_checksums {
+            warning!("Entry `{}' has {} blocks with bad checksums.", entry.name_string(), bad_checksums);
+        } else {
+            error!("Entry `{}' has {} blocks with bad checksums, use `--ignore-checksums' to extract it anyway.", entry.name_string(), bad_checksums);
+            return false;
+        }
+    }
+
+    let (base_address, image, has_holes) = match entry.memory_image() {
+        Some(image) => { image },
+        None => {
+            error!("Entry `{}' contains no data blocks.", entry.name_string());
+            return false;
+        },
+    };
+    if has_holes {
+        warning!("Entry `{}' doesn't load into a contiguous range, the holes are filled with zeros.", entry.name_string());
+    }
+
+    status!("Tape data entry name: `{}'", entry.name_string());
+    status!("Base address:          0x{:04X}", base_address);
+    match entry.entry_point {
+        Some(entry_point) => { status!("Entry point address:   0x{:04X}", entry_point); },
+        None => { status!("Entry point address:   (none, no end of file record)"); },
+    }
+
+    files::write_file(&image, out_path)
+}
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to unpack.", "FILE");
+    options.optopt("o", "output", "Name of the destination file (input filename with extension changed to .bin by default, numbered if the tape holds several entries). May be a template such as `{name}_{base:04X}.bin', with {name}, {stem} (of the input filename), {base}, {start}, {end}, {size} and {index} available.", "FILE");
+    options.optopt("e", "entry", "Only unpack the entry with the given number, counting from 1.", "NUMBER");
+    options.optflag("", "ignore-checksums", "Unpack entries even if some of their blocks have bad checksums.");
+    commands::add_output_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "unpack", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "unpack", &matches);
+
+    let variant = commands::retrieve_variant(&matches);
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    // Entries recorded several times are unpacked once, from the intact
+    // blocks of all their copies:
+    let entries: Vec<tape::Entry> = tape::group_copies(&entries).into_iter().map(|(first, count)| {
+        if count > 1 {
+            status!("Entry `{}' is recorded {} times back to back, combining the copies.", entries[first].name_string(), count);
+        }
+        tape::merge_copies(&entries[first..first + count])
+    }).collect();
+
+    let selected: Vec<usize> = match retrieve_entry_number(&matches, entries.len()) {
+        Some(index) => { vec![index] },
+        None => { (0..entries.len()).collect() },
+    };
+    let output_templated = match matches.opt_str("o") {
+        Some(argument) => { template::is_template(&argument) },
+        None => { false },
+    };
+    if selected.len() > 1 && matches.opt_present("o") && !output_templated {
+        error!("The tape contains {} entries, the `--output' option can only be used together with `--entry', or as a template.", entries.len());
+        process::exit(1);
+    }
+
+    let mut success = true;
+    let mut used_paths: Vec<path::PathBuf> = Vec::new();
+    for index in selected {
+        let out_filepath = match matches.opt_str("o") {
+            Some(argument) => {
+                let entry = &entries[index];
+                let (base_address, end) = entry.address_range().unwrap_or((0, 0));
+                let mut values = commands::entry_template_values(&in_filepath, &entry.name, base_address,
+                                                                 entry.entry_point.unwrap_or(0), (end - (base_address as u32)) as usize);
+                values.push(("index", template::Value::Number((index + 1) as u32)));
+                commands::expand_output_path(&argument, &values)
+            },
+            None => {
+                if entries.len() == 1 {
+                    files::default_output_path(&in_filepath, "bin")
+                } else {
+                    files::numbered_output_path(&in_filepath, index + 1, "bin")
+                }
+            },
+        };
+        if out_filepath == in_filepath {
+            error!("The input and output files are the same, aborting to prevent data loss.");
+            process::exit(1);
+        }
+        if used_paths.contains(&out_filepath) {
+            error!("Several entries would be written into `{}', use a template that tells them apart, such as one with {{index}}.", out_filepath.display());
+            process::exit(1);
+        }
+        used_paths.push(out_filepath.clone());
+
+        status!("");
+        if !unpack_entry(&entries[index], &out_filepath, matches.opt_present("ignore-checksums")) {
+            success = false;
+        }
+    }
+
+    if success {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
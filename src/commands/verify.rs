@@ -0,0 +1,180 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::path;
+use std::process;
+use std::time;
+
+use audio;
+use commands;
+use console;
+use files;
+use packing;
+use tape;
+
+
+// Each stage of the benchmark is run over and over for at least this long,
+// so that short tapes are timed right too:
+const BENCH_SECONDS: f64 = 0.5;
+
+// Runs the stage until enough time has passed, for the seconds it takes:
+pub fn time_stage(stage: &mut dyn FnMut()) -> f64 {
+    let started = time::Instant::now();
+    let mut runs = 0;
+    while runs == 0 || started.elapsed().as_secs_f64() < BENCH_SECONDS {
+        stage();
+        runs += 1;
+    }
+    started.elapsed().as_secs_f64() / (runs as f64)
+}
+
+// The tape stages go by the bytes of the tape, the audio ones by the time
+// the recording plays for:
+fn report_tape_stage(stage: &str, seconds: f64, bytes: usize) {
+    println!("    {:<16} {:>10.3} ms, {:.2} MB of tape a second", stage, seconds * 1000.0, (bytes as f64) / seconds / 1_000_000.0);
+}
+
+pub fn report_audio_stage(stage: &str, seconds: f64, audio_seconds: f64) {
+    println!("    {:<16} {:>10.3} ms, {:.1} times real time, an hour of tape in {:.1} seconds", stage, seconds * 1000.0,
+             audio_seconds / seconds, 3600.0 * seconds / audio_seconds);
+}
+
+// Times what's done with the tape, packing it, parsing it, and encoding and
+// decoding its recording, for telling how long batch jobs take and whether
+// a change made any of it slower:
+fn bench(matches: &getopts::Matches, in_filepath: &path::Path, buffer: &[u8], entries: &[tape::Entry], variant: &tape::Variant) -> ! {
+    let mut format = packing::TapeFormat::standard();
+    format.variant = variant.clone();
+    let mut pack_entries = Vec::new();
+    for entry in entries {
+        if let Some((start, image, _has_holes)) = entry.memory_image() {
+            pack_entries.push(packing::PackEntry::linear(in_filepath, image, entry.name.clone(), start, entry.entry_point.unwrap_or(start)));
+        }
+    }
+    let audio_format = commands::retrieve_audio_format(matches);
+    let wav = audio::generate_wav_file(&audio::encode_tape(buffer, &[], &audio_format, &mut |_done| {}), &audio_format);
+    let audio_seconds = audio::tape_offset_position(buffer.len(), &[], &audio_format) as f64 / (audio_format.sample_rate as f64);
+
+    println!("Benchmark of `{}', {} bytes, {:.1} seconds of audio at {} Hz:", in_filepath.display(), buffer.len(), audio_seconds, audio_format.sample_rate);
+    // The messages of the packer would get in between the results:
+    let verbosity = console::verbosity();
+    console::set_verbosity(console::QUIET);
+    let seconds = time_stage(&mut || {
+        let mut packed = Vec::with_capacity(buffer.len());
+        for entry in &pack_entries {
+            packing::generate_tape_entry(entry, &mut packed, &format);
+        }
+    });
+    console::set_verbosity(verbosity);
+    report_tape_stage("Packing", seconds, buffer.len());
+    let seconds = time_stage(&mut || { let _ = tape::parse_tape(buffer, variant); });
+    report_tape_stage("Parsing", seconds, buffer.len());
+    let seconds = time_stage(&mut || {
+        let samples = audio::encode_tape(buffer, &[], &audio_format, &mut |_done| {});
+        audio::generate_wav_file(&samples, &audio_format);
+    });
+    report_audio_stage("Encoding audio", seconds, audio_seconds);
+    let seconds = time_stage(&mut || {
+        if let Ok(recording) = audio::parse_wav_file(&wav) {
+            audio::decode_samples(&recording, variant.sync, 1.0, &mut |_done| {});
+        }
+    });
+    report_audio_stage("Decoding audio", seconds, audio_seconds);
+    process::exit(0);
+}
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to verify.", "FILE");
+    options.optflag("", "bench", "Time packing and parsing the tape, and encoding and decoding its recording in the audio format given, instead.");
+    commands::add_audio_options(&mut options);
+    commands::add_variant_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "verify", USAGE, false, &mut options, args);
+    let in_filepath = commands::retrieve_input_path(progname, "verify", &matches);
+
+    let variant = commands::retrieve_variant(&matches);
+    let buffer = match files::load_file(&in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+    let entries = match tape::parse_tape(&buffer, &variant) {
+        Ok(entries) => { entries },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    if matches.opt_present("bench") {
+        bench(&matches, &in_filepath, &buffer, &entries, &variant);
+    }
+
+    // Blocks damaged in one copy of an entry may be intact in another:
+    let groups = tape::group_copies(&entries);
+    let mut bad_blocks: usize = 0;
+    for &(first, count) in &groups {
+        let copies = &entries[first..first + count];
+        let merged = tape::merge_copies(copies);
+        if count > 1 {
+            status!("Entry `{}' is recorded {} times back to back.", merged.name_string(), count);
+        }
+
+        for (copy_index, entry) in copies.iter().enumerate() {
+            let label = if count > 1 {
+                format!("Entry `{}', copy {}", entry.name_string(), copy_index + 1)
+            } else {
+                format!("Entry `{}'", entry.name_string())
+            };
+            for (block_index, block) in entry.blocks.iter().enumerate() {
+                verbose!("{}, block {} at 0x{:04X}-0x{:04X}, {} bytes, checksum {}.",
+                         label, block_index + 1, block.load_address,
+                         block.end_address() - 1, block.data.len(), block.checksum.format(block.stored_checksum));
+                if block.checksum_ok() {
+                    continue;
+                }
+                if merged.blocks[block_index].checksum_ok() {
+                    warning!("{}: {}, block {} (offset {}, address 0x{:04X}): checksum mismatch, another copy holds the block intact.",
+                             in_filepath.display(), label, block_index + 1, block.offset, block.load_address);
+                } else {
+                    error!("{}: {}, block {} (offset {}, address 0x{:04X}): checksum mismatch, stored {}, computed {}.",
+                           in_filepath.display(), label, block_index + 1, block.offset,
+                           block.load_address, block.checksum.format(block.stored_checksum), block.checksum.format(block.computed_checksum()));
+                }
+            }
+        }
+        bad_blocks += merged.bad_checksum_count();
+    }
+
+    if bad_blocks == 0 {
+        status!("{}: OK, {} entries verified.", in_filepath.display(), groups.len());
+        process::exit(0);
+    } else {
+        error!("{}: {} blocks failed verification.", in_filepath.display(), bad_blocks);
+        process::exit(1);
+    }
+}
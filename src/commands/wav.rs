@@ -0,0 +1,467 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use getopts;
+
+use std::io;
+use std::path;
+use std::process;
+
+use audio;
+use commands;
+use files;
+use numbers;
+use packing;
+use progress;
+use stubs;
+use tape;
+
+
+// Decodes a freshly written recording, and compares it with the tape image,
+// from the first sync byte on, as the length of the leader isn't recorded
+// exactly:
+fn verify_recording(out_path: &path::Path, tape: &[u8], sync_byte: u8) -> bool {
+    let buffer = match files::load_file(out_path) {
+        Some(buffer) => { buffer },
+        None => { return false; },
+    };
+    let recording = match audio::parse_wav_file(&buffer) {
+        Ok(recording) => { recording },
+        Err(error) => {
+            error!("Verification of `{}' failed: {}.", out_path.display(), error);
+            return false;
+        },
+    };
+
+    let decoded = audio::decode_samples(&recording, sync_byte, 1.0, &mut |_done| {}).bytes;
+    let expected = match tape.iter().position(|byte| *byte == sync_byte) {
+        Some(sync_offset) => { &tape[sync_offset..] },
+        None => { &tape[tape.len()..] },
+    };
+    if decoded.as_slice() != expected {
+        let difference = decoded.iter().zip(expected).position(|(decoded, expected)| decoded != expected)
+                                .unwrap_or(decoded.len().min(expected.len()));
+        error!("Verification of `{}' failed, the recording decodes differently from byte {} after the first sync byte on.", out_path.display(), difference);
+        return false;
+    }
+
+    status!("Verified `{}', the recording decodes into the tape image.", out_path.display());
+    true
+}
+
+// Works out the number of leader bytes recorded in the given time:
+fn retrieve_leader_length(matches: &getopts::Matches) -> Option<usize> {
+    let argument = matches.opt_str("leader-seconds")?;
+    match argument.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds <= 600.0 => {
+            Some((seconds * 1_000_000.0 / (audio::BYTE_DURATION_US as f64)).ceil() as usize)
+        },
+        _ => {
+            error!("Invalid leader length `{}', expected a positive number of seconds, up to 600.", argument);
+            process::exit(1);
+        },
+    }
+}
+
+// Works out the length of the silence given with an option, in microseconds:
+fn retrieve_silence(matches: &getopts::Matches, option: &str) -> u32 {
+    let argument = match matches.opt_str(option) {
+        Some(argument) => { argument },
+        None => { return 0; },
+    };
+    match argument.parse::<f64>() {
+        Ok(seconds) if (0.0..=600.0).contains(&seconds) => { (seconds * 1_000_000.0).round() as u32 },
+        _ => {
+            error!("Invalid `--{}' length `{}', expected a number of seconds, up to 600.", option, argument);
+            process::exit(1);
+        },
+    }
+}
+
+// Copies the tape image with leaders of the given length in front of all of
+// its entries, in place of the ones it has:
+fn replace_leaders(buffer: &[u8], entries: &[tape::Entry], variant: &tape::Variant, leader_length: usize) -> Vec<u8> {
+    let mut tape = Vec::with_capacity(buffer.len() + entries.len() * leader_length);
+    let mut position = 0;
+
+    for entry in entries {
+        let sync_offset = match buffer[entry.start_offset..entry.end_offset].iter().position(|byte| *byte == variant.sync) {
+            Some(offset) => { entry.start_offset + offset },
+            None => { continue; },
+        };
+        tape.extend_from_slice(&buffer[position..entry.start_offset]);
+        tape.extend(variant.leader.iter().cycle().take(leader_length));
+        position = sync_offset;
+    }
+    tape.extend_from_slice(&buffer[position..]);
+
+    tape
+}
+
+// The single entry of a tape recorded as a normal entry holding the turbo or
+// the slow loader, followed by its data at the loader's bit rate:
+struct LoaderRecording {
+    loader_tape: Vec<u8>,
+    payload: Vec<u8>,
+    // Length of the bit cells of the payload:
+    cell_us: u32,
+}
+
+fn prepare_loader(entries: &[tape::Entry], matches: &getopts::Matches, leader_length: Option<usize>) -> Option<LoaderRecording> {
+    let option = if matches.opt_present("slow") { "slow" } else { "turbo" };
+    if entries.len() != 1 {
+        error!("The `--{}' option needs a tape image with a single entry, this one has {}.", option, entries.len());
+        return None;
+    }
+    let entry = &entries[0];
+    let (base_address, image, _has_holes) = match entry.memory_image() {
+        Some(image) => { image },
+        None => {
+            error!("Entry `{}' contains no data blocks.", entry.name_string());
+            return None;
+        },
+    };
+
+    // The length of the bit cells, and the bit rate they make:
+    let (cell_us, baud) = if matches.opt_present("slow") {
+        let factor = match matches.opt_str("slow-factor") {
+            Some(argument) => {
+                match argument.parse::<u32>() {
+                    Ok(factor) if stubs::SLOW_FACTORS.contains(&factor) => { factor },
+                    _ => {
+                        error!("Invalid slow factor `{}', expected 2, 3 or 4 times slower than the normal bit rate.", argument);
+                        return None;
+                    },
+                }
+            },
+            None => { 2 },
+        };
+        (audio::BIT_CELL_US * factor, 500 / factor)
+    } else {
+        let speed = match matches.opt_str("turbo-speed") {
+            Some(argument) => {
+                match argument.parse::<u32>() {
+                    Ok(speed) if stubs::TURBO_SPEEDS.contains(&speed) => { speed },
+                    _ => {
+                        error!("Invalid turbo speed `{}', expected 2, 3 or 4 times the normal bit rate.", argument);
+                        return None;
+                    },
+                }
+            },
+            None => { 2 },
+        };
+        (audio::BIT_CELL_US / speed, 500 * speed)
+    };
+    let loader_address = match matches.opt_str("turbo-loader") {
+        Some(argument) => {
+            match numbers::parse_number(&argument, 16) {
+                Ok(address) if address <= 0xFFFF => { address as u16 },
+                Ok(_address) => {
+                    error!("The turbo loader address `{}' doesn't fit into the Z80's address space.", argument);
+                    return None;
+                },
+                Err(error) => {
+                    error!("Failed to parse the turbo loader address `{}': {}.", argument, error);
+                    return None;
+                },
+            }
+        },
+        None => { 0x7F00 },
+    };
+    let leader_seconds = match matches.opt_str("turbo-leader") {
+        Some(argument) => {
+            match argument.parse::<u32>() {
+                Ok(seconds) if seconds > 0 => { seconds },
+                _ => {
+                    error!("Invalid turbo leader length `{}', expected a positive number of seconds.", argument);
+                    return None;
+                },
+            }
+        },
+        None => { 8 },
+    };
+
+    // The loader has to stay in place while the data comes in:
+    let loader = stubs::turbo_loader(loader_address, cell_us);
+    let loader_end = (loader_address as u32) + (loader.len() as u32);
+    let data_end = (base_address as u32) + (image.len() as u32);
+    if loader_end > 0x10000 || ((loader_address as u32) < data_end && (base_address as u32) < loader_end) {
+        error!("The turbo loader at 0x{:04X}-0x{:04X} would be overwritten by the data at 0x{:04X}-0x{:04X}, move it with `--turbo-loader'.",
+               loader_address, loader_end - 1, base_address, data_end - 1);
+        return None;
+    }
+    status!("{} loader of {} bytes at 0x{:04X}, reading the data at {} baud.", if option == "slow" { "Slow" } else { "Turbo" },
+            loader.len(), loader_address, baud);
+
+    let mut loader_tape = Vec::new();
+    // The loader doesn't come from a file of its own:
+    let loader_entry = packing::PackEntry::linear(path::Path::new(""), loader, entry.name.clone(), loader_address, loader_address);
+    let mut loader_format = packing::TapeFormat::standard();
+    if let Some(leader_length) = leader_length {
+        loader_format.leader_length = leader_length;
+    }
+    packing::generate_tape_entry(&loader_entry, &mut loader_tape, &loader_format);
+
+    // The second leader gives the time to start the loader with `/':
+    let leader_length = ((leader_seconds as u64) * (baud as u64) / 8) as usize;
+    // Data-only entries without the end of file record return to BASIC:
+    let entry_point = entry.entry_point.unwrap_or(tape::ROM_BASIC_READY);
+    let payload = stubs::turbo_payload(base_address, &image, entry_point, leader_length);
+
+    Some(LoaderRecording { loader_tape, payload, cell_us })
+}
+
+// Options shaping the recording, shared with the commands which play it:
+pub fn add_recording_options(options: &mut getopts::Options) {
+    options.optopt("", "block-gap", "Length of the silence inserted between data blocks, for hardware interfaces and slow loaders which need the time to keep up, in milliseconds if followed by `ms', or in bytes' worth of recording time otherwise (none by default).", "LENGTH");
+    options.optopt("", "leader-seconds", "Length of the leader in front of every entry in seconds, in place of the leaders of the tape image, leaving the time for the physical leader of the cassette and for the motor to start when mastering to tape. With `--turbo' or `--slow', the leader of the entry holding the loader.", "SECONDS");
+    options.optopt("", "silence-before", "Length of the silence before the first entry in seconds (none by default).", "SECONDS");
+    options.optopt("", "silence-between", "Length of the silence between entries in seconds, as left when recording several programs onto a cassette one after another (none by default).", "SECONDS");
+    options.optopt("", "silence-after", "Length of the silence after the last entry in seconds (none by default).", "SECONDS");
+    options.optflag("", "turbo", "Record the entry of the tape with a turbo loader: a normal entry holding a fast loader goes first, followed by the data at a higher bit rate, which the loader reads once started with `/'.");
+    options.optopt("", "turbo-speed", "Bit rate of the data recorded with `--turbo', 2, 3 or 4 times the normal one (2 by default).", "FACTOR");
+    options.optflag("", "slow", "Record the entry of the tape with a slow loader, for worn decks and bad cables which can't load tapes at the normal bit rate: a normal entry holding the loader goes first, followed by the data at a lower bit rate with longer pulses, which the loader reads once started with `/'.");
+    options.optopt("", "slow-factor", "How many times slower than the normal bit rate the data is recorded with `--slow', 2, 3 or 4 (2 by default).", "FACTOR");
+    options.optopt("", "turbo-loader", "Address to load the turbo or the slow loader to, which has to stay clear of the data (0x7F00 by default, in hex).", "ADDR");
+    options.optopt("", "turbo-leader", "Length of the leader in front of the data read by the turbo or the slow loader, which gives the time to start the loader (8 seconds by default).", "SECONDS");
+    commands::add_variant_options(options);
+    commands::add_audio_options(options);
+    commands::add_duration_options(options);
+}
+
+// Loads the tape image to be recorded, with the leaders replaced if asked to:
+pub fn load_tape(matches: &getopts::Matches, in_filepath: &path::Path, variant: &tape::Variant) -> (Vec<u8>, Vec<tape::Entry>) {
+    let buffer = match files::load_file(in_filepath) {
+        Some(buffer) => { buffer },
+        None => { process::exit(1); },
+    };
+
+    // Make sure we're not about to record garbage:
+    let entries = match tape::parse_tape(&buffer, variant) {
+        Ok(entries) => {
+            status!("{}: {} bytes, {} entries.", in_filepath.display(), buffer.len(), entries.len());
+            entries
+        },
+        Err(error) => {
+            error!("{}: {}.", in_filepath.display(), error);
+            process::exit(1);
+        },
+    };
+
+    match retrieve_leader_length(matches) {
+        Some(leader_length) if !matches.opt_present("turbo") && !matches.opt_present("slow") => {
+            status!("Recording leaders of {} bytes in front of the entries.", leader_length);
+            let tape = replace_leaders(&buffer, &entries, variant, leader_length);
+            match tape::parse_tape(&tape, variant) {
+                Ok(entries) => { (tape, entries) },
+                Err(error) => {
+                    error!("{}: {}.", in_filepath.display(), error);
+                    process::exit(1);
+                },
+            }
+        },
+        _ => { (buffer, entries) },
+    }
+}
+
+// How the tape image gets recorded, worked out from the options before any
+// of it is, so that mistakes in them don't leave half of a file behind:
+pub struct RecordingPlan {
+    gaps: Vec<(usize, u32)>,
+    silence_before: u32,
+    silence_after: u32,
+    loader: Option<LoaderRecording>,
+}
+
+pub fn plan_recording(matches: &getopts::Matches, entries: &[tape::Entry]) -> RecordingPlan {
+    // The pauses go in front of every block but the first one of an entry:
+    let gap_length = match commands::retrieve_gap_length(matches, "block-gap") {
+        Some(commands::GapLength::Bytes(length)) => { (length as u32).saturating_mul(audio::BYTE_DURATION_US) },
+        Some(commands::GapLength::Milliseconds(length)) => { length.saturating_mul(1000) },
+        None => { 0 },
+    };
+    let silence_between = retrieve_silence(matches, "silence-between");
+
+    let mut gaps = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if index != 0 && silence_between != 0 {
+            gaps.push((entry.start_offset, silence_between));
+        }
+        if gap_length != 0 {
+            for block in entry.blocks.iter().skip(1) {
+                gaps.push((block.offset, gap_length));
+            }
+        }
+    }
+
+    let loader = if matches.opt_present("turbo") || matches.opt_present("slow") {
+        if matches.opt_present("turbo") && matches.opt_present("slow") {
+            error!("The `--turbo' and `--slow' options can't be used together.");
+            process::exit(1);
+        }
+        if matches.opt_present("block-gap") {
+            error!("The `--turbo' and `--slow' options can't be used together with `--block-gap'.");
+            process::exit(1);
+        }
+        match prepare_loader(entries, matches, retrieve_leader_length(matches)) {
+            Some(loader) => { Some(loader) },
+            None => { process::exit(1); },
+        }
+    } else {
+        None
+    };
+
+    RecordingPlan {
+        gaps,
+        // Silence before the first entry and after the last one, as left on
+        // a cassette at its start and between recording sessions:
+        silence_before: retrieve_silence(matches, "silence-before"),
+        silence_after: retrieve_silence(matches, "silence-after"),
+        loader,
+    }
+}
+
+// Records the tape image as planned, passing the samples on to the sink a
+// block at a time as they're made, and telling where in them each of the
+// entries starts:
+pub fn stream_recording(matches: &getopts::Matches, plan: &RecordingPlan, buffer: &[u8], entries: &[tape::Entry],
+                        audio_format: &audio::AudioFormat, sink: &mut dyn FnMut(&[i16]) -> Result<(), String>) -> Result<Vec<usize>, String> {
+    let mut encoder = audio::Encoder::new(audio_format, sink);
+    encoder.silence(plan.silence_before)?;
+
+    let first = encoder.position();
+    let (starts, result) = match plan.loader {
+        Some(ref loader) => {
+            let length = loader.loader_tape.len() + loader.payload.len();
+            let mut progress = progress::Progress::new("Encoding", "bytes", length);
+            let result = encoder.tape(&loader.loader_tape, &[], &mut |done| progress.update(done))
+                                .and_then(|()| encoder.bytes(&loader.payload, loader.cell_us))
+                                .and_then(|()| encoder.silence(audio::BYTE_DURATION_US));
+            progress.update(length);
+            progress.finish();
+            (vec![first], result)
+        },
+        None => {
+            let mut progress = progress::Progress::new("Encoding", "bytes", buffer.len());
+            let result = encoder.tape(buffer, &plan.gaps, &mut |done| progress.update(done));
+            progress.finish();
+            let starts = entries.iter().map(|entry| first + audio::tape_offset_position(entry.start_offset, &plan.gaps, audio_format)).collect();
+            (starts, result)
+        },
+    };
+    result?;
+    encoder.silence(plan.silence_after)?;
+    let length = encoder.finish()?;
+
+    status!("Recorded {:.1} seconds of audio at {} Hz.",
+            (length as f64) / (audio_format.sample_rate as f64), audio_format.sample_rate);
+    commands::report_tape_duration(matches, (length as u64) * 1_000_000 / (audio_format.sample_rate as u64));
+
+    Ok(starts)
+}
+
+// Turns the tape image into the samples of the recording, held in memory
+// whole, also telling where in them each of the entries starts:
+pub fn encode_recording(matches: &getopts::Matches, buffer: &[u8], entries: &[tape::Entry], audio_format: &audio::AudioFormat) -> (Vec<i16>, Vec<usize>) {
+    let plan = plan_recording(matches, entries);
+    encode_planned(matches, &plan, buffer, entries, audio_format)
+}
+
+// The same, along a plan made already:
+pub fn encode_planned(matches: &getopts::Matches, plan: &RecordingPlan, buffer: &[u8], entries: &[tape::Entry], audio_format: &audio::AudioFormat) -> (Vec<i16>, Vec<usize>) {
+    let mut samples = Vec::new();
+    let mut sink = |block: &[i16]| -> Result<(), String> {
+        samples.extend_from_slice(block);
+        Ok(())
+    };
+    let starts = match stream_recording(matches, plan, buffer, entries, audio_format, &mut sink) {
+        Ok(starts) => { starts },
+        Err(error) => {
+            error!("{}.", error);
+            process::exit(1);
+        },
+    };
+
+    (samples, starts)
+}
+
+pub const USAGE: &str = "-i <file>";
+
+// The options of the command, also listed in its manual page:
+pub fn options() -> getopts::Options {
+    let mut options = getopts::Options::new();
+
+    options.optopt("i", "input", "The tape image to convert.", "FILE");
+    options.optopt("o", "output", "Name of the destination file (input filename with extension changed to .wav by default). May be a template such as `{stem}_{name}.wav', with {stem} (of the input filename) and {name}, {base}, {start}, {end} and {size} of the first entry available.", "FILE");
+    options.optflag("", "verify-after-write", "Read the written recording back, decode it, and check that it holds the tape image.");
+    add_recording_options(&mut options);
+    commands::add_output_options(&mut options);
+
+    options
+}
+
+pub fn run(progname: &str, args: &[String]) {
+    let mut options = options();
+    let matches = commands::parse_options(progname, "wav", USAGE, false, &mut options, args);
+    commands::apply_output_options(&matches);
+    let in_filepath = commands::retrieve_input_path(progname, "wav", &matches);
+
+    let variant = commands::retrieve_variant(&matches);
+    let audio_format = commands::retrieve_audio_format(&matches);
+    let (buffer, entries) = load_tape(&matches, &in_filepath, &variant);
+
+    let out_filepath = match matches.opt_str("o") {
+        Some(argument) => {
+            // The placeholders describe the first entry on the tape:
+            let values = match entries.first() {
+                Some(entry) => {
+                    let (base_address, end) = entry.address_range().unwrap_or((0, 0));
+                    commands::entry_template_values(&in_filepath, &entry.name, base_address,
+                                                    entry.entry_point.unwrap_or(0), (end - (base_address as u32)) as usize)
+                },
+                None => { commands::entry_template_values(&in_filepath, &[], 0, 0, 0) },
+            };
+            commands::expand_output_path(&argument, &values)
+        },
+        None => { files::default_output_path(&in_filepath, "wav") },
+    };
+    if in_filepath == out_filepath {
+        error!("The input and output files are the same, aborting to prevent data loss.");
+        process::exit(1);
+    }
+    if (matches.opt_present("turbo") || matches.opt_present("slow")) && matches.opt_present("verify-after-write") {
+        error!("The `--turbo' and `--slow' options can't be used together with `--verify-after-write'.");
+        process::exit(1);
+    }
+
+    // The recording goes into the file as it's made, rather than being held
+    // in memory whole:
+    let plan = plan_recording(&matches, &entries);
+    let written = files::write_file_streamed(&out_filepath, &mut |file| {
+        let mut wav = audio::WavWriter::new(io::BufWriter::new(file), &audio_format).map_err(|error| error.to_string())?;
+        stream_recording(&matches, &plan, &buffer, &entries, &audio_format,
+                         &mut |samples| wav.write(samples).map_err(|error| error.to_string()))?;
+        wav.finish().map_err(|error| error.to_string())?;
+        Ok(())
+    });
+    if !written {
+        process::exit(1);
+    }
+    if matches.opt_present("verify-after-write") && !verify_recording(&out_filepath, &buffer, variant.sync) {
+        process::exit(1);
+    }
+    process::exit(0);
+}
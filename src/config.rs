@@ -0,0 +1,262 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Defaults for command-line options may be kept in configuration files, a
+// user-level one and a per-project one:
+//
+//   $XDG_CONFIG_HOME/trs80m1-mltl/config.toml (or ~/.config/trs80m1-mltl/...)
+//   .trs80m1-mltl.toml in the current directory or any directory above it
+//
+// They use a small subset of TOML: keys are the long names of options, set
+// at the top level for all commands, or in a `[command]' section for one
+// command only.  Values are strings, integers or booleans; a boolean sets
+// or clears a flag, an integer given to a flag repeats it.
+//
+//   name-check = "warn"
+//
+//   [pack]
+//   output-dir = "build/tapes"
+//   leader-length = 512
+//
+// Environment variables named after the options, such as
+// TRS80_MLTL_OUTPUT_DIR for `--output-dir', serve as another layer of
+// defaults, TRS80_MLTL_MACHINE being another name for TRS80_MLTL_RAM.
+// Flags are set by values like `1', `yes' or `true'.
+//
+// Project settings win over user settings, environment variables win over
+// both, and options given on the command line win over everything, also
+// over the defaults of options they can't be given together with.
+// Relative paths are taken relative to the directory of the configuration
+// file.
+
+use std::env;
+use std::path;
+
+use files;
+
+pub const PROJECT_CONFIG_NAME: &str = ".trs80m1-mltl.toml";
+pub const ENVIRONMENT_PREFIX:  &str = "TRS80_MLTL_";
+
+// Options whose values are paths:
+const PATH_KEYS: &[&str] = &["output-dir", "log-file"];
+
+// Variables with the prefix which are read for other things than defaults:
+const OTHER_VARIABLES: &[&str] = &["PLUGIN_DIR"];
+
+// Variables named after something else than the option they set:
+const VARIABLE_ALIASES: &[(&str, &str)] = &[("MACHINE", "ram")];
+
+
+#[derive(Clone)]
+pub enum Value {
+    Str(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+pub struct Setting {
+    pub key: String,
+    pub value: Value,
+
+    // Where the setting comes from, for error messages, and whether it was
+    // given for the command in particular, rather than for all of them:
+    pub origin: String,
+    pub command_specific: bool,
+}
+
+
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let quote = text.chars().next().unwrap();
+    let mut result = String::new();
+    let mut chars = text[1..].char_indices();
+
+    while let Some((index, character)) = chars.next() {
+        if character == quote {
+            return Ok((result, &text[index + 2..]));
+        }
+        if character == '\\' && quote == '"' {
+            match chars.next() {
+                Some((_index, 'n'))  => { result.push('\n'); },
+                Some((_index, 't'))  => { result.push('\t'); },
+                Some((_index, '\\')) => { result.push('\\'); },
+                Some((_index, '"'))  => { result.push('"'); },
+                Some((_index, other)) => {
+                    return Err(format!("unsupported escape sequence `\\{}'", other));
+                },
+                None => { break; },
+            }
+        } else {
+            result.push(character);
+        }
+    }
+
+    Err("unterminated string".to_owned())
+}
+
+fn parse_value(text: &str) -> Result<Value, String> {
+    let text = text.trim();
+
+    let (value, rest) = if text.starts_with('"') || text.starts_with('\'') {
+        let (string, rest) = parse_string(text)?;
+        (Value::Str(string), rest)
+    } else {
+        let end = text.find('#').unwrap_or(text.len());
+        let word = text[..end].trim();
+        let value = if word == "true" {
+            Value::Boolean(true)
+        } else if word == "false" {
+            Value::Boolean(false)
+        } else {
+            match word.replace('_', "").parse::<i64>() {
+                Ok(number) => { Value::Integer(number) },
+                Err(_error) => {
+                    return Err(format!("invalid value `{}', strings need to be quoted", word));
+                },
+            }
+        };
+        (value, &text[end..])
+    };
+
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected `{}' after the value", rest));
+    }
+
+    Ok(value)
+}
+
+// Picks out the settings which apply to the given command:
+fn parse_config(text: &str, origin: &path::Path, command: &str) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+    let mut section = String::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let location = format!("{}:{}", origin.display(), line_index + 1);
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let end = match line.find(']') {
+                Some(end) => { end },
+                None => { return Err(format!("{}: unterminated section header", location)); },
+            };
+            section = line[1..end].trim().to_owned();
+            continue;
+        }
+
+        let equals = match line.find('=') {
+            Some(equals) => { equals },
+            None => { return Err(format!("{}: expected `key = value'", location)); },
+        };
+        let key = line[..equals].trim().trim_matches('"').to_owned();
+        let value = match parse_value(&line[equals + 1..]) {
+            Ok(Value::Str(ref string)) if PATH_KEYS.contains(&key.as_str()) => {
+                let directory = origin.parent().unwrap_or(path::Path::new(""));
+                Value::Str(directory.join(string).to_string_lossy().into_owned())
+            },
+            Ok(value) => { value },
+            Err(error) => { return Err(format!("{}: {}", location, error)); },
+        };
+
+        if section.is_empty() || section == command {
+            settings.push(Setting {
+                key,
+                value,
+                origin: location,
+                command_specific: !section.is_empty(),
+            });
+        }
+    }
+
+    Ok(settings)
+}
+
+// Where the user-level configuration file and the plugins are kept:
+pub fn user_config_directory() -> Option<path::PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(directory) if !directory.is_empty() => {
+            Some(path::PathBuf::from(directory).join("trs80m1-mltl"))
+        },
+        _ => {
+            env::var_os("HOME").map(|home| path::PathBuf::from(home).join(".config").join("trs80m1-mltl"))
+        },
+    }
+}
+
+fn user_config_path() -> Option<path::PathBuf> {
+    user_config_directory().map(|directory| directory.join("config.toml"))
+}
+
+fn project_config_path() -> Option<path::PathBuf> {
+    let current = env::current_dir().ok()?;
+
+    current.ancestors()
+           .map(|directory| directory.join(PROJECT_CONFIG_NAME))
+           .find(|candidate| candidate.is_file())
+}
+
+// The option set by the environment variable with the given name, less its
+// prefix:
+pub fn environment_key(name: &str) -> String {
+    match VARIABLE_ALIASES.iter().find(|&&(alias, _)| alias == name) {
+        Some(&(_, key)) => { key.to_owned() },
+        None            => { name.to_lowercase().replace('_', "-") },
+    }
+}
+
+// Settings from environment variables apply to all commands which have the
+// option in question:
+fn environment_settings() -> Vec<Setting> {
+    let mut settings = Vec::new();
+
+    for (variable, value) in env::vars() {
+        if let Some(name) = variable.strip_prefix(ENVIRONMENT_PREFIX).filter(|name| !OTHER_VARIABLES.contains(name)) {
+            settings.push(Setting {
+                key: environment_key(name),
+                value: Value::Str(value.clone()),
+                origin: format!("environment variable {}", variable),
+                command_specific: false,
+            });
+        }
+    }
+
+    settings
+}
+
+// Loads the settings for a command from all configuration files, if wanted,
+// and the environment, with the ones which should take precedence coming
+// last:
+pub fn load_settings(command: &str, use_files: bool) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+    let config_paths = if use_files { vec![user_config_path(), project_config_path()] } else { vec![] };
+
+    for config_path in config_paths.into_iter().flatten() {
+        if !config_path.is_file() {
+            continue;
+        }
+        let buffer = match files::load_file(&config_path) {
+            Some(buffer) => { buffer },
+            None => { return Err(format!("Failed to load the configuration file `{}'", config_path.display())); },
+        };
+        let text = String::from_utf8_lossy(&buffer);
+        settings.extend(parse_config(&text, &config_path, command)?);
+    }
+    settings.extend(environment_settings());
+
+    Ok(settings)
+}
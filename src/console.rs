@@ -0,0 +1,297 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Read;
+use std::io::Write;
+use std::path;
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use clock;
+
+// Verbosity levels, selected with `-q', nothing, `-v' and `-vv':
+pub const QUIET:        usize = 0;
+pub const NORMAL:       usize = 1;
+pub const VERBOSE:      usize = 2;
+pub const VERY_VERBOSE: usize = 3;
+
+static VERBOSITY: AtomicUsize = AtomicUsize::new(NORMAL);
+
+// The name diagnostics are prefixed with:
+static PROGNAME: Mutex<String> = Mutex::new(String::new());
+
+// The warnings and notes reported so far, for run summaries:
+static WARNINGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static NOTES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+// Where all messages are recorded as well, whatever the verbosity, set with
+// `--log-file':
+static LOG_FILE: Mutex<Option<fs::File>> = Mutex::new(None);
+
+// The progress bar on the last line of the terminal, if one is drawn, which
+// messages get printed above:
+static PROGRESS_LINE: Mutex<String> = Mutex::new(String::new());
+
+
+pub fn set_verbosity(level: usize) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> usize {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+pub fn set_progname(progname: &str) {
+    *PROGNAME.lock().unwrap() = progname.to_owned();
+}
+
+pub fn warnings() -> Vec<String> {
+    WARNINGS.lock().unwrap().clone()
+}
+
+pub fn notes() -> Vec<String> {
+    NOTES.lock().unwrap().clone()
+}
+
+// Opens the log file for appending, and records how the program was run:
+pub fn open_log_file(log_path: &path::Path) -> Result<(), String> {
+    let log_file = match fs::OpenOptions::new().create(true).append(true).open(log_path) {
+        Ok(file) => { file },
+        Err(error) => { return Err(format!("Failed to open the log file `{}': {}", log_path.display(), error)); },
+    };
+    *LOG_FILE.lock().unwrap() = Some(log_file);
+
+    let command_line: Vec<String> = env::args().collect();
+    log("START", &format_args!("{}", command_line.join(" ")));
+    Ok(())
+}
+
+fn log(level: &str, message: &fmt::Arguments) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    if let Some(ref mut file) = *log_file {
+        let message = message.to_string();
+        if message.is_empty() {
+            return;
+        }
+
+        // A log which can't be written shouldn't stop the work itself:
+        let _ = writeln!(file, "{} {:5} {}", clock::now().iso_stamp(), level, message);
+    }
+}
+
+// Draws the progress bar over the one drawn before, or clears it if the
+// line given is empty:
+pub fn show_progress(line: &str) {
+    let mut shown = PROGRESS_LINE.lock().unwrap();
+    let padding = shown.chars().count().saturating_sub(line.chars().count());
+    print!("\r{}{}", line, " ".repeat(padding));
+    if line.is_empty() {
+        print!("\r");
+    }
+    let _ = io::stdout().flush();
+    *shown = line.to_owned();
+}
+
+// Prints something above the progress bar, if one is drawn, and draws it
+// again below:
+fn above_progress<F: FnOnce()>(print: F) {
+    let shown = PROGRESS_LINE.lock().unwrap();
+    if !shown.is_empty() {
+        print!("\r{}\r", " ".repeat(shown.chars().count()));
+        let _ = io::stdout().flush();
+    }
+    print();
+    if !shown.is_empty() {
+        print!("{}", shown);
+        let _ = io::stdout().flush();
+    }
+}
+
+// Prints a message if the verbosity is at least the given level, the
+// macros below are the usual way to call this:
+pub fn print(level: usize, message: fmt::Arguments) {
+    log(match level {
+        QUIET | NORMAL => { "INFO" },
+        VERBOSE => { "DEBUG" },
+        _ => { "TRACE" },
+    }, &message);
+
+    if verbosity() >= level {
+        above_progress(|| println!("{}", message));
+    }
+}
+
+
+#[derive(Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+// Colors are only used on terminals, and never if the user asked for none
+// the usual way, see https://no-color.org/:
+fn use_color(terminal: bool) -> bool {
+    if !terminal {
+        return false;
+    }
+    match env::var_os("NO_COLOR") {
+        Some(value) => { value.is_empty() },
+        None => { env::var("TERM").map(|term| term != "dumb").unwrap_or(true) },
+    }
+}
+
+// Prints a diagnostic with its severity in front of it.  Errors and warnings
+// go to the standard error output, notes are status output and go along with
+// the banners, so `--quiet' hides them:
+pub fn report(severity: Severity, message: fmt::Arguments) {
+    let (label, color, log_level) = match severity {
+        Severity::Error   => { ("error",   "1;31", "ERROR") },
+        Severity::Warning => { ("warning", "1;33", "WARN") },
+        Severity::Note    => { ("note",    "1;36", "NOTE") },
+    };
+    log(log_level, &message);
+    let progname = PROGNAME.lock().unwrap().clone();
+
+    match severity {
+        Severity::Warning => { WARNINGS.lock().unwrap().push(message.to_string()); },
+        Severity::Note => { NOTES.lock().unwrap().push(message.to_string()); },
+        Severity::Error => { },
+    }
+
+    match severity {
+        Severity::Note => {
+            if verbosity() < NORMAL {
+                return;
+            }
+            above_progress(|| if use_color(io::stdout().is_terminal()) {
+                println!("{}: \x1b[{}m{}:\x1b[0m {}", progname, color, label, message);
+            } else {
+                println!("{}: {}: {}", progname, label, message);
+            });
+        },
+        _ => {
+            above_progress(|| if use_color(io::stderr().is_terminal()) {
+                eprintln!("{}: \x1b[{}m{}:\x1b[0m {}", progname, color, label, message);
+            } else {
+                eprintln!("{}: {}: {}", progname, label, message);
+            });
+        },
+    }
+}
+
+
+fn stty(args: &[&str]) -> Option<String> {
+    let output = process::Command::new("stty").args(args).stdin(process::Stdio::inherit())
+                                              .stderr(process::Stdio::null()).output().ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    } else {
+        None
+    }
+}
+
+// Single key presses read from the terminal as they're typed, without
+// waiting for Enter.  The terminal is switched over with stty, and back once
+// this is dropped:
+pub struct KeyReader {
+    saved_settings: String,
+    keys: mpsc::Receiver<u8>,
+}
+
+impl KeyReader {
+    // None if the standard input isn't a terminal, or can't be switched
+    // over:
+    pub fn start() -> Option<KeyReader> {
+        if !io::stdin().is_terminal() {
+            return None;
+        }
+        let saved_settings = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+
+        let (sender, keys) = mpsc::channel();
+        thread::spawn(move || {
+            let mut key = [0u8; 1];
+            while let Ok(1) = io::stdin().read(&mut key) {
+                if sender.send(key[0]).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Some(KeyReader { saved_settings, keys })
+    }
+
+    // The next key pressed, if there's one:
+    pub fn key(&self) -> Option<u8> {
+        self.keys.try_recv().ok()
+    }
+}
+
+impl Drop for KeyReader {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved_settings]);
+    }
+}
+
+
+// Diagnostics, prefixed with the program name and their severity:
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::console::report($crate::console::Severity::Error, format_args!($($arg)*))
+    };
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::console::report($crate::console::Severity::Warning, format_args!($($arg)*))
+    };
+}
+
+macro_rules! note {
+    ($($arg:tt)*) => {
+        $crate::console::report($crate::console::Severity::Note, format_args!($($arg)*))
+    };
+}
+
+// Banners and summaries, suppressed by `--quiet':
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::console::print($crate::console::NORMAL, format_args!($($arg)*))
+    };
+}
+
+// Per-block detail, shown with `-v':
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::console::print($crate::console::VERBOSE, format_args!($($arg)*))
+    };
+}
+
+// Even more detail, shown with `-vv':
+macro_rules! very_verbose {
+    ($($arg:tt)*) => {
+        $crate::console::print($crate::console::VERY_VERBOSE, format_args!($($arg)*))
+    };
+}
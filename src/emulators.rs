@@ -0,0 +1,140 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Starting emulators with a freshly made tape mounted, for trying it out
+// without going through their menus.
+
+use std::path;
+use std::process;
+
+use sound;
+
+
+pub struct Emulator {
+    pub name: &'static str,
+    // Emulating a Model I, with `{tape}' standing for the tape image:
+    pub args: &'static [&'static str],
+    // Option typing the text following it on the emulated keyboard once the
+    // machine is up:
+    pub keys_option: Option<&'static str>,
+    // Option loading the symbols for the debugger:
+    pub symbols_option: Option<&'static str>,
+}
+
+// In the order they're looked for:
+pub const EMULATORS: &[Emulator] = &[
+    Emulator { name: "trs80gp", args: &["-m1", "-c", "{tape}"],               keys_option: Some("-i"), symbols_option: Some("-ls") },
+    Emulator { name: "sdltrs",  args: &["-model", "1", "-cassette", "{tape}"], keys_option: None,      symbols_option: None },
+    Emulator { name: "xtrs",    args: &["-model", "1", "-cassette", "{tape}"], keys_option: None,      symbols_option: None },
+];
+
+// Finds the emulator with the given name, or the first one installed:
+pub fn find_emulator(name: Option<&str>) -> Result<&'static Emulator, String> {
+    let names: Vec<&str> = EMULATORS.iter().map(|emulator| emulator.name).collect();
+    match name {
+        Some(name) => {
+            match EMULATORS.iter().find(|emulator| emulator.name == name.to_lowercase()) {
+                Some(emulator) => { Ok(emulator) },
+                None => { Err(format!("Unknown emulator `{}', expected one of {}", name, names.join(", "))) },
+            }
+        },
+        None => {
+            match EMULATORS.iter().find(|emulator| sound::in_path(emulator.name)) {
+                Some(emulator) => { Ok(emulator) },
+                None => { Err(format!("None of the supported emulators ({}) is installed", names.join(", "))) },
+            }
+        },
+    }
+}
+
+// The keys which load a SYSTEM tape of the given entry name and run it,
+// from the `MEMORY SIZE?' question on:
+pub fn system_keys(name: &str) -> String {
+    format!("\nSYSTEM\n{}\n/\n", name.trim_end())
+}
+
+// Files of the keys to type, for emulators started by test scripts rather
+// than from here:
+#[derive(Clone, Copy, PartialEq)]
+pub enum KeysFormat {
+    // The keys as they're typed, for `trs80gp -i "$(cat game.keys)"' and
+    // anything else taking them as text:
+    Text,
+    // A Lua script for MAME's `-autoboot_script', posting them to the
+    // emulated keyboard:
+    MameScript,
+    // An ini file setting MAME's `autoboot_command', a `trs80.ini' in its
+    // ini path:
+    MameIni,
+}
+
+pub const KEYS_FORMATS: &[(&str, KeysFormat, &str)] = &[
+    ("text",     KeysFormat::Text,       "keys"),
+    ("mame",     KeysFormat::MameScript, "lua"),
+    ("mame-ini", KeysFormat::MameIni,    "ini"),
+];
+
+// MAME types the keys once the machine had this many seconds to come up to
+// the `MEMORY SIZE?' question:
+const MAME_AUTOBOOT_DELAY: u32 = 2;
+
+// The keys as MAME takes them in a string, with the line ends escaped:
+fn escape_keys(keys: &str) -> String {
+    keys.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// The keys file of the given format, for the tape and the file names given:
+pub fn generate_keys_file(keys: &str, format: KeysFormat, tape_name: &str, keys_name: &str) -> String {
+    match format {
+        KeysFormat::Text => { keys.to_owned() },
+        KeysFormat::MameScript => {
+            format!("-- Types the commands loading the tape, run with\n\
+                     -- mame trs80 -cass {} -autoboot_delay {} -autoboot_script {}\n\
+                     manager.machine.natkeyboard:post(\"{}\")\n", tape_name, MAME_AUTOBOOT_DELAY, keys_name, escape_keys(keys))
+        },
+        KeysFormat::MameIni => {
+            format!("# Types the commands loading the tape, for mame trs80 -cass {}\n\
+                     # with this file as trs80.ini in the ini path.\n\
+                     autoboot_command          {}\n\
+                     autoboot_delay            {}\n", tape_name, escape_keys(keys), MAME_AUTOBOOT_DELAY)
+        },
+    }
+}
+
+// Runs the emulator with the tape mounted, and waits for it to be closed:
+pub fn run(emulator: &Emulator, tape_path: &path::Path, keys: Option<&str>, symbols_path: Option<&path::Path>) -> Result<(), String> {
+    let tape = tape_path.to_string_lossy();
+    let mut command = process::Command::new(emulator.name);
+    command.args(emulator.args.iter().map(|arg| arg.replace("{tape}", &tape)));
+    if let Some(symbols_path) = symbols_path {
+        match emulator.symbols_option {
+            Some(option) => { command.arg(option).arg(symbols_path); },
+            None => { note!("The `{}' emulator can't be given symbols, leaving them out.", emulator.name); },
+        }
+    }
+    if let Some(keys) = keys {
+        match emulator.keys_option {
+            Some(option) => { command.arg(option).arg(keys); },
+            None => { return Err(format!("The `{}' emulator can't be given keys to type", emulator.name)); },
+        }
+    }
+
+    match command.status() {
+        Ok(status) if status.success() => { Ok(()) },
+        Ok(status) => { Err(format!("`{}' failed, {}", emulator.name, status)) },
+        Err(error) => { Err(format!("Failed to run `{}': {}", emulator.name, error)) },
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+use std::env;
+use std::path;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::process;
+use std::time;
+
+use clock;
+use plugins;
+
+// Whether existing output files may be replaced, set with `--force':
+static ALLOW_OVERWRITE: AtomicBool = AtomicBool::new(false);
+
+// Where output files with default names go, set with `--output-dir':
+static OUTPUT_DIRECTORY: Mutex<Option<path::PathBuf>> = Mutex::new(None);
+
+// Whether replaced output files are kept as timestamped backups, set with
+// `--backup', and how many of them to keep, with 0 for all of them:
+static MAKE_BACKUPS: AtomicBool = AtomicBool::new(false);
+static BACKUPS_KEPT: AtomicUsize = AtomicUsize::new(0);
+
+// Every file read so far, for dependency listings:
+static LOADED_FILES: Mutex<Vec<path::PathBuf>> = Mutex::new(Vec::new());
+
+// Held while asking whether to overwrite a file, so that the questions of
+// files written at the same time, as by batch jobs, come one at a time:
+static PROMPT: Mutex<()> = Mutex::new(());
+
+// Numbers the temporary directories made, and how many names are tried for
+// one before giving up:
+static TEMPORARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+const TEMPORARY_ATTEMPTS: usize = 100;
+
+
+pub fn set_allow_overwrite(allow: bool) {
+    ALLOW_OVERWRITE.store(allow, Ordering::Relaxed);
+}
+
+pub fn set_backups(enabled: bool, kept: usize) {
+    MAKE_BACKUPS.store(enabled, Ordering::Relaxed);
+    BACKUPS_KEPT.store(kept, Ordering::Relaxed);
+}
+
+pub fn set_output_directory(directory: path::PathBuf) {
+    *OUTPUT_DIRECTORY.lock().unwrap() = Some(directory);
+}
+
+fn in_output_directory(name: path::PathBuf) -> path::PathBuf {
+    match *OUTPUT_DIRECTORY.lock().unwrap() {
+        Some(ref directory) => { directory.join(name) },
+        None => { name },
+    }
+}
+
+pub fn loaded_files() -> Vec<path::PathBuf> {
+    LOADED_FILES.lock().unwrap().clone()
+}
+
+
+pub fn load_file(in_path: &path::Path) -> Option<Vec<u8>> {
+    {
+        let mut loaded_files = LOADED_FILES.lock().unwrap();
+        if !loaded_files.iter().any(|loaded| loaded == in_path) {
+            loaded_files.push(in_path.to_owned());
+        }
+    }
+
+    let mut in_file = match fs::File::open(in_path) {
+        Ok(file) => { file },
+        Err(error) => {
+            error!("Failed to open `{}' for reading: {}.",
+                   in_path.display(), error);
+            return None;
+        },
+    };
+    let mut buffer = Vec::new();
+    match in_file.read_to_end(&mut buffer) {
+        // Files of formats plugins read are turned into tape images:
+        Ok(_length) => {
+            match plugins::read_converted(in_path) {
+                Some(Ok(tape)) => { Some(tape) },
+                Some(Err(error)) => {
+                    error!("{}.", error);
+                    None
+                },
+                None => { Some(buffer) },
+            }
+        },
+        Err(error) => {
+            error!("Failed to load the content of `{}': {}.",
+                   in_path.display(), error);
+            None
+        },
+    }
+}
+
+// Asks the user whether to replace an existing file, if there is a user to
+// ask, that is:
+fn confirm_overwrite(out_path: &path::Path) -> bool {
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    let _prompt = PROMPT.lock().unwrap();
+    eprint!("The output file `{}' already exists, overwrite it? [y/N] ", out_path.display());
+    let _ = io::stderr().flush();
+
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(_length) => {
+            let answer = answer.trim().to_lowercase();
+            answer == "y" || answer == "yes"
+        },
+        Err(_error) => { false },
+    }
+}
+
+// Whether the file already holds exactly the given bytes, in which case it's
+// left alone, so that its modification time doesn't trigger rebuilds of
+// everything made from it:
+fn is_unchanged(buffer: &[u8], out_path: &path::Path) -> bool {
+    match fs::metadata(out_path) {
+        Ok(ref metadata) if metadata.is_file() && metadata.len() == buffer.len() as u64 => { },
+        _ => { return false; },
+    }
+    match fs::read(out_path) {
+        Ok(content) => { content == buffer },
+        Err(_error) => { false },
+    }
+}
+
+// Tapes written to files of formats plugins write are converted by them:
+pub fn write_file(buffer: &[u8], out_path: &path::Path) -> bool {
+    let converted;
+    let buffer = match plugins::write_converted(buffer, out_path) {
+        Some(Ok(data)) => {
+            converted = data;
+            &converted[..]
+        },
+        Some(Err(error)) => {
+            error!("{}.", error);
+            return false;
+        },
+        None => { buffer },
+    };
+    if is_unchanged(buffer, out_path) {
+        status!("Leaving `{}' as it is (unchanged).", out_path.display());
+        return true;
+    }
+    if out_path.exists() && !ALLOW_OVERWRITE.load(Ordering::Relaxed) && !confirm_overwrite(out_path) {
+        error!("The output file `{}' already exists, use `--force' to overwrite it.",
+               out_path.display());
+        return false;
+    }
+
+    create_file(buffer, out_path, MAKE_BACKUPS.load(Ordering::Relaxed))
+}
+
+// Same as the above, for when replacing the file is the point, such as when
+// appending to it:
+pub fn store_file(buffer: &[u8], out_path: &path::Path) -> bool {
+    let converted;
+    let buffer = match plugins::write_converted(buffer, out_path) {
+        Some(Ok(data)) => {
+            converted = data;
+            &converted[..]
+        },
+        Some(Err(error)) => {
+            error!("{}.", error);
+            return false;
+        },
+        None => { buffer },
+    };
+    if is_unchanged(buffer, out_path) {
+        status!("Leaving `{}' as it is (unchanged).", out_path.display());
+        return true;
+    }
+    create_file(buffer, out_path, MAKE_BACKUPS.load(Ordering::Relaxed))
+}
+
+// Same as `write_file', for output made as it's written, such as long
+// recordings, which `write' puts into the file.  Plugins don't convert
+// these, and the file is replaced even if it ends up the same, as that's
+// only known once it's written:
+pub fn write_file_streamed(out_path: &path::Path, write: &mut dyn FnMut(&mut fs::File) -> Result<(), String>) -> bool {
+    if out_path.exists() && !ALLOW_OVERWRITE.load(Ordering::Relaxed) && !confirm_overwrite(out_path) {
+        error!("The output file `{}' already exists, use `--force' to overwrite it.",
+               out_path.display());
+        return false;
+    }
+    if MAKE_BACKUPS.load(Ordering::Relaxed) && out_path.is_file() && !back_up(out_path) {
+        return false;
+    }
+
+    let mut out_file = match fs::File::create(out_path) {
+        Ok(file) => { file },
+        Err(error) => {
+            error!("Failed to open `{}' for writing: {}.",
+                   out_path.display(), error);
+            return false;
+        },
+    };
+    match write(&mut out_file) {
+        Ok(()) => {
+            let length = out_file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            status!("Successfully wrote {} bytes into `{}'.",
+                    length, out_path.display());
+            true
+        },
+        Err(error) => {
+            error!("Failed to save the output into `{}': {}.",
+                   out_path.display(), error);
+            // What's there is only a part of the output:
+            drop(out_file);
+            let _ = fs::remove_file(out_path);
+            false
+        },
+    }
+}
+
+// Where a backup goes among the others, named as `back_up' names them after
+// the prefix: by the timestamp, then by the counter of those made within the
+// same second, the first of which has none:
+fn backup_order(name: &str, prefix_length: usize, stamp_length: usize) -> (String, u32) {
+    let middle = &name[prefix_length..name.len() - 4];
+    let stamp_end = stamp_length.min(middle.len());
+    let counter = middle[stamp_end..].strip_prefix('-').and_then(|counter| counter.parse::<u32>().ok()).unwrap_or(0);
+    (middle[..stamp_end].to_owned(), counter)
+}
+
+// Moves the old version of an output file out of the way, to a name like
+// `game.cas.2024-06-01T12-00-00.bak', dropping the oldest backups if there
+// are more than wanted:
+fn back_up(out_path: &path::Path) -> bool {
+    let file_name = match out_path.file_name() {
+        Some(name) => { name.to_string_lossy().into_owned() },
+        None => { return true; },
+    };
+    let stamp = clock::now().filename_stamp();
+
+    let directory = match out_path.parent() {
+        Some(parent) if parent != path::Path::new("") => { parent.to_owned() },
+        _ => { path::PathBuf::from(".") },
+    };
+    let prefix = format!("{}.", file_name);
+    let order = |path: &path::PathBuf| backup_order(&path.file_name().unwrap_or_default().to_string_lossy(), prefix.len(), stamp.len());
+    let mut backups: Vec<path::PathBuf> = match fs::read_dir(&directory) {
+        Ok(listing) => {
+            listing.filter_map(|item| item.ok()).map(|item| item.path()).filter(|path| {
+                let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                name.starts_with(&prefix) && name.ends_with(".bak") && name.len() > prefix.len() + 4 &&
+                    name[prefix.len()..].starts_with(|character: char| character.is_ascii_digit())
+            }).collect()
+        },
+        Err(_error) => { Vec::new() },
+    };
+    backups.sort_by_key(order);
+
+    // Backups made within the same second are counted on from the last one,
+    // even if the ones before it were removed, so that they stay in order:
+    let counter = backups.iter().map(order).filter(|(backup_stamp, _counter)| *backup_stamp == stamp)
+                         .map(|(_stamp, counter)| counter.max(1) + 1).max();
+    let mut backup_path = match counter {
+        Some(counter) => { out_path.with_file_name(format!("{}.{}-{}.bak", file_name, stamp, counter)) },
+        None => { out_path.with_file_name(format!("{}.{}.bak", file_name, stamp)) },
+    };
+    let mut counter = counter.unwrap_or(1);
+    while backup_path.exists() {
+        counter += 1;
+        backup_path = out_path.with_file_name(format!("{}.{}-{}.bak", file_name, stamp, counter));
+    }
+    if let Err(error) = fs::rename(out_path, &backup_path) {
+        error!("Failed to back up `{}' as `{}': {}.", out_path.display(), backup_path.display(), error);
+        return false;
+    }
+    status!("Backed up the previous `{}' as `{}'.", out_path.display(), backup_path.display());
+    backups.push(backup_path);
+
+    let kept = BACKUPS_KEPT.load(Ordering::Relaxed);
+    if kept == 0 {
+        return true;
+    }
+    while backups.len() > kept {
+        let oldest = backups.remove(0);
+        match fs::remove_file(&oldest) {
+            Ok(()) => { verbose!("Removed the old backup `{}'.", oldest.display()); },
+            Err(error) => { warning!("Failed to remove the old backup `{}': {}.", oldest.display(), error); },
+        }
+    }
+    true
+}
+
+fn create_file(buffer: &[u8], out_path: &path::Path, backup_wanted: bool) -> bool {
+    if backup_wanted && out_path.is_file() && !back_up(out_path) {
+        return false;
+    }
+
+    let mut out_file = match fs::File::create(out_path) {
+        Ok(file) => { file },
+        Err(error) => {
+            error!("Failed to open `{}' for writing: {}.",
+                   out_path.display(), error);
+            return false;
+        },
+    };
+    match out_file.write_all(buffer) {
+        Ok(()) => {
+            status!("Successfully wrote {} bytes into `{}'.",
+                    buffer.len(), out_path.display());
+            true
+        },
+        Err(error) => {
+            error!("Failed to save the output into `{}': {}.",
+                   out_path.display(), error);
+            false
+        },
+    }
+}
+
+// Default output names are derived from the input file's name, with the
+// extension replaced, placed in the current working directory or the one
+// given with `--output-dir':
+pub fn default_output_path(in_path: &path::Path, extension: &str) -> path::PathBuf {
+    let mut new_name = match in_path.file_name() {
+        Some(name) => { (name.as_ref() as &path::Path).to_owned() },
+        None => { ("output".as_ref() as &path::Path).to_owned() },
+    };
+    new_name.set_extension(extension);
+
+    in_output_directory(new_name)
+}
+
+// Same as above, but with a suffix added to the file stem, used when one
+// input expands into several output files:
+pub fn numbered_output_path(in_path: &path::Path, number: usize, extension: &str) -> path::PathBuf {
+    let stem = match in_path.file_stem() {
+        Some(stem) => { stem.to_string_lossy().into_owned() },
+        None => { "output".to_owned() },
+    };
+    in_output_directory((format!("{}_{}.{}", stem, number, extension).as_ref() as &path::Path).to_owned())
+}
+
+// Matches a filename against a shell-style pattern with `*' and `?':
+fn matches_pattern(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => { name.is_empty() },
+        Some(&'*') => {
+            (0..=name.len()).any(|skipped| matches_pattern(&pattern[1..], &name[skipped..]))
+        },
+        Some(&'?') => { !name.is_empty() && matches_pattern(&pattern[1..], &name[1..]) },
+        Some(character) => {
+            name.first() == Some(character) && matches_pattern(&pattern[1..], &name[1..])
+        },
+    }
+}
+
+// Extensions of the files picked up from directories given as inputs, of
+// binaries to pack and of recordings to decode:
+pub const BINARY_EXTENSIONS: &[&str] = &["bin", "rom"];
+pub const TAPE_EXTENSIONS: &[&str] = &["cas"];
+pub const LISTING_EXTENSIONS: &[&str] = &["lst", "prn"];
+pub const SOURCE_EXTENSIONS: &[&str] = &["asm", "z80"];
+pub const RECORDING_EXTENSIONS: &[&str] = &["wav", "mp3", "ogg", "oga", "opus", "flac", "aif", "aiff", "m4a"];
+
+// Turns an input argument into the files it stands for: itself if it's a
+// plain file, the ones with the given extensions in it if it's a directory,
+// or the matching files if it's a pattern, for shells which don't expand
+// those:
+pub fn expand_input_pattern(argument: &str, extensions: &[&str]) -> Result<Vec<path::PathBuf>, String> {
+    let argument_path = (argument.as_ref() as &path::Path).to_owned();
+
+    let (directory, pattern) = if argument_path.is_dir() {
+        (argument_path, None)
+    } else {
+        let name = match argument_path.file_name() {
+            Some(name) => { name.to_string_lossy().into_owned() },
+            None => { return Ok(vec![argument_path]); },
+        };
+        if !name.contains('*') && !name.contains('?') {
+            return Ok(vec![argument_path]);
+        }
+        let directory = match argument_path.parent() {
+            Some(parent) if parent != path::Path::new("") => { parent.to_owned() },
+            _ => { path::PathBuf::from(".") },
+        };
+        let directory_name = directory.to_string_lossy();
+        if directory_name.contains('*') || directory_name.contains('?') {
+            return Err(format!("`{}': Patterns are only supported in the filename, not in the directories", argument));
+        }
+        (directory, Some(name.chars().collect::<Vec<char>>()))
+    };
+
+    let listing = match fs::read_dir(&directory) {
+        Ok(listing) => { listing },
+        Err(error) => { return Err(format!("Failed to list `{}': {}", directory.display(), error)); },
+    };
+
+    let mut found = Vec::new();
+    for item in listing {
+        let item = match item {
+            Ok(item) => { item },
+            Err(error) => { return Err(format!("Failed to list `{}': {}", directory.display(), error)); },
+        };
+        let item_path = item.path();
+        if !item_path.is_file() {
+            continue;
+        }
+        let name: Vec<char> = item.file_name().to_string_lossy().chars().collect();
+
+        let wanted = match pattern {
+            // Like the shells do, only match hidden files explicitly:
+            Some(ref pattern) => {
+                (name.first() != Some(&'.') || pattern.first() == Some(&'.')) && matches_pattern(pattern, &name)
+            },
+            None => {
+                match item_path.extension() {
+                    Some(extension) => {
+                        let extension = extension.to_string_lossy().to_lowercase();
+                        extensions.contains(&extension.as_str())
+                    },
+                    None => { false },
+                }
+            },
+        };
+        if wanted {
+            found.push(item_path);
+        }
+    }
+
+    if found.is_empty() {
+        return Err(format!("`{}' doesn't match any files", argument));
+    }
+    found.sort();
+    Ok(found)
+}
+
+// Writes a Make-style dependency file, telling that the target was made
+// from the given inputs.  Each input also gets an empty rule, so that Make
+// doesn't fail once one of them is removed:
+pub fn write_dependency_file(dep_path: &path::Path, target: &path::Path, inputs: &[path::PathBuf]) -> bool {
+    fn escape(path: &path::Path) -> String {
+        let mut escaped = String::new();
+        for character in path.to_string_lossy().chars() {
+            match character {
+                ' ' | '#' => { escaped.push('\\'); escaped.push(character); },
+                '$' => { escaped.push_str("$$"); },
+                character => { escaped.push(character); },
+            }
+        }
+        escaped
+    }
+
+    let mut text = format!("{}:", escape(target));
+    for input in inputs {
+        text.push_str(&format!(" \\\n  {}", escape(input)));
+    }
+    text.push('\n');
+    for input in inputs {
+        text.push_str(&format!("\n{}:\n", escape(input)));
+    }
+
+    // Generated listings aren't worth backing up:
+    is_unchanged(text.as_bytes(), dep_path) || create_file(text.as_bytes(), dep_path, false)
+}
+
+#[cfg(unix)]
+fn create_private_directory(directory: &path::Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new().mode(0o700).create(directory)
+}
+
+#[cfg(not(unix))]
+fn create_private_directory(directory: &path::Path) -> io::Result<()> {
+    fs::create_dir(directory)
+}
+
+// Makes a fresh directory in the temporary one, for the files handed to and
+// from other programs.  Anything already there under the name, which any
+// other user may have put there, is left alone and another name tried, so
+// that nothing is ever written through a link planted in its place:
+pub fn make_temporary_directory(prefix: &str) -> Result<path::PathBuf, String> {
+    let base = env::temp_dir();
+    let nanoseconds = time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|elapsed| elapsed.subsec_nanos()).unwrap_or(0);
+    for _attempt in 0..TEMPORARY_ATTEMPTS {
+        let number = TEMPORARY_COUNT.fetch_add(1, Ordering::Relaxed);
+        let directory = base.join(format!("{}-{}-{}-{:08x}", prefix, process::id(), number, nanoseconds));
+        match create_private_directory(&directory) {
+            Ok(()) => { return Ok(directory); },
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists => { continue; },
+            Err(error) => { return Err(format!("Failed to create the temporary directory `{}': {}", directory.display(), error)); },
+        }
+    }
+    Err(format!("Failed to find a free name for a temporary directory in `{}'", base.display()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backups_sort_by_time_then_counter() {
+        let prefix = "game.cas.";
+        let mut names = vec!["game.cas.2024-06-01T12-00-00-10.bak", "game.cas.2024-06-01T12-00-01.bak", "game.cas.2024-06-01T12-00-00-2.bak",
+                             "game.cas.2024-06-01T12-00-00.bak", "game.cas.2024-05-31T23-59-59-3.bak"];
+        names.sort_by_key(|name| backup_order(name, prefix.len(), "2024-06-01T12-00-00".len()));
+        assert_eq!(names, vec!["game.cas.2024-05-31T23-59-59-3.bak", "game.cas.2024-06-01T12-00-00.bak", "game.cas.2024-06-01T12-00-00-2.bak",
+                               "game.cas.2024-06-01T12-00-00-10.bak", "game.cas.2024-06-01T12-00-01.bak"]);
+    }
+    #[test]
+    fn temporary_directories_are_fresh() {
+        let first = make_temporary_directory("trs80m1-mltl-test").unwrap();
+        let second = make_temporary_directory("trs80m1-mltl-test").unwrap();
+        assert!(first != second);
+        for directory in &[&first, &second] {
+            assert!(directory.is_dir());
+            assert_eq!(fs::read_dir(directory).unwrap().count(), 0);
+        }
+        let _ = fs::remove_dir(&first);
+        let _ = fs::remove_dir(&second);
+    }
+}
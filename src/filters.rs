@@ -0,0 +1,246 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Clean-up of recordings ahead of the decoder, for the worn, noisy and badly
+// levelled tapes which don't decode as they are.
+
+use std::f64::consts;
+
+
+// How the level of the recording gets adjusted, once the other filters are
+// done with it:
+#[derive(Clone, Copy, PartialEq)]
+pub enum Gain {
+    // Recordings too quiet for the decoder get brought up to the target
+    // level, the others are left as they are:
+    Auto,
+    // Every recording is brought to the target level:
+    Normalize,
+    // By the given number of decibels:
+    Fixed(f64),
+}
+
+#[derive(Clone, Copy)]
+pub struct Filters {
+    // Cutoff of the high-pass filter removing the DC offset, in Hz:
+    pub dc_cutoff: Option<f64>,
+    // Lower and upper edge of the pass band, in Hz:
+    pub band_pass: Option<(f64, f64)>,
+    // How many times louder than the signal around them clicks are:
+    pub click_factor: Option<f64>,
+    pub gain: Gain,
+}
+
+impl Filters {
+    pub fn none() -> Filters {
+        Filters {
+            dc_cutoff: None,
+            band_pass: None,
+            click_factor: None,
+            gain: Gain::Fixed(0.0),
+        }
+    }
+}
+
+// The samples are worked on as floating point, and clipped on the way back:
+fn to_samples(levels: &[f64]) -> Vec<i16> {
+    levels.iter().map(|level| level.round().clamp(-32768.0, 32767.0) as i16).collect()
+}
+
+// A single-pole high-pass filter, which lets the pulses through and drops the
+// slowly wandering offset:
+fn remove_dc(samples: &[i16], sample_rate: u32, cutoff: f64) -> Vec<i16> {
+    let pole = (-2.0 * consts::PI * cutoff / (sample_rate as f64)).exp();
+
+    let mut levels = Vec::with_capacity(samples.len());
+    let mut previous_input = 0.0;
+    let mut previous_output = 0.0;
+    for sample in samples {
+        let input = *sample as f64;
+        previous_output = input - previous_input + pole * previous_output;
+        previous_input = input;
+        levels.push(previous_output);
+    }
+    to_samples(&levels)
+}
+
+// Second order Butterworth sections, as described in the Audio EQ Cookbook:
+fn biquad(levels: &mut [f64], sample_rate: u32, frequency: f64, high_pass: bool) {
+    let omega = 2.0 * consts::PI * frequency / (sample_rate as f64);
+    let alpha = omega.sin() / (2.0 * consts::FRAC_1_SQRT_2);
+    let cosine = omega.cos();
+
+    let (b0, b1, b2) = if high_pass {
+        ((1.0 + cosine) / 2.0, -(1.0 + cosine), (1.0 + cosine) / 2.0)
+    } else {
+        ((1.0 - cosine) / 2.0, 1.0 - cosine, (1.0 - cosine) / 2.0)
+    };
+    let (a0, a1, a2) = (1.0 + alpha, -2.0 * cosine, 1.0 - alpha);
+
+    let (mut x1, mut x2, mut y1, mut y2) = (0.0, 0.0, 0.0, 0.0);
+    for level in levels.iter_mut() {
+        let x0 = *level;
+        let y0 = (b0 * x0 + b1 * x1 + b2 * x2 - a1 * y1 - a2 * y2) / a0;
+        x2 = x1;
+        x1 = x0;
+        y2 = y1;
+        y1 = y0;
+        *level = y0;
+    }
+}
+
+fn band_pass(samples: &[i16], sample_rate: u32, low: f64, high: f64) -> Vec<i16> {
+    let mut levels: Vec<f64> = samples.iter().map(|sample| *sample as f64).collect();
+    biquad(&mut levels, sample_rate, low, true);
+    biquad(&mut levels, sample_rate, high, false);
+    to_samples(&levels)
+}
+
+// Blocks long enough to always hold a clock pulse, even on a slow tape:
+const CLICK_BLOCK_US: u64 = 3000;
+// Blocks on either side making up the neighbourhood a block is compared to:
+const CLICK_NEIGHBOURS: usize = 8;
+// The clicks are looked for above this frequency, clear of the offset and
+// the hum, which would hide them:
+const CLICK_CUTOFF:   f64 = 500.0;
+// Anything louder lasting longer is taken for signal:
+const CLICK_MAX_US: u64 = 1000;
+// Crackles swing back and forth, the loud samples this close together make
+// up a single click:
+const CLICK_JOIN_US: u64 = 100;
+
+// Finds the clicks, short bursts much louder than the signal around them,
+// and draws straight lines over them.  The level of the signal around is the
+// median of the peaks of the neighbouring blocks, which the clicks
+// themselves don't sway.  Returns the number of clicks removed:
+fn suppress_clicks(samples: &mut [i16], sample_rate: u32, factor: f64) -> usize {
+    let block = (((sample_rate as u64) * CLICK_BLOCK_US / 1_000_000) as usize).max(1);
+    let max_length = ((sample_rate as u64) * CLICK_MAX_US / 1_000_000) as usize;
+    let join_length = ((sample_rate as u64) * CLICK_JOIN_US / 1_000_000) as usize;
+    let reference = remove_dc(samples, sample_rate, CLICK_CUTOFF);
+    let peaks: Vec<i32> = reference.chunks(block).map(|chunk| {
+        chunk.iter().map(|sample| (*sample as i32).abs()).max().unwrap_or(0)
+    }).collect();
+
+    let mut limits = Vec::with_capacity(peaks.len());
+    for index in 0..peaks.len() {
+        let mut neighbourhood = peaks[index.saturating_sub(CLICK_NEIGHBOURS)..(index + CLICK_NEIGHBOURS + 1).min(peaks.len())].to_vec();
+        neighbourhood.sort_unstable();
+        limits.push((neighbourhood[neighbourhood.len() / 2] as f64) * factor);
+    }
+
+    let loud = |index: usize| ((reference[index] as i32).abs() as f64) > limits[index / block];
+
+    let mut clicks = 0;
+    let mut index = 0;
+    while index < samples.len() {
+        if !loud(index) {
+            index += 1;
+            continue;
+        }
+
+        let start = index;
+        while index < samples.len() && (index..(index + join_length + 1).min(samples.len())).any(loud) {
+            index += 1;
+        }
+        // Back to the last loud sample:
+        while !loud(index - 1) {
+            index -= 1;
+        }
+        if index - start > max_length {
+            continue;
+        }
+
+        let before = if start > 0 { samples[start - 1] as f64 } else { 0.0 };
+        let after = if index < samples.len() { samples[index] as f64 } else { 0.0 };
+        let span = (index - start + 1) as f64;
+        for (offset, sample) in samples[start..index].iter_mut().enumerate() {
+            *sample = (before + (after - before) * ((offset + 1) as f64) / span).round() as i16;
+        }
+        clicks += 1;
+    }
+    clicks
+}
+
+// The level recordings get normalized to, and the one below which `Auto'
+// brings them up to it, in dB below full scale:
+const TARGET_LEVEL_DB: f64 = -3.0;
+const QUIET_LEVEL_DB:  f64 = -20.0;
+
+// Fraction of the samples allowed above the level the recording is taken to
+// peak at, so that a few clicks don't keep the rest quiet:
+const LEVEL_OUTLIERS: f64 = 0.001;
+
+// How loud the recording is, in dB below full scale, none if it's silent:
+fn level_db(samples: &[i16]) -> Option<f64> {
+    let mut histogram = vec![0usize; 32769];
+    for sample in samples {
+        histogram[(*sample as i32).unsigned_abs() as usize] += 1;
+    }
+    let allowed = ((samples.len() as f64) * LEVEL_OUTLIERS) as usize;
+    let mut above = 0;
+    for (level, count) in histogram.iter().enumerate().rev() {
+        above += count;
+        if above > allowed {
+            return if level == 0 { None } else { Some(20.0 * ((level as f64) / 32767.0).log10()) };
+        }
+    }
+    None
+}
+
+// Adjusts the level of the samples as asked for, and tells by how much:
+fn adjust_gain(samples: &mut Vec<i16>, gain: Gain) {
+    let level = match level_db(samples) {
+        Some(level) => { level },
+        None => { return; },
+    };
+    let decibels = match gain {
+        Gain::Auto if level < QUIET_LEVEL_DB => { TARGET_LEVEL_DB - level },
+        Gain::Auto => { return; },
+        Gain::Normalize => { TARGET_LEVEL_DB - level },
+        Gain::Fixed(decibels) => { decibels },
+    };
+    if decibels.abs() < 0.05 {
+        return;
+    }
+
+    let factor = 10f64.powf(decibels / 20.0);
+    let levels: Vec<f64> = samples.iter().map(|sample| (*sample as f64) * factor).collect();
+    let clipped = levels.iter().filter(|level| level.abs() > 32767.0).count();
+    *samples = to_samples(&levels);
+    status!("{} the recording by {:.1} dB, from a level of {:.1} dB below full scale.",
+            if decibels > 0.0 { "Amplified" } else { "Attenuated" }, decibels.abs(), -level);
+    if clipped != 0 {
+        verbose!("{} samples were clipped.", clipped);
+    }
+}
+
+// Runs the samples through the filters asked for, the clicks go first, as
+// the other filters would smear them out, and the level gets adjusted last,
+// to what they left of the signal:
+pub fn apply(filters: &Filters, samples: &mut Vec<i16>, sample_rate: u32) {
+    if let Some(factor) = filters.click_factor {
+        let clicks = suppress_clicks(samples, sample_rate, factor);
+        verbose!("Removed {} clicks.", clicks);
+    }
+    if let Some(cutoff) = filters.dc_cutoff {
+        *samples = remove_dc(samples, sample_rate, cutoff);
+    }
+    if let Some((low, high)) = filters.band_pass {
+        *samples = band_pass(samples, sample_rate, low, high);
+    }
+    adjust_gain(samples, filters.gain);
+}
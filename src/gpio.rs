@@ -0,0 +1,124 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Driving the cassette input of the machine straight from a GPIO pin of a
+// Raspberry Pi, through the sysfs interface of the kernel.  The pin goes
+// high for the positive half of every pulse, and wants a coupling capacitor
+// and a divider down to the levels of a cassette deck on its way to the
+// machine.
+
+use std::fs;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path;
+use std::thread;
+use std::time;
+
+
+const SYSFS_GPIO: &str = "/sys/class/gpio";
+
+// The recording is made at this rate for the pin, a tenth of the length of
+// the shortest half pulse:
+pub const SAMPLE_RATE: u32 = 100_000;
+
+// Newer kernels number the pins of the Pi's own GPIO controller from the
+// base of its chip on, rather than from 0:
+fn chip_base() -> u32 {
+    let chips = match fs::read_dir(SYSFS_GPIO) {
+        Ok(chips) => { chips },
+        Err(_error) => { return 0; },
+    };
+    for chip in chips.filter_map(|chip| chip.ok()).map(|chip| chip.path()) {
+        let is_chip = chip.file_name().is_some_and(|name| name.to_string_lossy().starts_with("gpiochip"));
+        let label = fs::read_to_string(chip.join("label")).unwrap_or_default();
+        if is_chip && label.trim().starts_with("pinctrl-") {
+            if let Ok(base) = fs::read_to_string(chip.join("base")).unwrap_or_default().trim().parse::<u32>() {
+                return base;
+            }
+        }
+    }
+    0
+}
+
+pub struct Pin {
+    number: u32,
+    value: fs::File,
+    active_high: bool,
+}
+
+impl Pin {
+    // Sets up the pin with the given BCM number as an output, low while the
+    // signal rests:
+    pub fn open(pin: u32, active_high: bool) -> Result<Pin, String> {
+        let number = chip_base() + pin;
+        let directory = path::Path::new(SYSFS_GPIO).join(format!("gpio{}", number));
+        if !directory.exists() {
+            if let Err(error) = fs::write(path::Path::new(SYSFS_GPIO).join("export"), number.to_string()) {
+                return Err(format!("Failed to export GPIO {} through `{}': {}", pin, SYSFS_GPIO, error));
+            }
+        }
+
+        // The files of a freshly exported pin are only writable once udev
+        // gets around to it:
+        let resting = if active_high { "low" } else { "high" };
+        let mut attempts = 0;
+        while let Err(error) = fs::write(directory.join("direction"), resting) {
+            attempts += 1;
+            if attempts == 50 {
+                return Err(format!("Failed to make GPIO {} an output: {}", pin, error));
+            }
+            thread::sleep(time::Duration::from_millis(20));
+        }
+        match fs::OpenOptions::new().write(true).open(directory.join("value")) {
+            Ok(value) => { Ok(Pin { number, value, active_high }) },
+            Err(error) => { Err(format!("Failed to open GPIO {}: {}", pin, error)) },
+        }
+    }
+
+    pub fn set(&mut self, active: bool) -> Result<(), String> {
+        let level: &[u8] = if active == self.active_high { b"1" } else { b"0" };
+        match self.value.seek(SeekFrom::Start(0)).and_then(|_| self.value.write_all(level)) {
+            Ok(()) => { Ok(()) },
+            Err(error) => { Err(format!("Failed to set GPIO {}: {}", self.number, error)) },
+        }
+    }
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let _ = self.set(false);
+        let _ = fs::write(path::Path::new(SYSFS_GPIO).join("unexport"), self.number.to_string());
+    }
+}
+
+// The times the signal changes at, in microseconds from the start, with
+// whether it goes active, from a recording at the pin's rate:
+pub fn edges(samples: &[i16]) -> Vec<(u64, bool)> {
+    let mut edges = Vec::new();
+    let mut active = false;
+
+    for (position, sample) in samples.iter().enumerate() {
+        if (*sample > 0) != active {
+            active = !active;
+            edges.push(((position as u64) * 1_000_000 / (SAMPLE_RATE as u64), active));
+        }
+    }
+    if active {
+        edges.push(((samples.len() as u64) * 1_000_000 / (SAMPLE_RATE as u64), false));
+    }
+    edges
+}
@@ -0,0 +1,88 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// Just enough JSON to describe the results of a run to other programs.
+
+use std::fmt;
+
+
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Float(f64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(&'static str, Json)>),
+}
+
+fn write_string(formatter: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    write!(formatter, "\"")?;
+    for character in string.chars() {
+        match character {
+            '"'  => { write!(formatter, "\\\"")?; },
+            '\\' => { write!(formatter, "\\\\")?; },
+            '\n' => { write!(formatter, "\\n")?; },
+            '\r' => { write!(formatter, "\\r")?; },
+            '\t' => { write!(formatter, "\\t")?; },
+            character if (character as u32) < 0x20 => {
+                write!(formatter, "\\u{:04x}", character as u32)?;
+            },
+            character => { write!(formatter, "{}", character)?; },
+        }
+    }
+    write!(formatter, "\"")
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => { write!(formatter, "null") },
+            Json::Bool(value) => { write!(formatter, "{}", value) },
+            Json::Number(value) => { write!(formatter, "{}", value) },
+            // JSON has no way to write infinities:
+            Json::Float(value) if !value.is_finite() => { write!(formatter, "null") },
+            Json::Float(value) => { write!(formatter, "{}", value) },
+            Json::Str(ref value) => { write_string(formatter, value) },
+            Json::Array(ref items) => {
+                write!(formatter, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index != 0 {
+                        write!(formatter, ",")?;
+                    }
+                    write!(formatter, "{}", item)?;
+                }
+                write!(formatter, "]")
+            },
+            Json::Object(ref members) => {
+                write!(formatter, "{{")?;
+                for (index, &(key, ref value)) in members.iter().enumerate() {
+                    if index != 0 {
+                        write!(formatter, ",")?;
+                    }
+                    write_string(formatter, key)?;
+                    write!(formatter, ":{}", value)?;
+                }
+                write!(formatter, "}}")
+            },
+        }
+    }
+}
+
+pub fn strings(items: &[String]) -> Json {
+    Json::Array(items.iter().map(|item| Json::Str(item.clone())).collect())
+}
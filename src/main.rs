@@ -13,18 +13,76 @@
 // OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
 //
 
+
 extern crate getopts;
 
+#[macro_use]
+mod console;
+
+mod assemblers;
+mod audio;
+mod clock;
+mod cmdfile;
+mod coco;
+mod commands;
+mod config;
+mod emulators;
+mod files;
+mod filters;
+mod gpio;
+mod hashes;
+mod json;
+mod memory;
+mod numbers;
 mod packing;
+mod plot;
+mod plugins;
+mod progress;
+mod simulator;
+mod sound;
+mod stubs;
+mod symbols;
+mod tape;
+mod tapetext;
+mod template;
+mod watch;
+mod websocket;
+mod wizard;
+mod z80;
 
 use std::env;
+use std::io;
+use std::io::IsTerminal;
 use std::path;
 use std::process;
 
 
-fn print_usage(progname: &str, opts: getopts::Options) {
-    let brief = format!("Usage: {} [options] -i <file> -b <base_addr> -s <entry_point>", progname);
-    println!("{}", opts.usage(&brief));
+fn print_usage(progname: &str) {
+    println!("Usage: {} <command> [options]", progname);
+    println!();
+    println!("Commands:");
+    for command in commands::COMMANDS {
+        println!("    {:9}  {}", command.name, command.summary);
+    }
+    plugins::with_plugins(|plugins| {
+        if plugins.is_empty() {
+            return;
+        }
+        println!();
+        println!("Plugins:");
+        for plugin in plugins {
+            let mut formats = Vec::new();
+            if !plugin.reads.is_empty() {
+                formats.push(format!("reads {}", plugin.reads.join(", ")));
+            }
+            if !plugin.writes.is_empty() {
+                formats.push(format!("writes {}", plugin.writes.join(", ")));
+            }
+            println!("    {:9}  {} ({}).", plugin.name, plugin.summary.trim_end_matches('.'), formats.join(", "));
+        }
+    });
+    println!();
+    println!("See `{} <command> --help' for the options of each command.", progname);
 }
 
 // Figure out the name of the executable:
@@ -43,293 +101,37 @@ fn get_progname(arg0: &path::Path) -> String {
 }
 
 
-// Parse an unsigned hexadecimal number (command-line argument), with or without
-// an optional 0x prefix:
-fn parse_hex_arg(arg: &str) -> Option<u32> {
-    let mut chars = arg.chars();
-
-    let first_char = chars.next();
-    match first_char {
-        Some(first_char_ch) => {
-            let effective_first_char: char;
-
-            // Handle an optional '0x' prefix:
-            if first_char_ch == '0' {
-                effective_first_char = match chars.next() {
-                    Some(character) => {
-                        if character == 'x' || character == 'X' {
-                            '0'
-                        } else {
-                            character
-                        }
-                    }
-                    None => { '0' },
-                }
-            } else {
-                effective_first_char = first_char_ch;
-            }
-
-            let mut accumulator: u32 = match effective_first_char.to_digit(16) {
-                Some(digit) => { digit },
-                None => { return None },
-            };
-
-            for current_char in chars {
-                accumulator = match current_char.to_digit(16) {
-                    Some(digit) => { (accumulator << 4) | digit },
-                    None => { return None },
-                };
-            }
-
-            Some(accumulator)
-        },
-        None => { None },
-    }
-}
-fn retrieve_base_address(progname: &str, matches: &getopts::Matches) -> Option<(bool, u16)> {
-    match matches.opt_str("b") {
-        Some(argument) => {
-            match parse_hex_arg(&argument) {
-                Some(address) => {
-                    if address > 0xFFFF {
-                        eprintln!("{}: The specified base address 0x{:04X} doesn't fit into the Z80's address space.", progname, address);
-                        None
-                    } else {
-                        Some((true, address as u16))
-                    }
-                }
-                None => {
-                    eprintln!("{}: Failed to parse the base address argument `{}'.", progname, argument);
-                    None
-                },
-            }
-        },
-        None => {
-            eprintln!("{}: Base address not speciified, please provide it with the `--base' command-line option.", progname);
-            Some((false, 0))
-        },
-    }
-}
-fn retrieve_entry_point(progname: &str, matches: &getopts::Matches) -> Option<(bool, u16)> {
-    match matches.opt_str("s") {
-        Some(argument) => {
-            match parse_hex_arg(&argument) {
-                Some(address) => {
-                    if address > 0xFFFF {
-                        eprintln!("{}: The specified entry point address 0x{:04X} doesn't fit into the Z80's address space.", progname, address);
-                        None
-                    } else {
-                        Some((true, address as u16))
-                    }
-                }
-                None => {
-                    eprintln!("{}: Failed to parse the entry point address argument `{}'.", progname, argument);
-                    None
-                },
-            }
-        },
-        None => {
-            eprintln!("{}: Entry point not speciified, please provide it with the `--start' command-line option.", progname);
-            Some((false, 0))
-        },
-    }
-}
-// The return value is (name, contains_letters)
-fn retrieve_tape_entry_name(default: &str, matches: &getopts::Matches) -> (Vec<u8>, bool) {
-
-    let template = match matches.opt_str("n") {
-        Some(argument) => { argument.to_owned() },
-        None => { default.to_owned() },
-    };
-
-    let mut entry_name = vec![0x20; 6];
-    let mut name_iter: usize = 0;
-    let mut has_first_char = false;
-
-    for character in template.chars() {
-        if name_iter == 6 {
-            break;
-        }
-        // Rust strings are Unicode, but here, we need ASCII, and only
-        // letters and the space.
-        //
-        // Thankfully, ASCII is a subset of Unicode, and we can simply ignore
-        // anything which doesn't fit our criteria.
-        //
-        let char_val = character as u32;
-
-        let (new_byte, add_char) = if (char_val == 0x20) && has_first_char {
-            (0x20, true)
-        } else if (char_val >= 0x41) && (char_val <= 0x5A) {
-            has_first_char = true;
-            (char_val as u8, true)
-        } else if (char_val >= 0x61) && (char_val <= 0x7A) {
-            has_first_char = true;
-            ((char_val - 0x20) as u8, true)
-        } else {
-            (0, false)
-        };
-
-        if add_char {
-            entry_name[name_iter] = new_byte;
-            name_iter += 1;
-        }
-    }
-
-    assert!(entry_name.len() == 6);
-    (entry_name, has_first_char)
-}
-
-
 fn main() {
     let args: Vec<String> = env::args().collect();
     let progname = get_progname(args[0].as_ref());
+    console::set_progname(&progname);
 
-    let mut options = getopts::Options::new();
-
-    options.optopt("i", "input", "The file to pack into a machine language tape file.", "FILE");
-    options.optopt("o", "output", "Name of the destination file (input filename with extension changed to .cas by default).", "FILE");
-    options.optopt("b", "base", "Starting address of where the data will reside after being loaded (in hex).", "ADDR");
-    options.optopt("s", "start", "Address of the execution entry point (in hex).", "ADDR");
-    options.optopt("n", "name", "Name of the data entry on the tape (input filename without extension by default). The name is limited to 6 upper-case ASCII letters. The first 6 ASCII letters are converted to upper-case, and everything else except for spaces is stripped.", "NAME");
-    options.optflag("h", "help", "Show this help listing.");
-
-    let matches = match options.parse(&args[1..]) {
-        Ok(matches) => { matches },
-        Err(error) => {
-            eprintln!("{}: Argument parsing error: {}", progname, error);
-            process::exit(1);
-        },
-    };
-
-    // Help should always be handled first:
-    if matches.opt_present("h") {
-        print_usage(&progname, options);
+    if args.len() < 2 {
+        // Newcomers get walked through it, scripts get the usage:
+        if io::stdin().is_terminal() && io::stdout().is_terminal() {
+            wizard::run(&progname);
+        }
+        print_usage(&progname);
+        process::exit(1);
+    }
+    if args[1] == "-h" || args[1] == "--help" || args[1] == "help" {
+        print_usage(&progname);
         process::exit(0);
     }
 
-    // Mandatory arguments:
-    let mut missing_mand_arg = false;
-    let in_filepath = match matches.opt_str("i") {
-        Some(name) => {
-            let new_path = (name.as_ref() as &path::Path).to_owned();
-            if !new_path.is_file() {
-                eprintln!("{}: The specified input file `{}' is not a file.", progname, new_path.display());
-                process::exit(1);
-            }
-            match new_path.extension() {
-                Some(extension_os) => {
-                    let extension = extension_os.to_string_lossy().into_owned();
-                    let cmp_ext = extension.to_uppercase();
-
-                    if cmp_ext == "ASM" || cmp_ext == "C"   ||
-                       cmp_ext == "CXX" || cmp_ext == "CPP" ||
-                       cmp_ext == "C++" || cmp_ext == "F"   ||
-                       cmp_ext == "F66" || cmp_ext == "F77" ||
-                       cmp_ext == "F90" || cmp_ext == "F95" {
-
-                        eprintln!("{}: The specified input file `{}' has a `{}' extension, and is most likely a source code file.  Rename it if you _really_ want to pack it into a machine language cassette image.", progname, new_path.display(), extension);
-                        process::exit(1);
-                    }
-                    if cmp_ext == "CAS" || cmp_ext == "CPT" {
-                        eprintln!("{}: The specified input file `{}' has a `{}' extension, and is most likely already a cassette image.  Rename it if you _really_ want to pack it into a machine language cassette image.", progname, new_path.display(), extension);
-                        process::exit(1);
-                    }
-                    if cmp_ext == "LST" || cmp_ext == "TXT" ||
-                       cmp_ext == "INI" || cmp_ext == "CONF" {
-                        eprintln!("{}: The specified input file `{}' has a `{}' extension, and is most likely a plain text file.  Rename it if you _really_ want to pack it into a machine language cassette image.", progname, new_path.display(), extension);
-                        process::exit(1);
-                    }
-                    if cmp_ext != "" && cmp_ext != "BIN" && cmp_ext != "ROM" {
-                        eprintln!("{}: Warning: The specified input file `{}' has a `{}' extension (`rom' or `bin' expected).", progname, new_path.display(), extension);
-                    }
-                },
-                None => { },
-            }
-
-            new_path
+    match commands::find_command(&args[1]) {
+        Some(command) => {
+            (command.run)(&progname, &args[2..]);
         },
-        None => {
-            eprintln!("{}: Input file not specified, please provide it with the `--input' command-line option.", progname);
-            missing_mand_arg = true;
-            ("".as_ref() as &path::Path).to_owned()
-        }
-    };
 
-    let base_address = match retrieve_base_address(&progname, &matches) {
-        Some((found, address)) => {
-            if !found {
-                missing_mand_arg = true;
-            }
-            address
-        },
-        None => { process::exit(1); },
-    };
-    let entry_point = match retrieve_entry_point(&progname, &matches) {
-        Some((found, address)) => {
-            if !found {
-                missing_mand_arg = true;
-            }
-            address
+        // Options without a command in front of them are the original
+        // flat invocation, which is the same as `pack':
+        None if args[1].starts_with("-") => {
+            commands::pack::run(&progname, &args[1..]);
         },
-        None => { process::exit(1); },
-    };
-
-    if missing_mand_arg {
-        eprintln!("");
-        eprintln!("Some mandatory command-line options are missing, see `{} --help'.", progname);
-
-        process::exit(1);
-    }
-
-    // Optional arguments:
-
-    // The filename of the input filepath is used for defaults of optional
-    // arguments.
-    //
-    // I feel that unwrap is reasonable here because we've already checked
-    // that this is indeed a file, and that the argument is present.
-    //
-    let in_filename = (in_filepath.file_name().unwrap().as_ref() as &path::Path).to_owned();
-
-    let out_filepath = match matches.opt_str("o") {
-        Some(name) => { (name.as_ref() as &path::Path).to_owned() },
         None => {
-            let mut new_name = in_filename.clone();
-            new_name.set_extension("cas");
-
-            new_name
-        }
-    };
-    let mut default_entry_name = in_filename.clone();
-    default_entry_name.set_extension("");
-    let (tape_entry_name, name_has_letters) = retrieve_tape_entry_name(&default_entry_name.to_string_lossy().into_owned(), &matches);
-
-
-    println!("Input filename:       `{}'", in_filepath.display());
-    println!("Output filename:      `{}'", out_filepath.display());
-    println!("Tape data entry name: `{}'", String::from_utf8((&tape_entry_name).to_owned()).expect("invalid characters in the tape data entry name, these should've been filtered out"));
-    println!("Base address:          0x{:04X}", base_address);
-    println!("Entry point address:   0x{:04X}", entry_point);
-    println!("");
-
-    if in_filepath == out_filepath {
-        eprintln!("The input and output files are the same, aborting to prevent data loss.");
-        process::exit(1);
-    }
-    if !name_has_letters {
-        eprintln!("The name of the data entry to be \"recorded onto the tape\" is empty, this could be because there either are no plain ASCII letters in your input filename, or in the name you provided via the `--name' command-line option.");
-        eprintln!("");
-        eprintln!("Please provide a valid name for the data entry, see `{} --help'.", progname);
-
-        process::exit(1);
-    }
-
-    // Perform the packing:
-    if packing::pack(&in_filepath, &out_filepath, tape_entry_name.as_slice(),
-                     base_address, entry_point) {
-        process::exit(0);
-    } else {
-        process::exit(1);
+            error!("Unknown command `{}', see `{} --help'.", args[1], progname);
+            process::exit(1);
+        },
     }
 }
@@ -0,0 +1,87 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// The memory map of the Model I, for checking where tapes load to.
+
+// RAM starts right after the ROM and the memory mapped devices:
+pub const RAM_START: u32 = 0x4000;
+
+// The RAM sizes the Model I was sold with, and where their RAM ends
+// (exclusive):
+pub const RAM_SIZES: &[(&str, u32)] = &[
+    ("4k",  0x5000),
+    ("16k", 0x8000),
+    ("32k", 0xC000),
+    ("48k", 0x10000),
+];
+
+// Looks up the end of RAM for a size given as `16k', `16K' or `16':
+pub fn ram_end(size: &str) -> Option<u32> {
+    let size = size.to_lowercase();
+    let size = if size.ends_with('k') { size } else { format!("{}k", size) };
+
+    RAM_SIZES.iter().find(|&&(name, _end)| name == size).map(|&(_name, end)| end)
+}
+
+// Areas which tapes normally shouldn't load into:
+pub struct Region {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub start: u32,
+    pub end: u32,
+
+    // Whether loading there deserves a warning rather than just a note:
+    pub warn: bool,
+}
+
+pub const REGIONS: &[Region] = &[
+    Region { name: "rom", description: "the Level II ROM", start: 0x0000, end: 0x3000, warn: true },
+    Region { name: "io", description: "the unused and memory mapped I/O area", start: 0x3000, end: 0x3800, warn: true },
+    Region { name: "keyboard", description: "the keyboard matrix", start: 0x3800, end: 0x3C00, warn: true },
+    Region { name: "video", description: "video RAM", start: 0x3C00, end: 0x4000, warn: true },
+    Region { name: "system", description: "the ROM's work area", start: 0x4000, end: 0x42E9, warn: true },
+
+    // Only matters on machines with an expansion interface and disks:
+    Region { name: "dos", description: "the area used by TRSDOS", start: 0x42E9, end: 0x5200, warn: false },
+];
+
+impl Region {
+    // Whether the region overlaps the given range (with an exclusive end):
+    pub fn overlaps(&self, start: u32, end: u32) -> bool {
+        start < self.end && self.start < end
+    }
+}
+
+// Places outside of the loaded data which entry points commonly lead to:
+pub const KNOWN_ENTRY_POINTS: &[(u16, &str)] = &[
+    (0x0000, "the reset vector"),
+    (0x06CC, "the BASIC warm start"),
+    (0x1A19, "the BASIC `READY' prompt"),
+    (0x402D, "the DOS return vector"),
+];
+
+// What the ROM's tape loader itself uses while reading the blocks, data
+// loading over it either goes bad or hangs the load:
+pub const LOADER_AREAS: &[(u32, u32, &str)] = &[
+    (0x3C3E, 0x3C40, "the blinking asterisks the loader shows"),
+    (0x403D, 0x403E, "the copy of the cassette port the ROM keeps"),
+];
+
+// The SYSTEM command runs on BASIC's stack, which sits just below the
+// string space at the top of memory.  With the MEMORY SIZE question left
+// blank, this many bytes at the end of RAM are in use while loading:
+pub const LOADER_STACK_SPAN: u32 = 0x100;
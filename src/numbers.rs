@@ -0,0 +1,314 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Numbers on the command line may be written in any of the notations found
+// in assembler listings and magazines of the era:
+//
+//   0x8000, $8000, &H8000, 8000h    hexadecimal
+//   #32768                          decimal
+//   8000                            hexadecimal, or decimal with `--decimal'
+//
+// The plain form stays hexadecimal by default, since that's what the tool
+// has always accepted.  Plain decimal numbers are easily mistaken for it,
+// so plain hexadecimal ones past 16 bits are refused rather than read as
+// the larger number, such as 32768 meant as decimal and read as 0x32768.
+
+
+pub fn parse_number(arg: &str, bare_radix: u32) -> Result<u32, String> {
+    let arg = arg.trim();
+
+    if let Some(digits) = arg.strip_prefix('#') {
+        return parse_digits(digits, 10);
+    }
+
+    let hex_digits = arg.strip_prefix("0x")
+        .or_else(|| arg.strip_prefix("0X"))
+        .or_else(|| arg.strip_prefix("&H"))
+        .or_else(|| arg.strip_prefix("&h"))
+        .or_else(|| arg.strip_prefix('$'))
+        .or_else(|| arg.strip_suffix('h'))
+        .or_else(|| arg.strip_suffix('H'));
+
+    match hex_digits {
+        Some(digits) => { parse_digits(digits, 16) },
+        None => {
+            let value = parse_digits(arg, bare_radix)?;
+            if bare_radix == 16 && value > 0xFFFF {
+                return Err(if arg.chars().all(|character| character.is_ascii_digit()) {
+                    format!("0x{} doesn't fit into 16 bits, write it as #{} if it's meant to be decimal", arg, arg)
+                } else {
+                    format!("0x{} doesn't fit into 16 bits, write it as 0x{} if it's meant to be that large", arg, arg)
+                });
+            }
+            Ok(value)
+        },
+    }
+}
+
+fn parse_digits(digits: &str, radix: u32) -> Result<u32, String> {
+    if digits.is_empty() {
+        return Err("no digits found".to_owned());
+    }
+
+    let mut accumulator: u32 = 0;
+    for current_char in digits.chars() {
+        let digit = match current_char.to_digit(radix) {
+            Some(digit) => { digit },
+            None => {
+                return Err(format!("`{}' is not a valid {} digit", current_char,
+                                   if radix == 16 { "hexadecimal" } else { "decimal" }));
+            },
+        };
+        accumulator = match accumulator.checked_mul(radix).and_then(|value| value.checked_add(digit)) {
+            Some(value) => { value },
+            None => { return Err("the number is too large".to_owned()); },
+        };
+    }
+
+    Ok(accumulator)
+}
+
+
+// Address arguments may also be simple arithmetic expressions, such as
+// `base+0x20' or `0x8000-size', made of numbers in the above notations,
+// named values supplied by the caller, `+', `-', `*', `/' and parentheses.
+
+enum Token {
+    Value(i64),
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Open,
+    Close,
+}
+
+fn is_operator(character: char) -> bool {
+    "+-*/()".contains(character) || character.is_whitespace()
+}
+
+fn tokenize(expression: &str, bare_radix: u32, variables: &[(&str, u32)]) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+
+    while let Some(&character) = chars.peek() {
+        if character.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let token = match character {
+            '+' => { Token::Plus },
+            '-' => { Token::Minus },
+            '*' => { Token::Times },
+            '/' => { Token::Divide },
+            '(' => { Token::Open },
+            ')' => { Token::Close },
+            _ => {
+                let mut word = String::new();
+                while let Some(&character) = chars.peek() {
+                    if is_operator(character) {
+                        break;
+                    }
+                    word.push(character);
+                    chars.next();
+                }
+                let lowercase = word.to_lowercase();
+                let value = match variables.iter().find(|&&(name, _value)| name == lowercase) {
+                    Some(&(_name, value)) => { value },
+                    None => {
+                        parse_number(&word, bare_radix).map_err(|error| format!("`{}': {}", word, error))?
+                    },
+                };
+                tokens.push(Token::Value(value as i64));
+                continue;
+            },
+        };
+        tokens.push(token);
+        chars.next();
+    }
+
+    Ok(tokens)
+}
+
+// expression := term { ('+' | '-') term }
+fn parse_sum(tokens: &[Token], position: &mut usize) -> Result<i64, String> {
+    let mut accumulator = parse_product(tokens, position)?;
+
+    while *position < tokens.len() {
+        match tokens[*position] {
+            Token::Plus => {
+                *position += 1;
+                accumulator = accumulator.checked_add(parse_product(tokens, position)?)
+                                         .ok_or_else(|| "the result is too large".to_owned())?;
+            },
+            Token::Minus => {
+                *position += 1;
+                accumulator = accumulator.checked_sub(parse_product(tokens, position)?)
+                                         .ok_or_else(|| "the result is too large".to_owned())?;
+            },
+            _ => { break; },
+        }
+    }
+
+    Ok(accumulator)
+}
+
+// term := factor { ('*' | '/') factor }
+fn parse_product(tokens: &[Token], position: &mut usize) -> Result<i64, String> {
+    let mut accumulator = parse_factor(tokens, position)?;
+
+    while *position < tokens.len() {
+        match tokens[*position] {
+            Token::Times => {
+                *position += 1;
+                accumulator = accumulator.checked_mul(parse_factor(tokens, position)?)
+                                         .ok_or_else(|| "the result is too large".to_owned())?;
+            },
+            Token::Divide => {
+                *position += 1;
+                let divisor = parse_factor(tokens, position)?;
+                if divisor == 0 {
+                    return Err("division by zero".to_owned());
+                }
+                accumulator /= divisor;
+            },
+            _ => { break; },
+        }
+    }
+
+    Ok(accumulator)
+}
+
+// factor := value | '-' factor | '(' expression ')'
+fn parse_factor(tokens: &[Token], position: &mut usize) -> Result<i64, String> {
+    if *position >= tokens.len() {
+        return Err("the expression ends unexpectedly".to_owned());
+    }
+    *position += 1;
+
+    match tokens[*position - 1] {
+        Token::Value(value) => { Ok(value) },
+        Token::Minus => { Ok(-parse_factor(tokens, position)?) },
+        Token::Open => {
+            let value = parse_sum(tokens, position)?;
+            match tokens.get(*position) {
+                Some(&Token::Close) => {
+                    *position += 1;
+                    Ok(value)
+                },
+                _ => { Err("missing closing parenthesis".to_owned()) },
+            }
+        },
+        _ => { Err("expected a number or a name".to_owned()) },
+    }
+}
+
+pub fn evaluate_expression(expression: &str, bare_radix: u32, variables: &[(&str, u32)]) -> Result<u32, String> {
+    let tokens = tokenize(expression, bare_radix, variables)?;
+    if tokens.is_empty() {
+        return Err("no digits found".to_owned());
+    }
+
+    let mut position: usize = 0;
+    let value = parse_sum(&tokens, &mut position)?;
+    if position != tokens.len() {
+        return Err("unexpected trailing characters".to_owned());
+    }
+
+    if value < 0 {
+        Err(format!("the result is negative ({})", value))
+    } else if value > (u32::MAX as i64) {
+        Err("the result is too large".to_owned())
+    } else {
+        Ok(value as u32)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notations() {
+        for notation in &["0x8000", "0X8000", "$8000", "&H8000", "&h8000", "8000h", "8000H", "8000", " 8000 "] {
+            assert_eq!(parse_number(notation, 16), Ok(0x8000), "{}", notation);
+        }
+        assert_eq!(parse_number("#32768", 16), Ok(32768));
+        assert_eq!(parse_number("32768", 10), Ok(32768));
+        assert_eq!(parse_number("0x8000", 10), Ok(0x8000));
+        assert_eq!(parse_number("#32768", 10), Ok(32768));
+    }
+
+    #[test]
+    fn invalid_digits() {
+        assert!(parse_number("", 16).is_err());
+        assert!(parse_number("0x", 16).is_err());
+        assert!(parse_number("#", 16).is_err());
+        assert!(parse_number("80G0", 16).is_err());
+        assert!(parse_number("8A00", 10).is_err());
+        assert!(parse_number("#8A00", 16).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(parse_number("0xFFFFFFFF", 16), Ok(u32::MAX));
+        assert!(parse_number("0x100000000", 16).is_err());
+        assert!(parse_number("#4294967296", 16).is_err());
+        // Plain hexadecimal stops at 16 bits, but not the marked one:
+        assert_eq!(parse_number("FFFF", 16), Ok(0xFFFF));
+        assert!(parse_number("10000", 16).is_err());
+        assert!(parse_number("32768", 16).is_err());
+        assert_eq!(parse_number("0x10000", 16), Ok(0x10000));
+        assert_eq!(parse_number("100000", 10), Ok(100000));
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate_expression("2+3*4", 10, &[]), Ok(14));
+        assert_eq!(evaluate_expression("(2+3)*4", 10, &[]), Ok(20));
+        assert_eq!(evaluate_expression("20-6-4", 10, &[]), Ok(10));
+        assert_eq!(evaluate_expression("100/10/5", 10, &[]), Ok(2));
+        assert_eq!(evaluate_expression("10-2*3+1", 10, &[]), Ok(5));
+        assert_eq!(evaluate_expression("-2+5", 10, &[]), Ok(3));
+        assert_eq!(evaluate_expression("2*-3+10", 10, &[]), Ok(4));
+        assert_eq!(evaluate_expression(" ( 1 + 2 ) * ( 3 + 4 ) ", 10, &[]), Ok(21));
+    }
+
+    #[test]
+    fn radix_and_names() {
+        assert_eq!(evaluate_expression("8000+10", 16, &[]), Ok(0x8010));
+        assert_eq!(evaluate_expression("8000+10", 10, &[]), Ok(8010));
+        assert_eq!(evaluate_expression("0x8000+#16", 10, &[]), Ok(0x8010));
+        assert_eq!(evaluate_expression("$8000-size", 16, &[("size", 0x100)]), Ok(0x7F00));
+        assert_eq!(evaluate_expression("BASE+SIZE", 16, &[("base", 0x5200), ("size", 0x20)]), Ok(0x5220));
+        assert!(evaluate_expression("base+1", 16, &[]).is_err());
+    }
+
+    #[test]
+    fn invalid_expressions() {
+        assert!(evaluate_expression("", 16, &[]).is_err());
+        assert!(evaluate_expression("1+", 16, &[]).is_err());
+        assert!(evaluate_expression("(1+2", 16, &[]).is_err());
+        assert!(evaluate_expression("1+2)", 16, &[]).is_err());
+        assert!(evaluate_expression("4/0", 16, &[]).is_err());
+        assert!(evaluate_expression("1-2", 16, &[]).is_err());
+        assert!(evaluate_expression("0xFFFFFFFF+1", 16, &[]).is_err());
+        assert!(evaluate_expression("0xFFFFFFFF*0xFFFFFFFF*0xFFFFFFFF", 16, &[]).is_err());
+        assert!(evaluate_expression("0x7FFFFFFF*0xFFFFFFFF+0xFFFFFFFF+0xFFFFFFFF", 16, &[]).is_err());
+        assert!(evaluate_expression("-0x7FFFFFFF*0xFFFFFFFF-0xFFFFFFFF-0xFFFFFFFF", 16, &[]).is_err());
+    }
+}
@@ -14,65 +14,113 @@
 //
 
 use std::path;
-use std::fs;
-use std::io::prelude::*;
 
+use files;
+use tape;
 
-fn load_input_file(in_path: &path::Path, buffer: &mut Vec<u8>) -> Option<usize> {
-    let mut in_file = match fs::File::open(in_path) {
-        Ok(file) => { file },
-        Err(error) => {
-            eprintln!("Failed to open `{}' for reading: {}.",
-                      in_path.display(), error);
-            return None;
-        },
-    };
-    match in_file.read_to_end(buffer) {
-        Ok(length) => { Some(length) },
-        Err(error) => {
-            eprintln!("Failed to load the content of `{}': {}.",
-                      in_path.display(), error);
-            None
-        },
+
+// Runs of a fill byte which get left out of the tape, for memory which
+// holds the fill byte already when the tape gets loaded:
+#[derive(Clone, Copy)]
+pub struct SparseFill {
+    pub byte: u8,
+    pub min_run: usize,
+}
+
+// The order the blocks are recorded in, each carries its load address so
+// the ROM loader takes them in any order:
+#[derive(Clone, Copy, PartialEq)]
+pub enum BlockOrder {
+    Sequential,
+    Reverse,
+    // Every other block, and then the ones in between:
+    Interleave,
+}
+
+pub const BLOCK_ORDERS: &[(&str, BlockOrder)] = &[
+    ("sequential", BlockOrder::Sequential),
+    ("reverse",    BlockOrder::Reverse),
+    ("interleave", BlockOrder::Interleave),
+];
+
+// The indices of the blocks in the order they get recorded in:
+pub fn block_sequence(count: usize, order: BlockOrder) -> Vec<usize> {
+    match order {
+        BlockOrder::Sequential => { (0..count).collect() },
+        BlockOrder::Reverse => { (0..count).rev().collect() },
+        BlockOrder::Interleave => { (0..count).step_by(2).chain((1..count).step_by(2)).collect() },
     }
 }
 
-fn write_down_tape_file(output_buffer: &Vec<u8>, out_path: &path::Path) -> bool {
-    let mut out_file = match fs::File::create(out_path) {
-        Ok(file) => { file },
-        Err(error) => {
-            eprintln!("Failed to open `{}' for writing: {}.",
-                      out_path.display(), error);
-            return false;
-        },
-    };
-    match out_file.write_all(output_buffer.as_slice()) {
-        Ok(()) => {
-            println!("");
-            println!("Successfully wrote {} bytes into `{}'.",
-                     output_buffer.len(), out_path.display());
-            true
-        },
-        Err(error) => {
-            eprintln!("Failed to save the created tape into `{}': {}.",
-                      out_path.display(), error);
-            false
-        },
+// Parameters of how the entries get recorded onto the tape:
+pub struct TapeFormat {
+    pub leader_length: usize,
+
+    // Payload size of the data blocks, the last one may be shorter:
+    pub block_size: usize,
+
+    pub variant: tape::Variant,
+
+    // Number of zero bytes between data blocks, for loaders which need the
+    // time to keep up:
+    pub block_gap: usize,
+
+    pub sparse: Option<SparseFill>,
+
+    // Byte to fill up the last block of an entry to the full block size
+    // with, if it's to be padded:
+    pub pad_last_block: Option<u8>,
+
+    // Boundary the blocks must not cross, so that they start at multiples of
+    // it once past the first one:
+    pub block_alignment: Option<usize>,
+
+    // Number of times each entry is recorded, back to back:
+    pub copies: usize,
+
+    // Whether the data may wrap around past 0xFFFF to 0x0000, as some
+    // diagnostic and protection tapes do on purpose:
+    pub allow_wrap: bool,
+
+    pub block_order: BlockOrder,
+}
+
+impl TapeFormat {
+    // The format written by the Level II ROM:
+    pub fn standard() -> TapeFormat {
+        TapeFormat {
+            leader_length: tape::LEADER_LENGTH,
+            block_size: tape::MAX_BLOCK_SIZE,
+            variant: tape::Variant::standard(),
+            block_gap: 0,
+            sparse: None,
+            pad_last_block: None,
+            block_alignment: None,
+            copies: 1,
+            allow_wrap: false,
+            block_order: BlockOrder::Sequential,
+        }
     }
 }
 
-fn input_file_sanity_check(in_path: &path::Path, base_address: u16, length: usize) -> bool {
-    println!("{}: {} bytes loaded.", in_path.display(), length);
+fn input_file_sanity_check(in_path: &path::Path, base_address: u16, length: usize, allow_wrap: bool) -> bool {
+    status!("{}: {} bytes loaded.", in_path.display(), length);
 
-    if length > (0x10000 - (base_address as usize)) {
-        println!("");
-        eprintln!("The input file would not fit into the Z80's address space.");
-        eprintln!("With a base address of 0x{:04X}, you can only fit at most {} bytes.", base_address, (0x10000 - (base_address as usize)));
+    if allow_wrap && length > 0x10000 {
+        status!("");
+        error!("{}: The input file is larger than the Z80's address space.", in_path.display());
+
+        false
+    } else if !allow_wrap && length > (0x10000 - (base_address as usize)) {
+        status!("");
+        error!("{}: The input file would not fit into the Z80's address space.", in_path.display());
+        eprintln!("With a base address of 0x{:04X}, you can only fit at most {} bytes, unless the data is meant to wrap around to 0x0000 with `--allow-wrap'.",
+                  base_address, (0x10000 - (base_address as usize)));
 
         false
     } else if length == 0 {
-        println!("");
-        eprintln!("The input file is empty, there's nothing to write onto the tape.");
+        status!("");
+        error!("{}: The input file is empty, there's nothing to write onto the tape.", in_path.display());
 
         false
     } else {
@@ -80,32 +128,32 @@ fn input_file_sanity_check(in_path: &path::Path, base_address: u16, length: usiz
     }
 }
 
-fn generate_data_entry_header(entry_name: &[u8], buffer: &mut Vec<u8>) {
-    buffer.reserve(256 + 2 + 6);
+fn generate_data_entry_header(entry_name: &[u8], buffer: &mut Vec<u8>, format: &TapeFormat) {
+    buffer.reserve(format.leader_length + 2 + tape::NAME_LENGTH);
+    let header_offset = buffer.len();
 
     // Tape Leader:
-    for _counter in 0..256 {
-        buffer.push(0);
-    }
+    buffer.extend(format.variant.leader.iter().cycle().take(format.leader_length));
 
     // Sync byte:
-    buffer.push(0xA5);
+    buffer.push(format.variant.sync);
 
     // Header byte indicating system format:
-    buffer.push(0x55);
+    buffer.push(format.variant.header);
 
     // 6 character file name in ASCII:
-    for count in 0..6 {
-        buffer.push(entry_name[count]);
-    }
+    buffer.extend_from_slice(&entry_name[..tape::NAME_LENGTH]);
+
+    very_verbose!("Leader of {} bytes, sync byte, header and name at tape offsets {}-{}.",
+                  format.leader_length, header_offset, buffer.len() - 1);
 }
 
-fn pack_chunk(chunk_to_pack: &[u8], output_buffer: &mut Vec<u8>, load_address: u16) -> usize {
-    output_buffer.reserve(5 + chunk_to_pack.len());
-    let mut checksum: u8 = 0;
+fn pack_chunk(chunk_to_pack: &[u8], output_buffer: &mut Vec<u8>, load_address: u16, variant: &tape::Variant) -> usize {
+    output_buffer.reserve(4 + variant.checksum.length() + chunk_to_pack.len());
+    let block_offset = output_buffer.len();
 
     // Data header:
-    output_buffer.push(0x3C);
+    output_buffer.push(variant.data);
 
     // Length of data, 0 = 256:
     match chunk_to_pack.len() {
@@ -117,51 +165,180 @@ fn pack_chunk(chunk_to_pack: &[u8], output_buffer: &mut Vec<u8>, load_address: u
     output_buffer.push((load_address & 0x00FF) as u8);
     output_buffer.push(((load_address & 0xFF00) >> 8) as u8);
 
-    checksum = checksum.wrapping_add((load_address & 0x00FF) as u8);
-    checksum = checksum.wrapping_add(((load_address & 0xFF00) >> 8) as u8);
-
-    for chunk_iter in 0..chunk_to_pack.len() {
-        output_buffer.push(chunk_to_pack[chunk_iter]);
-        checksum = checksum.wrapping_add(chunk_to_pack[chunk_iter]);
-    }
+    output_buffer.extend_from_slice(chunk_to_pack);
 
     // A checksum of the data and the load address:
-    output_buffer.push(checksum);
+    let checksum = variant.checksum.compute(load_address, chunk_to_pack);
+    variant.checksum.push(checksum, output_buffer);
+
+    verbose!("Block at 0x{:04X}-0x{:04X}, {} bytes, checksum {}.",
+             load_address, (load_address as usize) + chunk_to_pack.len() - 1,
+             chunk_to_pack.len(), variant.checksum.format(checksum));
+    very_verbose!("    Written at tape offsets {}-{}.", block_offset, output_buffer.len() - 1);
 
     // Return the size of the packed chunk:
     chunk_to_pack.len()
 }
 
-fn pack_binary_image(input_buffer: &Vec<u8>, output_buffer: &mut Vec<u8>, base_address: u16) {
-    let binary_image_length = input_buffer.len();
-    let mut already_packed: usize = 0;
+// Splits the input into the stretches to be packed, as (offset, length),
+// leaving out the runs of the fill byte which are long enough:
+fn sparse_segments(input_buffer: &[u8], sparse: Option<SparseFill>) -> Vec<(usize, usize)> {
+    let sparse = match sparse {
+        Some(sparse) => { sparse },
+        None => { return vec![(0, input_buffer.len())]; },
+    };
+
+    let mut segments = Vec::new();
+    let mut segment_start: usize = 0;
+    let mut position: usize = 0;
+    while position < input_buffer.len() {
+        if input_buffer[position] != sparse.byte {
+            position += 1;
+            continue;
+        }
+        let run_start = position;
+        while position < input_buffer.len() && input_buffer[position] == sparse.byte {
+            position += 1;
+        }
+        if (position - run_start) >= sparse.min_run {
+            if run_start > segment_start {
+                segments.push((segment_start, run_start - segment_start));
+            }
+            very_verbose!("Leaving out {} fill bytes at offsets {}-{}.", position - run_start, run_start, position - 1);
+            segment_start = position;
+        }
+    }
+    if input_buffer.len() > segment_start {
+        segments.push((segment_start, input_buffer.len() - segment_start));
+    }
+
+    segments
+}
+
+// Splits the segments further where blocks of their own have to start:
+fn split_segments(segments: Vec<(usize, usize)>, block_breaks: &[usize]) -> Vec<(usize, usize)> {
+    let mut split = Vec::with_capacity(segments.len() + block_breaks.len());
+
+    for (mut start, mut length) in segments {
+        for &position in block_breaks {
+            if position > start && position < start + length {
+                split.push((start, position - start));
+                length -= position - start;
+                start = position;
+            }
+        }
+        split.push((start, length));
+    }
+
+    split
+}
+
+// Works out the blocks the loaded stretches of an entry get split into, as
+// the offsets where they start and their lengths, padding included:
+pub fn block_layout(entry: &PackEntry, format: &TapeFormat) -> Vec<Stretch> {
+    let mut segments = Vec::new();
+    for &(start, length) in &entry.loaded {
+        let stretch = sparse_segments(&entry.data[start..start + length], format.sparse);
+        segments.extend(stretch.into_iter().map(|(offset, length)| (start + offset, length)));
+    }
+    let segments = split_segments(segments, &entry.block_breaks);
+
+    let mut layout = Vec::new();
+    for (segment_index, &(segment_start, segment_length)) in segments.iter().enumerate() {
+        let mut already_packed: usize = 0;
+
+        while already_packed < segment_length {
+            let offset = segment_start + already_packed;
+            let address = ((entry.base_address as usize) + offset) % 0x10000;
+
+            // Blocks may not cross the alignment boundaries, nor the end of
+            // the address space, when padded or wrapping around:
+            let mut room = format.block_size.min(0x10000 - address);
+            if let Some(alignment) = format.block_alignment {
+                room = room.min(alignment - (address % alignment));
+            }
+            let chunk_size = room.min(segment_length - already_packed);
+            let last_chunk = segment_index + 1 == segments.len() && already_packed + chunk_size == segment_length;
+
+            if last_chunk && format.pad_last_block.is_some() {
+                layout.push((offset, room));
+            } else {
+                layout.push((offset, chunk_size));
+            }
+            already_packed += chunk_size;
+        }
+    }
+    layout
+}
+
+// Packs the given stretches of the image, the rest of it doesn't get loaded.
+// The blocks listed in `block_addresses' (by their index) load at the given
+// addresses rather than at the ones of their data:
+fn pack_binary_image(entry: &PackEntry, output_buffer: &mut Vec<u8>, format: &TapeFormat) {
+    let input_buffer = &entry.data;
+    let block_size = format.block_size;
     let mut full_chunks_count: usize = 0;
-    let mut last_chunk_size: Option<usize> = None;
+    let mut short_chunk_sizes: Vec<usize> = Vec::new();
+    let mut packed_total: usize = 0;
+
+    let layout = block_layout(entry, format);
+    for (position, &index) in block_sequence(layout.len(), format.block_order).iter().enumerate() {
+        let (offset, length) = layout[index];
+        if position != 0 {
+            for _counter in 0..format.block_gap {
+                output_buffer.push(0);
+            }
+        }
+        let address = entry.block_address(index, offset);
+        let data_address = entry.base_address.wrapping_add(offset as u16);
+        if address != data_address {
+            verbose!("Block {} loads at 0x{:04X} instead of 0x{:04X}.", index + 1, address, data_address);
+        }
 
-    while already_packed < binary_image_length {
-        if (binary_image_length - already_packed) > 255 {
-            already_packed += pack_chunk(&input_buffer[already_packed..already_packed+256], output_buffer, base_address + (already_packed as u16));
+        let data_end = input_buffer.len().min(offset + length);
+        let mut chunk = input_buffer[offset..data_end].to_vec();
+        if let Some(pad_byte) = format.pad_last_block {
+            if chunk.len() < length {
+                very_verbose!("Padding the last block with {} bytes of 0x{:02X}.", length - chunk.len(), pad_byte);
+                chunk.resize(length, pad_byte);
+            }
+        }
+        pack_chunk(&chunk, output_buffer, address, &format.variant);
+
+        if chunk.len() == block_size {
             full_chunks_count += 1;
         } else {
-            last_chunk_size = Some(pack_chunk(&input_buffer[already_packed..], output_buffer, base_address + (already_packed as u16)));
-            already_packed += last_chunk_size.unwrap();
+            short_chunk_sizes.push(chunk.len());
         }
+        packed_total += data_end - offset;
     }
 
-    match last_chunk_size {
-        Some(size) => {
-            println!("Packed {} chunks of 256 bytes and 1 chunk of {} bytes.",
-                     full_chunks_count, size);
+    match short_chunk_sizes.len() {
+        0 => {
+            status!("Packed {} chunks of {} bytes.", full_chunks_count, block_size);
+        },
+        1 => {
+            status!("Packed {} chunks of {} bytes and 1 chunk of {} bytes.",
+                    full_chunks_count, block_size, short_chunk_sizes[0]);
         },
-        None => {
-            println!("Packed {} chunks of 256 bytes.", full_chunks_count);
+        count => {
+            status!("Packed {} chunks of {} bytes and {} shorter chunks.",
+                    full_chunks_count, block_size, count);
         },
     }
+    let loaded_total: usize = entry.loaded.iter().map(|&(_, length)| length).sum();
+    if packed_total != loaded_total {
+        let stretches = 1 + layout.windows(2).filter(|pair| pair[1].0 != pair[0].0 + pair[0].1).count();
+        status!("Left out {} bytes of fill, the data is split into {} stretches.",
+                loaded_total - packed_total, stretches);
+    }
 }
 
-fn finalize_data_entry(entry_point: u16, output_buffer: &mut Vec<u8>) {
+fn finalize_data_entry(entry_point: u16, output_buffer: &mut Vec<u8>, eof_marker: u8) {
+    very_verbose!("End of file marker at tape offset {}, entry point 0x{:04X}.",
+                  output_buffer.len(), entry_point);
     // End of file marker:
-    output_buffer.push(0x78);
+    output_buffer.push(eof_marker);
 
     // lsb, msb of the entry point:
     output_buffer.push((entry_point & 0x00FF) as u8);
@@ -169,27 +346,117 @@ fn finalize_data_entry(entry_point: u16, output_buffer: &mut Vec<u8>) {
 }
 
 
-pub fn pack(in_path: &path::Path, out_path: &path::Path, entry_name: &[u8],
-            base_address: u16, entry_point: u16) -> bool {
-    assert!(entry_name.len() == 6);
+// An `(offset, length)' stretch of an image:
+pub type Stretch = (usize, usize);
 
-    let mut input_buffer  = Vec::new();
-    let mut output_buffer = Vec::new();
+// An entry to be recorded onto the tape, with everything resolved:
+pub struct PackEntry {
+    pub in_path: path::PathBuf,
+    pub data: Vec<u8>,
+    pub name: Vec<u8>,
+    pub base_address: u16,
+    pub entry_point: u16,
 
-    match load_input_file(in_path, &mut input_buffer) {
-        Some(length) => {
-            assert!(length == input_buffer.len());
-            if !input_file_sanity_check(in_path, base_address, length) {
-                return false;
-            }
+    // Where the data of the input file runs at, for lining its symbols up
+    // with the code: the base address, unless the data gets moved there or
+    // segments load below it:
+    pub code_address: u16,
+
+    // The stretches of the data which get loaded, sorted.  Entries put
+    // together from several segments leave out what lies between them:
+    pub loaded: Vec<Stretch>,
+
+    // Offsets within the data where a new block has to start, sorted:
+    pub block_breaks: Vec<usize>,
+
+    // Blocks loading at other addresses than the ones of their data, by
+    // their index, as on some oddball original tapes:
+    pub block_addresses: Vec<(usize, u16)>,
+
+    // Texts of the comment records going in front of the blocks:
+    pub comments: Vec<String>,
+
+    // Whether the entry ends with the end of file record, data-only ones
+    // for programs with their own loaders may go without it:
+    pub end_record: bool,
+}
+
+impl PackEntry {
+    // An entry loading all of its data, in blocks of the usual sizes:
+    pub fn linear(in_path: &path::Path, data: Vec<u8>, name: Vec<u8>, base_address: u16, entry_point: u16) -> PackEntry {
+        let length = data.len();
+
+        PackEntry {
+            in_path: in_path.to_owned(),
+            data,
+            name,
+            base_address,
+            entry_point,
+            code_address: base_address,
+            loaded: vec![(0, length)],
+            block_breaks: Vec::new(),
+            block_addresses: Vec::new(),
+            comments: Vec::new(),
+            end_record: true,
         }
-        None => {
-            return false;
+    }
+
+    // Where the block with the given index, holding the data from the given
+    // offset on, loads into:
+    pub fn block_address(&self, index: usize, offset: usize) -> u16 {
+        match self.block_addresses.iter().find(|&&(block, _address)| block == index) {
+            Some(&(_block, address)) => { address },
+            None => { self.base_address.wrapping_add(offset as u16) },
         }
     }
-    generate_data_entry_header(entry_name, &mut output_buffer);
-    pack_binary_image(&input_buffer, &mut output_buffer, base_address);
-    finalize_data_entry(entry_point, &mut output_buffer);
+}
+
+// Appends the complete tape entry to the output, without the end of file
+// record if the entry goes without it:
+pub fn generate_tape_entry(entry: &PackEntry, output_buffer: &mut Vec<u8>, format: &TapeFormat) {
+    assert!(entry.name.len() == tape::NAME_LENGTH);
+
+    generate_data_entry_header(&entry.name, output_buffer, format);
+    for comment in &entry.comments {
+        tape::push_comment(comment, output_buffer);
+    }
+    pack_binary_image(entry, output_buffer, format);
+    if entry.end_record {
+        finalize_data_entry(entry.entry_point, output_buffer, format.variant.eof);
+    }
+}
+
+// Records the entries one after another onto a single tape, following the
+// content of an existing tape if appending to one, returns the written image:
+pub fn pack(entries: &[PackEntry], format: &TapeFormat, out_path: &path::Path,
+            existing_tape: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let appending = existing_tape.is_some();
+    let mut output_buffer = existing_tape.unwrap_or_default();
 
-    write_down_tape_file(&output_buffer, out_path)
+    for entry in entries {
+        assert!(entry.name.len() == tape::NAME_LENGTH);
+
+        if !input_file_sanity_check(&entry.in_path, entry.base_address, entry.data.len(), format.allow_wrap) {
+            return None;
+        }
+        for copy in 0..format.copies {
+            if format.copies > 1 {
+                verbose!("Copy {} of {}:", copy + 1, format.copies);
+            }
+            generate_tape_entry(entry, &mut output_buffer, format);
+        }
+    }
+
+    status!("");
+    let written = if appending {
+        files::store_file(&output_buffer, out_path)
+    } else {
+        files::write_file(&output_buffer, out_path)
+    };
+
+    if written {
+        Some(output_buffer)
+    } else {
+        None
+    }
 }
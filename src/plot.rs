@@ -0,0 +1,144 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// SVG plots of recordings with what the decoder made of them drawn over the
+// waveform, for telling why a tape doesn't decode.
+
+use std::fmt::Write;
+
+use audio;
+
+
+const WIDTH: usize = 1600;
+const HEIGHT: usize = 400;
+
+// The top and the bottom of the waveform, and the lines the bits and the
+// labels are written on:
+const WAVE_TOP: usize = 60;
+const WAVE_BOTTOM: usize = 300;
+const PULSE_BOTTOM: usize = 310;
+const BITS_LINE: usize = 330;
+const AXIS_LINE: usize = 370;
+const LABEL_LINE: usize = 20;
+
+// Steps between the ticks of the time axis, in seconds:
+const TICK_STEPS: &[f64] = &[0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0];
+
+// A place of interest in the recording, such as where a block starts:
+pub struct Marker {
+    pub position: usize,
+    pub label: String,
+    // Drawn in red, such as for blocks with bad checksums:
+    pub bad: bool,
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+// Plots the samples from `start' up to `end' of the recording, with the
+// pulses found, the bits told apart and the markers given:
+pub fn plot_recording(recording: &audio::Recording, start: usize, end: usize, decoded: &audio::Decoded, markers: &[Marker]) -> String {
+    let span = (end - start).max(1) as f64;
+    let x_of = |position: usize| ((position - start) as f64) * (WIDTH as f64) / span;
+    let in_range = |position: usize| position >= start && position < end;
+    let middle = ((WAVE_TOP + WAVE_BOTTOM) / 2) as f64;
+    let y_of = |sample: i16| middle - (sample as f64) * ((WAVE_BOTTOM - WAVE_TOP) as f64) / 65536.0;
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"monospace\" font-size=\"11\">",
+                     WIDTH, HEIGHT, WIDTH, HEIGHT);
+    let _ = writeln!(svg, "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>", WIDTH, HEIGHT);
+    let _ = writeln!(svg, "<line x1=\"0\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#ccc\"/>", middle, WIDTH, middle);
+
+    // The waveform, as the lowest and the highest sample of each column
+    // when zoomed out, or joined up samples when zoomed in:
+    let samples = &recording.samples[start..end];
+    let mut path = String::new();
+    if samples.len() > WIDTH {
+        for column in 0..WIDTH {
+            let from = column * samples.len() / WIDTH;
+            let to = ((column + 1) * samples.len() / WIDTH).max(from + 1);
+            let low = samples[from..to].iter().cloned().min().unwrap_or(0);
+            let high = samples[from..to].iter().cloned().max().unwrap_or(0);
+            let _ = write!(path, "M{} {:.1}V{:.1}", column, y_of(high), y_of(low) + 0.5);
+        }
+    } else {
+        for (index, sample) in samples.iter().enumerate() {
+            let _ = write!(path, "{}{:.1} {:.1}", if index == 0 { "M" } else { "L" }, x_of(start + index), y_of(*sample));
+        }
+    }
+    let _ = writeln!(svg, "<path d=\"{}\" fill=\"none\" stroke=\"#333\" stroke-width=\"1\"/>", path);
+
+    // The pulses found, as ticks below the waveform:
+    let mut ticks = String::new();
+    for position in audio::find_pulses(&recording.samples, recording.sample_rate).into_iter().filter(|position| in_range(*position)) {
+        let _ = write!(ticks, "M{:.1} {}V{}", x_of(position), WAVE_BOTTOM, PULSE_BOTTOM);
+    }
+    let _ = writeln!(svg, "<path d=\"{}\" stroke=\"#d00\" stroke-width=\"1\"/>", ticks);
+
+    // The bits, written out when there's room, the marginal ones in red, or
+    // as ticks of their own when there isn't:
+    let bits = decoded.bits_between(start, end);
+    // As long on average as the bits plotted, which differ with the baud
+    // rate:
+    let bit_length = if bits.len() > 1 {
+        ((bits[bits.len() - 1].position - bits[0].position) as f64) / ((bits.len() - 1) as f64)
+    } else {
+        (audio::BIT_CELL_US as f64) * (recording.sample_rate as f64) / 1_000_000.0
+    };
+    let bit_width = bit_length * (WIDTH as f64) / span;
+    if bit_width >= 8.0 {
+        for bit in bits {
+            let colour = if bit.margin < audio::MARGINAL_BIT { "#d00" } else if bit.value { "#000" } else { "#888" };
+            let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\" fill=\"{}\">{}</text>",
+                             x_of(bit.position) + bit_width / 2.0, BITS_LINE, colour, bit.value as u8);
+        }
+    } else {
+        let (mut ones, mut zeros) = (String::new(), String::new());
+        for bit in bits {
+            let _ = write!(if bit.value { &mut ones } else { &mut zeros }, "M{:.1} {}v{}", x_of(bit.position), BITS_LINE - 10, if bit.value { 10 } else { 4 });
+        }
+        let _ = writeln!(svg, "<path d=\"{}\" stroke=\"#000\" stroke-width=\"1\"/>", ones);
+        let _ = writeln!(svg, "<path d=\"{}\" stroke=\"#888\" stroke-width=\"1\"/>", zeros);
+    }
+
+    // The markers, as lines across the plot, their labels staggered so that
+    // ones close together can still be read:
+    for (index, marker) in markers.iter().filter(|marker| in_range(marker.position)).enumerate() {
+        let x = x_of(marker.position);
+        let colour = if marker.bad { "#d00" } else { "#06c" };
+        let _ = writeln!(svg, "<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"{}\" stroke-dasharray=\"4 3\"/>",
+                         x, LABEL_LINE + 4, x, PULSE_BOTTOM, colour);
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" fill=\"{}\">{}</text>", x + 3.0, LABEL_LINE + (index % 3) * 13, colour, escape(&marker.label));
+    }
+
+    // The time axis, in seconds from the start of the recording:
+    let seconds = span / (recording.sample_rate as f64);
+    let step = TICK_STEPS.iter().cloned().find(|step| seconds / step <= 12.0).unwrap_or(60.0);
+    let first_second = (start as f64) / (recording.sample_rate as f64);
+    let mut tick = (first_second / step).ceil() * step;
+    let _ = writeln!(svg, "<line x1=\"0\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"#000\"/>", AXIS_LINE, WIDTH, AXIS_LINE);
+    while tick < first_second + seconds {
+        let x = (tick - first_second) * (WIDTH as f64) / seconds;
+        let _ = writeln!(svg, "<line x1=\"{:.1}\" y1=\"{}\" x2=\"{:.1}\" y2=\"{}\" stroke=\"#000\"/>", x, AXIS_LINE, x, AXIS_LINE + 5);
+        let _ = writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{:.3} s</text>", x, AXIS_LINE + 18, tick);
+        tick += step;
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
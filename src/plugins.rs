@@ -0,0 +1,238 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Converters for formats of other tools, added by third parties as plugins.
+// Plugins are programs kept in the `plugins' directory next to the user-level
+// configuration file, ~/.config/trs80m1-mltl/plugins, or the directory named
+// by TRS80_MLTL_PLUGIN_DIR, and run as:
+//
+//   <plugin> describe                  tells what the plugin converts
+//   <plugin> read <file> <tape.cas>    converts a file into a tape image
+//   <plugin> write <tape.cas> <file>   converts a tape image into a file
+//
+// Describing itself, a plugin writes a line for each of what it does:
+//
+//   summary: Tapes of the Foo emulator
+//   reads: foo fo1
+//   writes: foo
+//
+// Files read with an extension a plugin reads are converted by it before
+// they're used, and so are the tapes written to files with an extension it
+// writes, so that every command works with them.  The formats the tool
+// deals with itself can't be taken over.
+
+use std::env;
+use std::fs;
+use std::path;
+use std::process;
+use std::sync::Mutex;
+
+use config;
+use files;
+
+
+pub struct Plugin {
+    pub name: String,
+    pub path: path::PathBuf,
+    pub summary: String,
+    // Extensions of the files it converts into tape images, and the other
+    // way around, in lower case:
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+// Extensions of files the tool reads and writes in its own right:
+const OWN_EXTENSIONS: &[&str] = &["toml", "txt", "json", "svg", "pulses", "bds", "cmd", "sym", "d"];
+
+// The plugins found, looked for the first time they're needed:
+static PLUGINS: Mutex<Option<Vec<Plugin>>> = Mutex::new(None);
+
+
+pub fn plugin_directory() -> Option<path::PathBuf> {
+    match env::var_os(format!("{}PLUGIN_DIR", config::ENVIRONMENT_PREFIX)) {
+        Some(directory) if !directory.is_empty() => { Some(path::PathBuf::from(directory)) },
+        _ => { config::user_config_directory().map(|directory| directory.join("plugins")) },
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(file_path: &path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    match fs::metadata(file_path) {
+        Ok(metadata) => { metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 },
+        Err(_error) => { false },
+    }
+}
+
+// Elsewhere there are no permission bits to go by, programs are told apart
+// by their extension:
+#[cfg(not(unix))]
+fn is_executable(file_path: &path::Path) -> bool {
+    let executable = match file_path.extension() {
+        Some(extension) => { ["exe", "bat", "cmd"].iter().any(|known| extension.eq_ignore_ascii_case(known)) },
+        None => { false },
+    };
+    executable && file_path.is_file()
+}
+
+// Asks a plugin what it converts, None if it doesn't answer properly:
+fn describe(plugin_path: &path::Path) -> Option<Plugin> {
+    let name = plugin_path.file_stem()?.to_string_lossy().into_owned();
+    let output = match process::Command::new(plugin_path).arg("describe").stdin(process::Stdio::null()).output() {
+        Ok(output) if output.status.success() => { output },
+        Ok(output) => {
+            warning!("The plugin `{}' failed to describe itself, {}, ignoring it.", plugin_path.display(), output.status);
+            return None;
+        },
+        Err(error) => {
+            warning!("Failed to run the plugin `{}': {}, ignoring it.", plugin_path.display(), error);
+            return None;
+        },
+    };
+
+    let mut plugin = Plugin { name, path: plugin_path.to_owned(), summary: String::new(), reads: Vec::new(), writes: Vec::new() };
+    let is_taken = |extension: &String| {
+        if is_own_extension(extension) {
+            warning!("The plugin `{}' can't take over `{}' files, they're read and written by the tool itself.", plugin_path.display(), extension);
+            return false;
+        }
+        true
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let (key, value) = match line.find(':') {
+            Some(colon) => { (line[..colon].trim(), line[colon + 1..].trim()) },
+            None => { continue; },
+        };
+        let extensions = || value.split_whitespace().map(|extension| extension.trim_start_matches('.').to_lowercase());
+        match key {
+            "summary" => { plugin.summary = value.to_owned(); },
+            "reads" => { plugin.reads = extensions().filter(&is_taken).collect(); },
+            "writes" => { plugin.writes = extensions().filter(&is_taken).collect(); },
+            _ => { },
+        }
+    }
+    if plugin.reads.is_empty() && plugin.writes.is_empty() {
+        warning!("The plugin `{}' neither reads nor writes anything, ignoring it.", plugin_path.display());
+        return None;
+    }
+    Some(plugin)
+}
+
+fn discover() -> Vec<Plugin> {
+    let directory = match plugin_directory() {
+        Some(directory) => { directory },
+        None => { return Vec::new(); },
+    };
+    let mut plugin_paths: Vec<path::PathBuf> = match fs::read_dir(&directory) {
+        Ok(listing) => { listing.filter_map(|item| item.ok()).map(|item| item.path()).filter(|item| is_executable(item)).collect() },
+        Err(_error) => { return Vec::new(); },
+    };
+    plugin_paths.sort();
+
+    let plugins: Vec<Plugin> = plugin_paths.iter().filter_map(|plugin_path| describe(plugin_path)).collect();
+    for plugin in &plugins {
+        verbose!("Plugin `{}' from `{}', reads: {}, writes: {}.", plugin.name, plugin.path.display(),
+                 plugin.reads.join(" "), plugin.writes.join(" "));
+    }
+    plugins
+}
+
+// Runs `action' on the plugins, looking for them first if that hasn't been
+// done yet:
+pub fn with_plugins<T, F: FnOnce(&[Plugin]) -> T>(action: F) -> T {
+    let mut plugins = PLUGINS.lock().unwrap();
+    if plugins.is_none() {
+        *plugins = Some(discover());
+    }
+    action(plugins.as_ref().unwrap())
+}
+
+fn is_own_extension(extension: &str) -> bool {
+    [OWN_EXTENSIONS, files::BINARY_EXTENSIONS, files::TAPE_EXTENSIONS, files::RECORDING_EXTENSIONS,
+     files::LISTING_EXTENSIONS, files::SOURCE_EXTENSIONS].iter().any(|extensions| extensions.contains(&extension))
+}
+
+// The extension of a file, if it's one plugins may deal with:
+fn lowercase_extension(file_path: &path::Path) -> Option<String> {
+    let extension = file_path.extension()?.to_string_lossy().to_lowercase();
+    if is_own_extension(&extension) { None } else { Some(extension) }
+}
+
+// Runs a plugin on a pair of files, the output one in the given directory,
+// and returns what it wrote there.  Messages name `shown_path', the file the
+// user knows of:
+fn convert(plugin_path: &path::Path, action: &str, in_path: &path::Path, shown_path: &path::Path,
+           directory: &path::Path, out_name: &str) -> Result<Vec<u8>, String> {
+    let out_path = directory.join(out_name);
+    let name = plugin_path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+
+    verbose!("Running the plugin `{}' to {} `{}'.", name, action, shown_path.display());
+    match process::Command::new(plugin_path).arg(action).arg(in_path).arg(&out_path).stdin(process::Stdio::null()).output() {
+        Ok(output) => {
+            let messages = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+            if output.status.success() {
+                for line in messages.lines() {
+                    verbose!("{}: {}", name, line);
+                }
+                fs::read(&out_path).map_err(|error| format!("The plugin `{}' wrote nothing for `{}': {}", name, shown_path.display(), error))
+            } else {
+                for line in messages.lines() {
+                    eprintln!("{}", line);
+                }
+                Err(format!("The plugin `{}' failed to convert `{}', {}", name, shown_path.display(), output.status))
+            }
+        },
+        Err(error) => { Err(format!("Failed to run the plugin `{}': {}", name, error)) },
+    }
+}
+
+// The tape image a file read is converted into, if a plugin reads files like
+// it, None if none does:
+pub fn read_converted(in_path: &path::Path) -> Option<Result<Vec<u8>, String>> {
+    let extension = lowercase_extension(in_path)?;
+    let plugin_path = with_plugins(|plugins| plugins.iter().find(|plugin| plugin.reads.contains(&extension)).map(|plugin| plugin.path.clone()))?;
+
+    // Each conversion is done in a fresh directory, removed with whatever the
+    // plugin left in it:
+    let directory = match files::make_temporary_directory("trs80m1-mltl-plugin") {
+        Ok(directory) => { directory },
+        Err(error) => { return Some(Err(error)); },
+    };
+    let result = convert(&plugin_path, "read", in_path, in_path, &directory, "converted.cas");
+    let _ = fs::remove_dir_all(&directory);
+    Some(result)
+}
+
+// What a tape image written to the given file is converted into, if a
+// plugin writes files like it, None if none does.  The plugin gets the tape
+// in a file of its own:
+pub fn write_converted(buffer: &[u8], out_path: &path::Path) -> Option<Result<Vec<u8>, String>> {
+    let extension = lowercase_extension(out_path)?;
+    let plugin_path = with_plugins(|plugins| plugins.iter().find(|plugin| plugin.writes.contains(&extension)).map(|plugin| plugin.path.clone()))?;
+
+    let directory = match files::make_temporary_directory("trs80m1-mltl-plugin") {
+        Ok(directory) => { directory },
+        Err(error) => { return Some(Err(error)); },
+    };
+    let tape_path = directory.join("tape.cas");
+    let result = match fs::write(&tape_path, buffer) {
+        Ok(()) => { convert(&plugin_path, "write", &tape_path, out_path, &directory, &format!("converted.{}", extension)) },
+        Err(error) => { Err(format!("Failed to write `{}' for the plugin to convert: {}", tape_path.display(), error)) },
+    };
+    let _ = fs::remove_dir_all(&directory);
+    Some(result)
+}
@@ -0,0 +1,81 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+use std::io;
+use std::io::IsTerminal;
+use std::time;
+
+use console;
+
+const BAR_WIDTH: usize = 30;
+
+
+// A progress bar for long operations, drawn on the terminal only if the
+// standard output is one, and not in quiet mode:
+pub struct Progress {
+    label: String,
+    unit: &'static str,
+    total: usize,
+    enabled: bool,
+    started: time::Instant,
+    last_permille: Option<usize>,
+}
+
+impl Progress {
+    pub fn new(label: &str, unit: &'static str, total: usize) -> Progress {
+        Progress {
+            label: label.to_owned(),
+            unit,
+            total,
+            enabled: io::stdout().is_terminal() && console::verbosity() >= console::NORMAL,
+            started: time::Instant::now(),
+            last_permille: None,
+        }
+    }
+
+    pub fn update(&mut self, done: usize) {
+        if !self.enabled || self.total == 0 {
+            return;
+        }
+
+        // Only redraw when there's a visible change:
+        let permille = (done.min(self.total) * 1000) / self.total;
+        if self.last_permille == Some(permille) {
+            return;
+        }
+        self.last_permille = Some(permille);
+
+        let filled = (permille * BAR_WIDTH) / 1000;
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let eta = if done > 0 && done < self.total {
+            format!(", ETA {:.0}s", elapsed * ((self.total - done) as f64) / (done as f64))
+        } else {
+            String::new()
+        };
+
+        console::show_progress(&format!("{} [{}{}] {:3}%  {}/{} {}{}", self.label, "#".repeat(filled),
+                                        ".".repeat(BAR_WIDTH - filled), permille / 10, done, self.total, self.unit, eta));
+    }
+
+    // Clears the bar, so that whatever gets printed next starts on a clean
+    // line:
+    pub fn finish(&mut self) {
+        if self.enabled && self.last_permille.is_some() {
+            console::show_progress("");
+        }
+        self.last_permille = None;
+    }
+}
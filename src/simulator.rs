@@ -0,0 +1,594 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// A Model I loading a tape in simulation, for checking that the ROM reads it
+// as intended.  There's no ROM image to run, so its cassette routines are
+// done here, the way the ROM does them: the sync byte is looked for bit by
+// bit, entries are picked by their names, anything other than the block
+// markers is passed over, and the checksums are checked.  Programs run on
+// the Z80 core once loaded, the ROM routines they call done the same way:
+//
+//   0x0212     selects the cassette and turns the motor on
+//   0x01F8     turns the motor off
+//   0x0296     looks for the leader and the sync byte
+//   0x0235     reads a byte into A
+//   0x0314     reads a word into HL
+//   0x0264     writes a byte, which goes nowhere
+//   0x0287     writes the leader and the sync byte, which go nowhere
+//   0x0033     displays the character in A at the cursor
+//   0x01C9     clears the screen
+//   0x002B     scans the keyboard, no key is ever down
+//   0x0060     waits for a while, which takes no time at all
+//
+// Calls to anything else in the ROM end the simulation, and so do the ones
+// which would wait for a key, and reads of the cassette port, since the
+// tape isn't played into it.
+//
+// What the load depends on until it's done is watched for blocks loading
+// over it, the reason tapes die at the last block on real machines: the
+// ROM's own loader areas and its stack during SYSTEM, and afterwards, for
+// programs reading the rest of the tape themselves, their stack and the
+// code they go on running.
+
+use memory;
+use tape;
+use z80;
+
+
+// The ROM routines done here:
+const ROM_SELECT_CASSETTE: u16 = 0x0212;
+const ROM_MOTOR_OFF:       u16 = 0x01F8;
+const ROM_FIND_SYNC:       u16 = 0x0296;
+const ROM_READ_BYTE:       u16 = 0x0235;
+const ROM_READ_WORD:       u16 = 0x0314;
+const ROM_WRITE_BYTE:      u16 = 0x0264;
+const ROM_WRITE_LEADER:    u16 = 0x0287;
+const ROM_DISPLAY:         u16 = 0x0033;
+const ROM_CLEAR_SCREEN:    u16 = 0x01C9;
+const ROM_SCAN_KEYBOARD:   u16 = 0x002B;
+const ROM_WAIT_KEY:        u16 = 0x0049;
+const ROM_DELAY:           u16 = 0x0060;
+const ROM_RESET:           u16 = 0x0000;
+const ROM_BASIC_WARM:      u16 = 0x06CC;
+
+const ROM_END:       u32 = 0x3000;
+const VIDEO_START:   u32 = 0x3C00;
+const VIDEO_END:     u32 = 0x4000;
+const SCREEN_WIDTH:  usize = 64;
+const CASSETTE_PORT: u8 = 0xFF;
+
+// Where the ROM shows the blinking asterisk while loading, and the `C' of a
+// bad checksum:
+const ASTERISK_ADDRESS: u16 = 0x3C3F;
+const CHECKSUM_ERROR_ADDRESS: u16 = 0x3C3E;
+
+// How far below the end of RAM the stack sits when the program is started,
+// within the span BASIC keeps there:
+const STACK_DEPTH: u32 = 0x40;
+
+
+// A block the ROM read, as it read it:
+pub struct LoadedBlock {
+    pub address: u16,
+    pub length: usize,
+    pub checksum_ok: bool,
+    // Bytes which went into the ROM, the keyboard or past the end of RAM:
+    pub lost_bytes: usize,
+}
+
+// Memory the load still needed, which got loaded over before it was done.
+// The end is inclusive:
+pub struct Clobber {
+    pub start: u16,
+    pub end: u16,
+    pub what: String,
+    pub by: String,
+}
+
+// Adds the clobbered byte to the list, joining it onto the previous one
+// when it goes right behind it:
+fn add_clobber(clobbers: &mut Vec<Clobber>, address: u16, what: &str, by: &str) {
+    if let Some(last) = clobbers.last_mut() {
+        if last.what == what && last.by == by && last.end.wrapping_add(1) == address {
+            last.end = address;
+            return;
+        }
+    }
+    clobbers.push(Clobber { start: address, end: address, what: what.to_owned(), by: by.to_owned() });
+}
+
+pub struct Load {
+    pub name: Vec<u8>,
+    pub blocks: Vec<LoadedBlock>,
+    pub entry_point: u16,
+    // Bytes between the blocks the ROM passed over:
+    pub skipped_bytes: usize,
+    // Entries with other names the ROM passed over looking for this one:
+    pub passed_entries: Vec<Vec<u8>>,
+    pub clobbers: Vec<Clobber>,
+}
+
+// Why a program stopped running:
+pub enum Stop {
+    // Returned to BASIC, through the `READY' prompt or its warm start:
+    Basic,
+    Reset,
+    Halted(u16),
+    // Called a ROM routine which isn't simulated:
+    RomCall(u16),
+    // Waits for a key, at the address the ROM is called from:
+    KeyWait(u16),
+    // Reads the cassette port itself:
+    CassettePort(u16),
+    // Wanted more of the tape than there is:
+    TapeEnd(u16),
+    // Goes on running code it loaded over while reading the tape, at the
+    // address of that code:
+    Clobbered(u16),
+    // Was still running after the instructions allowed:
+    StillRunning(u16),
+}
+
+impl Stop {
+    pub fn describe(&self) -> String {
+        match *self {
+            Stop::Basic => { "returns to BASIC".to_owned() },
+            Stop::Reset => { "resets the machine".to_owned() },
+            Stop::Halted(address) => { format!("halts at 0x{:04X}", address) },
+            Stop::RomCall(address) => { format!("calls the ROM at 0x{:04X}, which the simulation doesn't include", address) },
+            Stop::KeyWait(address) => { format!("waits for a key at 0x{:04X}", address) },
+            Stop::CassettePort(address) => { format!("reads the cassette port itself at 0x{:04X}, which the simulation doesn't play the tape into", address) },
+            Stop::TapeEnd(address) => { format!("reads past the end of the tape at 0x{:04X}", address) },
+            Stop::Clobbered(address) => { format!("goes on running the code at 0x{:04X} it loaded over, and likely crashes", address) },
+            Stop::StillRunning(address) => { format!("is still running, at 0x{:04X}", address) },
+        }
+    }
+}
+
+pub struct Machine {
+    pub memory: Vec<u8>,
+    ram_end: u32,
+    tape: Vec<u8>,
+    // Position on the tape, in bits:
+    bit_position: usize,
+    cursor: usize,
+    // Writes into the ROM, the keyboard or past the end of RAM:
+    pub lost_writes: usize,
+    cassette_port_read: bool,
+    // What the program loaded over while reading the tape itself:
+    pub clobbers: Vec<Clobber>,
+    // Whether the program has the cassette going, between selecting it and
+    // turning the motor off:
+    reading_tape: bool,
+    // The instruction running and the part of the stack in use, while the
+    // program runs:
+    running_at: u16,
+    live_stack: Option<(u16, u16)>,
+    // The first bytes of the instructions run so far, and the ones of them
+    // changed while reading the tape, with what changed them:
+    executed: Vec<bool>,
+    overwritten: Vec<Option<u16>>,
+}
+
+impl z80::Bus for Machine {
+    fn read(&mut self, address: u16) -> u8 {
+        let address = address as u32;
+        if address >= VIDEO_START && address < self.ram_end {
+            self.memory[address as usize]
+        } else if (0x3800..VIDEO_START).contains(&address) {
+            // No key is down:
+            0x00
+        } else {
+            0xFF
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if self.reading_tape && self.memory[address as usize] != value {
+            self.watch_write(address);
+        }
+        let address = address as u32;
+        if address >= VIDEO_START && address < self.ram_end {
+            self.memory[address as usize] = value;
+        } else {
+            self.lost_writes += 1;
+        }
+    }
+
+    fn input(&mut self, port: u16) -> u8 {
+        if port as u8 == CASSETTE_PORT {
+            self.cassette_port_read = true;
+        }
+        0xFF
+    }
+
+    fn output(&mut self, _port: u16, _value: u8) {
+    }
+}
+
+impl Machine {
+    pub fn new(tape: &[u8], ram_end: u32) -> Machine {
+        let mut memory = vec![0x00; 0x10000];
+        for byte in &mut memory[VIDEO_START as usize..VIDEO_END as usize] {
+            *byte = b' ';
+        }
+        Machine { memory, ram_end, tape: tape.to_owned(), bit_position: 0, cursor: 0, lost_writes: 0, cassette_port_read: false,
+                  clobbers: Vec::new(), reading_tape: false, running_at: 0, live_stack: None,
+                  executed: vec![false; 0x10000], overwritten: vec![None; 0x10000] }
+    }
+
+    // Notes a change the program makes while reading the tape to its stack,
+    // or to code it has run, which counts once it's run again:
+    fn watch_write(&mut self, address: u16) {
+        let by = format!("The program at 0x{:04X}", self.running_at);
+        if let Some((stack_start, stack_end)) = self.live_stack {
+            if address >= stack_start && address < stack_end {
+                add_clobber(&mut self.clobbers, address, "the stack in use", &by);
+            }
+        }
+        if self.executed[address as usize] && self.overwritten[address as usize].is_none() {
+            self.overwritten[address as usize] = Some(self.running_at);
+        }
+    }
+
+    // What SYSTEM relies on while loading, as the start, the exclusive end
+    // and a description:
+    fn loader_areas(&self) -> Vec<(u32, u32, &'static str)> {
+        let mut areas = memory::LOADER_AREAS.to_vec();
+        let ram_end = self.ram_end.min(0x10000);
+        areas.push((ram_end - memory::LOADER_STACK_SPAN, ram_end, "the stack the ROM uses while loading"));
+        areas
+    }
+
+    // Where the tape is at, in bytes:
+    pub fn tape_offset(&self) -> usize {
+        self.bit_position / 8
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = *self.tape.get(self.bit_position / 8)?;
+        let bit = (byte >> (7 - self.bit_position % 8)) & 1 != 0;
+        self.bit_position += 1;
+        Some(bit)
+    }
+
+    // Reads a byte from wherever the bits are at, which is a byte boundary
+    // of the image unless the sync byte was found off one:
+    pub fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = 0u8;
+        for _bit in 0..8 {
+            byte = (byte << 1) | (self.read_bit()? as u8);
+        }
+        Some(byte)
+    }
+
+    // Reads bits until the last eight of them are the sync byte:
+    pub fn find_sync(&mut self) -> bool {
+        let mut shift_register = 0u8;
+        loop {
+            match self.read_bit() {
+                Some(bit) => { shift_register = (shift_register << 1) | (bit as u8); },
+                None => { return false; },
+            }
+            if shift_register == tape::SYNC_BYTE {
+                return true;
+            }
+        }
+    }
+
+    fn store(&mut self, address: u16, value: u8) -> bool {
+        let lost_before = self.lost_writes;
+        z80::Bus::write(self, address, value);
+        self.lost_writes == lost_before
+    }
+
+    fn display(&mut self, character: u8) {
+        let screen_size = (VIDEO_END - VIDEO_START) as usize;
+        match character {
+            0x08 => { self.cursor = self.cursor.saturating_sub(1); },
+            0x0D => { self.cursor = (self.cursor / SCREEN_WIDTH + 1) * SCREEN_WIDTH; },
+            0x1C => { self.cursor = 0; },
+            0x1D => { self.cursor -= self.cursor % SCREEN_WIDTH; },
+            0x1E | 0x1F => {
+                let end = if character == 0x1E { (self.cursor / SCREEN_WIDTH + 1) * SCREEN_WIDTH } else { screen_size };
+                for offset in self.cursor..end {
+                    self.memory[VIDEO_START as usize + offset] = b' ';
+                }
+            },
+            0x20..=0xFF => {
+                self.memory[VIDEO_START as usize + self.cursor] = character;
+                self.cursor += 1;
+            },
+            _ => { },
+        }
+        // Scrolling up at the bottom of the screen:
+        if self.cursor >= screen_size {
+            let video = &mut self.memory[VIDEO_START as usize..VIDEO_END as usize];
+            video.copy_within(SCREEN_WIDTH.., 0);
+            for byte in &mut video[screen_size - SCREEN_WIDTH..] {
+                *byte = b' ';
+            }
+            self.cursor -= SCREEN_WIDTH;
+        }
+    }
+
+    // The lines of the screen with anything on them, graphics characters
+    // shown as `#':
+    pub fn screen_lines(&self) -> Vec<String> {
+        let video = &self.memory[VIDEO_START as usize..VIDEO_END as usize];
+        let lines: Vec<String> = video.chunks(SCREEN_WIDTH).map(|line| {
+            line.iter().map(|&byte| match byte & 0x7F {
+                _ if byte >= 0x80 => { '#' },
+                character @ 0x20..=0x7E => { character as char },
+                _ => { '?' },
+            }).collect::<String>().trim_end().to_owned()
+        }).collect();
+        let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+        let last = lines.iter().rposition(|line| !line.is_empty()).map_or(first, |last| last + 1);
+        lines[first..last].to_vec()
+    }
+
+    // Loads an entry the way SYSTEM does, the one with the given name, or
+    // the first one found:
+    pub fn system_load(&mut self, wanted_name: Option<&[u8]>) -> Result<Load, String> {
+        let mut passed_entries = Vec::new();
+        let name = loop {
+            if !self.find_sync() {
+                return Err(match wanted_name {
+                    Some(name) => { format!("The tape ends before an entry named `{}' shows up", String::from_utf8_lossy(name)) },
+                    None => { "The tape ends before an entry shows up".to_owned() },
+                });
+            }
+            if self.read_byte() != Some(tape::SYSTEM_HEADER) {
+                continue;
+            }
+            let mut name = Vec::with_capacity(tape::NAME_LENGTH);
+            for _count in 0..tape::NAME_LENGTH {
+                name.push(self.read_byte().ok_or("The tape ends in the middle of the name of an entry")?);
+            }
+            match wanted_name {
+                Some(wanted) if wanted != &name[..] => { passed_entries.push(name); },
+                _ => { break name; },
+            }
+        };
+
+        let ends_early = |what: &str, offset: usize| format!("The tape ends while the ROM reads {} at offset {}", what, offset);
+        let loader_areas = self.loader_areas();
+        let mut clobbers = Vec::new();
+        let mut blocks = Vec::new();
+        let mut skipped_bytes = 0;
+        loop {
+            let offset = self.tape_offset();
+            let marker = self.read_byte().ok_or_else(|| ends_early("the next block", offset))?;
+            if marker == tape::EOF_MARKER {
+                let low = self.read_byte().ok_or_else(|| ends_early("the entry point", offset))?;
+                let high = self.read_byte().ok_or_else(|| ends_early("the entry point", offset))?;
+                return Ok(Load { name, blocks, entry_point: ((high as u16) << 8) | (low as u16), skipped_bytes, passed_entries, clobbers });
+            }
+            if marker != tape::DATA_MARKER {
+                skipped_bytes += 1;
+                continue;
+            }
+
+            let length = match self.read_byte().ok_or_else(|| ends_early("a block", offset))? {
+                0 => { tape::MAX_BLOCK_SIZE },
+                length => { length as usize },
+            };
+            let low = self.read_byte().ok_or_else(|| ends_early("a block", offset))?;
+            let high = self.read_byte().ok_or_else(|| ends_early("a block", offset))?;
+            let address = ((high as u16) << 8) | (low as u16);
+            let mut checksum = low.wrapping_add(high);
+            let mut lost_bytes = 0;
+            let by = format!("Block {} at 0x{:04X}", blocks.len() + 1, address);
+            for index in 0..length {
+                let byte = self.read_byte().ok_or_else(|| ends_early("a block", offset))?;
+                checksum = checksum.wrapping_add(byte);
+                let byte_address = address.wrapping_add(index as u16);
+                if let Some(&(_start, _end, what)) = loader_areas.iter().find(|&&(start, end, _what)| (start..end).contains(&(byte_address as u32))) {
+                    add_clobber(&mut clobbers, byte_address, what, &by);
+                }
+                if !self.store(byte_address, byte) {
+                    lost_bytes += 1;
+                }
+            }
+            let stored_checksum = self.read_byte().ok_or_else(|| ends_early("a block", offset))?;
+            let checksum_ok = stored_checksum == checksum;
+
+            // The asterisk blinks with every block, a bad one leaves a `C':
+            let asterisk = if self.memory[ASTERISK_ADDRESS as usize] == b'*' { b' ' } else { b'*' };
+            self.store(ASTERISK_ADDRESS, asterisk);
+            if !checksum_ok {
+                self.store(CHECKSUM_ERROR_ADDRESS, b'C');
+            }
+            blocks.push(LoadedBlock { address, length, checksum_ok, lost_bytes });
+        }
+    }
+
+    // Does what the ROM routine at the address would, returning whether it's
+    // simulated at all, or why the program stops there:
+    fn rom_call(&mut self, cpu: &mut z80::Cpu, address: u16) -> Option<Stop> {
+        let caller = z80::Bus::read(self, cpu.sp) as u16 | ((z80::Bus::read(self, cpu.sp.wrapping_add(1)) as u16) << 8);
+        match address {
+            ROM_RESET => { return Some(Stop::Reset); },
+            tape::ROM_BASIC_READY | ROM_BASIC_WARM => { return Some(Stop::Basic); },
+            ROM_SELECT_CASSETTE => { self.reading_tape = true; },
+            ROM_MOTOR_OFF => { self.reading_tape = false; },
+            ROM_WRITE_BYTE | ROM_WRITE_LEADER => { },
+            ROM_SCAN_KEYBOARD => { cpu.a = 0x00; },
+            ROM_DELAY => { cpu.set_bc(0); },
+            ROM_FIND_SYNC => {
+                self.reading_tape = true;
+                if !self.find_sync() {
+                    return Some(Stop::TapeEnd(caller));
+                }
+            },
+            ROM_READ_BYTE => {
+                self.reading_tape = true;
+                match self.read_byte() {
+                    Some(byte) => { cpu.a = byte; },
+                    None => { return Some(Stop::TapeEnd(caller)); },
+                }
+            },
+            ROM_READ_WORD => {
+                self.reading_tape = true;
+                match (self.read_byte(), self.read_byte()) {
+                    (Some(low), Some(high)) => { cpu.set_hl(((high as u16) << 8) | (low as u16)); },
+                    _ => { return Some(Stop::TapeEnd(caller)); },
+                }
+            },
+            ROM_DISPLAY => {
+                let character = cpu.a;
+                self.display(character);
+            },
+            ROM_CLEAR_SCREEN => {
+                self.cursor = 0;
+                self.display(0x1F);
+            },
+            ROM_WAIT_KEY => { return Some(Stop::KeyWait(caller)); },
+            _ => { return Some(Stop::RomCall(address)); },
+        }
+
+        // Back to the caller, as the routine's RET would:
+        cpu.pc = cpu.pop(self);
+        None
+    }
+
+    // Runs the program from the entry point for up to the given number of
+    // instructions, returning why it stopped and how many it ran:
+    pub fn run(&mut self, entry_point: u16, instructions: usize) -> (Stop, usize) {
+        let mut cpu = z80::Cpu::new();
+        cpu.pc = entry_point;
+        cpu.sp = (self.ram_end.min(0x10000) - STACK_DEPTH) as u16;
+        let stack_top = cpu.sp;
+
+        for count in 0..instructions {
+            if (cpu.pc as u32) < ROM_END {
+                let address = cpu.pc;
+                if let Some(stop) = self.rom_call(&mut cpu, address) {
+                    return (stop, count);
+                }
+                continue;
+            }
+            if cpu.halted {
+                return (Stop::Halted(cpu.pc), count);
+            }
+            let address = cpu.pc;
+            if let Some(writer) = self.overwritten[address as usize].take() {
+                add_clobber(&mut self.clobbers, address, "the code it goes on running", &format!("The program at 0x{:04X}", writer));
+                return (Stop::Clobbered(address), count);
+            }
+            self.executed[address as usize] = true;
+            // The stack from two bytes above the stack pointer on, which
+            // EX (SP),HL leaves out, while it's on the one the program was
+            // started with:
+            self.live_stack = if cpu.sp <= stack_top && stack_top - cpu.sp < memory::LOADER_STACK_SPAN as u16 {
+                Some((cpu.sp.saturating_add(2), stack_top))
+            } else {
+                None
+            };
+            self.running_at = address;
+            cpu.step(self);
+            self.live_stack = None;
+            if self.cassette_port_read {
+                return (Stop::CassettePort(address), count);
+            }
+        }
+        (Stop::StillRunning(cpu.pc), instructions)
+    }
+}
+
+// Whether the tape is in the format the ROM reads, the only one it can be
+// simulated in:
+pub fn is_rom_format(variant: &tape::Variant) -> bool {
+    let standard = tape::Variant::standard();
+    variant.sync == standard.sync && variant.header == standard.header && variant.data == standard.data &&
+    variant.eof == standard.eof && variant.checksum == standard.checksum
+}
+
+// The end of RAM of the machine simulated, a 48K one unless told otherwise:
+pub fn default_ram_end() -> u32 {
+    memory::RAM_SIZES[memory::RAM_SIZES.len() - 1].1
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stubs;
+
+    // A SYSTEM entry of a single block:
+    fn system_tape(name: &[u8; 6], address: u16, data: &[u8], entry_point: u16) -> Vec<u8> {
+        let mut buffer = vec![0x00; 32];
+        buffer.extend_from_slice(&[tape::SYNC_BYTE, tape::SYSTEM_HEADER]);
+        buffer.extend_from_slice(name);
+        buffer.extend_from_slice(&[tape::DATA_MARKER, data.len() as u8, (address & 0x00FF) as u8, (address >> 8) as u8]);
+        buffer.extend_from_slice(data);
+        buffer.push(tape::block_checksum(address, data));
+        buffer.extend_from_slice(&[tape::EOF_MARKER, (entry_point & 0x00FF) as u8, (entry_point >> 8) as u8]);
+        buffer
+    }
+
+    #[test]
+    fn relocated_program_runs_in_place() {
+        // LD A,42h; LD (7000h),A; HALT, run from where it's moved to:
+        let program = [0x3E, 0x42, 0x32, 0x00, 0x70, 0x76];
+        let mut staged = program.to_vec();
+        staged.extend(stubs::relocation_stub(0x8000, 0x6000, program.len() as u16, 0x6000));
+        let stub_address = 0x8000 + program.len() as u16;
+
+        let mut machine = Machine::new(&system_tape(b"RELOC ", 0x8000, &staged, stub_address), default_ram_end());
+        let load = machine.system_load(None).unwrap();
+        assert_eq!(load.name, b"RELOC ");
+        assert_eq!(load.entry_point, stub_address);
+        assert!(load.blocks[0].checksum_ok);
+        assert!(load.clobbers.is_empty());
+
+        match machine.run(load.entry_point, 1000) {
+            (Stop::Halted(address), _count) => { assert_eq!(address, 0x6005); },
+            (stop, _count) => { panic!("the program {}", stop.describe()); },
+        }
+        assert_eq!(&machine.memory[0x6000..0x6006], &program);
+        assert_eq!(machine.memory[0x7000], 0x42);
+    }
+
+    #[test]
+    fn entries_picked_by_name() {
+        let mut buffer = system_tape(b"FIRST ", 0x7000, &[0x76], 0x7000);
+        buffer.extend(system_tape(b"SECOND", 0x7100, &[0x00, 0x76], 0x7100));
+
+        let mut machine = Machine::new(&buffer, default_ram_end());
+        let load = machine.system_load(Some(b"SECOND")).unwrap();
+        assert_eq!(load.passed_entries, vec![b"FIRST ".to_vec()]);
+        assert_eq!(machine.memory[0x7000], 0x00);
+        match machine.run(load.entry_point, 1000) {
+            (Stop::Halted(address), count) => { assert_eq!((address, count), (0x7101, 2)); },
+            (stop, _count) => { panic!("the program {}", stop.describe()); },
+        }
+    }
+
+    #[test]
+    fn bad_checksums_leave_a_c() {
+        let mut buffer = system_tape(b"BAD   ", 0x7000, &[0x01, 0x02, 0x03], 0x7000);
+        let checksum_offset = buffer.len() - 4;
+        buffer[checksum_offset] ^= 0xFF;
+
+        let mut machine = Machine::new(&buffer, default_ram_end());
+        let load = machine.system_load(None).unwrap();
+        assert!(!load.blocks[0].checksum_ok);
+        assert_eq!(machine.memory[CHECKSUM_ERROR_ADDRESS as usize], b'C');
+    }
+}
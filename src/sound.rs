@@ -0,0 +1,327 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Playing and capturing recordings through the sound card.  The standard
+// library has no audio input or output, so that's left to one of the usual
+// command line players and recorders, with the data passing through their
+// standard input or output.
+
+use std::env;
+use std::io;
+use std::path;
+use std::process;
+
+
+// How a program is told which audio device to use:
+#[derive(Clone, Copy, PartialEq)]
+pub enum DeviceChoice {
+    // An option, followed by the name of the device:
+    Option(&'static str),
+    // An environment variable holding the name of the device:
+    Environment(&'static str),
+    // None, the program always uses the default device:
+    Fixed,
+}
+
+// The sound systems the devices are listed from:
+#[derive(Clone, Copy, PartialEq)]
+pub enum SoundSystem {
+    Alsa,
+    PulseAudio,
+    // The one of the operating system, CoreAudio on macOS, WASAPI on Windows
+    // and ALSA elsewhere, as SoX uses:
+    Native,
+}
+
+pub struct Program {
+    pub name: &'static str,
+    // With `{rate}' standing for the sample rate:
+    pub args: &'static [&'static str],
+    pub device_choice: DeviceChoice,
+    // Where the devices it can be told to use are listed from:
+    pub sound_system: Option<SoundSystem>,
+}
+
+// An audio device, named the way the programs are told to use it:
+pub struct Device {
+    pub name: String,
+    pub description: String,
+}
+
+// In the order they're looked for, the ALSA and PulseAudio ones go first as
+// they come with most Linux systems.  The players read WAV data:
+pub const PLAYERS: &[Program] = &[
+    Program { name: "aplay",  args: &["-q", "-"],                                         device_choice: DeviceChoice::Option("-D"),
+              sound_system: Some(SoundSystem::Alsa) },
+    Program { name: "paplay", args: &[],                                                  device_choice: DeviceChoice::Option("--device"),
+              sound_system: Some(SoundSystem::PulseAudio) },
+    Program { name: "ffplay", args: &["-nodisp", "-autoexit", "-loglevel", "error", "-"], device_choice: DeviceChoice::Fixed,
+              sound_system: None },
+    Program { name: "play",   args: &["-q", "-t", "wav", "-"],                            device_choice: DeviceChoice::Environment("AUDIODEV"),
+              sound_system: Some(SoundSystem::Native) },
+];
+
+// The recorders write raw signed 16-bit little-endian mono samples:
+pub const RECORDERS: &[Program] = &[
+    Program { name: "arecord", args: &["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-r", "{rate}"],             device_choice: DeviceChoice::Option("-D"),
+              sound_system: Some(SoundSystem::Alsa) },
+    Program { name: "parec",   args: &["--format=s16le", "--channels=1", "--rate={rate}"],                       device_choice: DeviceChoice::Option("--device"),
+              sound_system: Some(SoundSystem::PulseAudio) },
+    Program { name: "rec",     args: &["-q", "-t", "raw", "-e", "signed", "-b", "16", "-c", "1", "-r", "{rate}", "-"], device_choice: DeviceChoice::Environment("AUDIODEV"),
+              sound_system: Some(SoundSystem::Native) },
+];
+
+// The converters decode compressed recordings into the same raw samples as
+// the recorders write, `{input}' stands for the recording:
+pub const CONVERTERS: &[Program] = &[
+    Program { name: "ffmpeg", args: &["-v", "error", "-i", "{input}", "-f", "s16le", "-ac", "1", "-ar", "{rate}", "-"], device_choice: DeviceChoice::Fixed,
+              sound_system: None },
+    Program { name: "sox",    args: &["{input}", "-t", "raw", "-e", "signed", "-b", "16", "-c", "1", "-r", "{rate}", "-"],  device_choice: DeviceChoice::Fixed,
+              sound_system: None },
+];
+
+// Whether a program of the given name is installed:
+pub fn in_path(name: &str) -> bool {
+    match env::var_os("PATH") {
+        Some(paths) => { env::split_paths(&paths).any(|dir| dir.join(name).is_file()) },
+        None => { false },
+    }
+}
+
+// Finds the program with the given name, or the first one installed, `kind'
+// tells the players from the recorders in the messages:
+pub fn find_program(programs: &'static [Program], kind: &str, name: Option<&str>) -> Result<&'static Program, String> {
+    let names: Vec<&str> = programs.iter().map(|program| program.name).collect();
+    match name {
+        Some(name) => {
+            match programs.iter().find(|program| program.name == name) {
+                Some(program) => { Ok(program) },
+                None => { Err(format!("Unknown {} `{}', expected one of {}", kind, name, names.join(", "))) },
+            }
+        },
+        None => {
+            match programs.iter().find(|program| in_path(program.name)) {
+                Some(program) => { Ok(program) },
+                None => { Err(format!("None of the supported {}s ({}) is installed", kind, names.join(", "))) },
+            }
+        },
+    }
+}
+
+fn command(program: &Program, device: Option<&str>, sample_rate: u32) -> Result<process::Command, String> {
+    let mut command = process::Command::new(program.name);
+    if let Some(device) = device {
+        match program.device_choice {
+            DeviceChoice::Option(option) => { command.arg(option).arg(device); },
+            DeviceChoice::Environment(variable) => { command.env(variable, device); },
+            DeviceChoice::Fixed => { return Err(format!("The `{}' program can't be told which device to use", program.name)); },
+        }
+    }
+    let rate = sample_rate.to_string();
+    command.args(program.args.iter().map(|arg| arg.replace("{rate}", &rate)));
+
+    Ok(command)
+}
+
+// Starts playing, the WAV data goes to the standard input of the child:
+pub fn start_player(player: &Program, device: Option<&str>) -> Result<process::Child, String> {
+    let mut command = command(player, device, 0)?;
+    command.stdin(process::Stdio::piped());
+
+    match command.spawn() {
+        Ok(child) => { Ok(child) },
+        Err(error) => { Err(format!("Failed to run `{}': {}", player.name, error)) },
+    }
+}
+
+// Waits for a player to finish playing:
+pub fn wait_for_player(player: &Program, mut child: process::Child) -> Result<(), String> {
+    match child.wait() {
+        Ok(status) if status.success() => { Ok(()) },
+        Ok(status) => { Err(format!("`{}' failed, {}", player.name, status)) },
+        Err(error) => { Err(format!("Failed to wait for `{}': {}", player.name, error)) },
+    }
+}
+
+// Plays WAV data as it's made, `write' passing it on to the player, and
+// returns once it's done:
+pub fn play(player: &Program, device: Option<&str>, write: &mut dyn FnMut(&mut dyn io::Write) -> Result<(), String>) -> Result<(), String> {
+    let mut child = start_player(player, device)?;
+    // The player may quit early, such as when the device is busy, which is
+    // reported by its exit status rather than by the failed write:
+    let written = match child.stdin.take() {
+        Some(mut stdin) => { write(&mut stdin) },
+        None => { Err(format!("Failed to pass the recording to `{}'", player.name)) },
+    };
+
+    wait_for_player(player, child)?;
+    written
+}
+
+// Starts capturing, the samples come from the standard output of the child:
+pub fn capture(recorder: &Program, device: Option<&str>, sample_rate: u32) -> Result<process::Child, String> {
+    let mut command = command(recorder, device, sample_rate)?;
+    command.stdout(process::Stdio::piped()).stdin(process::Stdio::null());
+
+    match command.spawn() {
+        Ok(child) => { Ok(child) },
+        Err(error) => { Err(format!("Failed to run `{}': {}", recorder.name, error)) },
+    }
+}
+
+// Runs a program listing the devices, for what it writes:
+fn listing(name: &str, args: &[&str]) -> Result<String, String> {
+    let output = match process::Command::new(name).args(args).stdin(process::Stdio::null()).output() {
+        Ok(output) => { output },
+        Err(error) => { return Err(format!("Failed to run `{}': {}", name, error)); },
+    };
+    if !output.status.success() {
+        return Err(format!("`{}' failed, {}: {}", name, output.status, String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// `aplay -L' and `arecord -L' give the name of every device on a line of its
+// own, followed by indented lines describing it:
+fn list_alsa_devices(input: bool) -> Result<Vec<Device>, String> {
+    let mut devices: Vec<Device> = Vec::new();
+    for line in listing(if input { "arecord" } else { "aplay" }, &["-L"])?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some(device) = devices.last_mut() {
+                if !device.description.is_empty() {
+                    device.description.push_str(", ");
+                }
+                device.description.push_str(line.trim());
+            }
+        } else {
+            devices.push(Device { name: line.trim().to_owned(), description: String::new() });
+        }
+    }
+
+    Ok(devices)
+}
+
+// `pactl list short' gives a line of tab separated fields for every sink or
+// source, its number, its name, the module it comes from, its sample format
+// and its state:
+fn list_pulseaudio_devices(input: bool) -> Result<Vec<Device>, String> {
+    let mut devices = Vec::new();
+    for line in listing("pactl", &["list", "short", if input { "sources" } else { "sinks" }])?.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 2 {
+            continue;
+        }
+        let mut description = fields[3..].join(", ");
+        // The monitors of the sinks capture what's played through them:
+        if fields[1].ends_with(".monitor") {
+            description = format!("monitor of a sink, {}", description);
+        }
+        devices.push(Device { name: fields[1].to_owned(), description });
+    }
+
+    Ok(devices)
+}
+
+// `system_profiler SPAudioDataType' gives the name of every device indented
+// by 8 spaces and followed by a colon, and its properties under it, the
+// number of channels telling the inputs from the outputs:
+fn list_coreaudio_devices(input: bool) -> Result<Vec<Device>, String> {
+    let channels = if input { "Input Channels:" } else { "Output Channels:" };
+    let default = if input { "Default Input Device: Yes" } else { "Default Output Device: Yes" };
+    let mut devices: Vec<(Device, bool)> = Vec::new();
+    for line in listing("system_profiler", &["SPAudioDataType"])?.lines() {
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        if indent == 8 && line.ends_with(':') {
+            devices.push((Device { name: line.trim_end_matches(':').to_owned(), description: String::new() }, false));
+        } else if let Some(&mut (ref mut device, ref mut wanted)) = devices.last_mut() {
+            if line.starts_with(channels) {
+                *wanted = true;
+            } else if line == default {
+                device.description = String::from("the default");
+            } else if line.starts_with("Transport:") && device.description.is_empty() {
+                device.description = line["Transport:".len()..].trim().to_lowercase();
+            }
+        }
+    }
+
+    Ok(devices.into_iter().filter(|&(ref _device, wanted)| wanted).map(|(device, _wanted)| device).collect())
+}
+
+// The audio endpoints of WASAPI, the outputs having instance IDs under
+// `{0.0.0.00000000}' and the inputs under `{0.0.1.00000000}'.  SoX opens
+// them by the names the older waveaudio interface gives them, cut down to
+// 31 characters:
+fn list_wasapi_devices(input: bool) -> Result<Vec<Device>, String> {
+    let kind = if input { "{0.0.1.00000000}" } else { "{0.0.0.00000000}" };
+    let script = "Get-PnpDevice -Class AudioEndpoint -Status OK | ForEach-Object { $_.InstanceId + \"`t\" + $_.FriendlyName }";
+    let mut devices = Vec::new();
+    for line in listing("powershell", &["-NoProfile", "-Command", script])?.lines() {
+        let mut fields = line.trim().splitn(2, '\t');
+        match (fields.next(), fields.next()) {
+            (Some(instance), Some(name)) if instance.to_uppercase().contains(kind) => {
+                let short: String = name.chars().take(31).collect();
+                let description = if short != name { name.to_owned() } else { String::new() };
+                devices.push(Device { name: short, description });
+            },
+            _ => {},
+        }
+    }
+
+    Ok(devices)
+}
+
+// Lists the input or the output devices the program can be told to use:
+pub fn list_devices(program: &Program, input: bool) -> Result<Vec<Device>, String> {
+    match program.sound_system {
+        Some(SoundSystem::Alsa) => { list_alsa_devices(input) },
+        Some(SoundSystem::PulseAudio) => { list_pulseaudio_devices(input) },
+        Some(SoundSystem::Native) if cfg!(target_os = "macos") => { list_coreaudio_devices(input) },
+        Some(SoundSystem::Native) if cfg!(windows) => { list_wasapi_devices(input) },
+        Some(SoundSystem::Native) => { list_alsa_devices(input) },
+        None => { Err(format!("The `{}' program can't be told which device to use, so there are none to list", program.name)) },
+    }
+}
+
+// Converts a recording into samples at the given rate:
+pub fn convert(converter: &Program, in_path: &path::Path, sample_rate: u32) -> Result<Vec<i16>, String> {
+    let rate = sample_rate.to_string();
+    let mut command = process::Command::new(converter.name);
+    for arg in converter.args {
+        if *arg == "{input}" {
+            command.arg(in_path);
+        } else {
+            command.arg(arg.replace("{rate}", &rate));
+        }
+    }
+    command.stdin(process::Stdio::null());
+
+    let output = match command.output() {
+        Ok(output) => { output },
+        Err(error) => { return Err(format!("Failed to run `{}': {}", converter.name, error)); },
+    };
+    if !output.status.success() {
+        return Err(format!("`{}' failed, {}: {}", converter.name, output.status,
+                           String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(output.stdout.chunks(2).filter(|sample| sample.len() == 2)
+                     .map(|sample| ((sample[0] as u16) | ((sample[1] as u16) << 8)) as i16).collect())
+}
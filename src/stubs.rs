@@ -0,0 +1,775 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+// Small Z80 routines added to the programs on the tape, assembled by hand.
+
+use tape;
+
+const LD_HL:     u8 = 0x21;
+const LD_DE:     u8 = 0x11;
+const LD_BC:     u8 = 0x01;
+const PREFIX_ED: u8 = 0xED;
+const LDIR:      u8 = 0xB0;
+const LDDR:      u8 = 0xB8;
+const JP:        u8 = 0xC3;
+
+// Length of the relocation stub:
+pub const RELOCATION_STUB_LENGTH: usize = 14;
+
+fn push_word(code: &mut Vec<u8>, word: u16) {
+    code.push((word & 0x00FF) as u8);
+    code.push(((word & 0xFF00) >> 8) as u8);
+}
+
+// Copies `length' bytes from `source' to `destination', and jumps to the
+// entry point:
+//
+//   LD   HL, source
+//   LD   DE, destination
+//   LD   BC, length
+//   LDIR
+//   JP   entry_point
+//
+// If the destination overlaps the end of the source, the copy is done from
+// the last byte down, with LDDR, so that nothing is overwritten before it's
+// copied.
+//
+pub fn relocation_stub(source: u16, destination: u16, length: u16, entry_point: u16) -> Vec<u8> {
+    assert!(length != 0);
+    let mut code = Vec::with_capacity(RELOCATION_STUB_LENGTH);
+
+    let backwards = destination > source && (destination - source) < length;
+    if backwards {
+        code.push(LD_HL);
+        push_word(&mut code, source.wrapping_add(length - 1));
+        code.push(LD_DE);
+        push_word(&mut code, destination.wrapping_add(length - 1));
+    } else {
+        code.push(LD_HL);
+        push_word(&mut code, source);
+        code.push(LD_DE);
+        push_word(&mut code, destination);
+    }
+    code.push(LD_BC);
+    push_word(&mut code, length);
+    code.push(PREFIX_ED);
+    code.push(if backwards { LDDR } else { LDIR });
+    code.push(JP);
+    push_word(&mut code, entry_point);
+
+    code
+}
+
+
+// A routine being put together, the addresses of its labels are only known
+// once all of it is, so the references to them get filled in at the end:
+struct Routine {
+    origin: u16,
+    code: Vec<u8>,
+    labels: Vec<(&'static str, usize)>,
+
+    // Position of the reference, the label, and whether it's a relative
+    // jump displacement rather than an absolute address:
+    references: Vec<(usize, &'static str, bool)>,
+}
+
+impl Routine {
+    fn new(origin: u16) -> Routine {
+        Routine {
+            origin,
+            code: Vec::new(),
+            labels: Vec::new(),
+            references: Vec::new(),
+        }
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn emit_word(&mut self, word: u16) {
+        push_word(&mut self.code, word);
+    }
+
+    fn label(&mut self, name: &'static str) {
+        self.labels.push((name, self.code.len()));
+    }
+
+    // An instruction followed by the address of a label:
+    fn emit_absolute(&mut self, opcode: &[u8], name: &'static str) {
+        self.emit(opcode);
+        self.references.push((self.code.len(), name, false));
+        self.emit(&[0x00, 0x00]);
+    }
+
+    // A relative jump to a label:
+    fn emit_relative(&mut self, opcode: u8, name: &'static str) {
+        self.emit(&[opcode]);
+        self.references.push((self.code.len(), name, true));
+        self.emit(&[0x00]);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for &(position, name, relative) in &self.references {
+            let target = match self.labels.iter().find(|&&(label, _offset)| label == name) {
+                Some(&(_label, offset)) => { offset },
+                None => { panic!("undefined label `{}' in a stub", name); },
+            };
+            if relative {
+                let displacement = (target as isize) - (position as isize + 1);
+                assert!((-128..=127).contains(&displacement), "relative jump out of range in a stub");
+                self.code[position] = displacement as i8 as u8;
+            } else {
+                let address = self.origin.wrapping_add(target as u16);
+                self.code[position] = (address & 0x00FF) as u8;
+                self.code[position + 1] = ((address & 0xFF00) >> 8) as u8;
+            }
+        }
+        self.code
+    }
+}
+
+const DI:        u8 = 0xF3;
+const LD_A:      u8 = 0x3E;
+const LD_B:      u8 = 0x06;
+const LD_C:      u8 = 0x0E;
+const OUT_A:     u8 = 0xD3;
+const IN_A:      u8 = 0xDB;
+const RLA:       u8 = 0x17;
+const JR_NC:     u8 = 0x30;
+const JR_NZ:     u8 = 0x20;
+const DJNZ:      u8 = 0x10;
+const CALL:      u8 = 0xCD;
+const RET:       u8 = 0xC9;
+const RL_C:      [u8; 2] = [0xCB, 0x11];
+
+// The cassette port: reading bit 7 tells whether a pulse came in since the
+// last write, writing resets that and sets the motor relay with bit 2:
+const CASSETTE_PORT: u8 = 0xFF;
+const MOTOR_ON:      u8 = 0x04;
+
+// Clock of the Model I's Z80, in Hz:
+pub const CPU_CLOCK_HZ: u64 = 1_774_080;
+
+// The fast bit rates, as multiples of the ROM's, which the turbo loader
+// can be built for, and the slow ones, as fractions of it:
+pub const TURBO_SPEEDS: &[u32] = &[2, 3, 4];
+pub const SLOW_FACTORS: &[u32] = &[2, 3, 4];
+
+// Waits for about the given time with DJNZ loops, less the cycles spent by
+// the surrounding instructions.  Delays longer than one loop of B can count
+// take several of them:
+fn emit_delay(routine: &mut Routine, microseconds: u32, overhead_cycles: u64) {
+    let cycles = (microseconds as u64) * CPU_CLOCK_HZ / 1_000_000;
+    let mut count = (cycles.saturating_sub(overhead_cycles) / 13).max(1);
+    while count > 0 {
+        let loops = count.min(255);
+        routine.emit(&[LD_B, loops as u8, DJNZ, 0xFE]);    // LD B,loops; DJNZ $
+        count -= loops;
+    }
+}
+
+// A loader for data recorded with bit cells of `cell_us', faster than the
+// ROM records them for turbo tapes, or slower for decks and cables which
+// can't keep up with the ROM's, run from a normal SYSTEM entry.  It turns
+// the motor back on, waits for the sync byte after the leader, and reads:
+//
+//   2 bytes      lsb, msb of the load address
+//   2 bytes      lsb, msb of the length of the data
+//   N bytes      the data
+//   1 byte       sum of the data
+//   2 bytes      lsb, msb of the entry point
+//
+// It then jumps to the entry point if the sum matches, or puts a `C' in the
+// top right corner of the screen and returns to BASIC, like the ROM does
+// for checksum errors.
+//
+// Bits are read the way the ROM reads them: once a clock pulse is seen, the
+// pulse detector is reset a quarter of a cell later, and checked for the
+// data pulse at three quarters of the cell.
+//
+pub fn turbo_loader(origin: u16, cell_us: u32) -> Vec<u8> {
+    let mut routine = Routine::new(origin);
+
+    routine.emit(&[DI, LD_A, MOTOR_ON, OUT_A, CASSETTE_PORT, LD_C, 0x00]);
+
+    // Look for the sync byte, bit by bit:
+    routine.label("sync");
+    routine.emit_absolute(&[CALL], "get_bit");
+    routine.emit(&RL_C);
+    routine.emit(&[0x79, 0xFE, 0xA5]);                  // LD A,C; CP 0A5h
+    routine.emit_relative(JR_NZ, "sync");
+
+    // The load address and the length:
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x6F]);                              // LD L,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x67]);                              // LD H,A
+    routine.emit_absolute(&[0x22], "address");          // LD (address),HL
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x5F]);                              // LD E,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x57]);                              // LD D,A
+    routine.emit_absolute(&[0xED, 0x53], "length");     // LD (length),DE
+
+    // The data, there's only a quarter of a cell to spare between bytes:
+    routine.label("data");
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x77, 0x23, 0x1B, 0x7A, 0xB3]);      // LD (HL),A; INC HL; DEC DE; LD A,D; OR E
+    routine.emit_relative(JR_NZ, "data");
+
+    // The sum and the entry point:
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit_absolute(&[0x32], "sum");              // LD (sum),A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x6F]);                              // LD L,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x67]);                              // LD H,A
+    routine.emit_absolute(&[0x22], "entry");            // LD (entry),HL
+    routine.emit(&[0xAF, OUT_A, CASSETTE_PORT]);        // XOR A; OUT (0FFh),A
+
+    // With the motor off, check the sum of what was loaded:
+    routine.emit_absolute(&[0x2A], "address");          // LD HL,(address)
+    routine.emit_absolute(&[0xED, 0x5B], "length");     // LD DE,(length)
+    routine.emit(&[LD_C, 0x00]);
+    routine.label("check");
+    routine.emit(&[0x79, 0x86, 0x4F, 0x23, 0x1B, 0x7A, 0xB3]); // LD A,C; ADD A,(HL); LD C,A; INC HL; DEC DE; LD A,D; OR E
+    routine.emit_relative(JR_NZ, "check");
+    routine.emit_absolute(&[0x3A], "sum");              // LD A,(sum)
+    routine.emit(&[0xB9]);                              // CP C
+    routine.emit_relative(JR_NZ, "error");
+    routine.emit_absolute(&[0x2A], "entry");            // LD HL,(entry)
+    routine.emit(&[0xE9]);                              // JP (HL)
+
+    routine.label("error");
+    routine.emit(&[LD_A, b'C', 0x32, 0x3F, 0x3C]);      // LD A,'C'; LD (3C3Fh),A
+    routine.emit(&[JP, 0x19, 0x1A]);                    // JP 1A19h
+
+    // Reads a byte into A, the marker bit in C falls out after 8 bits:
+    routine.label("get_byte");
+    routine.emit(&[LD_C, 0x01]);
+    routine.label("next_bit");
+    routine.emit_absolute(&[CALL], "get_bit");
+    routine.emit(&RL_C);
+    routine.emit_relative(JR_NC, "next_bit");
+    routine.emit(&[0x79, RET]);                         // LD A,C
+
+    // Reads a bit into the carry flag:
+    routine.label("get_bit");
+    routine.emit(&[IN_A, CASSETTE_PORT, RLA]);
+    routine.emit_relative(JR_NC, "get_bit");
+    emit_delay(&mut routine, cell_us / 4, 20);
+    routine.emit(&[LD_A, MOTOR_ON, OUT_A, CASSETTE_PORT]);
+    emit_delay(&mut routine, cell_us / 2, 13);
+    routine.emit(&[IN_A, CASSETTE_PORT, RLA, LD_A, MOTOR_ON, OUT_A, CASSETTE_PORT, RET]);
+
+    // Variables:
+    routine.label("address");
+    routine.emit(&[0x00, 0x00]);
+    routine.label("length");
+    routine.emit(&[0x00, 0x00]);
+    routine.label("entry");
+    routine.emit(&[0x00, 0x00]);
+    routine.label("sum");
+    routine.emit(&[0x00]);
+
+    routine.finish()
+}
+
+// The fast part of a turbo tape, as read by the turbo loader, starting with
+// the leader:
+pub fn turbo_payload(load_address: u16, data: &[u8], entry_point: u16, leader_length: usize) -> Vec<u8> {
+    assert!(!data.is_empty() && data.len() <= 0xFFFF);
+    let mut payload = vec![0x00; leader_length];
+
+    payload.push(0xA5);
+    push_word(&mut payload, load_address);
+    push_word(&mut payload, data.len() as u16);
+    payload.extend_from_slice(data);
+    payload.push(data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)));
+    push_word(&mut payload, entry_point);
+
+    // Room for the loader to read the last bit properly:
+    payload.push(0x00);
+
+    payload
+}
+
+// Length of the verification record:
+pub const VERIFICATION_RECORD_LENGTH: usize = 6;
+
+// The record describing the data of a program, placed after it, so that the
+// loaded program can be checked:
+//
+//   2 bytes      lsb, msb of the first address of the data
+//   2 bytes      lsb, msb of one past the last address of the data
+//   2 bytes      lsb, msb of the CRC-16 of the data (CCITT, from 0xFFFF)
+//
+pub fn verification_record(start: u16, data: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(VERIFICATION_RECORD_LENGTH);
+
+    push_word(&mut record, start);
+    push_word(&mut record, start.wrapping_add(data.len() as u16));
+    push_word(&mut record, tape::crc16(data.iter()));
+
+    record
+}
+
+// Checks the data described by the verification record at `record', and
+// jumps to the entry point if it's intact, or puts a `C' in the top right
+// corner of the screen and returns to BASIC otherwise:
+pub fn verifier(origin: u16, record: u16, entry_point: u16) -> Vec<u8> {
+    let mut routine = Routine::new(origin);
+
+    routine.emit(&[0x2A]);                              // LD HL,(start)
+    routine.emit_word(record);
+    routine.emit(&[LD_BC, 0xFF, 0xFF]);
+
+    // CRC-16 in BC, a byte at a time, with the bits counted in E:
+    routine.label("byte");
+    routine.emit(&[0x7E, 0xA8, 0x47, 0x1E, 0x08]);      // LD A,(HL); XOR B; LD B,A; LD E,8
+    routine.label("bit");
+    routine.emit(&[0xCB, 0x21, 0xCB, 0x10]);            // SLA C; RL B
+    routine.emit_relative(JR_NC, "no_xor");
+    routine.emit(&[0x78, 0xEE, 0x10, 0x47]);            // LD A,B; XOR 10h; LD B,A
+    routine.emit(&[0x79, 0xEE, 0x21, 0x4F]);            // LD A,C; XOR 21h; LD C,A
+    routine.label("no_xor");
+    routine.emit(&[0x1D]);                              // DEC E
+    routine.emit_relative(JR_NZ, "bit");
+
+    // Up to the end address:
+    routine.emit(&[0x23, 0xE5, 0xED, 0x5B]);            // INC HL; PUSH HL; LD DE,(end)
+    routine.emit_word(record.wrapping_add(2));
+    routine.emit(&[0xB7, 0xED, 0x52, 0xE1]);            // OR A; SBC HL,DE; POP HL
+    routine.emit_relative(JR_NZ, "byte");
+
+    routine.emit(&[0x2A]);                              // LD HL,(crc)
+    routine.emit_word(record.wrapping_add(4));
+    routine.emit(&[0xB7, 0xED, 0x42]);                  // OR A; SBC HL,BC
+    routine.emit_relative(JR_NZ, "error");
+    routine.emit(&[JP]);
+    routine.emit_word(entry_point);
+
+    routine.label("error");
+    routine.emit(&[LD_A, b'C', 0x32, 0x3F, 0x3C]);      // LD A,'C'; LD (3C3Fh),A
+    routine.emit(&[JP, 0x19, 0x1A]);                    // JP 1A19h
+
+    routine.finish()
+}
+
+// How the bytes of a block of a test pattern follow one another, from the
+// first byte of the block on, by the instruction the checker steps them
+// with:
+#[derive(Clone, Copy, PartialEq)]
+pub enum PatternStep {
+    // INC A, counting up:
+    Increment,
+    // RLCA, walking a bit around:
+    Rotate,
+    // NOP, the same byte over and over:
+    Hold,
+}
+
+impl PatternStep {
+    pub fn next(self, value: u8) -> u8 {
+        match self {
+            PatternStep::Increment => { value.wrapping_add(1) },
+            PatternStep::Rotate => { value.rotate_left(1) },
+            PatternStep::Hold => { value },
+        }
+    }
+
+    fn opcode(self) -> u8 {
+        match self {
+            PatternStep::Increment => { 0x3C },
+            PatternStep::Rotate => { 0x07 },
+            PatternStep::Hold => { 0x00 },
+        }
+    }
+}
+
+// Length of the blocks of a test pattern:
+pub const PATTERN_BLOCK_LENGTH: usize = 256;
+
+// The ROM's routine displaying the character in A at the cursor:
+const ROM_DISPLAY: u16 = 0x0033;
+
+// Checks the blocks of a test pattern loaded from `data_start' on, each
+// given by its first byte and how the rest follow, and displays a line with
+// a `.' for every intact block and an `X' for every damaged one, ending in
+// `OK' or `BAD', before returning to BASIC.  The step of each block gets
+// patched into the loop comparing it:
+pub fn pattern_checker(origin: u16, data_start: u16, blocks: &[(u8, PatternStep)]) -> Vec<u8> {
+    assert!(!blocks.is_empty() && blocks.len() <= 255);
+    let mut routine = Routine::new(origin);
+
+    routine.emit(&[0xAF]);                              // XOR A
+    routine.emit_absolute(&[0x32], "bad");              // LD (bad),A
+    routine.emit(&[LD_A, blocks.len() as u8]);
+    routine.emit_absolute(&[0x32], "count");            // LD (count),A
+    routine.emit_absolute(&[LD_HL], "title");
+    routine.emit_absolute(&[CALL], "print");
+    routine.emit(&[LD_HL]);
+    routine.emit_word(data_start);
+    routine.emit_absolute(&[LD_DE], "table");
+
+    // Set up the expected first byte in C, the step, and the mark:
+    routine.label("block");
+    routine.emit(&[0x1A, 0x4F, 0x13, 0x1A]);            // LD A,(DE); LD C,A; INC DE; LD A,(DE)
+    routine.emit_absolute(&[0x32], "step");             // LD (step),A
+    routine.emit(&[0x13, LD_A, b'.']);                  // INC DE; LD A,'.'
+    routine.emit_absolute(&[0x32], "mark");             // LD (mark),A
+    routine.emit(&[LD_B, 0x00]);
+
+    // Compare the 256 bytes of the block:
+    routine.label("byte");
+    routine.emit(&[0x79, 0xBE]);                        // LD A,C; CP (HL)
+    routine.emit_relative(0x28, "same");                // JR Z,same
+    routine.emit(&[LD_A, b'X']);
+    routine.emit_absolute(&[0x32], "mark");             // LD (mark),A
+    routine.emit_absolute(&[0x32], "bad");              // LD (bad),A
+    routine.label("same");
+    routine.emit(&[0x23, 0x79]);                        // INC HL; LD A,C
+    routine.label("step");
+    routine.emit(&[0x00, 0x4F]);                        // (step); LD C,A
+    routine.emit_relative(DJNZ, "byte");
+
+    routine.emit_absolute(&[0x3A], "mark");             // LD A,(mark)
+    routine.emit_absolute(&[CALL], "display");
+    routine.emit_absolute(&[0x3A], "count");            // LD A,(count)
+    routine.emit(&[0x3D]);                              // DEC A
+    routine.emit_absolute(&[0x32], "count");            // LD (count),A
+    routine.emit_relative(JR_NZ, "block");
+
+    routine.emit_absolute(&[LD_HL], "ok");
+    routine.emit_absolute(&[0x3A], "bad");              // LD A,(bad)
+    routine.emit(&[0xB7]);                              // OR A
+    routine.emit_relative(0x28, "report");              // JR Z,report
+    routine.emit_absolute(&[LD_HL], "failed");
+    routine.label("report");
+    routine.emit_absolute(&[CALL], "print");
+    routine.emit(&[JP]);
+    routine.emit_word(tape::ROM_BASIC_READY);
+
+    // Displays the text at HL, up to a zero byte:
+    routine.label("print");
+    routine.emit(&[0x7E, 0xB7, 0xC8]);                  // LD A,(HL); OR A; RET Z
+    routine.emit_absolute(&[CALL], "display");
+    routine.emit(&[0x23]);                              // INC HL
+    routine.emit_relative(0x18, "print");               // JR print
+
+    // Displays the character in A, keeping the registers:
+    routine.label("display");
+    routine.emit(&[0xE5, 0xD5, 0xC5]);                  // PUSH HL; PUSH DE; PUSH BC
+    routine.emit(&[CALL]);
+    routine.emit_word(ROM_DISPLAY);
+    routine.emit(&[0xC1, 0xD1, 0xE1, RET]);             // POP BC; POP DE; POP HL
+
+    // The first byte and the step of each block, the texts and the
+    // variables:
+    routine.label("table");
+    for &(first, step) in blocks {
+        routine.emit(&[first, step.opcode()]);
+    }
+    routine.label("title");
+    routine.emit(b"TEST PATTERN \0");
+    routine.label("ok");
+    routine.emit(b" OK\r\0");
+    routine.label("failed");
+    routine.emit(b" BAD\r\0");
+    routine.label("bad");
+    routine.emit(&[0x00]);
+    routine.label("count");
+    routine.emit(&[0x00]);
+    routine.label("mark");
+    routine.emit(&[0x00]);
+
+    routine.finish()
+}
+
+// The ports of the RS-232-C interface: writing the first resets the UART,
+// the second sets the baud rates, the third both tells whether a byte came
+// in, with bit 7, and sets the word format, and the fourth holds the byte:
+const SERIAL_RESET_PORT:   u8 = 0xE8;
+const SERIAL_BAUD_PORT:    u8 = 0xE9;
+const SERIAL_STATUS_PORT:  u8 = 0xEA;
+const SERIAL_DATA_PORT:    u8 = 0xEB;
+// 8 data bits, no parity, 1 stop bit, with DTR and RTS on:
+const SERIAL_WORD_FORMAT:  u8 = 0x6F;
+
+// The baud rates the interface offers, in Hz, with the switch settings of
+// its baud rate generator, the receiving rate in the low nibble and the
+// transmitting one in the high nibble:
+pub const SERIAL_BAUD_RATES: &[(u32, u8)] = &[
+    (300,   0x55),
+    (600,   0x66),
+    (1200,  0x77),
+    (2400,  0xAA),
+    (4800,  0xCC),
+    (9600,  0xEE),
+    (19200, 0xFF),
+];
+
+// Receives a program sent over the RS-232-C interface, as the records of a
+// SYSTEM tape from the first block on, which it reads the way the ROM reads
+// them off the tape:
+//
+//   3Ch, length, lsb, msb of the load address, data, checksum   for a block
+//   78h, lsb, msb of the entry point                            at the end
+//
+// Anything else between the records is skipped.  The star in the top right
+// corner of the screen blinks for every block, and a `C' is put there for
+// a checksum error before returning to BASIC, like the ROM does.
+pub fn serial_receiver(origin: u16, baud_switches: u8) -> Vec<u8> {
+    let mut routine = Routine::new(origin);
+
+    routine.emit(&[DI, 0xAF, OUT_A, SERIAL_RESET_PORT]);                // XOR A; OUT (0E8h),A
+    routine.emit(&[LD_A, baud_switches, OUT_A, SERIAL_BAUD_PORT]);
+    routine.emit(&[LD_A, SERIAL_WORD_FORMAT, OUT_A, SERIAL_STATUS_PORT]);
+    routine.emit(&[LD_A, b'*', 0x32, 0x3F, 0x3C]);                      // LD A,'*'; LD (3C3Fh),A
+
+    routine.label("record");
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0xFE, 0x3C]);                                        // CP 3Ch
+    routine.emit_relative(0x28, "block");                               // JR Z,block
+    routine.emit(&[0xFE, 0x78]);                                        // CP 78h
+    routine.emit_relative(JR_NZ, "record");
+
+    // The end of file record, with the entry point:
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x6F]);                                              // LD L,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x67, 0xE9]);                                        // LD H,A; JP (HL)
+
+    // A block, its length in B, with 0 standing for 256, the checksum
+    // summed up in C:
+    routine.label("block");
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x47]);                                              // LD B,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x6F]);                                              // LD L,A
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x67, 0x85, 0x4F]);                                  // LD H,A; ADD A,L; LD C,A
+    routine.label("data");
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0x77, 0x23, 0x81, 0x4F]);                            // LD (HL),A; INC HL; ADD A,C; LD C,A
+    routine.emit_relative(DJNZ, "data");
+    routine.emit_absolute(&[CALL], "get_byte");
+    routine.emit(&[0xB9]);                                              // CP C
+    routine.emit_relative(JR_NZ, "error");
+    routine.emit(&[0x3A, 0x3F, 0x3C, 0xEE, b'*' ^ b' ', 0x32, 0x3F, 0x3C]); // LD A,(3C3Fh); XOR 0Ah; LD (3C3Fh),A
+    routine.emit_relative(0x18, "record");                              // JR record
+
+    routine.label("error");
+    routine.emit(&[LD_A, b'C', 0x32, 0x3F, 0x3C]);                      // LD A,'C'; LD (3C3Fh),A
+    routine.emit(&[JP, 0x19, 0x1A]);                                    // JP 1A19h
+
+    // Waits for a byte to come in, and reads it into A:
+    routine.label("get_byte");
+    routine.emit(&[IN_A, SERIAL_STATUS_PORT, RLA]);
+    routine.emit_relative(JR_NC, "get_byte");
+    routine.emit(&[IN_A, SERIAL_DATA_PORT, RET]);
+
+    routine.finish()
+}
+
+// What gets sent to the serial receiver for the blocks given by their load
+// addresses and data, of up to 256 bytes each:
+pub fn serial_payload(blocks: &[(u16, &[u8])], entry_point: u16) -> Vec<u8> {
+    let mut payload = Vec::new();
+
+    for &(load_address, data) in blocks {
+        assert!(!data.is_empty() && data.len() <= 256);
+        payload.push(0x3C);
+        payload.push(data.len() as u8);
+        push_word(&mut payload, load_address);
+        payload.extend_from_slice(data);
+        payload.push(tape::block_checksum(load_address, data));
+    }
+    payload.push(0x78);
+    push_word(&mut payload, entry_point);
+
+    payload
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio;
+    use tape;
+    use z80;
+
+    // The most instructions a stub is allowed to take in a test:
+    const STEP_LIMIT: usize = 20_000_000;
+
+    // All RAM, with the cassette port playing bits into the turbo loader.
+    // The core keeps no count of the clock cycles, so the pulses come in
+    // as the loader resets the pulse detector rather than in time, a clock
+    // pulse once it looks for one, then the data pulse if the bit is set:
+    struct TestBus {
+        memory: Vec<u8>,
+        bits: Vec<bool>,
+        position: usize,
+        clock_seen: bool,
+        data_phase: bool,
+    }
+
+    impl TestBus {
+        fn new(tape: &[u8]) -> TestBus {
+            let bits = tape.iter().flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 != 0)).collect();
+            TestBus { memory: vec![0x00; 0x10000], bits, position: 0, clock_seen: false, data_phase: false }
+        }
+
+        fn load(&mut self, address: u16, code: &[u8]) {
+            self.memory[address as usize..address as usize + code.len()].copy_from_slice(code);
+        }
+    }
+
+    impl z80::Bus for TestBus {
+        fn read(&mut self, address: u16) -> u8 {
+            self.memory[address as usize]
+        }
+
+        fn write(&mut self, address: u16, value: u8) {
+            self.memory[address as usize] = value;
+        }
+
+        fn input(&mut self, port: u16) -> u8 {
+            if port as u8 != CASSETTE_PORT || self.position >= self.bits.len() {
+                return 0x00;
+            }
+            if self.data_phase {
+                if self.bits[self.position] { 0x80 } else { 0x00 }
+            } else {
+                self.clock_seen = true;
+                0x80
+            }
+        }
+
+        fn output(&mut self, port: u16, _value: u8) {
+            if port as u8 != CASSETTE_PORT {
+                return;
+            }
+            if self.data_phase {
+                self.position += 1;
+                self.data_phase = false;
+            } else if self.clock_seen {
+                self.clock_seen = false;
+                self.data_phase = true;
+            }
+        }
+    }
+
+    // Runs from the address until the program gets to the other one, returns
+    // whether it did:
+    fn run_until(bus: &mut TestBus, start: u16, stop: u16) -> bool {
+        let mut cpu = z80::Cpu::new();
+        cpu.pc = start;
+        cpu.sp = 0xFF00;
+        for _count in 0..STEP_LIMIT {
+            if cpu.pc == stop {
+                return true;
+            }
+            if cpu.halted {
+                return false;
+            }
+            cpu.step(bus);
+        }
+        false
+    }
+
+    fn test_data(length: usize) -> Vec<u8> {
+        (0..length).map(|index| ((index * 37 + 11) as u8) ^ ((index >> 3) as u8)).collect()
+    }
+
+    // Loads the data at the source, and runs the stub from out of the way
+    // of the copy, which `pack' sees to on tapes:
+    fn relocate(source: u16, destination: u16, length: usize) -> TestBus {
+        let data = test_data(length);
+        let stub_address = 0xC000;
+        let stub = relocation_stub(source, destination, length as u16, 0x4321);
+        assert_eq!(stub.len(), RELOCATION_STUB_LENGTH);
+
+        let mut bus = TestBus::new(&[]);
+        bus.load(source, &data);
+        bus.load(stub_address, &stub);
+        assert!(run_until(&mut bus, stub_address, 0x4321));
+        assert_eq!(&bus.memory[destination as usize..destination as usize + length], &data[..]);
+        bus
+    }
+
+    #[test]
+    fn relocation_apart() {
+        relocate(0x8000, 0x5000, 300);
+        relocate(0x5000, 0x8000, 300);
+        relocate(0x9000, 0x9000 - 1, 1);
+    }
+
+    #[test]
+    fn relocation_overlapping_upwards() {
+        let stub = relocation_stub(0x6000, 0x6010, 0x100, 0x6010);
+        assert_eq!(stub[10], LDDR);
+        relocate(0x6000, 0x6010, 0x100);
+        relocate(0x6000, 0x6001, 0x100);
+    }
+
+    #[test]
+    fn relocation_overlapping_downwards() {
+        let stub = relocation_stub(0x6010, 0x6000, 0x100, 0x6000);
+        assert_eq!(stub[10], LDIR);
+        let bus = relocate(0x6010, 0x6000, 0x100);
+        // The end of the source, past the copy, is left as it was:
+        assert_eq!(&bus.memory[0x6100..0x6110], &test_data(0x100)[0xF0..]);
+    }
+
+    // Plays the payload into the turbo loader built for the cell length,
+    // returns where it went and the memory:
+    fn turbo_load(cell_us: u32, payload: &[u8], stop: u16) -> Option<TestBus> {
+        let loader = turbo_loader(0x7000, cell_us);
+        let mut bus = TestBus::new(payload);
+        bus.load(0x7000, &loader);
+        if run_until(&mut bus, 0x7000, stop) { Some(bus) } else { None }
+    }
+
+    #[test]
+    fn turbo_loader_loads() {
+        let data = test_data(700);
+        let cells = TURBO_SPEEDS.iter().map(|speed| audio::BIT_CELL_US / speed)
+                                .chain(SLOW_FACTORS.iter().map(|factor| audio::BIT_CELL_US * factor));
+        for cell_us in cells {
+            let payload = turbo_payload(0x8000, &data, 0x8123, 32);
+            let bus = match turbo_load(cell_us, &payload, 0x8123) {
+                Some(bus) => { bus },
+                None => { panic!("the loader for {} us cells didn't get to the entry point", cell_us); },
+            };
+            assert_eq!(&bus.memory[0x8000..0x8000 + data.len()], &data[..], "{} us cells", cell_us);
+            assert_eq!(bus.memory[0x3C3F], 0x00);
+        }
+    }
+
+    #[test]
+    fn turbo_loader_checks_the_sum() {
+        let mut payload = turbo_payload(0x8000, &test_data(100), 0x8000, 32);
+        // Past the leader, the sync byte, the address and the length:
+        payload[32 + 1 + 4 + 50] ^= 0x10;
+
+        let bus = match turbo_load(audio::BIT_CELL_US / 2, &payload, tape::ROM_BASIC_READY) {
+            Some(bus) => { bus },
+            None => { panic!("the loader didn't return to BASIC"); },
+        };
+        assert_eq!(bus.memory[0x3C3F], b'C');
+    }
+}
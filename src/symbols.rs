@@ -0,0 +1,238 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Symbols of the programs packed, read from the symbol files or listings of
+// the assembler, for the debuggers of the emulators to show the source with.
+//
+// Symbol files give a symbol per line in any of the usual notations, with
+// `;' starting comments:
+//
+//   start   EQU  5200H
+//   loop:   EQU  $5210
+//   buffer  =    0x6000
+//   print        5230
+//   5240         done
+//
+// Listings give the address and the bytes assembled in front of each line
+// of the source, optionally after the line number, such as
+//
+//   12  5200  3E 00        start:  ld   a,0
+//
+// the labels of their lines being the symbols, and the EQUs values of their
+// own, which don't move with the code.
+
+use numbers;
+
+
+pub struct Symbol {
+    pub name: String,
+    pub value: u16,
+    // Whether it's the address of something in the code, or just a value:
+    pub is_label: bool,
+}
+
+pub struct ListingLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub source: String,
+}
+
+pub struct Symbols {
+    pub symbols: Vec<Symbol>,
+    pub lines: Vec<ListingLine>,
+}
+
+fn is_identifier(word: &str) -> bool {
+    match word.chars().next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' || first == '.' || first == '@' => {
+            word.chars().all(|character| character.is_ascii_alphanumeric() || "_.$@?".contains(character))
+        },
+        _ => { false },
+    }
+}
+
+fn parse_value(word: &str) -> Option<u16> {
+    match numbers::parse_number(word, 16) {
+        Ok(value) if value <= 0xFFFF => { Some(value as u16) },
+        _ => { None },
+    }
+}
+
+// A symbol in any of the notations of symbol files:
+fn parse_symbol_line(line: &str) -> Option<Symbol> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, value) = match words.as_slice() {
+        [name, keyword, value] if keyword.eq_ignore_ascii_case("equ") || *keyword == "=" || keyword.eq_ignore_ascii_case(".equ") => { (*name, *value) },
+        [first, second] => {
+            match (parse_value(second), parse_value(first)) {
+                (Some(_value), _) if is_identifier(first.trim_end_matches(':')) => { (*first, *second) },
+                (_, Some(_value)) => { (*second, *first) },
+                _ => { return None; },
+            }
+        },
+        _ => { return None; },
+    };
+    let name = name.trim_end_matches(':');
+    match parse_value(value) {
+        Some(value) if is_identifier(name) => { Some(Symbol { name: name.to_owned(), value, is_label: true }) },
+        _ => { None },
+    }
+}
+
+pub fn parse_symbol_file(text: &str) -> Result<Symbols, String> {
+    let mut symbols = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_symbol_line(line) {
+            Some(symbol) => { symbols.push(symbol); },
+            None => { return Err(format!("line {}: Expected a symbol and its value, found `{}'", index + 1, line)); },
+        }
+    }
+
+    Ok(Symbols { symbols, lines: Vec::new() })
+}
+
+fn is_hex_word(word: &str, length: usize) -> bool {
+    word.len() == length && word.chars().all(|character| character.is_ascii_hexdigit())
+}
+
+// A line of a listing with code or data, the address and the bytes being
+// found by their number of digits:
+fn parse_listing_line(line: &str, numbered: bool) -> Option<ListingLine> {
+    let mut rest = line.trim_start();
+    // Assemblers mark relocatable addresses with a quote, and lines from
+    // macros and included files with a plus:
+    let is_address = |word: &str| is_hex_word(word.trim_end_matches('\''), 4);
+    if numbered {
+        let number = rest.split_whitespace().next()?;
+        line_number(number)?;
+        rest = rest[number.len()..].trim_start();
+    }
+    let address_word = rest.split_whitespace().next()?;
+    if !is_address(address_word) {
+        return None;
+    }
+    let address = u16::from_str_radix(address_word.trim_end_matches('\''), 16).ok()?;
+    rest = rest[address_word.len()..].trim_start();
+
+    let mut bytes = Vec::new();
+    while let Some(word) = rest.split_whitespace().next() {
+        if word.is_empty() || word.len() % 2 != 0 || word.len() > 8 || !word.chars().all(|character| character.is_ascii_hexdigit()) {
+            break;
+        }
+        for pair in 0..word.len() / 2 {
+            bytes.push(u8::from_str_radix(&word[pair * 2..pair * 2 + 2], 16).ok()?);
+        }
+        rest = rest[word.len()..].trim_start();
+    }
+    Some(ListingLine { address, bytes, source: rest.to_owned() })
+}
+
+fn line_number(word: &str) -> Option<usize> {
+    word.trim_end_matches('+').parse::<usize>().ok()
+}
+
+// Whether the lines of the listing start with their numbers, which may
+// look just like addresses, but count up by ones from line to line:
+fn is_numbered(text: &str) -> bool {
+    let numbers: Vec<usize> = text.lines().filter_map(|line| line.split_whitespace().next().and_then(line_number)).collect();
+    let in_turn = numbers.windows(2).filter(|pair| pair[1] == pair[0] + 1).count();
+    numbers.len() > 1 && in_turn * 2 >= numbers.len() - 1
+}
+
+pub fn parse_listing(text: &str) -> Symbols {
+    let mut symbols: Vec<Symbol> = Vec::new();
+    let mut lines = Vec::new();
+
+    let numbered = is_numbered(text);
+    for line in text.lines() {
+        let line = line.trim_end();
+        match parse_listing_line(line, numbered) {
+            Some(listed) => {
+                let code = listed.source.split(';').next().unwrap_or("");
+                let words: Vec<&str> = code.split_whitespace().collect();
+                match words.as_slice() {
+                    // The value goes where listings put the address, it's
+                    // no line of the code:
+                    [name, keyword, ..] if keyword.eq_ignore_ascii_case("equ") || *keyword == "=" => {
+                        symbols.push(Symbol { name: name.trim_end_matches(':').to_owned(), value: listed.address, is_label: false });
+                        continue;
+                    },
+                    [label, ..] if label.ends_with(':') && is_identifier(label.trim_end_matches(':')) => {
+                        symbols.push(Symbol { name: label.trim_end_matches(':').to_owned(), value: listed.address, is_label: true });
+                    },
+                    _ => {},
+                }
+                lines.push(listed);
+            },
+            // The symbol table at the end of the listing:
+            None => {
+                if let Some(symbol) = parse_symbol_line(line.split(';').next().unwrap_or("")) {
+                    if !symbols.iter().any(|known| known.name == symbol.name) {
+                        symbols.push(symbol);
+                    }
+                }
+            },
+        }
+    }
+
+    Symbols { symbols, lines: lines.into_iter().filter(|line: &ListingLine| !line.bytes.is_empty() || !line.source.is_empty()).collect() }
+}
+
+impl Symbols {
+    // The lowest address with code or data on it, where the listing was
+    // assembled to start:
+    pub fn origin(&self) -> Option<u16> {
+        self.lines.iter().filter(|line| !line.bytes.is_empty()).map(|line| line.address).min()
+    }
+
+    // Moves the code by the given offset, the values staying as they are:
+    pub fn shift(&mut self, offset: u16) {
+        for symbol in self.symbols.iter_mut().filter(|symbol| symbol.is_label) {
+            symbol.value = symbol.value.wrapping_add(offset);
+        }
+        for line in self.lines.iter_mut() {
+            line.address = line.address.wrapping_add(offset);
+        }
+    }
+}
+
+// The binary debuggable source files written by zmac and read by the
+// debugger of trs80gp, the listing's lines with their bytes followed by the
+// symbols:
+pub fn generate_bds(symbols: &Symbols, source_name: &str) -> String {
+    let mut bds = String::from("binary-debuggable-source\n");
+
+    if !symbols.lines.is_empty() {
+        bds.push_str(&format!("0000 0000 f {}\n", source_name));
+    }
+    for line in &symbols.lines {
+        if !line.bytes.is_empty() {
+            let bytes: String = line.bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+            bds.push_str(&format!("{:04x} {:04x} d {}\n", line.address, line.address, bytes));
+        }
+        bds.push_str(&format!("{:04x} {:04x} s {}\n", line.address, line.address, line.source));
+    }
+    for symbol in &symbols.symbols {
+        bds.push_str(&format!("{:04x} {} {}\n", symbol.value, if symbol.is_label { 'a' } else { 'v' }, symbol.name));
+    }
+
+    bds
+}
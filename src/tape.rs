@@ -0,0 +1,609 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Layout of a Level II SYSTEM (machine language) tape entry:
+//
+//   leader:      a run of zero bytes, 256 of them when written by the ROM,
+//                clones and custom loaders may expect another pattern
+//   0xA5         sync byte
+//   0x55         SYSTEM format header byte
+//   6 bytes      entry name in ASCII, padded with spaces
+//
+//   0x3C         data block marker, followed by:
+//     1 byte       length of the data, 0 = 256
+//     2 bytes      lsb, msb of the load address
+//     N bytes      the data
+//     1 byte       checksum of the load address and the data
+//
+//   0x78         end of file marker, followed by:
+//     2 bytes      lsb, msb of the entry point
+//
+// The ROM's SYSTEM loader skips over anything other than the data block
+// marker and the end of file marker while looking for the next block.
+//
+// This makes room for comment records after the name, which the loader
+// passes over harmlessly:
+//
+//   0xA3         comment marker (`#' with bit 7 set), followed by:
+//     N bytes      the text in ASCII, with bit 7 set on every character
+//     0x8D         carriage return with bit 7 set, ending the comment
+
+use coco;
+
+
+pub const LEADER_LENGTH:  usize = 256;
+pub const NAME_LENGTH:    usize = 6;
+
+pub const SYNC_BYTE:      u8 = 0xA5;
+pub const SYSTEM_HEADER:  u8 = 0x55;
+pub const BASIC_HEADER:   u8 = 0xD3;
+pub const DATA_MARKER:    u8 = 0x3C;
+pub const EOF_MARKER:     u8 = 0x78;
+
+pub const MAX_BLOCK_SIZE: usize = 256;
+
+// The high speed Model III and 4 tapes are recorded at 1500 baud, with a
+// leader of these and a sync byte of their own, the rest is the same:
+pub const HIGH_SPEED_LEADER: u8 = 0x55;
+pub const HIGH_SPEED_SYNC:   u8 = 0x7F;
+
+pub const COMMENT_MARKER: u8 = 0xA3;
+pub const COMMENT_END:    u8 = 0x8D;
+
+// Ways of checksumming the load address and the data of a block, the ROM
+// uses the plain sum, custom loaders may use any of the others:
+#[derive(Clone, Copy, PartialEq)]
+pub enum Checksum {
+    Sum,
+    // The two's complement of the sum, everything adds up to zero with it:
+    NegatedSum,
+    Xor,
+    // CRC-8 with the polynomial 0x07, starting from zero:
+    Crc8,
+    // CRC-16 with the CCITT polynomial 0x1021, starting from 0xFFFF, stored
+    // lsb first:
+    Crc16,
+}
+
+pub const CHECKSUMS: &[(&str, Checksum)] = &[
+    ("sum",         Checksum::Sum),
+    ("negated-sum", Checksum::NegatedSum),
+    ("xor",         Checksum::Xor),
+    ("crc8",        Checksum::Crc8),
+    ("crc16",       Checksum::Crc16),
+];
+
+impl Checksum {
+    // Number of bytes the checksum takes up at the end of a block:
+    pub fn length(self) -> usize {
+        match self {
+            Checksum::Crc16 => { 2 },
+            _ => { 1 },
+        }
+    }
+
+    pub fn compute(self, load_address: u16, data: &[u8]) -> u16 {
+        let address_bytes = [(load_address & 0x00FF) as u8, ((load_address & 0xFF00) >> 8) as u8];
+        let bytes = address_bytes.iter().chain(data);
+
+        match self {
+            Checksum::Sum => { block_checksum(load_address, data) as u16 },
+            Checksum::NegatedSum => { block_checksum(load_address, data).wrapping_neg() as u16 },
+            Checksum::Xor => { bytes.fold(0u8, |checksum, byte| checksum ^ byte) as u16 },
+            Checksum::Crc8 => {
+                let mut crc: u8 = 0x00;
+                for byte in bytes {
+                    crc ^= *byte;
+                    for _bit in 0..8 {
+                        crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+                    }
+                }
+                crc as u16
+            },
+            Checksum::Crc16 => { crc16(bytes) },
+        }
+    }
+
+    pub fn push(self, checksum: u16, buffer: &mut Vec<u8>) {
+        buffer.push((checksum & 0x00FF) as u8);
+        if self.length() == 2 {
+            buffer.push(((checksum & 0xFF00) >> 8) as u8);
+        }
+    }
+
+    // The checksum in hex, padded to its length:
+    pub fn format(self, checksum: u16) -> String {
+        format!("0x{:01$X}", checksum, self.length() * 2)
+    }
+}
+
+// The leader, the marker bytes and the checksums of the format, which
+// loaders other than the ROM's may expect different ones of:
+#[derive(Clone)]
+pub struct Variant {
+    // Repeated for the length of the leader:
+    pub leader: Vec<u8>,
+    pub sync:   u8,
+    pub header: u8,
+    pub data:   u8,
+    pub eof:    u8,
+    pub checksum: Checksum,
+}
+
+impl Variant {
+    pub fn standard() -> Variant {
+        Variant {
+            leader: vec![0x00],
+            sync:   SYNC_BYTE,
+            header: SYSTEM_HEADER,
+            data:   DATA_MARKER,
+            eof:    EOF_MARKER,
+            checksum: Checksum::Sum,
+        }
+    }
+
+    // The same, but with the leader and the sync byte of high speed tapes:
+    pub fn high_speed(&self) -> Variant {
+        Variant {
+            leader: vec![HIGH_SPEED_LEADER],
+            sync:   HIGH_SPEED_SYNC,
+            ..self.clone()
+        }
+    }
+
+    // Whether the byte may be a part of the leader, silence is taken as
+    // one regardless of the pattern:
+    pub fn is_leader_byte(&self, byte: u8) -> bool {
+        byte == 0x00 || self.leader.contains(&byte)
+    }
+}
+
+// Whether the text can be recorded as a comment, plain printable ASCII:
+pub fn comment_valid(text: &str) -> bool {
+    text.bytes().all(|byte| (0x20..=0x7E).contains(&byte))
+}
+
+// Appends a comment record with the given text, which has to be valid:
+pub fn push_comment(text: &str, buffer: &mut Vec<u8>) {
+    buffer.push(COMMENT_MARKER);
+    buffer.extend(text.bytes().map(|byte| byte | 0x80));
+    buffer.push(COMMENT_END);
+}
+
+// Reads a comment record from the position, just past its marker.  Returns
+// None, leaving the position alone, if there isn't a well formed one:
+pub fn read_comment(buffer: &[u8], position: &mut usize) -> Option<String> {
+    let length = buffer[*position..].iter().position(|byte| *byte == COMMENT_END)?;
+    let text = &buffer[*position..*position + length];
+    if !text.iter().all(|byte| *byte >= 0xA0 && *byte <= 0xFE) {
+        return None;
+    }
+    *position += length + 1;
+
+    Some(text.iter().map(|byte| (byte & 0x7F) as char).collect())
+}
+
+// Entry point for data-only tapes, the Level II ROM's `READY' prompt, which
+// puts the user back into BASIC:
+pub const ROM_BASIC_READY: u16 = 0x1A19;
+
+
+#[derive(Clone)]
+pub struct Block {
+    // Position of the block's data marker within the tape image:
+    pub offset: usize,
+    pub load_address: u16,
+    pub data: Vec<u8>,
+    pub checksum: Checksum,
+    pub stored_checksum: u16,
+}
+
+impl Block {
+    pub fn computed_checksum(&self) -> u16 {
+        self.checksum.compute(self.load_address, &self.data)
+    }
+    pub fn checksum_ok(&self) -> bool {
+        self.computed_checksum() == self.stored_checksum
+    }
+
+    // One past the last address the block loads into:
+    pub fn end_address(&self) -> u32 {
+        (self.load_address as u32) + (self.data.len() as u32)
+    }
+}
+
+#[derive(Clone)]
+pub struct Entry {
+    // Position of the first leader byte and one past the last byte of the
+    // entry within the tape image:
+    pub start_offset: usize,
+    pub end_offset:   usize,
+
+    pub name: Vec<u8>,
+    pub blocks: Vec<Block>,
+
+    // None for data-only entries without the end of file record, which
+    // are read by a program's own loader:
+    pub entry_point: Option<u16>,
+
+    // Texts of the comment records, in the order they were found in:
+    pub comments: Vec<String>,
+
+    // Bytes between blocks which the ROM loader would silently skip:
+    pub skipped_bytes: usize,
+}
+
+impl Entry {
+    pub fn name_string(&self) -> String {
+        String::from_utf8_lossy(&self.name).into_owned()
+    }
+
+    pub fn data_length(&self) -> usize {
+        self.blocks.iter().map(|block| block.data.len()).sum()
+    }
+
+    pub fn bad_checksum_count(&self) -> usize {
+        self.blocks.iter().filter(|block| !block.checksum_ok()).count()
+    }
+
+    // Lowest address loaded into and one past the highest one, or None if
+    // the entry has no data blocks:
+    pub fn address_range(&self) -> Option<(u16, u32)> {
+        let start = self.blocks.iter().map(|block| block.load_address).min();
+        let end   = self.blocks.iter().map(|block| block.end_address()).max();
+
+        match (start, end) {
+            (Some(start), Some(end)) => { Some((start, end)) },
+            _ => { None },
+        }
+    }
+
+    // Builds the memory image the entry would produce when loaded, with any
+    // holes between blocks left as zeros.
+    //
+    // The return value is (base_address, image, has_holes).
+    //
+    pub fn memory_image(&self) -> Option<(u16, Vec<u8>, bool)> {
+        let (start, end) = match self.address_range() {
+            Some(range) => { range },
+            None => { return None; },
+        };
+        let mut image = vec![0; (end - (start as u32)) as usize];
+        let mut filled = vec![false; image.len()];
+
+        for block in &self.blocks {
+            let offset = (block.load_address - start) as usize;
+            for (index, byte) in block.data.iter().enumerate() {
+                image[offset + index] = *byte;
+                filled[offset + index] = true;
+            }
+        }
+        let has_holes = filled.iter().any(|was_filled| !was_filled);
+
+        Some((start, image, has_holes))
+    }
+
+    // Whether the other entry is a copy of this one, with the same blocks,
+    // whose data matches wherever both checksums are right:
+    pub fn is_copy_of(&self, other: &Entry) -> bool {
+        if self.name != other.name || self.entry_point != other.entry_point || self.blocks.len() != other.blocks.len() {
+            return false;
+        }
+        self.blocks.iter().zip(&other.blocks).all(|(block, other_block)| {
+            block.load_address == other_block.load_address && block.data.len() == other_block.data.len() &&
+            (!block.checksum_ok() || !other_block.checksum_ok() || block.data == other_block.data)
+        })
+    }
+}
+
+// Finds the entries recorded several times back to back, as publishers did
+// so that a dropout wouldn't ruin the tape.  Returns the index of the first
+// copy and the number of copies for each distinct entry:
+pub fn group_copies(entries: &[Entry]) -> Vec<(usize, usize)> {
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(&mut (first, ref mut count)) = groups.last_mut() {
+            if entries[first].is_copy_of(entry) {
+                *count += 1;
+                continue;
+            }
+        }
+        groups.push((index, 1));
+    }
+
+    groups
+}
+
+// Combines the copies of an entry, taking each block from the first copy
+// which holds it intact:
+pub fn merge_copies(copies: &[Entry]) -> Entry {
+    let mut merged = copies[0].clone();
+
+    for (block_index, block) in merged.blocks.iter_mut().enumerate() {
+        if block.checksum_ok() {
+            continue;
+        }
+        if let Some(copy) = copies.iter().find(|copy| copy.blocks[block_index].checksum_ok()) {
+            *block = copy.blocks[block_index].clone();
+        }
+    }
+
+    merged
+}
+
+
+// CRC-16 with the CCITT polynomial 0x1021, starting from 0xFFFF:
+pub fn crc16<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _bit in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+pub fn block_checksum(load_address: u16, data: &[u8]) -> u8 {
+    let mut checksum: u8 = 0;
+
+    checksum = checksum.wrapping_add((load_address & 0x00FF) as u8);
+    checksum = checksum.wrapping_add(((load_address & 0xFF00) >> 8) as u8);
+    for byte in data {
+        checksum = checksum.wrapping_add(*byte);
+    }
+
+    checksum
+}
+
+fn read_byte(buffer: &[u8], position: &mut usize, what: &str) -> Result<u8, String> {
+    if *position < buffer.len() {
+        let byte = buffer[*position];
+        *position += 1;
+        Ok(byte)
+    } else {
+        Err(format!("The tape image ends unexpectedly while reading {} at offset {}", what, *position))
+    }
+}
+
+fn read_word(buffer: &[u8], position: &mut usize, what: &str) -> Result<u16, String> {
+    let lsb = read_byte(buffer, position, what)? as u16;
+    let msb = read_byte(buffer, position, what)? as u16;
+
+    Ok((msb << 8) | lsb)
+}
+
+// Whether the leader bytes at the position are the leader of another entry,
+// or make up the rest of the tape:
+fn next_entry_follows(buffer: &[u8], position: usize, variant: &Variant) -> bool {
+    let leader_end = match buffer[position..].iter().position(|byte| !variant.is_leader_byte(*byte)) {
+        Some(length) => { position + length },
+        None => { return true; },
+    };
+
+    buffer[leader_end] == variant.sync && buffer.get(leader_end + 1) == Some(&variant.header)
+}
+
+fn parse_entry(buffer: &[u8], position: &mut usize, variant: &Variant) -> Result<Entry, String> {
+    let start_offset = *position;
+
+    while *position < buffer.len() && variant.is_leader_byte(buffer[*position]) {
+        *position += 1;
+    }
+    let sync = read_byte(buffer, position, "the sync byte")?;
+    if sync != variant.sync {
+        return Err(format!("Expected the sync byte 0x{:02X} at offset {}, found 0x{:02X}", variant.sync, *position - 1, sync));
+    }
+    let header = read_byte(buffer, position, "the header byte")?;
+    if header == BASIC_HEADER && variant.header != BASIC_HEADER {
+        return Err(format!("The entry at offset {} is a BASIC program, only SYSTEM entries are supported", start_offset));
+    } else if header != variant.header {
+        return Err(format!("Expected the SYSTEM header byte 0x{:02X} at offset {}, found 0x{:02X}", variant.header, *position - 1, header));
+    }
+
+    let mut name = Vec::with_capacity(NAME_LENGTH);
+    for _count in 0..NAME_LENGTH {
+        name.push(read_byte(buffer, position, "the entry name")?);
+    }
+
+    let mut blocks = Vec::new();
+    let mut comments = Vec::new();
+    let mut skipped_bytes: usize = 0;
+    loop {
+        let offset = *position;
+
+        // Entries without the end of file record end with the tape, or at
+        // the leader of the next one:
+        if offset == buffer.len() || (variant.is_leader_byte(buffer[offset]) && next_entry_follows(buffer, offset, variant)) {
+            return Ok(Entry {
+                start_offset,
+                end_offset: offset,
+                name,
+                blocks,
+                entry_point: None,
+                comments,
+                skipped_bytes,
+            });
+        }
+        let marker = read_byte(buffer, position, "a block marker")?;
+
+        if marker == variant.data {
+            let length = match read_byte(buffer, position, "a block length")? {
+                0 => { MAX_BLOCK_SIZE },
+                length => { length as usize },
+            };
+            let load_address = read_word(buffer, position, "a block load address")?;
+            if (*position + length) > buffer.len() {
+                return Err(format!("The tape image ends unexpectedly inside the data block at offset {}", offset));
+            }
+            let data = buffer[*position..*position + length].to_owned();
+            *position += length;
+            let mut stored_checksum = read_byte(buffer, position, "a block checksum")? as u16;
+            if variant.checksum.length() == 2 {
+                stored_checksum |= (read_byte(buffer, position, "a block checksum")? as u16) << 8;
+            }
+
+            blocks.push(Block {
+                offset,
+                load_address,
+                data,
+                checksum: variant.checksum,
+                stored_checksum,
+            });
+        } else if marker == variant.eof {
+            let entry_point = read_word(buffer, position, "the entry point")?;
+
+            return Ok(Entry {
+                start_offset,
+                end_offset: *position,
+                name,
+                blocks,
+                entry_point: Some(entry_point),
+                comments,
+                skipped_bytes,
+            });
+        } else if marker == COMMENT_MARKER {
+            match read_comment(buffer, position) {
+                Some(text) => { comments.push(text); },
+                None => { skipped_bytes += 1; },
+            }
+        } else {
+            skipped_bytes += 1;
+        }
+    }
+}
+
+// Parses all the SYSTEM entries recorded one after another in a tape image:
+pub fn parse_tape(buffer: &[u8], variant: &Variant) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut position: usize = 0;
+
+    loop {
+        // Trailing silence after the last entry is fine:
+        if buffer[position..].iter().all(|byte| variant.is_leader_byte(*byte)) {
+            break;
+        }
+        match parse_entry(buffer, &mut position, variant) {
+            Ok(entry) => { entries.push(entry); },
+            Err(_error) if entries.is_empty() && coco::is_coco_tape(buffer) => {
+                return Err("The tape image is one of a Color Computer, convert it with the `coco' command".to_owned());
+            },
+            Err(error) => { return Err(error); },
+        }
+    }
+
+    if entries.is_empty() {
+        Err("The tape image doesn't contain any entries".to_owned())
+    } else {
+        Ok(entries)
+    }
+}
+
+// Records the entries of a tape image again with the leader and the sync
+// byte of another variant, such as high speed tapes as 500 baud ones:
+pub fn reframe(buffer: &[u8], entries: &[Entry], from: &Variant, to: &Variant) -> Vec<u8> {
+    let mut reframed = Vec::with_capacity(buffer.len());
+    for entry in entries {
+        let sync_offset = entry.start_offset + buffer[entry.start_offset..entry.end_offset].iter()
+                                                     .position(|byte| !from.is_leader_byte(*byte)).unwrap_or(0);
+        reframed.extend(to.leader.iter().cycle().take(LEADER_LENGTH));
+        reframed.push(to.sync);
+        reframed.extend_from_slice(&buffer[sync_offset + 1..entry.end_offset]);
+    }
+    reframed
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The load address makes up the first two of the bytes checksummed,
+    // 0x3231 puts `12' in front of the rest of the usual check string:
+    const CHECK_ADDRESS: u16 = 0x3231;
+    const CHECK_DATA: &[u8] = b"3456789";
+
+    // A tape with a single entry of a single block of the check data:
+    fn check_tape(variant: &Variant) -> Vec<u8> {
+        let mut buffer = vec![0x00; 16];
+        buffer.push(variant.sync);
+        buffer.push(variant.header);
+        buffer.extend_from_slice(b"CHECK ");
+        buffer.push(variant.data);
+        buffer.push(CHECK_DATA.len() as u8);
+        buffer.push((CHECK_ADDRESS & 0x00FF) as u8);
+        buffer.push(((CHECK_ADDRESS & 0xFF00) >> 8) as u8);
+        buffer.extend_from_slice(CHECK_DATA);
+        variant.checksum.push(variant.checksum.compute(CHECK_ADDRESS, CHECK_DATA), &mut buffer);
+        buffer.push(variant.eof);
+        buffer.extend_from_slice(&[0x00, 0x70]);
+        buffer
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!(Checksum::Sum.compute(CHECK_ADDRESS, CHECK_DATA), 0xDD);
+        assert_eq!(Checksum::NegatedSum.compute(CHECK_ADDRESS, CHECK_DATA), 0x23);
+        assert_eq!(Checksum::Xor.compute(CHECK_ADDRESS, CHECK_DATA), 0x31);
+        assert_eq!(Checksum::Crc8.compute(CHECK_ADDRESS, CHECK_DATA), 0xF4);
+        assert_eq!(Checksum::Crc16.compute(CHECK_ADDRESS, CHECK_DATA), 0x29B1);
+        assert_eq!(crc16(b"123456789".iter()), 0x29B1);
+        assert_eq!(crc16([].iter()), 0xFFFF);
+    }
+
+    #[test]
+    fn negated_sum_adds_up_to_zero() {
+        let checksum = Checksum::NegatedSum.compute(CHECK_ADDRESS, CHECK_DATA) as u8;
+        assert_eq!(block_checksum(CHECK_ADDRESS, CHECK_DATA).wrapping_add(checksum), 0x00);
+    }
+
+    #[test]
+    fn stored_lsb_first() {
+        let mut buffer = Vec::new();
+        Checksum::Crc16.push(0x29B1, &mut buffer);
+        Checksum::Crc8.push(0xF4, &mut buffer);
+        assert_eq!(buffer, [0xB1, 0x29, 0xF4]);
+
+        assert_eq!(Checksum::Sum.format(0x0D), "0x0D");
+        assert_eq!(Checksum::Crc16.format(0x29B1), "0x29B1");
+    }
+
+    #[test]
+    fn checksums_survive_parsing() {
+        for &(name, checksum) in CHECKSUMS {
+            let variant = Variant { checksum, ..Variant::standard() };
+            let entries = parse_tape(&check_tape(&variant), &variant).unwrap();
+            assert_eq!(entries.len(), 1, "{}", name);
+            assert_eq!(entries[0].entry_point, Some(0x7000), "{}", name);
+            assert!(entries[0].blocks[0].checksum_ok(), "{}", name);
+            assert_eq!(entries[0].blocks[0].data, CHECK_DATA, "{}", name);
+        }
+    }
+
+    #[test]
+    fn corruption_detected() {
+        for &(name, checksum) in CHECKSUMS {
+            let variant = Variant { checksum, ..Variant::standard() };
+            let mut buffer = check_tape(&variant);
+            let data_offset = buffer.iter().position(|byte| *byte == b'3').unwrap();
+            buffer[data_offset] ^= 0x01;
+
+            let entries = parse_tape(&buffer, &variant).unwrap();
+            assert!(!entries[0].blocks[0].checksum_ok(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Tape images as text, for keeping them in version control, where they diff
+// line by line.  Every byte of the tape is accounted for by the records, so
+// that the text makes the very same tape image again:
+//
+//   format leader-pattern 00 sync-byte 0xA5 header-byte 0x55 data-marker 0x3C eof-marker 0x78 checksum sum
+//
+//   entry 1
+//   fill 256 x 0x00              a run of the same byte, such as the leader
+//   name "GAME  "                the sync byte, the header byte and the name
+//   comment "Level 1"            a comment record
+//   block 0x5200 checksum 0x1A   a data block, its data following:
+//     5200  21 00 3C 11 01 3C 01 FF 03 36 20 ED B0 C3 00 52
+//   bytes 12 34                  bytes the loader skips over
+//   end 0x5200                   the end of file record with the entry point
+//
+// A block's checksum may also be given as `auto', to have it computed for
+// data edited by hand.  The addresses at the start of the data lines are
+// only there to be read, and everything from a `#' on is a comment.
+
+use numbers;
+use tape;
+
+
+const BYTES_PER_LINE: usize = 16;
+
+// Runs of the same byte at least this long are written as fills:
+const MIN_FILL: usize = 8;
+
+
+// Quotes the name of an entry, which may hold any bytes:
+fn quote(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for byte in bytes {
+        match *byte {
+            b'"' | b'\\' => { quoted.push('\\'); quoted.push(*byte as char); },
+            0x20..=0x7E => { quoted.push(*byte as char); },
+            _ => { quoted.push_str(&format!("\\x{:02X}", byte)); },
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn push_hex_lines(text: &mut String, prefix: &str, bytes: &[u8]) {
+    for line in bytes.chunks(BYTES_PER_LINE) {
+        let hex: Vec<String> = line.iter().map(|byte| format!("{:02X}", byte)).collect();
+        text.push_str(&format!("{}{}\n", prefix, hex.join(" ")));
+    }
+}
+
+// Writes bytes which aren't a part of any record, runs as fills, comment
+// records as comments and the rest as they are:
+fn push_loose_bytes(text: &mut String, bytes: &[u8]) {
+    let mut pending: Vec<u8> = Vec::new();
+    let mut position = 0;
+    while position < bytes.len() {
+        let run = bytes[position..].iter().take_while(|byte| **byte == bytes[position]).count();
+        let mut comment_end = position + 1;
+        let comment = if bytes[position] == tape::COMMENT_MARKER { tape::read_comment(bytes, &mut comment_end) } else { None };
+
+        if run < MIN_FILL && comment.is_none() {
+            pending.push(bytes[position]);
+            position += 1;
+            continue;
+        }
+        push_hex_lines(text, "bytes ", &pending);
+        pending.clear();
+        match comment {
+            Some(comment) => {
+                text.push_str(&format!("comment {}\n", quote(comment.as_bytes())));
+                position = comment_end;
+            },
+            None => {
+                text.push_str(&format!("fill {} x 0x{:02X}\n", run, bytes[position]));
+                position += run;
+            },
+        }
+    }
+    push_hex_lines(text, "bytes ", &pending);
+}
+
+// Describes the tape image, whose entries are given as parsed with the
+// variant:
+pub fn generate_text(buffer: &[u8], entries: &[tape::Entry], variant: &tape::Variant, title: &str) -> String {
+    let checksum_name = tape::CHECKSUMS.iter().find(|&&(_name, checksum)| checksum == variant.checksum).map(|&(name, _checksum)| name).unwrap_or("sum");
+    let leader: Vec<String> = variant.leader.iter().map(|byte| format!("{:02X}", byte)).collect();
+    let mut text = format!("# {}\nformat leader-pattern {} sync-byte 0x{:02X} header-byte 0x{:02X} data-marker 0x{:02X} eof-marker 0x{:02X} checksum {}\n",
+                           title, leader.join(","), variant.sync, variant.header, variant.data, variant.eof, checksum_name);
+
+    let mut position = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        text.push_str(&format!("\nentry {}\n", index + 1));
+        let sync_offset = entry.start_offset + buffer[entry.start_offset..entry.end_offset].iter()
+                                                     .position(|byte| !variant.is_leader_byte(*byte)).unwrap_or(0);
+        push_loose_bytes(&mut text, &buffer[position..sync_offset]);
+        text.push_str(&format!("name {}\n", quote(&buffer[sync_offset + 2..sync_offset + 2 + tape::NAME_LENGTH])));
+        position = sync_offset + 2 + tape::NAME_LENGTH;
+
+        for block in &entry.blocks {
+            push_loose_bytes(&mut text, &buffer[position..block.offset]);
+            let note = if block.checksum_ok() {
+                format!("{} bytes", block.data.len())
+            } else {
+                format!("{} bytes, bad checksum, {} expected", block.data.len(), block.checksum.format(block.computed_checksum()))
+            };
+            text.push_str(&format!("block 0x{:04X} checksum {}  # {}\n", block.load_address, block.checksum.format(block.stored_checksum), note));
+            for (line_index, line) in block.data.chunks(BYTES_PER_LINE).enumerate() {
+                let address = block.load_address.wrapping_add((line_index * BYTES_PER_LINE) as u16);
+                push_hex_lines(&mut text, &format!("  {:04X}  ", address), line);
+            }
+            position = block.offset + 4 + block.data.len() + block.checksum.length();
+        }
+
+        // An end of file record which doesn't fit after the last block, as
+        // with entries parsed from another tape image, is left to the bytes:
+        match entry.entry_point {
+            Some(entry_point) if entry.end_offset >= position + 3 => {
+                push_loose_bytes(&mut text, &buffer[position..entry.end_offset - 3]);
+                text.push_str(&format!("end 0x{:04X}\n", entry_point));
+            },
+            _ => {
+                push_loose_bytes(&mut text, &buffer[position..entry.end_offset.max(position)]);
+            },
+        }
+        position = entry.end_offset.max(position);
+    }
+
+    if position < buffer.len() {
+        text.push_str("\n# After the last entry:\n");
+        push_loose_bytes(&mut text, &buffer[position..]);
+    }
+    text
+}
+
+// Splits a line into its words, quoted strings staying whole with their
+// quotes, up to a comment:
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut characters = line.chars().peekable();
+    while let Some(&character) = characters.peek() {
+        if character.is_whitespace() {
+            characters.next();
+        } else if character == '#' {
+            break;
+        } else if character == '"' {
+            let mut word = String::new();
+            word.push(characters.next().unwrap());
+            loop {
+                match characters.next() {
+                    Some('\\') => {
+                        word.push('\\');
+                        word.push(characters.next().ok_or("the string ends with a `\\'")?);
+                    },
+                    Some('"') => {
+                        word.push('"');
+                        break;
+                    },
+                    Some(character) => { word.push(character); },
+                    None => { return Err("the string isn't closed with a `\"'".to_owned()); },
+                }
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&character) = characters.peek() {
+                if character.is_whitespace() || character == '#' {
+                    break;
+                }
+                word.push(character);
+                characters.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+
+fn unquote(word: &str) -> Result<Vec<u8>, String> {
+    if word.len() < 2 || !word.starts_with('"') || !word.ends_with('"') {
+        return Err(format!("expected a string in quotes, found `{}'", word));
+    }
+    let mut bytes = Vec::new();
+    let mut characters = word[1..word.len() - 1].chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => {
+                match characters.next() {
+                    Some('x') => {
+                        let digits: String = characters.by_ref().take(2).collect();
+                        match u8::from_str_radix(&digits, 16) {
+                            Ok(byte) if digits.len() == 2 => { bytes.push(byte); },
+                            _ => { return Err(format!("invalid escape `\\x{}' in {}", digits, word)); },
+                        }
+                    },
+                    Some(escaped) if escaped == '"' || escaped == '\\' => { bytes.push(escaped as u8); },
+                    _ => { return Err(format!("invalid escape in {}", word)); },
+                }
+            },
+            ' '..='~' => { bytes.push(character as u8); },
+            _ => { return Err(format!("{} holds characters other than printable ASCII, write them as `\\xNN'", word)); },
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_value(word: &str, maximum: u32, what: &str) -> Result<u32, String> {
+    match numbers::parse_number(word, 16) {
+        Ok(value) if value <= maximum => { Ok(value) },
+        Ok(_value) => { Err(format!("{} `{}' is out of range, it goes up to 0x{:X}", what, word, maximum)) },
+        Err(error) => { Err(format!("invalid {} `{}': {}", what, word, error)) },
+    }
+}
+
+fn push_word(value: u16, buffer: &mut Vec<u8>) {
+    buffer.push((value & 0x00FF) as u8);
+    buffer.push((value >> 8) as u8);
+}
+
+// A data block being read, finished when the next record starts:
+struct PendingBlock {
+    load_address: u16,
+    // None for `auto':
+    checksum: Option<u16>,
+    data: Vec<u8>,
+}
+
+fn push_block(block: PendingBlock, variant: &tape::Variant, buffer: &mut Vec<u8>) -> Result<(), String> {
+    if block.data.is_empty() || block.data.len() > tape::MAX_BLOCK_SIZE {
+        return Err(format!("the block at 0x{:04X} holds {} bytes, blocks hold 1 to {}", block.load_address, block.data.len(), tape::MAX_BLOCK_SIZE));
+    }
+    buffer.push(variant.data);
+    buffer.push((block.data.len() % tape::MAX_BLOCK_SIZE) as u8);
+    push_word(block.load_address, buffer);
+    buffer.extend_from_slice(&block.data);
+    let checksum = block.checksum.unwrap_or_else(|| variant.checksum.compute(block.load_address, &block.data));
+    variant.checksum.push(checksum, buffer);
+    Ok(())
+}
+
+// Makes the tape image a text describes, returning the variant of the format
+// it's in too:
+pub fn parse_text(text: &str) -> Result<(Vec<u8>, tape::Variant), String> {
+    let mut variant = tape::Variant::standard();
+    let mut buffer = Vec::new();
+    let mut block: Option<PendingBlock> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let error_at = |error: String| format!("line {}: {}", index + 1, error);
+        let words = split_words(line).map_err(&error_at)?;
+        if words.is_empty() {
+            continue;
+        }
+
+        // Data lines of the block being read start with an address:
+        if let Some(ref mut block) = block {
+            if line.starts_with(char::is_whitespace) {
+                for word in &words[1..] {
+                    block.data.push(parse_value(word, 0xFF, "data byte").map_err(&error_at)? as u8);
+                }
+                continue;
+            }
+        }
+        if let Some(finished) = block.take() {
+            push_block(finished, &variant, &mut buffer).map_err(&error_at)?;
+        }
+
+        let arguments = &words[1..];
+        match (words[0].as_str(), arguments.len()) {
+            ("format", _) => {
+                if arguments.len() % 2 != 0 {
+                    return Err(error_at("expected the format as pairs of a setting and its value".to_owned()));
+                }
+                for pair in arguments.chunks(2) {
+                    let value = || parse_value(&pair[1], 0xFF, &pair[0]).map(|value| value as u8).map_err(&error_at);
+                    match pair[0].as_str() {
+                        "leader-pattern" => {
+                            variant.leader = Vec::new();
+                            for byte in pair[1].split(',') {
+                                variant.leader.push(parse_value(byte, 0xFF, "leader byte").map_err(&error_at)? as u8);
+                            }
+                        },
+                        "sync-byte" => { variant.sync = value()?; },
+                        "header-byte" => { variant.header = value()?; },
+                        "data-marker" => { variant.data = value()?; },
+                        "eof-marker" => { variant.eof = value()?; },
+                        "checksum" => {
+                            variant.checksum = match tape::CHECKSUMS.iter().find(|&&(name, _checksum)| name == pair[1]) {
+                                Some(&(_name, checksum)) => { checksum },
+                                None => { return Err(error_at(format!("unknown checksum scheme `{}'", pair[1]))); },
+                            };
+                        },
+                        setting => { return Err(error_at(format!("unknown format setting `{}'", setting))); },
+                    }
+                }
+            },
+            ("entry", 1) => { },
+            ("fill", 3) if arguments[1] == "x" => {
+                let count = numbers::parse_number(&arguments[0], 10).map_err(|error| error_at(format!("invalid count `{}': {}", arguments[0], error)))?;
+                let byte = parse_value(&arguments[2], 0xFF, "fill byte").map_err(&error_at)? as u8;
+                buffer.resize(buffer.len() + count as usize, byte);
+            },
+            ("bytes", _) => {
+                for word in arguments {
+                    buffer.push(parse_value(word, 0xFF, "byte").map_err(&error_at)? as u8);
+                }
+            },
+            ("name", 1) => {
+                let name = unquote(&arguments[0]).map_err(&error_at)?;
+                if name.len() != tape::NAME_LENGTH {
+                    return Err(error_at(format!("names are {} characters long, padded with spaces, {} is {}", tape::NAME_LENGTH, arguments[0], name.len())));
+                }
+                buffer.push(variant.sync);
+                buffer.push(variant.header);
+                buffer.extend(name);
+            },
+            ("comment", 1) => {
+                let comment = String::from_utf8_lossy(&unquote(&arguments[0]).map_err(&error_at)?).into_owned();
+                if !tape::comment_valid(&comment) {
+                    return Err(error_at("comments can only hold printable ASCII".to_owned()));
+                }
+                tape::push_comment(&comment, &mut buffer);
+            },
+            ("block", 3) if arguments[1] == "checksum" => {
+                let load_address = parse_value(&arguments[0], 0xFFFF, "load address").map_err(&error_at)? as u16;
+                let maximum = if variant.checksum.length() == 2 { 0xFFFF } else { 0xFF };
+                let checksum = if arguments[2] == "auto" {
+                    None
+                } else {
+                    Some(parse_value(&arguments[2], maximum, "checksum").map_err(&error_at)? as u16)
+                };
+                block = Some(PendingBlock { load_address, checksum, data: Vec::new() });
+            },
+            ("end", 1) => {
+                let entry_point = parse_value(&arguments[0], 0xFFFF, "entry point").map_err(&error_at)? as u16;
+                buffer.push(variant.eof);
+                push_word(entry_point, &mut buffer);
+            },
+            _ => {
+                return Err(error_at(format!("can't make sense of `{}'", line.trim())));
+            },
+        }
+    }
+    if let Some(finished) = block.take() {
+        push_block(finished, &variant, &mut buffer).map_err(|error| format!("line {}: {}", text.lines().count(), error))?;
+    }
+
+    Ok((buffer, variant))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two entries, the first with a comment and a block with a bad checksum
+    // but no end of file record, the second with bytes the loader skips
+    // over in front of its end of file record, and a bit of silence after
+    // the last one:
+    fn awkward_tape(variant: &tape::Variant) -> Vec<u8> {
+        let mut buffer = vec![0x00; 32];
+        buffer.push(variant.sync);
+        buffer.push(variant.header);
+        buffer.extend_from_slice(b"FIRST ");
+        tape::push_comment("Level 1", &mut buffer);
+        let data: Vec<u8> = (0..40).collect();
+        let checksum = variant.checksum.compute(0x5200, &data) ^ 0x01;
+        push_block(PendingBlock { load_address: 0x5200, checksum: Some(checksum), data }, variant, &mut buffer).unwrap();
+
+        buffer.extend_from_slice(&[0x00; 32]);
+        buffer.push(variant.sync);
+        buffer.push(variant.header);
+        buffer.extend_from_slice(b"SECOND");
+        push_block(PendingBlock { load_address: 0x7000, checksum: None, data: vec![0xC9; 20] }, variant, &mut buffer).unwrap();
+        buffer.extend_from_slice(&[0x12, 0x34]);
+        buffer.push(variant.eof);
+        push_word(0x7000, &mut buffer);
+        buffer.extend_from_slice(&[0x00; 3]);
+        buffer
+    }
+
+    #[test]
+    fn text_makes_the_same_tape() {
+        for &(name, checksum) in tape::CHECKSUMS {
+            let variant = tape::Variant { checksum, ..tape::Variant::standard() };
+            let buffer = awkward_tape(&variant);
+            let entries = tape::parse_tape(&buffer, &variant).unwrap();
+            assert_eq!(entries.len(), 2, "{}", name);
+            assert_eq!(entries[0].comments, ["Level 1"], "{}", name);
+            assert!(!entries[0].blocks[0].checksum_ok(), "{}", name);
+            assert_eq!(entries[0].entry_point, None, "{}", name);
+            assert_eq!(entries[1].skipped_bytes, 2, "{}", name);
+
+            let text = generate_text(&buffer, &entries, &variant, "Test tape.");
+            let (parsed, parsed_variant) = parse_text(&text).unwrap();
+            assert!(parsed == buffer, "{}:\n{}", name, text);
+            assert!(parsed_variant.checksum == checksum, "{}", name);
+        }
+    }
+
+    #[test]
+    fn misplaced_end_left_to_the_bytes() {
+        let variant = tape::Variant::standard();
+        let buffer = awkward_tape(&variant);
+        let mut entries = tape::parse_tape(&buffer, &variant).unwrap();
+        let block = &entries[1].blocks[0];
+        entries[1].end_offset = block.offset + 4 + block.data.len() + 1 + 1;
+
+        let text = generate_text(&buffer, &entries, &variant, "Test tape.");
+        assert!(!text.contains("\nend "));
+        assert!(parse_text(&text).unwrap().0 == buffer);
+    }
+}
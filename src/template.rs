@@ -0,0 +1,154 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// Output filename templates, such as `{name}_{base:04X}.cas', where the
+// placeholders are replaced by values describing the output.  A placeholder
+// may carry a format, made of an optional `0' for zero padding, a width,
+// and `x' or `X' for hexadecimal or `d' for decimal numbers.  Literal braces
+// are written as `{{' and `}}'.
+
+pub enum Value {
+    Str(String),
+    Number(u32),
+}
+
+
+pub fn is_template(text: &str) -> bool {
+    text.contains('{')
+}
+
+fn format_value(value: &Value, format: &str) -> Result<String, String> {
+    let zero_pad = format.starts_with('0');
+    let radix_char = format.chars().last().filter(|character| !character.is_ascii_digit());
+    let width_text = &format[..format.len() - radix_char.map_or(0, |character| character.len_utf8())];
+    let width = if width_text.is_empty() {
+        0
+    } else {
+        match width_text.parse::<usize>() {
+            Ok(width) => { width },
+            Err(_error) => { return Err(format!("invalid format `{}'", format)); },
+        }
+    };
+
+    let text = match *value {
+        Value::Str(ref string) => {
+            if radix_char.is_some() {
+                return Err(format!("the format `{}' is only valid for numbers", format));
+            }
+            string.clone()
+        },
+        Value::Number(number) => {
+            match radix_char {
+                Some('x') => { format!("{:x}", number) },
+                Some('X') => { format!("{:X}", number) },
+                Some('d') | None => { format!("{}", number) },
+                Some(other) => { return Err(format!("unknown number format `{}'", other)); },
+            }
+        },
+    };
+
+    let padding = width.saturating_sub(text.chars().count());
+    let pad_char = if zero_pad { "0" } else { " " };
+    match *value {
+        Value::Number(_number) => { Ok(pad_char.repeat(padding) + &text) },
+        Value::Str(_) => { Ok(text + &pad_char.repeat(padding)) },
+    }
+}
+
+pub fn expand(template: &str, values: &[(&str, Value)]) -> Result<String, String> {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(character) = chars.next() {
+        if character == '}' {
+            if chars.next() != Some('}') {
+                return Err("a `}' closes no placeholder, write it as `}}'".to_owned());
+            }
+            result.push('}');
+            continue;
+        }
+        if character != '{' {
+            result.push(character);
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            result.push('{');
+            continue;
+        }
+
+        let mut placeholder = String::new();
+        loop {
+            match chars.next() {
+                Some('}') => { break; },
+                Some(character) => { placeholder.push(character); },
+                None => { return Err(format!("unterminated placeholder `{{{}'", placeholder)); },
+            }
+        }
+        let (key, format) = match placeholder.find(':') {
+            Some(colon) => { (&placeholder[..colon], &placeholder[colon + 1..]) },
+            None => { (placeholder.as_str(), "") },
+        };
+
+        match values.iter().find(|&&(name, ref _value)| name == key) {
+            Some(&(_name, ref value)) => { result.push_str(&format_value(value, format)?); },
+            None => {
+                let known: Vec<&str> = values.iter().map(|&(name, ref _value)| name).collect();
+                return Err(format!("unknown placeholder `{{{}}}', the available ones are {}", key, known.join(", ")));
+            },
+        }
+    }
+
+    Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> Vec<(&'static str, Value)> {
+        vec![("name", Value::Str("GAME".to_owned())), ("base", Value::Number(0x5200)), ("size", Value::Number(300))]
+    }
+
+    #[test]
+    fn placeholders_replaced() {
+        assert_eq!(expand("{name}.cas", &values()).unwrap(), "GAME.cas");
+        assert_eq!(expand("{name}_{base:04X}.cas", &values()).unwrap(), "GAME_5200.cas");
+        assert_eq!(expand("{size:06x}-{size:d}-{size:5}", &values()).unwrap(), "00012c-300-  300");
+        assert_eq!(expand("{name:8}|", &values()).unwrap(), "GAME    |");
+        assert_eq!(expand("plain.cas", &values()).unwrap(), "plain.cas");
+    }
+
+    #[test]
+    fn braces_escaped() {
+        assert_eq!(expand("{{{name}}}", &values()).unwrap(), "{GAME}");
+        assert_eq!(expand("a}}b{{c", &values()).unwrap(), "a}b{c");
+        assert!(expand("a}b", &values()).is_err());
+        assert!(expand("{name}}", &values()).is_err());
+        assert!(expand("{name", &values()).is_err());
+    }
+
+    #[test]
+    fn bad_placeholders_refused() {
+        let error = expand("{title}.cas", &values()).unwrap_err();
+        assert!(error.contains("`{title}'") && error.contains("name, base, size"), "{}", error);
+        assert!(expand("{name:04X}", &values()).is_err());
+        assert!(expand("{name:x}", &values()).is_err());
+        assert!(expand("{base:q}", &values()).is_err());
+        assert!(expand("{base:4-}", &values()).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// Re-running things whenever their input files change.  The files are
+// polled, as there's no portable way of being told about the changes.
+
+use std::fs;
+use std::path;
+use std::thread;
+use std::time;
+
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+// Editors and assemblers don't always write a file in one go, so wait for
+// a while after noticing a change:
+const SETTLE_DELAY: time::Duration = time::Duration::from_millis(200);
+
+
+fn modification_times(paths: &[path::PathBuf]) -> Vec<Option<time::SystemTime>> {
+    paths.iter().map(|path| {
+        match fs::metadata(path) {
+            Ok(metadata) => { metadata.modified().ok() },
+            Err(_error) => { None },
+        }
+    }).collect()
+}
+
+// Runs `rebuild' right away, and then every time one of the files changes,
+// until the program is interrupted:
+pub fn watch(paths: &[path::PathBuf], rebuild: &mut dyn FnMut()) -> ! {
+    let mut times = modification_times(paths);
+    rebuild();
+
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        if modification_times(paths) == times {
+            continue;
+        }
+
+        thread::sleep(SETTLE_DELAY);
+        times = modification_times(paths);
+
+        // Wait for files which are being replaced to reappear:
+        if times.iter().any(|time| time.is_none()) {
+            continue;
+        }
+        rebuild();
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+// The little of WebSockets (RFC 6455) which serving tapes to web pages
+// takes: the handshake, and frames small enough to go out whole.
+
+use std::io;
+use std::io::Write;
+
+use hashes;
+
+
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xA;
+
+// Appended to the key of the client, to prove the server understood it:
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+
+    for group in bytes.chunks(3) {
+        let value = ((group[0] as u32) << 16) | ((*group.get(1).unwrap_or(&0) as u32) << 8) | (*group.get(2).unwrap_or(&0) as u32);
+        for position in 0..4 {
+            if position <= group.len() {
+                text.push(ALPHABET[((value >> (18 - position * 6)) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+// The answer to the client's request to switch to the WebSocket protocol:
+pub fn handshake_response(key: &str) -> String {
+    let accept = base64(&hashes::sha1(format!("{}{}", key.trim(), HANDSHAKE_GUID).as_bytes()));
+    format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept)
+}
+
+// Frames from the server go unmasked, and whole:
+pub fn write_frame(writer: &mut dyn Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = vec![0x80 | opcode];
+    if payload.len() < 126 {
+        header.push(payload.len() as u8);
+    } else if payload.len() <= 0xFFFF {
+        header.push(126);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    } else {
+        header.push(127);
+        header.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    }
+    writer.write_all(&header)?;
+    writer.write_all(payload)
+}
+
+// The longest frames taken from clients, which only send short commands.
+// Control frames can't be any longer than this by the RFC:
+const MAX_CONTROL_LENGTH: usize = 125;
+pub const MAX_CLIENT_FRAME: usize = 1024;
+
+// Status sent in the close frame for frames which break the protocol:
+pub const PROTOCOL_ERROR: u16 = 1002;
+
+// Takes the first whole frame from the bytes received so far, unmasking it,
+// or None if it hasn't all arrived yet.  Control messages are short, so
+// there's no need for frames in pieces.  Frames which aren't masked, as all
+// the ones from clients have to be, or which are longer than any command,
+// are refused as soon as their header is in, so that no more than one short
+// frame is ever waited for:
+pub fn take_frame(received: &mut Vec<u8>) -> Result<Option<(u8, Vec<u8>)>, String> {
+    if received.len() < 2 {
+        return Ok(None);
+    }
+    let opcode = received[0] & 0x0F;
+    if received[1] & 0x80 == 0 {
+        return Err("The client sent a frame which isn't masked".to_owned());
+    }
+    let (length, offset) = match received[1] & 0x7F {
+        126 => {
+            if received.len() < 4 {
+                return Ok(None);
+            }
+            (u16::from_be_bytes([received[2], received[3]]) as u64, 4)
+        },
+        127 => {
+            if received.len() < 10 {
+                return Ok(None);
+            }
+            let mut length = [0u8; 8];
+            length.copy_from_slice(&received[2..10]);
+            (u64::from_be_bytes(length), 10)
+        },
+        length => { (length as u64, 2) },
+    };
+    let limit = if opcode & 0x8 != 0 { MAX_CONTROL_LENGTH } else { MAX_CLIENT_FRAME };
+    if length > limit as u64 {
+        return Err(format!("The client sent a frame of {} bytes, longer than the {} allowed", length, limit));
+    }
+    let length = length as usize;
+    if received.len() < offset + 4 + length {
+        return Ok(None);
+    }
+
+    let mask = [received[offset], received[offset + 1], received[offset + 2], received[offset + 3]];
+    let start = offset + 4;
+    let payload: Vec<u8> = received[start..start + length].iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]).collect();
+    received.drain(..start + length);
+    Ok(Some((opcode, payload)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Masks the payload the way a client does:
+    fn client_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = Vec::new();
+        write_frame(&mut frame, opcode, payload).unwrap();
+        frame.truncate(frame.len() - payload.len());
+        frame[1] |= 0x80;
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+        frame
+    }
+
+    #[test]
+    fn handshake_accepts_the_key() {
+        // The sample handshake of RFC 6455:
+        let response = handshake_response("dGhlIHNhbXBsZSBub25jZQ==");
+        assert!(response.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(response.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+    }
+
+    #[test]
+    fn frames_taken_whole() {
+        // The masked `Hello' of RFC 6455, followed by the start of another:
+        let mut received = vec![0x81, 0x85, 0x37, 0xFA, 0x21, 0x3D, 0x7F, 0x9F, 0x4D, 0x51, 0x58, 0x89];
+        assert_eq!(take_frame(&mut received), Ok(Some((TEXT, b"Hello".to_vec()))));
+        assert_eq!(received, [0x89]);
+        assert_eq!(take_frame(&mut received), Ok(None));
+
+        let mut received = client_frame(PING, b"ping");
+        received.extend(client_frame(TEXT, &[b'x'; 300]));
+        // Until the first frame is all there, there's nothing to take yet:
+        for length in 0..received.len() - 300 - 8 {
+            let mut partial = received[..length].to_vec();
+            assert_eq!(take_frame(&mut partial), Ok(None), "{} bytes", length);
+            assert_eq!(partial.len(), length);
+        }
+        assert_eq!(take_frame(&mut received), Ok(Some((PING, b"ping".to_vec()))));
+        assert_eq!(take_frame(&mut received), Ok(Some((TEXT, vec![b'x'; 300]))));
+        assert!(received.is_empty());
+    }
+
+    #[test]
+    fn bad_frames_refused() {
+        // Unmasked, as from a server:
+        let mut received = Vec::new();
+        write_frame(&mut received, TEXT, b"play").unwrap();
+        assert!(take_frame(&mut received).is_err());
+
+        // Longer than allowed, known from the header alone:
+        let mut received = client_frame(PING, &[0; 126]);
+        received.truncate(4);
+        assert!(take_frame(&mut received).is_err());
+        let mut received = client_frame(BINARY, &[0; MAX_CLIENT_FRAME + 1]);
+        received.truncate(8);
+        assert!(take_frame(&mut received).is_err());
+
+        // Lengths which don't fit anywhere:
+        let mut received = vec![0x82, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00];
+        assert!(take_frame(&mut received).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// An interactive walk through packing a program, for when the program is
+// started on a terminal without any arguments.  The answers are turned into
+// the equivalent command lines, which are shown and then run, so that the
+// usual validation applies and the user learns how to skip the questions
+// the next time.
+
+use std::env;
+use std::io;
+use std::io::Write;
+use std::path;
+use std::process;
+
+use files;
+use numbers;
+
+
+// Asks a question, returns the trimmed answer, or the default if the answer
+// is empty.  Running out of input ends the program:
+fn ask(question: &str, default: Option<&str>) -> String {
+    match default {
+        Some(default) => { print!("{} [{}]: ", question, default); },
+        None => { print!("{}: ", question); },
+    }
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => {
+            println!();
+            process::exit(1);
+        },
+        Ok(_length) => { },
+    }
+
+    let answer = answer.trim();
+    match default {
+        Some(default) if answer.is_empty() => { default.to_owned() },
+        _ => { answer.to_owned() },
+    }
+}
+
+fn ask_input_file() -> (path::PathBuf, usize) {
+    loop {
+        let answer = ask("Raw binary file to pack", None);
+        if answer.is_empty() {
+            continue;
+        }
+        let in_path = (answer.as_ref() as &path::Path).to_owned();
+        if !in_path.is_file() {
+            println!("`{}' is not a file, please try again.", in_path.display());
+            continue;
+        }
+        match files::load_file(&in_path) {
+            Some(ref data) if data.is_empty() => {
+                println!("`{}' is empty, please choose another file.", in_path.display());
+            },
+            Some(data) => { return (in_path, data.len()); },
+            None => { },
+        }
+    }
+}
+
+fn ask_name(in_path: &path::Path) -> String {
+    let mut default_name = match in_path.file_stem() {
+        Some(stem) => { stem.to_string_lossy().into_owned() },
+        None => { String::new() },
+    };
+    default_name = default_name.chars().filter(|character| character.is_ascii_alphabetic())
+                               .take(6).collect::<String>().to_uppercase();
+
+    loop {
+        let default = if default_name.is_empty() { None } else { Some(default_name.as_str()) };
+        let answer = ask("Name of the tape entry, up to 6 letters", default);
+        if !answer.chars().any(|character| character.is_ascii_alphabetic()) {
+            println!("The name needs to contain at least one letter.");
+            continue;
+        }
+        if answer.len() > 6 {
+            println!("The name is too long, it can have at most 6 characters.");
+            continue;
+        }
+        return answer;
+    }
+}
+
+fn ask_address(question: &str, default: Option<&str>, size: usize) -> u16 {
+    loop {
+        let answer = ask(question, default);
+        match numbers::parse_number(&answer, 16) {
+            Ok(address) if (address as usize) + size > 0x10000 => {
+                println!("The program wouldn't fit into the address space at 0x{:04X}.", address);
+            },
+            Ok(address) => { return address as u16; },
+            Err(error) => { println!("{}, please try again.", error); },
+        }
+    }
+}
+
+fn ask_format() -> bool {
+    loop {
+        let answer = ask("Output format, `cas' for emulators or `wav' for a real cassette", Some("cas"));
+        match answer.to_lowercase().as_str() {
+            "cas" => { return false; },
+            "wav" => { return true; },
+            _ => { println!("Please answer `cas' or `wav'."); },
+        }
+    }
+}
+
+// Runs another instance of this program with the given arguments:
+fn run_command(progname: &str, args: &[String]) -> bool {
+    println!();
+    println!("Running: {} {}", progname, args.join(" "));
+    println!();
+
+    let executable = match env::current_exe() {
+        Ok(executable) => { executable },
+        Err(error) => {
+            error!("Failed to find the program's executable: {}.", error);
+            return false;
+        },
+    };
+    match process::Command::new(executable).args(args).status() {
+        Ok(status) => { status.success() },
+        Err(error) => {
+            error!("Failed to run the command: {}.", error);
+            false
+        },
+    }
+}
+
+pub fn run(progname: &str) {
+    println!("Welcome to {}, this will walk you through packing a program into a", progname);
+    println!("tape image.  Addresses are hexadecimal, press Enter to accept the defaults");
+    println!("shown in brackets.  Run `{} --help' to use the program directly.", progname);
+    println!();
+
+    let (in_path, size) = ask_input_file();
+    let name = ask_name(&in_path);
+    let base_address = ask_address("Base address to load the program at", None, size);
+    let base_string = format!("{:04X}", base_address);
+    let entry_point = ask_address("Entry point address", Some(&base_string), 0);
+    let wav_output = ask_format();
+
+    let cas_default = files::default_output_path(&in_path, "cas");
+    let cas_path = ask("Tape image to write", Some(&cas_default.to_string_lossy()));
+
+    let mut pack_args: Vec<String> = vec!["pack".to_owned(),
+                                          "-i".to_owned(), in_path.to_string_lossy().into_owned(),
+                                          "-o".to_owned(), cas_path.clone(),
+                                          "-n".to_owned(), name,
+                                          "-b".to_owned(), base_string];
+    if entry_point != base_address {
+        pack_args.push("-s".to_owned());
+        pack_args.push(format!("{:04X}", entry_point));
+    }
+    if !run_command(progname, &pack_args) {
+        process::exit(1);
+    }
+
+    if wav_output {
+        let wav_default = files::default_output_path(cas_path.as_ref(), "wav");
+        let wav_path = ask("Recording to write", Some(&wav_default.to_string_lossy()));
+        let wav_args: Vec<String> = vec!["wav".to_owned(), "-i".to_owned(), cas_path, "-o".to_owned(), wav_path];
+        if !run_command(progname, &wav_args) {
+            process::exit(1);
+        }
+    }
+    process::exit(0);
+}
@@ -0,0 +1,748 @@
+// Copyright (c) 2017 Marek Benc <dusxmt@gmx.com>
+//
+// Permission to use, copy, modify, and distribute this software for any
+// purpose with or without fee is hereby granted, provided that the above
+// copyright notice and this permission notice appear in all copies.
+//
+// THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+// WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+// MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+// ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+// WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+// ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+// OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+//
+
+
+
+// A Z80 core for running the programs on tapes in simulation, instruction by
+// instruction.  It does all the documented instructions, and the IXH, IXL,
+// IYH and IYL ones, but keeps no count of the clock cycles and takes no
+// interrupts, none of which the simulation needs.  The flags are right but
+// for the two undocumented bits.
+
+// What the CPU is attached to:
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    fn input(&mut self, port: u16) -> u8;
+    fn output(&mut self, port: u16, value: u8);
+}
+
+const FLAG_C:  u8 = 0x01;
+const FLAG_N:  u8 = 0x02;
+const FLAG_PV: u8 = 0x04;
+const FLAG_H:  u8 = 0x10;
+const FLAG_Z:  u8 = 0x40;
+const FLAG_S:  u8 = 0x80;
+
+// Which register pair stands in for HL, changed by the DD and FD prefixes:
+#[derive(Clone, Copy, PartialEq)]
+enum Index {
+    HL,
+    IX,
+    IY,
+}
+
+#[derive(Clone, Default)]
+pub struct Cpu {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    // AF', BC', DE' and HL':
+    pub alternate: [u16; 4],
+    pub ix: u16,
+    pub iy: u16,
+    pub sp: u16,
+    pub pc: u16,
+    pub i: u8,
+    pub r: u8,
+    pub iff1: bool,
+    pub iff2: bool,
+    pub interrupt_mode: u8,
+    pub halted: bool,
+}
+
+fn parity(value: u8) -> bool {
+    value.count_ones().is_multiple_of(2)
+}
+
+// The sign, zero and parity flags of a result:
+fn szp(value: u8) -> u8 {
+    let mut flags = value & FLAG_S;
+    if value == 0 {
+        flags |= FLAG_Z;
+    }
+    if parity(value) {
+        flags |= FLAG_PV;
+    }
+    flags
+}
+
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu { sp: 0xFFFF, f: 0xFF, a: 0xFF, ..Cpu::default() }
+    }
+
+    pub fn bc(&self) -> u16 { ((self.b as u16) << 8) | (self.c as u16) }
+    pub fn de(&self) -> u16 { ((self.d as u16) << 8) | (self.e as u16) }
+    pub fn hl(&self) -> u16 { ((self.h as u16) << 8) | (self.l as u16) }
+    pub fn af(&self) -> u16 { ((self.a as u16) << 8) | (self.f as u16) }
+    pub fn set_bc(&mut self, value: u16) { self.b = (value >> 8) as u8; self.c = value as u8; }
+    pub fn set_de(&mut self, value: u16) { self.d = (value >> 8) as u8; self.e = value as u8; }
+    pub fn set_hl(&mut self, value: u16) { self.h = (value >> 8) as u8; self.l = value as u8; }
+    pub fn set_af(&mut self, value: u16) { self.a = (value >> 8) as u8; self.f = value as u8; }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn fetch<B: Bus>(&mut self, bus: &mut B) -> u8 {
+        let value = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let low = self.fetch(bus) as u16;
+        let high = self.fetch(bus) as u16;
+        (high << 8) | low
+    }
+
+    fn read_word<B: Bus>(bus: &mut B, address: u16) -> u16 {
+        (bus.read(address) as u16) | ((bus.read(address.wrapping_add(1)) as u16) << 8)
+    }
+
+    fn write_word<B: Bus>(bus: &mut B, address: u16, value: u16) {
+        bus.write(address, value as u8);
+        bus.write(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn push<B: Bus>(&mut self, bus: &mut B, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        Cpu::write_word(bus, self.sp, value);
+    }
+
+    pub fn pop<B: Bus>(&mut self, bus: &mut B) -> u16 {
+        let value = Cpu::read_word(bus, self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
+    }
+
+    fn index_register(&self, index: Index) -> u16 {
+        match index {
+            Index::HL => { self.hl() },
+            Index::IX => { self.ix },
+            Index::IY => { self.iy },
+        }
+    }
+
+    fn set_index_register(&mut self, index: Index, value: u16) {
+        match index {
+            Index::HL => { self.set_hl(value); },
+            Index::IX => { self.ix = value; },
+            Index::IY => { self.iy = value; },
+        }
+    }
+
+    // The register pairs as numbered in the instructions, with SP or AF as
+    // the last one:
+    fn pair(&self, number: u8, index: Index, with_af: bool) -> u16 {
+        match number {
+            0 => { self.bc() },
+            1 => { self.de() },
+            2 => { self.index_register(index) },
+            _ => { if with_af { self.af() } else { self.sp } },
+        }
+    }
+
+    fn set_pair(&mut self, number: u8, index: Index, with_af: bool, value: u16) {
+        match number {
+            0 => { self.set_bc(value); },
+            1 => { self.set_de(value); },
+            2 => { self.set_index_register(index, value); },
+            _ => { if with_af { self.set_af(value); } else { self.sp = value; } },
+        }
+    }
+
+    // The registers as numbered in the instructions, but for 6, (HL), H and L
+    // standing for the halves of IX or IY with the prefixes:
+    fn register(&self, number: u8, index: Index) -> u8 {
+        match (number, index) {
+            (0, _) => { self.b },
+            (1, _) => { self.c },
+            (2, _) => { self.d },
+            (3, _) => { self.e },
+            (4, Index::HL) => { self.h },
+            (4, Index::IX) => { (self.ix >> 8) as u8 },
+            (4, Index::IY) => { (self.iy >> 8) as u8 },
+            (5, Index::HL) => { self.l },
+            (5, Index::IX) => { self.ix as u8 },
+            (5, Index::IY) => { self.iy as u8 },
+            _ => { self.a },
+        }
+    }
+
+    fn set_register(&mut self, number: u8, index: Index, value: u8) {
+        match (number, index) {
+            (0, _) => { self.b = value; },
+            (1, _) => { self.c = value; },
+            (2, _) => { self.d = value; },
+            (3, _) => { self.e = value; },
+            (4, Index::HL) => { self.h = value; },
+            (4, Index::IX) => { self.ix = (self.ix & 0x00FF) | ((value as u16) << 8); },
+            (4, Index::IY) => { self.iy = (self.iy & 0x00FF) | ((value as u16) << 8); },
+            (5, Index::HL) => { self.l = value; },
+            (5, Index::IX) => { self.ix = (self.ix & 0xFF00) | (value as u16); },
+            (5, Index::IY) => { self.iy = (self.iy & 0xFF00) | (value as u16); },
+            _ => { self.a = value; },
+        }
+    }
+
+    // The address of (HL), or of (IX+d) and (IY+d), whose displacement
+    // follows the opcode:
+    fn memory_operand<B: Bus>(&mut self, bus: &mut B, index: Index) -> u16 {
+        match index {
+            Index::HL => { self.hl() },
+            _ => {
+                let displacement = self.fetch(bus) as i8;
+                self.index_register(index).wrapping_add(displacement as u16)
+            },
+        }
+    }
+
+    fn condition(&self, number: u8) -> bool {
+        match number {
+            0 => { !self.flag(FLAG_Z) },
+            1 => { self.flag(FLAG_Z) },
+            2 => { !self.flag(FLAG_C) },
+            3 => { self.flag(FLAG_C) },
+            4 => { !self.flag(FLAG_PV) },
+            5 => { self.flag(FLAG_PV) },
+            6 => { !self.flag(FLAG_S) },
+            _ => { self.flag(FLAG_S) },
+        }
+    }
+
+    // ADD, ADC, SUB, SBC, AND, XOR, OR and CP, by their number:
+    fn alu(&mut self, operation: u8, value: u8) {
+        let a = self.a;
+        let carry = if self.flag(FLAG_C) && (operation == 1 || operation == 3) { 1 } else { 0 };
+        match operation {
+            0 | 1 => {
+                let result = (a as u16) + (value as u16) + carry;
+                let overflow = (a ^ value) & 0x80 == 0 && (a ^ result as u8) & 0x80 != 0;
+                self.a = result as u8;
+                self.f = (self.a & FLAG_S) | if self.a == 0 { FLAG_Z } else { 0 } |
+                         if (a & 0x0F) + (value & 0x0F) + (carry as u8) > 0x0F { FLAG_H } else { 0 } |
+                         if overflow { FLAG_PV } else { 0 } | if result > 0xFF { FLAG_C } else { 0 };
+            },
+            2 | 3 | 7 => {
+                let result = (a as i16) - (value as i16) - (carry as i16);
+                let overflow = (a ^ value) & 0x80 != 0 && (a ^ result as u8) & 0x80 != 0;
+                let result_byte = result as u8;
+                self.f = (result_byte & FLAG_S) | if result_byte == 0 { FLAG_Z } else { 0 } |
+                         if ((a & 0x0F) as i16) - ((value & 0x0F) as i16) - (carry as i16) < 0 { FLAG_H } else { 0 } |
+                         if overflow { FLAG_PV } else { 0 } | FLAG_N | if result < 0 { FLAG_C } else { 0 };
+                if operation != 7 {
+                    self.a = result_byte;
+                }
+            },
+            4 => {
+                self.a &= value;
+                self.f = szp(self.a) | FLAG_H;
+            },
+            5 => {
+                self.a ^= value;
+                self.f = szp(self.a);
+            },
+            _ => {
+                self.a |= value;
+                self.f = szp(self.a);
+            },
+        }
+    }
+
+    fn increment(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.f = (self.f & FLAG_C) | (result & FLAG_S) | if result == 0 { FLAG_Z } else { 0 } |
+                 if value & 0x0F == 0x0F { FLAG_H } else { 0 } | if value == 0x7F { FLAG_PV } else { 0 };
+        result
+    }
+
+    fn decrement(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.f = (self.f & FLAG_C) | (result & FLAG_S) | if result == 0 { FLAG_Z } else { 0 } |
+                 if value & 0x0F == 0x00 { FLAG_H } else { 0 } | if value == 0x80 { FLAG_PV } else { 0 } | FLAG_N;
+        result
+    }
+
+    fn add16(&mut self, left: u16, right: u16) -> u16 {
+        let result = (left as u32) + (right as u32);
+        self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) |
+                 if (left & 0x0FFF) + (right & 0x0FFF) > 0x0FFF { FLAG_H } else { 0 } |
+                 if result > 0xFFFF { FLAG_C } else { 0 };
+        result as u16
+    }
+
+    // ADC HL and SBC HL, which set all the flags:
+    fn adc16(&mut self, left: u16, right: u16, subtract: bool) -> u16 {
+        let carry = if self.flag(FLAG_C) { 1 } else { 0 };
+        let (result, half, overflow, carry_out) = if subtract {
+            let result = (left as i32) - (right as i32) - carry;
+            (result as u16, ((left & 0x0FFF) as i32) - ((right & 0x0FFF) as i32) - carry < 0,
+             (left ^ right) & 0x8000 != 0 && (left ^ result as u16) & 0x8000 != 0, result < 0)
+        } else {
+            let result = (left as i32) + (right as i32) + carry;
+            (result as u16, ((left & 0x0FFF) as i32) + ((right & 0x0FFF) as i32) + carry > 0x0FFF,
+             (left ^ right) & 0x8000 == 0 && (left ^ result as u16) & 0x8000 != 0, result > 0xFFFF)
+        };
+        self.f = ((result >> 8) as u8 & FLAG_S) | if result == 0 { FLAG_Z } else { 0 } | if half { FLAG_H } else { 0 } |
+                 if overflow { FLAG_PV } else { 0 } | if subtract { FLAG_N } else { 0 } | if carry_out { FLAG_C } else { 0 };
+        result
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SLL and SRL, by their number:
+    fn rotate(&mut self, operation: u8, value: u8) -> u8 {
+        let carry_in = self.flag(FLAG_C) as u8;
+        let (result, carry_out) = match operation {
+            0 => { (value.rotate_left(1), value & 0x80 != 0) },
+            1 => { (value.rotate_right(1), value & 0x01 != 0) },
+            2 => { ((value << 1) | carry_in, value & 0x80 != 0) },
+            3 => { ((value >> 1) | (carry_in << 7), value & 0x01 != 0) },
+            4 => { (value << 1, value & 0x80 != 0) },
+            5 => { ((value >> 1) | (value & 0x80), value & 0x01 != 0) },
+            6 => { ((value << 1) | 0x01, value & 0x80 != 0) },
+            _ => { (value >> 1, value & 0x01 != 0) },
+        };
+        self.f = szp(result) | if carry_out { FLAG_C } else { 0 };
+        result
+    }
+
+    fn daa(&mut self) {
+        let mut correction = 0;
+        let mut carry = self.flag(FLAG_C);
+        if self.flag(FLAG_H) || self.a & 0x0F > 9 {
+            correction |= 0x06;
+        }
+        if carry || self.a > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+        let before = self.a;
+        self.a = if self.flag(FLAG_N) { self.a.wrapping_sub(correction) } else { self.a.wrapping_add(correction) };
+        let half = if self.flag(FLAG_N) { self.flag(FLAG_H) && before & 0x0F < 6 } else { before & 0x0F > 9 };
+        self.f = szp(self.a) | (self.f & FLAG_N) | if half { FLAG_H } else { 0 } | if carry { FLAG_C } else { 0 };
+    }
+
+    // Runs the next instruction:
+    pub fn step<B: Bus>(&mut self, bus: &mut B) {
+        if self.halted {
+            return;
+        }
+        self.r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7F);
+        let mut opcode = self.fetch(bus);
+        let mut index = Index::HL;
+        while opcode == 0xDD || opcode == 0xFD {
+            index = if opcode == 0xDD { Index::IX } else { Index::IY };
+            opcode = self.fetch(bus);
+        }
+        match opcode {
+            0xCB => { self.execute_cb(bus, index); },
+            0xED => { self.execute_ed(bus); },
+            _ => { self.execute(bus, opcode, index); },
+        }
+    }
+
+    fn execute<B: Bus>(&mut self, bus: &mut B, opcode: u8, index: Index) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match x {
+            0 => {
+                match z {
+                    0 => {
+                        match y {
+                            0 => { },
+                            1 => {
+                                let af = self.af();
+                                self.set_af(self.alternate[0]);
+                                self.alternate[0] = af;
+                            },
+                            2 => {
+                                let displacement = self.fetch(bus) as i8;
+                                self.b = self.b.wrapping_sub(1);
+                                if self.b != 0 {
+                                    self.pc = self.pc.wrapping_add(displacement as u16);
+                                }
+                            },
+                            _ => {
+                                let displacement = self.fetch(bus) as i8;
+                                if y == 3 || self.condition(y - 4) {
+                                    self.pc = self.pc.wrapping_add(displacement as u16);
+                                }
+                            },
+                        }
+                    },
+                    1 => {
+                        if q == 0 {
+                            let value = self.fetch_word(bus);
+                            self.set_pair(p, index, false, value);
+                        } else {
+                            let (left, right) = (self.index_register(index), self.pair(p, index, false));
+                            let result = self.add16(left, right);
+                            self.set_index_register(index, result);
+                        }
+                    },
+                    2 => {
+                        match (p, q) {
+                            (0, 0) => { bus.write(self.bc(), self.a); },
+                            (1, 0) => { bus.write(self.de(), self.a); },
+                            (2, 0) => {
+                                let address = self.fetch_word(bus);
+                                Cpu::write_word(bus, address, self.index_register(index));
+                            },
+                            (3, 0) => {
+                                let address = self.fetch_word(bus);
+                                bus.write(address, self.a);
+                            },
+                            (0, _) => { self.a = bus.read(self.bc()); },
+                            (1, _) => { self.a = bus.read(self.de()); },
+                            (2, _) => {
+                                let address = self.fetch_word(bus);
+                                let value = Cpu::read_word(bus, address);
+                                self.set_index_register(index, value);
+                            },
+                            _ => {
+                                let address = self.fetch_word(bus);
+                                self.a = bus.read(address);
+                            },
+                        }
+                    },
+                    3 => {
+                        let value = self.pair(p, index, false);
+                        let value = if q == 0 { value.wrapping_add(1) } else { value.wrapping_sub(1) };
+                        self.set_pair(p, index, false, value);
+                    },
+                    4 | 5 => {
+                        if y == 6 {
+                            let address = self.memory_operand(bus, index);
+                            let value = bus.read(address);
+                            let value = if z == 4 { self.increment(value) } else { self.decrement(value) };
+                            bus.write(address, value);
+                        } else {
+                            let value = self.register(y, index);
+                            let value = if z == 4 { self.increment(value) } else { self.decrement(value) };
+                            self.set_register(y, index, value);
+                        }
+                    },
+                    6 => {
+                        if y == 6 {
+                            let address = self.memory_operand(bus, index);
+                            let value = self.fetch(bus);
+                            bus.write(address, value);
+                        } else {
+                            let value = self.fetch(bus);
+                            self.set_register(y, index, value);
+                        }
+                    },
+                    _ => {
+                        match y {
+                            0..=3 => {
+                                // RLCA, RRCA, RLA and RRA leave S, Z and P/V alone:
+                                let flags = self.f & (FLAG_S | FLAG_Z | FLAG_PV);
+                                let value = self.a;
+                                self.a = self.rotate(y, value);
+                                self.f = (self.f & FLAG_C) | flags;
+                            },
+                            4 => { self.daa(); },
+                            5 => {
+                                self.a = !self.a;
+                                self.f |= FLAG_H | FLAG_N;
+                            },
+                            6 => { self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_PV)) | FLAG_C; },
+                            _ => {
+                                let half = if self.flag(FLAG_C) { FLAG_H } else { 0 };
+                                self.f = ((self.f & (FLAG_S | FLAG_Z | FLAG_PV | FLAG_C)) ^ FLAG_C) | half;
+                            },
+                        }
+                    },
+                }
+            },
+            1 => {
+                if y == 6 && z == 6 {
+                    self.halted = true;
+                    self.pc = self.pc.wrapping_sub(1);
+                } else if y == 6 {
+                    // With (IX+d), the other register is the plain one:
+                    let address = self.memory_operand(bus, index);
+                    bus.write(address, self.register(z, Index::HL));
+                } else if z == 6 {
+                    let address = self.memory_operand(bus, index);
+                    let value = bus.read(address);
+                    self.set_register(y, Index::HL, value);
+                } else {
+                    let value = self.register(z, index);
+                    self.set_register(y, index, value);
+                }
+            },
+            2 => {
+                let value = if z == 6 {
+                    let address = self.memory_operand(bus, index);
+                    bus.read(address)
+                } else {
+                    self.register(z, index)
+                };
+                self.alu(y, value);
+            },
+            _ => {
+                match z {
+                    0 => {
+                        if self.condition(y) {
+                            self.pc = self.pop(bus);
+                        }
+                    },
+                    1 => {
+                        if q == 0 {
+                            let value = self.pop(bus);
+                            self.set_pair(p, index, true, value);
+                        } else {
+                            match p {
+                                0 => { self.pc = self.pop(bus); },
+                                1 => {
+                                    let (bc, de, hl) = (self.bc(), self.de(), self.hl());
+                                    self.set_bc(self.alternate[1]);
+                                    self.set_de(self.alternate[2]);
+                                    self.set_hl(self.alternate[3]);
+                                    self.alternate[1] = bc;
+                                    self.alternate[2] = de;
+                                    self.alternate[3] = hl;
+                                },
+                                2 => { self.pc = self.index_register(index); },
+                                _ => { self.sp = self.index_register(index); },
+                            }
+                        }
+                    },
+                    2 => {
+                        let address = self.fetch_word(bus);
+                        if self.condition(y) {
+                            self.pc = address;
+                        }
+                    },
+                    3 => {
+                        match y {
+                            0 => { self.pc = self.fetch_word(bus); },
+                            2 => {
+                                let port = self.fetch(bus);
+                                bus.output(((self.a as u16) << 8) | (port as u16), self.a);
+                            },
+                            3 => {
+                                let port = self.fetch(bus);
+                                self.a = bus.input(((self.a as u16) << 8) | (port as u16));
+                            },
+                            4 => {
+                                let value = Cpu::read_word(bus, self.sp);
+                                Cpu::write_word(bus, self.sp, self.index_register(index));
+                                self.set_index_register(index, value);
+                            },
+                            5 => {
+                                let (de, hl) = (self.de(), self.hl());
+                                self.set_de(hl);
+                                self.set_hl(de);
+                            },
+                            6 => {
+                                self.iff1 = false;
+                                self.iff2 = false;
+                            },
+                            _ => {
+                                self.iff1 = true;
+                                self.iff2 = true;
+                            },
+                        }
+                    },
+                    4 => {
+                        let address = self.fetch_word(bus);
+                        if self.condition(y) {
+                            let return_address = self.pc;
+                            self.push(bus, return_address);
+                            self.pc = address;
+                        }
+                    },
+                    5 => {
+                        if q == 0 {
+                            let value = self.pair(p, index, true);
+                            self.push(bus, value);
+                        } else {
+                            let address = self.fetch_word(bus);
+                            let return_address = self.pc;
+                            self.push(bus, return_address);
+                            self.pc = address;
+                        }
+                    },
+                    6 => {
+                        let value = self.fetch(bus);
+                        self.alu(y, value);
+                    },
+                    _ => {
+                        let return_address = self.pc;
+                        self.push(bus, return_address);
+                        self.pc = (y as u16) * 8;
+                    },
+                }
+            },
+        }
+    }
+
+    // The rotations, shifts and bit instructions:
+    fn execute_cb<B: Bus>(&mut self, bus: &mut B, index: Index) {
+        // With the prefixes, the displacement comes before the opcode:
+        let address = if index == Index::HL { self.hl() } else { self.memory_operand(bus, index) };
+        let opcode = self.fetch(bus);
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let in_memory = z == 6 || index != Index::HL;
+        let value = if in_memory { bus.read(address) } else { self.register(z, Index::HL) };
+
+        let result = match x {
+            0 => { self.rotate(y, value) },
+            1 => {
+                let bit = value & (1 << y);
+                self.f = (self.f & FLAG_C) | FLAG_H | if bit == 0 { FLAG_Z | FLAG_PV } else { 0 } | (bit & FLAG_S);
+                return;
+            },
+            2 => { value & !(1 << y) },
+            _ => { value | (1 << y) },
+        };
+        if in_memory {
+            bus.write(address, result);
+        }
+        if z != 6 {
+            self.set_register(z, Index::HL, result);
+        }
+    }
+
+    // The block instructions and the rest of the ones with the ED prefix,
+    // the undefined ones doing nothing:
+    fn execute_ed<B: Bus>(&mut self, bus: &mut B) {
+        let opcode = self.fetch(bus);
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+        let (p, q) = (y >> 1, y & 1);
+        match (x, z) {
+            (1, 0) => {
+                let value = bus.input(self.bc());
+                self.f = (self.f & FLAG_C) | szp(value);
+                if y != 6 {
+                    self.set_register(y, Index::HL, value);
+                }
+            },
+            (1, 1) => {
+                let value = if y == 6 { 0 } else { self.register(y, Index::HL) };
+                bus.output(self.bc(), value);
+            },
+            (1, 2) => {
+                let (left, right) = (self.hl(), self.pair(p, Index::HL, false));
+                let result = self.adc16(left, right, q == 0);
+                self.set_hl(result);
+            },
+            (1, 3) => {
+                let address = self.fetch_word(bus);
+                if q == 0 {
+                    Cpu::write_word(bus, address, self.pair(p, Index::HL, false));
+                } else {
+                    let value = Cpu::read_word(bus, address);
+                    self.set_pair(p, Index::HL, false, value);
+                }
+            },
+            (1, 4) => {
+                let value = self.a;
+                self.a = 0;
+                self.alu(2, value);
+            },
+            (1, 5) => {
+                self.pc = self.pop(bus);
+                self.iff1 = self.iff2;
+            },
+            (1, 6) => { self.interrupt_mode = [0, 0, 1, 2][(y & 3) as usize]; },
+            (1, 7) => {
+                match y {
+                    0 => { self.i = self.a; },
+                    1 => { self.r = self.a; },
+                    2 | 3 => {
+                        self.a = if y == 2 { self.i } else { self.r };
+                        self.f = (self.f & FLAG_C) | (self.a & FLAG_S) | if self.a == 0 { FLAG_Z } else { 0 } |
+                                 if self.iff2 { FLAG_PV } else { 0 };
+                    },
+                    4 | 5 => {
+                        let address = self.hl();
+                        let value = bus.read(address);
+                        let (memory, a) = if y == 4 {
+                            ((self.a << 4) | (value >> 4), (self.a & 0xF0) | (value & 0x0F))
+                        } else {
+                            ((value << 4) | (self.a & 0x0F), (self.a & 0xF0) | (value >> 4))
+                        };
+                        bus.write(address, memory);
+                        self.a = a;
+                        self.f = (self.f & FLAG_C) | szp(self.a);
+                    },
+                    _ => { },
+                }
+            },
+            (2, 0..=3) if y >= 4 => { self.block_instruction(bus, y, z); },
+            _ => { },
+        }
+    }
+
+    // LDI, CPI, INI and OUTI, their decrementing and their repeating forms:
+    fn block_instruction<B: Bus>(&mut self, bus: &mut B, y: u8, z: u8) {
+        let step: u16 = if y & 1 == 0 { 1 } else { 0xFFFF };
+        let repeat = y >= 6;
+        let hl = self.hl();
+        match z {
+            0 => {
+                let value = bus.read(hl);
+                bus.write(self.de(), value);
+                self.set_de(self.de().wrapping_add(step));
+                self.set_hl(hl.wrapping_add(step));
+                self.set_bc(self.bc().wrapping_sub(1));
+                self.f = (self.f & (FLAG_S | FLAG_Z | FLAG_C)) | if self.bc() != 0 { FLAG_PV } else { 0 };
+                if repeat && self.bc() != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            },
+            1 => {
+                let value = bus.read(hl);
+                let result = self.a.wrapping_sub(value);
+                self.set_hl(hl.wrapping_add(step));
+                self.set_bc(self.bc().wrapping_sub(1));
+                self.f = (self.f & FLAG_C) | (result & FLAG_S) | if result == 0 { FLAG_Z } else { 0 } |
+                         if self.a & 0x0F < value & 0x0F { FLAG_H } else { 0 } | if self.bc() != 0 { FLAG_PV } else { 0 } | FLAG_N;
+                if repeat && self.bc() != 0 && result != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            },
+            2 => {
+                let value = bus.input(self.bc());
+                bus.write(hl, value);
+                self.set_hl(hl.wrapping_add(step));
+                self.b = self.b.wrapping_sub(1);
+                self.f = (self.f & FLAG_C) | FLAG_N | if self.b == 0 { FLAG_Z } else { 0 };
+                if repeat && self.b != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            },
+            _ => {
+                let value = bus.read(hl);
+                self.b = self.b.wrapping_sub(1);
+                bus.output(self.bc(), value);
+                self.set_hl(hl.wrapping_add(step));
+                self.f = (self.f & FLAG_C) | FLAG_N | if self.b == 0 { FLAG_Z } else { 0 };
+                if repeat && self.b != 0 {
+                    self.pc = self.pc.wrapping_sub(2);
+                }
+            },
+        }
+    }
+}